@@ -1,14 +1,19 @@
-
 layout(location = 0) in vec3 v_pos;
 layout(location = 1) in vec3 v_normal;
 layout(location = 2) in vec2 v_uv;
 layout(location=3) in vec3 v_tangent;
 layout(location=4) in vec3 v_bitangent;
+layout(location=5) in uvec4 v_joints;
+layout(location=6) in vec4 v_weights;
+
+// must match renderer::MAX_GPU_JOINTS
+const int MAX_JOINTS = 48;
 
 uniform mat4 projection;
 
 uniform mat4 modelview;
 uniform mat4 normal_matrix;
+uniform mat4 joint_matrices[MAX_JOINTS];
 
 out vec2 frag_uv;
 out vec3 frag_normal;
@@ -16,20 +21,33 @@ out vec3 frag_pos;
 out vec3 frag_eye_normal;
 out mat3 frag_tbn_matrix;
 
-mat3 make_tbn_matrix(vec3 eye_normal){
-    vec3 eye_t = normalize(vec3(normal_matrix * vec4(v_tangent, 0.0)));
-    vec3 eye_b = normalize(vec3(normal_matrix * vec4(v_bitangent, 0.0)));
+// blends the joint matrices moving the vertex. Unskinned vertices have
+// no weights, and are left in place
+mat4 skin_matrix(){
+    float total = dot(v_weights, vec4(1.0));
+    return v_weights.x * joint_matrices[v_joints.x]
+        + v_weights.y * joint_matrices[v_joints.y]
+        + v_weights.z * joint_matrices[v_joints.z]
+        + v_weights.w * joint_matrices[v_joints.w]
+        + (1.0 - total) * mat4(1.0);
+}
+
+mat3 make_tbn_matrix(mat4 skin, vec3 eye_normal){
+    vec3 eye_t = normalize(vec3(normal_matrix * skin * vec4(v_tangent, 0.0)));
+    vec3 eye_b = normalize(vec3(normal_matrix * skin * vec4(v_bitangent, 0.0)));
     return mat3(eye_t, eye_b, normalize(eye_normal));
 }
 
 void
 main()
 {
+  mat4 skin = skin_matrix();
+  vec3 normal = normalize((skin * vec4(v_normal, 0.0)).xyz);
   frag_uv = v_uv;
-  frag_normal = v_normal;
-  frag_eye_normal = normalize((normal_matrix * vec4(v_normal, 0.0)).xyz);
-  vec4 eye = modelview * vec4(v_pos, 1.0);
+  frag_normal = normal;
+  frag_eye_normal = normalize((normal_matrix * vec4(normal, 0.0)).xyz);
+  vec4 eye = modelview * skin * vec4(v_pos, 1.0);
   gl_Position = projection * eye;
   frag_pos = eye.xyz;
-  frag_tbn_matrix = make_tbn_matrix(frag_eye_normal);
-}
\ No newline at end of file
+  frag_tbn_matrix = make_tbn_matrix(skin, frag_eye_normal);
+}
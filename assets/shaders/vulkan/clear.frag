@@ -0,0 +1,15 @@
+#version 450
+
+layout(push_constant) uniform ClearData
+{
+  vec4 color;
+}
+clear_data;
+
+layout(location = 0) out vec4 out_color;
+
+void
+main()
+{
+  out_color = clear_data.color;
+}
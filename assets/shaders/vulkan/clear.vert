@@ -0,0 +1,10 @@
+#version 450
+
+// A triangle covering the viewport, on the far plane. Drawn with no
+// vertex buffer to clear a view's part of the framebuffer.
+void
+main()
+{
+  vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+  gl_Position = vec4(uv * 2.0 - 1.0, 1.0, 1.0);
+}
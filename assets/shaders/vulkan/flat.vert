@@ -5,6 +5,12 @@ layout(location = 1) in vec3 v_normal;
 layout(location = 2) in vec2 v_uv;
 layout(location = 3) in vec3 v_tangent;
 layout(location = 4) in vec3 v_bitangent;
+layout(location = 5) in uvec4 v_joints;
+layout(location = 6) in vec4 v_weights;
+
+// must match renderer::MAX_GPU_JOINTS and MAX_GPU_MORPH_TARGETS
+const int MAX_JOINTS = 48;
+const int MAX_MORPH_TARGETS = 8;
 
 layout(set = 0, binding = 0) uniform MatrixData
 {
@@ -14,22 +20,66 @@ layout(set = 0, binding = 0) uniform MatrixData
 }
 m;
 
+layout(set = 0, binding = 1) uniform JointData
+{
+  mat4 joints[MAX_JOINTS];
+}
+skin_data;
+
+layout(set = 0, binding = 2) uniform MorphData
+{
+  vec4 weights[MAX_MORPH_TARGETS / 4];
+  // number of targets and of vertices in the mesh
+  uvec4 counts;
+}
+morph;
+
+// position, normal and tangent deltas of each vertex, target by target
+layout(set = 0, binding = 3) readonly buffer MorphDeltas
+{
+  vec4 deltas[];
+}
+morph_deltas;
+
 layout(location = 0) out vec2 frag_uv; 
 layout(location = 1) out vec3 frag_pos; 
 layout(location = 2) out mat3 frag_tbn_matrix; 
 
-mat3 make_tbn_matrix(vec3 eye_normal){
-    vec3 eye_t = normalize(vec3(m.normal * vec4(v_tangent, 0.0)));
-    vec3 eye_b = normalize(vec3(m.normal * vec4(v_bitangent, 0.0)));
+// blends the joint matrices moving the vertex. Unskinned vertices have
+// no weights, and are left in place
+mat4 skin_matrix(){
+    float total = dot(v_weights, vec4(1.0));
+    return v_weights.x * skin_data.joints[v_joints.x]
+        + v_weights.y * skin_data.joints[v_joints.y]
+        + v_weights.z * skin_data.joints[v_joints.z]
+        + v_weights.w * skin_data.joints[v_joints.w]
+        + (1.0 - total) * mat4(1.0);
+}
+
+mat3 make_tbn_matrix(mat4 skin, vec3 tangent, vec3 eye_normal){
+    vec3 eye_t = normalize(vec3(m.normal * skin * vec4(tangent, 0.0)));
+    vec3 eye_b = normalize(vec3(m.normal * skin * vec4(v_bitangent, 0.0)));
     return mat3(eye_t, eye_b, normalize(eye_normal));
 }
 
 void
 main()
 {
-  vec3 eye_normal = vec3(m.normal * vec4(v_normal, 0.0));
-  frag_tbn_matrix = make_tbn_matrix(eye_normal);
-  vec4 eye_pos = m.modelview * vec4(v_position, 1.0);
+  vec3 position = v_position;
+  vec3 normal = v_normal;
+  vec3 tangent = v_tangent;
+  for (uint t = 0; t < morph.counts.x; t++) {
+    float weight = morph.weights[t / 4][t % 4];
+    uint base = (t * morph.counts.y + uint(gl_VertexIndex)) * 3;
+    position += weight * morph_deltas.deltas[base].xyz;
+    normal += weight * morph_deltas.deltas[base + 1].xyz;
+    tangent += weight * morph_deltas.deltas[base + 2].xyz;
+  }
+
+  mat4 skin = skin_matrix();
+  vec3 eye_normal = vec3(m.normal * skin * vec4(normal, 0.0));
+  frag_tbn_matrix = make_tbn_matrix(skin, tangent, eye_normal);
+  vec4 eye_pos = m.modelview * skin * vec4(position, 1.0);
   frag_pos = eye_pos.xyz;
   frag_uv = v_uv;
   gl_Position = m.projection * eye_pos;
@@ -115,6 +115,16 @@ impl GenerationalIndexAllocator {
         e.is_live && e.generation == index.generation
     }
 
+    /// The live index at a slot, if the slot is allocated
+    pub fn live_at(&self, index: usize) -> Option<GenerationalIndex> {
+        self.entries.get(index).filter(|e| e.is_live).map(|e| {
+            GenerationalIndex {
+                index,
+                generation: e.generation,
+            }
+        })
+    }
+
     /// Produces an iterator of live entry indices. In the context of a game
     /// ecs, this would iterate through in-scene entities
     pub fn iter_live(&self) -> GenerationalIndexIter {
@@ -8,6 +8,10 @@ pub struct IndexArray<T>
 
 {
     pub(crate) array: Vec<Option<T>>,
+    /// Indices written since the last `take_changed`, without repeats
+    changed: Vec<usize>,
+    /// Whether each index is in `changed`
+    is_changed: Vec<bool>,
 }
 
 impl<T> IndexArray<T>
@@ -17,8 +21,14 @@ impl<T> IndexArray<T>
         IndexArray::with_capacity(256)
     }
     pub fn with_capacity(capacity: usize) -> Self {
+        IndexArray::from_vec((0..capacity).map(|_| None).collect())
+    }
+
+    fn from_vec(array: Vec<Option<T>>) -> Self {
         IndexArray {
-            array: (0..capacity).map(|_| None).collect(),
+            array,
+            changed: Vec::new(),
+            is_changed: Vec::new(),
         }
     }
     pub fn reserve(&mut self, size: usize) {
@@ -33,6 +43,7 @@ impl<T> IndexArray<T>
             self.reserve(i * 2);
         }
         self.array[i] = Some(value);
+        self.mark_changed(i);
     }
 
     pub fn remove(&mut self, index: GenerationalIndex) -> Option<T> {
@@ -41,7 +52,11 @@ impl<T> IndexArray<T>
         if self.array.len() <= i {
             None
         } else {
-            replace(&mut self.array[i], None)
+            let removed = replace(&mut self.array[i], None);
+            if removed.is_some() {
+                self.mark_changed(i);
+            }
+            removed
         }
     }
 
@@ -53,7 +68,31 @@ impl<T> IndexArray<T>
         &mut self,
         index: GenerationalIndex,
     ) -> Option<&mut Option<T>> {
-        self.array.get_mut(index.index())
+        let i = index.index();
+        if i < self.array.len() {
+            self.mark_changed(i);
+        }
+        self.array.get_mut(i)
+    }
+
+    /// Takes the indices written since the last call, by `insert`,
+    /// `remove` or mutable access, in the order they were first written.
+    /// Mutable access counts as a write whether or not the value changed.
+    pub fn take_changed(&mut self) -> Vec<usize> {
+        for &i in &self.changed {
+            self.is_changed[i] = false;
+        }
+        std::mem::replace(&mut self.changed, Vec::new())
+    }
+
+    fn mark_changed(&mut self, i: usize) {
+        if self.is_changed.len() <= i {
+            self.is_changed.resize(self.array.len().max(i + 1), false);
+        }
+        if !self.is_changed[i] {
+            self.is_changed[i] = true;
+            self.changed.push(i);
+        }
     }
 }
 
@@ -79,9 +118,7 @@ impl<T> IndexMut<GenerationalIndex> for IndexArray<T>
 
 #[test]
 fn test_getters() {
-    let mut ie = IndexArray {
-        array: vec![Some(1), None, None, Some(2)],
-    };
+    let mut ie = IndexArray::from_vec(vec![Some(1), None, None, Some(2)]);
     {
         assert_eq!(ie.get(GenerationalIndex::new(0, 0)), Some(&1));
         assert_eq!(ie.get(GenerationalIndex::new(1, 0)), None);
@@ -96,9 +133,22 @@ fn test_getters() {
 
 #[test]
 fn test_insert() {
-    let mut ie: IndexArray<i32> = IndexArray {
-        array: vec![None; 1],
-    };
+    let mut ie: IndexArray<i32> = IndexArray::from_vec(vec![None; 1]);
     ie.insert(GenerationalIndex::new(10, 0), 10);
     assert_eq!(ie.get(GenerationalIndex::new(10, 0)), Some(&10));
 }
+
+#[test]
+fn test_change_tracking() {
+    let mut ie: IndexArray<i32> = IndexArray::with_capacity(4);
+    ie.insert(GenerationalIndex::new(2, 0), 2);
+    ie.insert(GenerationalIndex::new(10, 0), 10);
+    ie.insert(GenerationalIndex::new(2, 0), 3);
+    assert_eq!(ie.take_changed(), vec![2, 10]);
+    assert!(ie.take_changed().is_empty());
+
+    ie[GenerationalIndex::new(10, 0)] = Some(11);
+    assert_eq!(ie.remove(GenerationalIndex::new(1, 0)), None);
+    assert_eq!(ie.remove(GenerationalIndex::new(2, 0)), Some(3));
+    assert_eq!(ie.take_changed(), vec![10, 2]);
+}
@@ -0,0 +1,247 @@
+//! An application framework: implement `App` for a game's hooks and hand
+//! it to `run`, which owns the platform, renderer, world and main loop.
+
+use crate::config::PlatformConfig;
+use crate::game::assets::AssetServer;
+use crate::game::camera::CameraConfig;
+use crate::game::component_stores::AnyComponentStore;
+use crate::game::main_loop::{FrameTick, MainLoopState};
+use crate::game::replay::{InputRecorder, InputReplay, RECORD_INPUT_VAR};
+use crate::game::timer::FixedTimestep;
+use crate::game::EntityWorld;
+use crate::platform_system::asset_path;
+use crate::renderer::Renderer;
+use crate::sdl_platform::Platform;
+use log::*;
+use sdl2::event::Event;
+use std::time::Duration;
+
+/// Default timestep of `App::fixed_update`
+pub const DEFAULT_FIXED_STEP: Duration = Duration::from_nanos(16_666_667);
+/// Most fixed updates run in one frame
+pub const DEFAULT_MAX_FIXED_STEPS: u32 = 5;
+
+/// A renderer `run` can create and draw a frame with
+pub trait AppRenderer: Renderer {
+    /// Graphics API state that must outlive the renderer, such as an
+    /// OpenGL context
+    type Backend;
+
+    /// Creates the platform window and the renderer drawing to it
+    fn create(
+        config: &PlatformConfig,
+    ) -> Result<(Platform, Self::Backend, Self), failure::Error>;
+
+    /// Draws the world and presents it to the window
+    fn render_frame<CS>(
+        &self,
+        platform: &Platform,
+        world: &EntityWorld<Self, CS>,
+    ) where
+        CS: crate::game::TryGetComponent;
+}
+
+#[cfg(feature = "backend-gl")]
+impl AppRenderer for crate::renderer::backend_gl::GlRenderer {
+    type Backend = crate::sdl_platform::GlPlatformBuilder;
+
+    fn create(
+        config: &PlatformConfig,
+    ) -> Result<(Platform, Self::Backend, Self), failure::Error> {
+        use crate::sdl_platform::{platform, OpenGLVersion};
+        let (platform, gl) = platform()
+            .with_config(config.clone())
+            .with_opengl(OpenGLVersion::GL41)
+            .build_gl()?;
+        let renderer = Self::new(&platform.window)?;
+        Ok((platform, gl, renderer))
+    }
+
+    fn render_frame<CS>(
+        &self,
+        platform: &Platform,
+        world: &EntityWorld<Self, CS>,
+    ) where
+        CS: crate::game::TryGetComponent,
+    {
+        self.clear();
+        self.render_scene(world);
+        platform.window.gl_swap_window();
+    }
+}
+
+#[cfg(feature = "backend-vulkan")]
+impl AppRenderer for crate::renderer::backend_vk::VulkanRenderer {
+    type Backend = ();
+
+    fn create(
+        config: &PlatformConfig,
+    ) -> Result<(Platform, Self::Backend, Self), failure::Error> {
+        use crate::renderer::backend_vk::VulkanPlatformHooks;
+        let platform = crate::sdl_platform::platform()
+            .with_config(config.clone())
+            .build(&VulkanPlatformHooks)?;
+        let renderer = Self::with_vsync(&platform.window, config.vsync)?;
+        Ok((platform, (), renderer))
+    }
+
+    fn render_frame<CS>(
+        &self,
+        platform: &Platform,
+        world: &EntityWorld<Self, CS>,
+    ) where
+        CS: crate::game::TryGetComponent,
+    {
+        self.draw_frame(&platform.window, world);
+    }
+}
+
+/// The world type apps run
+pub type AppWorld<R> = EntityWorld<R, AnyComponentStore>;
+
+/// Everything `run` owns, lent to the app's hooks
+pub struct AppContext<R: AppRenderer> {
+    // fields drop in order, so the world and renderer go before the
+    // graphics context and window they were created from
+    pub world: AppWorld<R>,
+    pub renderer: R,
+    pub main_loop: MainLoopState,
+    /// Loads assets in the background, uploading them each frame
+    pub assets: AssetServer,
+    /// Hands out the steps of `App::fixed_update`, following game time
+    pub fixed_timestep: FixedTimestep,
+    pub backend: R::Backend,
+    pub platform: Platform,
+}
+
+impl<R: AppRenderer> AppContext<R> {
+    /// Ends the main loop after the current frame
+    pub fn quit(&mut self) {
+        self.main_loop.set_is_running(false);
+    }
+}
+
+/// Hooks of a game run by `run`. Each frame runs `on_event` for each
+/// event, then the world's update, `fixed_update` for each fixed step,
+/// `update`, the asset uploads and `render`.
+pub trait App<R: AppRenderer> {
+    /// Called once before the first frame, to load assets and spawn
+    /// entities. An error ends the app.
+    fn init(&mut self, _ctx: &mut AppContext<R>) -> Result<(), failure::Error> {
+        Ok(())
+    }
+
+    /// Called for each SDL event, after the engine has handled it
+    fn on_event(&mut self, _ctx: &mut AppContext<R>, _event: &Event) {}
+
+    /// Called once a frame with the frame's real time
+    fn update(&mut self, _ctx: &mut AppContext<R>, _delta: Duration) {}
+
+    /// Called zero or more times a frame with a constant step of game
+    /// time, for simulations that depend on a steady rate
+    fn fixed_update(&mut self, _ctx: &mut AppContext<R>, _step: Duration) {}
+
+    /// Draws the frame. Draws the world by default.
+    fn render(&mut self, ctx: &mut AppContext<R>) {
+        ctx.renderer.render_frame(&ctx.platform, &ctx.world);
+    }
+
+    /// Called once after the last frame
+    fn shutdown(&mut self, _ctx: &mut AppContext<R>) {}
+}
+
+/// Creates the platform and renderer from the config, then runs the app
+/// until it quits or the window closes.
+/// With `SLSENGINE_REPLAY_INPUT` set to a recording, the recording's
+/// frames drive the world in place of live input, and the app quits once
+/// they run out. With `SLSENGINE_RECORD_INPUT` set to a path, the frames'
+/// input is recorded there on shutdown.
+pub fn run<R, A>(
+    mut app: A,
+    config: PlatformConfig,
+) -> Result<(), failure::Error>
+where
+    R: AppRenderer,
+    A: App<R>,
+{
+    let (platform, backend, renderer) = R::create(&config)?;
+    let mut world = EntityWorld::new(
+        &renderer,
+        AnyComponentStore::with_built_in_components(),
+    );
+    let camera_config = asset_path().join("assets/config/camera.toml");
+    match CameraConfig::load(&camera_config) {
+        Ok(config) => world.set_camera_config(config),
+        Err(e) => warn!("using default camera config: {}", e),
+    }
+    let mut ctx = AppContext {
+        world,
+        renderer,
+        main_loop: MainLoopState::new().with_target_fps(config.target_fps),
+        assets: AssetServer::default(),
+        fixed_timestep: FixedTimestep::new(
+            DEFAULT_FIXED_STEP,
+            DEFAULT_MAX_FIXED_STEPS,
+        ),
+        backend,
+        platform,
+    };
+    app.init(&mut ctx)?;
+
+    let mut replay = InputReplay::from_env()?;
+    if let Some(replay) = replay.as_mut() {
+        replay.start(&mut ctx.world);
+        ctx.main_loop.set_live_input(false);
+    }
+    let record_path = std::env::var_os(RECORD_INPUT_VAR);
+    let mut recorder = record_path
+        .as_ref()
+        .map(|_| InputRecorder::new(&mut ctx.world));
+
+    ctx.main_loop.start();
+    while ctx.main_loop.is_running() {
+        let events = ctx.main_loop.poll_events(
+            &ctx.platform.window,
+            &ctx.platform.event_pump,
+            &ctx.renderer,
+            &mut ctx.world,
+        );
+        for event in &events {
+            app.on_event(&mut ctx, event);
+        }
+        if !ctx.main_loop.is_running() {
+            break;
+        }
+        let FrameTick { mut delta, .. } = ctx.main_loop.tick_frame();
+        if let Some(replay) = replay.as_mut() {
+            match replay.feed(&mut ctx.world) {
+                Some(recorded) => delta = recorded,
+                None => {
+                    info!("input replay finished");
+                    break;
+                }
+            }
+        }
+        if let (Some(recorder), Some(input_state)) =
+            (recorder.as_mut(), ctx.world.input_state.as_ref())
+        {
+            recorder.record(delta, &ctx.world.input_snapshot, input_state);
+        }
+        ctx.world.update(delta);
+        ctx.renderer.on_update(delta, &ctx.world);
+        let step = ctx.fixed_timestep.step;
+        for _ in 0..ctx.fixed_timestep.advance(ctx.world.clock.game_delta()) {
+            app.fixed_update(&mut ctx, step);
+        }
+        app.update(&mut ctx, delta);
+        ctx.assets.update(&mut ctx.world, &mut ctx.renderer);
+        app.render(&mut ctx);
+    }
+    app.shutdown(&mut ctx);
+    if let (Some(recorder), Some(path)) = (recorder, record_path) {
+        if let Err(e) = recorder.finish().save(path) {
+            error!("could not save input recording: {}", e);
+        }
+    }
+    Ok(())
+}
@@ -1,84 +1,42 @@
-#![allow(dead_code)]
-
-use hibitset::BitSet;
-#[cfg(feature = "backend-vulkan")]
-use slsengine::renderer::backend_vk;
-use slsengine::{
-    game::*,
-    game::component_stores::NullComponentStore,
-    renderer::*,
-    sdl_platform::{self, Platform},
-};
-
-use failure;
-#[cfg(feature = "backend-gl")]
-use slsengine::renderer::backend_gl;
-#[cfg(feature = "backend-gl")]
-use slsengine::renderer::backend_gl::gl_renderer::GlRenderer;
-
-use slsengine::game;
-use slsengine::sdl_platform::OpenGLVersion::GL45;
-use slsengine::sdl_platform::OpenGLVersion;
-
-struct App<R: Renderer> {
-    platform: Platform,
-    renderer: R,
-    main_loop: MainLoopState,
-    world: EntityWorld<R, NullComponentStore>,
+use log::*;
+use slsengine::app::{self, App, AppContext, AppRenderer};
+use slsengine::config::PlatformConfig;
+use slsengine::game::InputMap;
+use slsengine::platform_system::asset_path;
+
+/// Shows a model, with the default camera controls
+struct ModelViewer;
+
+impl<R: AppRenderer> App<R> for ModelViewer {
+    fn init(&mut self, ctx: &mut AppContext<R>) -> Result<(), failure::Error> {
+        let bindings = asset_path().join("assets/config/input_bindings.toml");
+        match InputMap::load(&bindings) {
+            Ok(input) => ctx.world.input = input,
+            Err(e) => warn!("using default input bindings: {}", e),
+        }
+        // the window stays responsive while the model loads
+        ctx.assets
+            .load_model(asset_path().join("assets/models/DamagedHelmet.glb"));
+        Ok(())
+    }
 }
 
-#[cfg(feature = "backend-vulkan")]
-fn setup_vk() -> Result<App<backend_vk::VulkanRenderer>, failure::Error> {
-    let platform =
-        sdl_platform::platform().build(&backend_vk::VulkanPlatformHooks)?;
-    let renderer = backend_vk::VulkanRenderer::new(&platform.window)?;
-    let main_loop = MainLoopState::new();
-    let world = EntityWorld::new(&renderer, NullComponentStore);
-    Ok(App {
-        platform,
-        renderer,
-        main_loop,
-        world,
-    })
-}
-
-#[cfg(feature = "backend-gl")]
-fn setup_gl() -> Result<App<backend_gl::GlRenderer>, failure::Error> {
-    let (platform, gl) =
-        sdl_platform::platform().with_opengl(OpenGLVersion::GL41).build_gl()?;
-    let renderer = backend_gl::GlRenderer::new(&platform.window)?;
-    let main_loop = MainLoopState::new();
-    let world = EntityWorld::new(&renderer, NullComponentStore);
-    Ok(App {
-        platform,
-        renderer,
-        main_loop,
-        world,
-    })
-}
-
-#[derive(Debug)]
-struct Point(u32, u32);
-
-impl Component for Point {}
-
-fn run_app<R: Renderer>(app: App<R>) -> Result<(), failure::Error> {
-    Ok(())
-}
-
-#[cfg(feature="backend-vulkan")]
-fn main() -> Result<(), i32> {
-
-    setup_vk().and_then(&run_app).map_err(|e| {
-        eprintln!("app error: {}", e);
-       1
-    })
-}
-
-#[cfg(all(not(feature="backend-vulkan"), feature="backend-gl"))]
 fn main() -> Result<(), i32> {
-
-    setup_vk().and_then(&run_app).map_err(|e| {
+    env_logger::init();
+    let config = PlatformConfig::default();
+
+    #[cfg(feature = "backend-vulkan")]
+    let result = app::run::<slsengine::renderer::backend_vk::VulkanRenderer, _>(
+        ModelViewer,
+        config,
+    );
+    #[cfg(all(not(feature = "backend-vulkan"), feature = "backend-gl"))]
+    let result = app::run::<slsengine::renderer::backend_gl::GlRenderer, _>(
+        ModelViewer,
+        config,
+    );
+
+    result.map_err(|e| {
         eprintln!("app error: {}", e);
         1
     })
@@ -5,6 +5,11 @@ pub struct PlatformConfig {
     pub antialiasing: AntiAliasing,
     pub allow_highdpi: bool,
     pub fullscreen: bool,
+    #[serde(default)]
+    pub vsync: VSync,
+    /// Frame rate the main loop is capped at, if any
+    #[serde(default)]
+    pub target_fps: Option<f64>,
 }
 
 impl Default for PlatformConfig {
@@ -15,10 +20,29 @@ impl Default for PlatformConfig {
             antialiasing: AntiAliasing::None,
             allow_highdpi: true,
             fullscreen: false,
+            vsync: VSync::default(),
+            target_fps: None,
         }
     }
 }
 
+/// Whether presenting a frame waits for the display's vertical blank
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum VSync {
+    /// Present immediately, which may tear
+    Off,
+    On,
+    /// Wait for the vertical blank unless the frame is late, in which case
+    /// present immediately. Falls back to On where unsupported.
+    Adaptive,
+}
+
+impl Default for VSync {
+    fn default() -> Self {
+        VSync::On
+    }
+}
+
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum AntiAliasing {
     None,
@@ -0,0 +1,402 @@
+//! Keyframed animation clips, as imported from glTF.
+
+use crate::math::*;
+use cgmath::*;
+
+/// How values between keyframes are found
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Holds each keyframe's value until the next
+    Step,
+    /// Lerps between values, and slerps between rotations
+    Linear,
+    /// Hermite spline through the values, with tangents stored
+    /// alongside each keyframe
+    CubicSpline,
+}
+
+/// Keyframe values of a channel. Cubic spline channels store an
+/// in-tangent, a value and an out-tangent for each keyframe, in that
+/// order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Keyframes {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vec3>),
+    /// Morph target weights, with one weight per target for each value
+    Weights(Vec<f32>),
+}
+
+/// A sampled channel value
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelValue {
+    Translation(Vec3),
+    Rotation(Quaternion<f32>),
+    Scale(Vec3),
+    Weights(Vec<f32>),
+}
+
+impl ChannelValue {
+    /// Overwrites the part of a transform the value animates. Transforms
+    /// only have uniform scale, so the largest axis of a scale is used.
+    /// Morph weights don't affect the transform.
+    pub fn apply(&self, transform: &mut Decomposed<Vec3, Quaternion<f32>>) {
+        match *self {
+            ChannelValue::Translation(t) => transform.disp = t,
+            ChannelValue::Rotation(r) => transform.rot = r,
+            ChannelValue::Scale(s) => transform.scale = s.x.max(s.y).max(s.z),
+            ChannelValue::Weights(_) => (),
+        }
+    }
+}
+
+/// Animates one property of one node
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    /// Index of the animated node in its model
+    pub node: usize,
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds, in increasing order
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+}
+
+/// The keyframe before `time`, and how far `time` is towards the next
+/// one. Times outside the keyframes clamp to the first or last.
+fn keyframe_at(times: &[f32], time: f32) -> (usize, f32) {
+    let last = times.len() - 1;
+    if time <= times[0] || last == 0 {
+        return (0, 0.0);
+    }
+    if time >= times[last] {
+        return (last, 0.0);
+    }
+    // the first keyframe after `time`
+    let next = match times.binary_search_by(|t| t.partial_cmp(&time).unwrap()) {
+        Ok(i) => return (i, 0.0),
+        Err(i) => i,
+    };
+    let (t0, t1) = (times[next - 1], times[next]);
+    (next - 1, (time - t0) / (t1 - t0))
+}
+
+/// Hermite basis weights for p0, m0, p1 and m1
+fn hermite(u: f32) -> [f32; 4] {
+    let (u2, u3) = (u * u, u * u * u);
+    [
+        2.0 * u3 - 3.0 * u2 + 1.0,
+        u3 - 2.0 * u2 + u,
+        -2.0 * u3 + 3.0 * u2,
+        u3 - u2,
+    ]
+}
+
+/// Slerps along the shorter arc between two rotations
+pub fn slerp_shortest(
+    a: Quaternion<f32>,
+    b: Quaternion<f32>,
+    amount: f32,
+) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    a.slerp(b, amount)
+}
+
+impl Channel {
+    /// Number of keyframes
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// Time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.times.last().cloned().unwrap_or(0.0)
+    }
+
+    /// The channel's value at `time` seconds
+    pub fn sample(&self, time: f32) -> Option<ChannelValue> {
+        if self.times.is_empty() {
+            return None;
+        }
+        let (i, u) = keyframe_at(&self.times, time);
+        let dt = if i + 1 < self.times.len() {
+            self.times[i + 1] - self.times[i]
+        } else {
+            0.0
+        };
+        Some(match self.keyframes {
+            Keyframes::Translation(ref values) => {
+                ChannelValue::Translation(self.sample_vector(values, i, u, dt))
+            }
+            Keyframes::Scale(ref values) => {
+                ChannelValue::Scale(self.sample_vector(values, i, u, dt))
+            }
+            Keyframes::Rotation(ref values) => {
+                ChannelValue::Rotation(self.sample_rotation(values, i, u, dt))
+            }
+            Keyframes::Weights(ref values) => {
+                ChannelValue::Weights(self.sample_weights(values, i, u, dt))
+            }
+        })
+    }
+
+    /// Number of morph targets a weights channel animates
+    pub fn target_count(&self) -> usize {
+        let per_keyframe = match self.interpolation {
+            Interpolation::CubicSpline => 3,
+            _ => 1,
+        };
+        match self.keyframes {
+            Keyframes::Weights(ref values) if !self.times.is_empty() => {
+                values.len() / (self.times.len() * per_keyframe)
+            }
+            _ => 0,
+        }
+    }
+
+    fn sample_vector(
+        &self,
+        values: &[Vec3],
+        i: usize,
+        u: f32,
+        dt: f32,
+    ) -> Vec3 {
+        match self.interpolation {
+            Interpolation::CubicSpline => {
+                let value = |k: usize| values[3 * k + 1];
+                if u == 0.0 {
+                    return value(i);
+                }
+                let w = hermite(u);
+                value(i) * w[0]
+                    + values[3 * i + 2] * (w[1] * dt)
+                    + value(i + 1) * w[2]
+                    + values[3 * (i + 1)] * (w[3] * dt)
+            }
+            Interpolation::Linear if u > 0.0 => {
+                values[i].lerp(values[i + 1], u)
+            }
+            _ => values[i],
+        }
+    }
+
+    fn sample_weights(
+        &self,
+        values: &[f32],
+        i: usize,
+        u: f32,
+        dt: f32,
+    ) -> Vec<f32> {
+        let n = self.target_count();
+        // the values of the keyframe `k`, or its tangents with `part` 0
+        // for in-tangents and 2 for out-tangents
+        let cubic = |k: usize, part: usize| &values[(3 * k + part) * n..][..n];
+        match self.interpolation {
+            Interpolation::CubicSpline if u > 0.0 => {
+                let w = hermite(u);
+                let (p0, m0) = (cubic(i, 1), cubic(i, 2));
+                let (p1, m1) = (cubic(i + 1, 1), cubic(i + 1, 0));
+                (0..n)
+                    .map(|t| {
+                        p0[t] * w[0]
+                            + m0[t] * w[1] * dt
+                            + p1[t] * w[2]
+                            + m1[t] * w[3] * dt
+                    })
+                    .collect()
+            }
+            Interpolation::CubicSpline => cubic(i, 1).to_vec(),
+            Interpolation::Linear if u > 0.0 => {
+                let (a, b) =
+                    (&values[i * n..][..n], &values[(i + 1) * n..][..n]);
+                a.iter().zip(b).map(|(a, b)| a + (b - a) * u).collect()
+            }
+            _ => values[i * n..][..n].to_vec(),
+        }
+    }
+
+    fn sample_rotation(
+        &self,
+        values: &[Quaternion<f32>],
+        i: usize,
+        u: f32,
+        dt: f32,
+    ) -> Quaternion<f32> {
+        match self.interpolation {
+            Interpolation::CubicSpline => {
+                let value = |k: usize| values[3 * k + 1];
+                if u == 0.0 {
+                    return value(i).normalize();
+                }
+                let w = hermite(u);
+                (value(i) * w[0]
+                    + values[3 * i + 2] * (w[1] * dt)
+                    + value(i + 1) * w[2]
+                    + values[3 * (i + 1)] * (w[3] * dt))
+                    .normalize()
+            }
+            Interpolation::Linear if u > 0.0 => {
+                slerp_shortest(values[i], values[i + 1], u)
+            }
+            _ => values[i],
+        }
+    }
+}
+
+/// A named set of channels played together
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    pub fn new(name: Option<String>, channels: Vec<Channel>) -> Self {
+        AnimationClip { name, channels }
+    }
+
+    /// Time of the clip's last keyframe, in seconds
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .map(Channel::duration)
+            .fold(0.0, f32::max)
+    }
+
+    /// Samples every channel at `time`, giving the node each value
+    /// applies to
+    pub fn sample(&self, time: f32) -> Vec<(usize, ChannelValue)> {
+        self.channels
+            .iter()
+            .filter_map(|c| c.sample(time).map(|v| (c.node, v)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn near(a: Vec3, b: Vec3) -> bool {
+        (a - b).magnitude() < 1e-5
+    }
+
+    fn translation(interpolation: Interpolation, values: Vec<Vec3>) -> Channel {
+        Channel {
+            node: 0,
+            interpolation,
+            times: vec![1.0, 2.0, 4.0],
+            keyframes: Keyframes::Translation(values),
+        }
+    }
+
+    fn sample_translation(channel: &Channel, time: f32) -> Vec3 {
+        match channel.sample(time) {
+            Some(ChannelValue::Translation(t)) => t,
+            other => panic!("expected a translation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_step_and_linear() {
+        let values = vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(2.0, 0.0, 0.0),
+            vec3(2.0, 4.0, 0.0),
+        ];
+        let linear = translation(Interpolation::Linear, values.clone());
+        assert!(near(sample_translation(&linear, 0.0), values[0]));
+        assert!(near(sample_translation(&linear, 1.5), vec3(1.0, 0.0, 0.0)));
+        assert!(near(sample_translation(&linear, 2.0), values[1]));
+        assert!(near(sample_translation(&linear, 3.0), vec3(2.0, 2.0, 0.0)));
+        assert!(near(sample_translation(&linear, 9.0), values[2]));
+
+        let step = translation(Interpolation::Step, values.clone());
+        assert!(near(sample_translation(&step, 1.99), values[0]));
+        assert!(near(sample_translation(&step, 3.9), values[1]));
+        assert_eq!(step.duration(), 4.0);
+    }
+
+    #[test]
+    fn test_cubic_spline() {
+        let zero = Vec3::zero();
+        // flat tangents ease in and out of each keyframe
+        let values = vec![
+            zero,
+            vec3(0.0, 0.0, 0.0),
+            zero,
+            zero,
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.5, 0.0, 0.0),
+            zero,
+            vec3(1.0, 0.0, 0.0),
+            zero,
+        ];
+        let channel = translation(Interpolation::CubicSpline, values);
+        assert!(near(sample_translation(&channel, 1.5), vec3(0.5, 0.0, 0.0)));
+        let early = sample_translation(&channel, 1.25).x;
+        assert!(early > 0.0 && early < 0.25);
+        assert!(near(sample_translation(&channel, 2.0), vec3(1.0, 0.0, 0.0)));
+        // the out-tangent of the second keyframe is scaled by the 2 second
+        // gap: p(u) = 1 + (u^3 - 2u^2 + u) * 0.5 * 2
+        let x = sample_translation(&channel, 3.0).x;
+        assert!((x - 1.125).abs() < 1e-5, "x was {}", x);
+    }
+
+    #[test]
+    fn test_weights() {
+        let mut channel = Channel {
+            node: 0,
+            interpolation: Interpolation::Linear,
+            times: vec![0.0, 2.0],
+            keyframes: Keyframes::Weights(vec![0.0, 1.0, 1.0, 0.0]),
+        };
+        assert_eq!(channel.target_count(), 2);
+        assert_eq!(
+            channel.sample(0.5),
+            Some(ChannelValue::Weights(vec![0.25, 0.75]))
+        );
+
+        // flat tangents around each pair of weights
+        channel.interpolation = Interpolation::CubicSpline;
+        channel.keyframes = Keyframes::Weights(vec![
+            0.0, 0.0, 0.0, 1.0, 0.0, 0.0, //
+            0.0, 0.0, 1.0, 0.0, 0.0, 0.0,
+        ]);
+        assert_eq!(channel.target_count(), 2);
+        assert_eq!(
+            channel.sample(1.0),
+            Some(ChannelValue::Weights(vec![0.5, 0.5]))
+        );
+        assert_eq!(
+            channel.sample(2.0),
+            Some(ChannelValue::Weights(vec![1.0, 0.0]))
+        );
+    }
+
+    #[test]
+    fn test_rotation_slerps_shortest_arc() {
+        let a = Quaternion::from_angle_y(Deg(10.0));
+        // the same rotation as 350 degrees, but with the opposite sign
+        let b = -Quaternion::from_angle_y(Deg(-10.0));
+        let channel = Channel {
+            node: 3,
+            interpolation: Interpolation::Linear,
+            times: vec![0.0, 1.0],
+            keyframes: Keyframes::Rotation(vec![a, b]),
+        };
+        let clip = AnimationClip::new(None, vec![channel]);
+        let sampled = clip.sample(0.5);
+        assert_eq!(sampled.len(), 1);
+        assert_eq!(sampled[0].0, 3);
+        let rot = match sampled[0].1 {
+            ChannelValue::Rotation(r) => r,
+            ref other => panic!("expected a rotation, got {:?}", other),
+        };
+        let forward = rot.rotate_vector(-Vec3::unit_z());
+        assert!(near(forward, -Vec3::unit_z()));
+    }
+}
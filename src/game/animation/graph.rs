@@ -0,0 +1,988 @@
+//! Animation state machines, blending clips by gameplay parameters.
+//!
+//! An `AnimationGraph` is a stack of layers, each a state machine whose
+//! states play a clip or a blend space of clips. Transitions between
+//! states fire when their conditions on the graph's parameters hold, and
+//! cross-fade from the old state to the new one. Layers either override
+//! the layers below them or add their motion on top, optionally limited
+//! to some nodes by a bone mask.
+
+use super::{clip_time, slerp_shortest, AnimationClip, PlaybackMode};
+use crate::game::{component::*, TryGetComponent};
+use crate::math::*;
+use crate::renderer::model::{Model, NodeData};
+use cgmath::*;
+use log::warn;
+use std::{collections::HashMap, sync::Arc};
+
+/// Local transform of a node
+pub type NodePose = Decomposed<Vec3, Quaternion<f32>>;
+
+/// Blends between two node poses
+pub fn blend_node(a: &NodePose, b: &NodePose, amount: f32) -> NodePose {
+    Decomposed {
+        scale: a.scale + (b.scale - a.scale) * amount,
+        rot: slerp_shortest(a.rot, b.rot, amount),
+        disp: a.disp.lerp(b.disp, amount),
+    }
+}
+
+/// A value gameplay code sets to drive transitions and blend spaces
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Parameter {
+    Float(f32),
+    Bool(bool),
+    /// A flag that stays set until a transition consumes it
+    Trigger(bool),
+}
+
+/// A test on a parameter. Missing parameters fail every test.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Greater(String, f32),
+    Less(String, f32),
+    IsTrue(String),
+    IsFalse(String),
+    /// Holds while the trigger is set, and resets it when the transition
+    /// fires
+    Triggered(String),
+}
+
+impl Condition {
+    fn holds(&self, params: &HashMap<String, Parameter>) -> bool {
+        use self::Parameter::*;
+        match *self {
+            Condition::Greater(ref name, threshold) => match params.get(name) {
+                Some(&Float(value)) => value > threshold,
+                _ => false,
+            },
+            Condition::Less(ref name, threshold) => match params.get(name) {
+                Some(&Float(value)) => value < threshold,
+                _ => false,
+            },
+            Condition::IsTrue(ref name) => {
+                params.get(name) == Some(&Bool(true))
+            }
+            Condition::IsFalse(ref name) => {
+                params.get(name) == Some(&Bool(false))
+            }
+            Condition::Triggered(ref name) => {
+                params.get(name) == Some(&Trigger(true))
+            }
+        }
+    }
+}
+
+/// What a state plays. Clips are indices into the graph's clips.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Motion {
+    Clip(usize),
+    /// Blends the two clips around a float parameter's value, such as
+    /// idle, walk and run by speed
+    Blend1D {
+        parameter: String,
+        points: Vec<(f32, usize)>,
+    },
+    /// Blends clips placed on a plane by two float parameters, such as
+    /// strafing by sideways and forward speed
+    Blend2D {
+        parameters: (String, String),
+        points: Vec<(Vector2<f32>, usize)>,
+    },
+}
+
+/// Weights of the points of a 1D blend space at `x`, which is clamped to
+/// the range of the points
+pub fn blend_1d(points: &[f32], x: f32) -> Vec<f32> {
+    let mut weights = vec![0.0; points.len()];
+    let mut order: Vec<usize> = (0..points.len()).collect();
+    order.sort_by(|&a, &b| points[a].total_cmp(&points[b]));
+    let (first, last) = match (order.first(), order.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return weights,
+    };
+    if x <= points[first] {
+        weights[first] = 1.0;
+    } else if x >= points[last] {
+        weights[last] = 1.0;
+    } else {
+        for pair in order.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            if x <= points[b] {
+                let t = (x - points[a]) / (points[b] - points[a]);
+                weights[a] = 1.0 - t;
+                weights[b] = t;
+                break;
+            }
+        }
+    }
+    weights
+}
+
+/// Weights of the points of a 2D blend space at `p`, by gradient band
+/// interpolation: each point's weight falls off towards every other
+/// point, so a point's clip plays alone at that point.
+pub fn blend_2d(points: &[Vector2<f32>], p: Vector2<f32>) -> Vec<f32> {
+    let mut weights: Vec<f32> = points
+        .iter()
+        .enumerate()
+        .map(|(i, &pi)| {
+            points
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, &pj)| {
+                    let edge = pj - pi;
+                    let length2 = edge.magnitude2();
+                    if length2 == 0.0 {
+                        return 1.0;
+                    }
+                    (1.0 - (p - pi).dot(edge) / length2).max(0.0).min(1.0)
+                })
+                .fold(1.0, f32::min)
+        })
+        .collect();
+    let total: f32 = weights.iter().sum();
+    if total > 0.0 {
+        for w in &mut weights {
+            *w /= total;
+        }
+    }
+    weights
+}
+
+impl Motion {
+    /// Clips to sample and their weights under the given parameters
+    pub fn weights(
+        &self,
+        params: &HashMap<String, Parameter>,
+    ) -> Vec<(usize, f32)> {
+        let float = |name: &str| match params.get(name) {
+            Some(&Parameter::Float(value)) => value,
+            _ => 0.0,
+        };
+        let weighted = |clips: Vec<usize>, weights: Vec<f32>| {
+            clips
+                .into_iter()
+                .zip(weights)
+                .filter(|&(_, w)| w > 0.0)
+                .collect()
+        };
+        match *self {
+            Motion::Clip(clip) => vec![(clip, 1.0)],
+            Motion::Blend1D {
+                ref parameter,
+                ref points,
+            } => {
+                let positions: Vec<f32> = points.iter().map(|p| p.0).collect();
+                weighted(
+                    points.iter().map(|p| p.1).collect(),
+                    blend_1d(&positions, float(parameter)),
+                )
+            }
+            Motion::Blend2D {
+                parameters: (ref x, ref y),
+                ref points,
+            } => {
+                let positions: Vec<_> = points.iter().map(|p| p.0).collect();
+                weighted(
+                    points.iter().map(|p| p.1).collect(),
+                    blend_2d(&positions, vec2(float(x), float(y))),
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+    pub name: String,
+    pub motion: Motion,
+    pub speed: f32,
+    pub mode: PlaybackMode,
+}
+
+impl State {
+    pub fn new(name: &str, motion: Motion) -> Self {
+        State {
+            name: name.to_owned(),
+            motion,
+            speed: 1.0,
+            mode: PlaybackMode::Loop,
+        }
+    }
+
+    /// A state playing a single clip
+    pub fn clip(name: &str, clip: usize) -> Self {
+        State::new(name, Motion::Clip(clip))
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: PlaybackMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+/// Moves a layer from one state to another when all of its conditions
+/// hold
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    /// State the transition leaves, or None to leave any other state
+    pub from: Option<String>,
+    pub to: String,
+    pub conditions: Vec<Condition>,
+    /// Seconds to cross-fade from the old state into the new one
+    pub duration: f32,
+    /// How far through the old state, in plays of its motion, the
+    /// transition has to wait
+    pub exit_time: Option<f32>,
+}
+
+impl Transition {
+    pub fn new(from: &str, to: &str) -> Self {
+        Transition {
+            from: Some(from.to_owned()),
+            to: to.to_owned(),
+            conditions: Vec::new(),
+            duration: 0.0,
+            exit_time: None,
+        }
+    }
+
+    /// A transition out of any state but its destination
+    pub fn from_any(to: &str) -> Self {
+        Transition {
+            from: None,
+            ..Transition::new("", to)
+        }
+    }
+
+    pub fn when(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    pub fn fade(mut self, duration: f32) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn exit_time(mut self, exit_time: f32) -> Self {
+        self.exit_time = Some(exit_time);
+        self
+    }
+}
+
+/// Per-node weights limiting which nodes a layer animates
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoneMask {
+    pub weights: Vec<f32>,
+}
+
+impl BoneMask {
+    /// A mask leaving out every node
+    pub fn empty(node_count: usize) -> Self {
+        BoneMask {
+            weights: vec![0.0; node_count],
+        }
+    }
+
+    /// Includes a node and all of its descendants
+    pub fn with_subtree(mut self, nodes: &[NodeData], root: usize) -> Self {
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            if node < self.weights.len() {
+                self.weights[node] = 1.0;
+            }
+            stack.extend(nodes.get(node).map_or(&[][..], |n| &n.children));
+        }
+        self
+    }
+
+    /// Sets the weight of a single node
+    pub fn with_node(mut self, node: usize, weight: f32) -> Self {
+        if node < self.weights.len() {
+            self.weights[node] = weight;
+        }
+        self
+    }
+
+    pub fn weight(&self, node: usize) -> f32 {
+        self.weights.get(node).cloned().unwrap_or(0.0)
+    }
+}
+
+/// How a layer combines with the layers below it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LayerBlend {
+    /// Blends towards the layer's pose by its weight
+    Override,
+    /// Adds the layer's motion relative to the first frame of its clips
+    Additive,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Playback {
+    state: usize,
+    /// Plays of the state's motion since it was entered
+    time: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Fade {
+    from: Playback,
+    elapsed: f32,
+    duration: f32,
+}
+
+/// A state machine whose pose is blended onto the layers below it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub name: String,
+    /// States of the layer. The layer starts in the first.
+    pub states: Vec<State>,
+    pub transitions: Vec<Transition>,
+    pub weight: f32,
+    pub blend: LayerBlend,
+    pub mask: Option<BoneMask>,
+    current: Option<Playback>,
+    fade: Option<Fade>,
+}
+
+impl Layer {
+    pub fn new(name: &str) -> Self {
+        Layer {
+            name: name.to_owned(),
+            states: Vec::new(),
+            transitions: Vec::new(),
+            weight: 1.0,
+            blend: LayerBlend::Override,
+            mask: None,
+            current: None,
+            fade: None,
+        }
+    }
+
+    pub fn with_state(mut self, state: State) -> Self {
+        self.states.push(state);
+        self
+    }
+
+    pub fn with_transition(mut self, transition: Transition) -> Self {
+        self.transitions.push(transition);
+        self
+    }
+
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn with_mask(mut self, mask: BoneMask) -> Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    pub fn additive(mut self) -> Self {
+        self.blend = LayerBlend::Additive;
+        self
+    }
+
+    fn state_index(&self, name: &str) -> Option<usize> {
+        self.states.iter().position(|s| s.name == name)
+    }
+
+    /// Name of the state the layer is in, or fading into
+    pub fn current_state(&self) -> Option<&str> {
+        let index = self.current.map_or(0, |c| c.state);
+        self.states.get(index).map(|s| s.name.as_str())
+    }
+
+    /// Whether the layer is cross-fading between states
+    pub fn is_fading(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    /// Switches straight to a state, without a transition
+    pub fn jump_to(&mut self, state: &str) -> bool {
+        match self.state_index(state) {
+            Some(state) => {
+                self.current = Some(Playback { state, time: 0.0 });
+                self.fade = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fires the first transition whose conditions hold, consuming its
+    /// triggers
+    fn transition(&mut self, params: &mut HashMap<String, Parameter>) {
+        let current = match self.current {
+            Some(current) => current,
+            None => return,
+        };
+        let from = &self.states[current.state].name;
+        let fired = self.transitions.iter().find(|t| {
+            let leaves = match t.from {
+                Some(ref name) => name == from,
+                None => t.to != *from,
+            };
+            leaves
+                && t.exit_time.map_or(true, |exit| current.time >= exit)
+                && t.conditions.iter().all(|c| c.holds(params))
+        });
+        let transition = match fired {
+            Some(transition) => transition,
+            None => return,
+        };
+        let to = match self.state_index(&transition.to) {
+            Some(to) => to,
+            None => {
+                warn!("transition to missing state {:?}", transition.to);
+                return;
+            }
+        };
+        for condition in &transition.conditions {
+            if let Condition::Triggered(ref name) = *condition {
+                params.insert(name.clone(), Parameter::Trigger(false));
+            }
+        }
+        // a transition during a fade drops the state faded out of
+        self.fade = if transition.duration > 0.0 {
+            Some(Fade {
+                from: current,
+                elapsed: 0.0,
+                duration: transition.duration,
+            })
+        } else {
+            None
+        };
+        self.current = Some(Playback {
+            state: to,
+            time: 0.0,
+        });
+    }
+
+    fn advance(
+        &mut self,
+        dt: f32,
+        clips: &[Arc<AnimationClip>],
+        params: &HashMap<String, Parameter>,
+    ) {
+        let states = &self.states;
+        let advance = |playback: &mut Playback| {
+            let state = &states[playback.state];
+            // blend spaces play at the weighted speed of their clips, so
+            // their cycles stay in step
+            let duration: f32 = state
+                .motion
+                .weights(params)
+                .iter()
+                .map(|&(clip, w)| {
+                    clips.get(clip).map_or(0.0, |c| c.duration()) * w
+                })
+                .sum();
+            if duration > 0.0 {
+                playback.time += dt * state.speed / duration;
+            }
+        };
+        if let Some(ref mut current) = self.current {
+            advance(current);
+        }
+        if let Some(mut fade) = self.fade {
+            advance(&mut fade.from);
+            fade.elapsed += dt;
+            self.fade = if fade.elapsed < fade.duration {
+                Some(fade)
+            } else {
+                None
+            };
+        }
+    }
+
+    fn sample(
+        &self,
+        playback: Playback,
+        clips: &[Arc<AnimationClip>],
+        params: &HashMap<String, Parameter>,
+        rest: &[NodePose],
+    ) -> Vec<NodePose> {
+        let state = &self.states[playback.state];
+        let mut pose: Option<Vec<NodePose>> = None;
+        let mut total = 0.0;
+        for (clip, weight) in state.motion.weights(params) {
+            let clip = match clips.get(clip) {
+                Some(clip) => clip,
+                None => continue,
+            };
+            let duration = clip.duration();
+            let time =
+                clip_time(playback.time * duration, duration, state.mode);
+            let mut sampled = rest.to_vec();
+            for (node, value) in clip.sample(time) {
+                if let Some(node_pose) = sampled.get_mut(node) {
+                    value.apply(node_pose);
+                }
+            }
+            // running weighted average of the clips
+            total += weight;
+            pose = Some(match pose {
+                Some(pose) => pose
+                    .iter()
+                    .zip(&sampled)
+                    .map(|(a, b)| blend_node(a, b, weight / total))
+                    .collect(),
+                None => sampled,
+            });
+        }
+        pose.unwrap_or_else(|| rest.to_vec())
+    }
+
+    /// The layer's pose, cross-faded between states
+    fn pose(
+        &self,
+        clips: &[Arc<AnimationClip>],
+        params: &HashMap<String, Parameter>,
+        rest: &[NodePose],
+        playback: Playback,
+    ) -> Vec<NodePose> {
+        let pose = self.sample(playback, clips, params, rest);
+        match self.fade {
+            Some(fade) => {
+                let from = self.sample(fade.from, clips, params, rest);
+                let amount = fade.elapsed / fade.duration;
+                from.iter()
+                    .zip(&pose)
+                    .map(|(a, b)| blend_node(a, b, amount))
+                    .collect()
+            }
+            None => pose,
+        }
+    }
+
+    /// Blends the layer onto the pose of the layers below
+    fn apply(
+        &self,
+        clips: &[Arc<AnimationClip>],
+        params: &HashMap<String, Parameter>,
+        rest: &[NodePose],
+        pose: &mut [NodePose],
+    ) {
+        let current = match self.current {
+            Some(current) if self.weight > 0.0 => current,
+            _ => return,
+        };
+        let layer_pose = self.pose(clips, params, rest, current);
+        let weight = |node: usize| {
+            self.weight * self.mask.as_ref().map_or(1.0, |m| m.weight(node))
+        };
+        match self.blend {
+            LayerBlend::Override => {
+                for (node, (out, layer)) in
+                    pose.iter_mut().zip(&layer_pose).enumerate()
+                {
+                    *out = blend_node(out, layer, weight(node));
+                }
+            }
+            LayerBlend::Additive => {
+                let reference = Playback {
+                    time: 0.0,
+                    ..current
+                };
+                let reference = self.sample(reference, clips, params, rest);
+                for (node, ((out, layer), reference)) in
+                    pose.iter_mut().zip(&layer_pose).zip(&reference).enumerate()
+                {
+                    let w = weight(node);
+                    let rot = reference.rot.invert() * layer.rot;
+                    out.disp += (layer.disp - reference.disp) * w;
+                    out.rot =
+                        out.rot * slerp_shortest(Quaternion::one(), rot, w);
+                    if reference.scale != 0.0 {
+                        out.scale *=
+                            1.0 + (layer.scale / reference.scale - 1.0) * w;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Animates the nodes of a skeleton by layered state machines. Gameplay
+/// code drives it by setting parameters.
+///
+/// The graph writes every target's transform on each update, so it
+/// replaces an AnimationPlayer on the same targets; stop the player
+/// `spawn_model` attaches before adding a graph.
+#[derive(Debug, Clone)]
+pub struct AnimationGraph {
+    pub clips: Vec<Arc<AnimationClip>>,
+    /// Entity animated by each node index the clips target, or None for
+    /// nodes that weren't spawned
+    pub targets: Vec<Option<Entity>>,
+    /// Transforms of the nodes that no clip animates
+    pub rest_pose: Vec<NodePose>,
+    /// Layers from the bottom up
+    pub layers: Vec<Layer>,
+    params: HashMap<String, Parameter>,
+}
+
+impl Component for AnimationGraph {}
+
+impl AnimationGraph {
+    pub fn new(
+        clips: Vec<Arc<AnimationClip>>,
+        targets: Vec<Option<Entity>>,
+        rest_pose: Vec<NodePose>,
+    ) -> Self {
+        AnimationGraph {
+            clips,
+            targets,
+            rest_pose,
+            layers: Vec::new(),
+            params: HashMap::new(),
+        }
+    }
+
+    /// A graph over the animations of a model, whose nodes were spawned
+    /// as `targets`
+    pub fn for_model(model: &Model, targets: Vec<Option<Entity>>) -> Self {
+        let rest_pose = model.nodes.iter().map(|n| n.transform).collect();
+        AnimationGraph::new(model.animations.clone(), targets, rest_pose)
+    }
+
+    pub fn with_layer(mut self, layer: Layer) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Index of the clip with the given name
+    pub fn clip_index(&self, name: &str) -> Option<usize> {
+        self.clips
+            .iter()
+            .position(|c| c.name.as_ref().map(String::as_str) == Some(name))
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|l| l.name == name)
+    }
+
+    pub fn layer_mut(&mut self, name: &str) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|l| l.name == name)
+    }
+
+    pub fn set_float(&mut self, name: &str, value: f32) {
+        self.params.insert(name.to_owned(), Parameter::Float(value));
+    }
+
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.params.insert(name.to_owned(), Parameter::Bool(value));
+    }
+
+    /// Sets a trigger, which stays set until a transition consumes it
+    pub fn set_trigger(&mut self, name: &str) {
+        self.params
+            .insert(name.to_owned(), Parameter::Trigger(true));
+    }
+
+    pub fn reset_trigger(&mut self, name: &str) {
+        self.params
+            .insert(name.to_owned(), Parameter::Trigger(false));
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<Parameter> {
+        self.params.get(name).cloned()
+    }
+
+    /// Fires transitions, advances every layer by `dt` seconds and
+    /// returns the blended pose of each node
+    pub fn update(&mut self, dt: f32) -> Vec<NodePose> {
+        let AnimationGraph {
+            ref clips,
+            ref rest_pose,
+            ref mut layers,
+            ref mut params,
+            ..
+        } = *self;
+        let mut pose = rest_pose.clone();
+        for layer in layers.iter_mut() {
+            if layer.states.is_empty() {
+                continue;
+            }
+            if layer.current.is_none() {
+                layer.current = Some(Playback {
+                    state: 0,
+                    time: 0.0,
+                });
+            }
+            layer.transition(params);
+            layer.advance(dt, clips, params);
+            layer.apply(clips, params, rest_pose, &mut pose);
+        }
+        pose
+    }
+}
+
+/// Updates every AnimationGraph and writes its pose into its targets'
+/// transforms
+pub fn update_animation_graphs<CS: TryGetComponent>(
+    components: &ComponentManager<CS>,
+    dt: f32,
+) {
+    let (graphs, transforms) = match (
+        components.get_components::<AnimationGraph>(),
+        components.get_components::<TransformComponent>(),
+    ) {
+        (Some(graphs), Some(transforms)) => (graphs, transforms),
+        _ => return,
+    };
+    let mut graphs = graphs.write().expect("poisoned RwLock");
+    let mut transforms = transforms.write().expect("poisoned RwLock");
+    for entity in components.entities() {
+        let graph = match graphs[*entity] {
+            Some(ref mut graph) => graph,
+            None => continue,
+        };
+        let pose = graph.update(dt);
+        for (&target, node_pose) in graph.targets.iter().zip(pose) {
+            let target = match target {
+                Some(target) => target,
+                None => continue,
+            };
+            if let Some(ref mut transform) = transforms[*target] {
+                transform.transform = node_pose;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::animation::{Channel, Interpolation, Keyframes};
+    use crate::game::test_util::*;
+
+    fn near(a: Vec3, b: Vec3) -> bool {
+        (a - b).magnitude() < 1e-4
+    }
+
+    fn rest(nodes: usize) -> Vec<NodePose> {
+        vec![
+            Decomposed {
+                scale: 1.0,
+                rot: Quaternion::one(),
+                disp: Vec3::zero(),
+            };
+            nodes
+        ]
+    }
+
+    /// A one second clip moving nodes linearly between two positions
+    fn moving(
+        name: &str,
+        nodes: &[usize],
+        from: Vec3,
+        to: Vec3,
+    ) -> Arc<AnimationClip> {
+        let channels = nodes
+            .iter()
+            .map(|&node| Channel {
+                node,
+                interpolation: Interpolation::Linear,
+                times: vec![0.0, 1.0],
+                keyframes: Keyframes::Translation(vec![from, to]),
+            })
+            .collect();
+        Arc::new(AnimationClip::new(Some(name.to_owned()), channels))
+    }
+
+    fn holding(name: &str, position: Vec3) -> Arc<AnimationClip> {
+        moving(name, &[0], position, position)
+    }
+
+    #[test]
+    fn test_blend_1d() {
+        let points = [2.0, 0.0, 1.0];
+        assert_eq!(blend_1d(&points, -1.0), vec![0.0, 1.0, 0.0]);
+        assert_eq!(blend_1d(&points, 0.25), vec![0.0, 0.75, 0.25]);
+        assert_eq!(blend_1d(&points, 1.5), vec![0.5, 0.0, 0.5]);
+        assert_eq!(blend_1d(&points, 3.0), vec![1.0, 0.0, 0.0]);
+        assert!(blend_1d(&[], 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_blend_2d() {
+        let points = [
+            vec2(0.0, 0.0),
+            vec2(1.0, 0.0),
+            vec2(-1.0, 0.0),
+            vec2(0.0, 1.0),
+        ];
+        let weights = blend_2d(&points, vec2(1.0, 0.0));
+        assert_eq!(weights, vec![0.0, 1.0, 0.0, 0.0]);
+        let weights = blend_2d(&points, vec2(0.0, 0.5));
+        assert!((weights[0] - 0.5).abs() < 1e-5, "{:?}", weights);
+        assert!((weights[3] - 0.5).abs() < 1e-5, "{:?}", weights);
+        assert!((weights.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_blend_space_by_speed() {
+        let clips = vec![
+            holding("Idle", vec3(0.0, 0.0, 0.0)),
+            holding("Walk", vec3(1.0, 0.0, 0.0)),
+            holding("Run", vec3(3.0, 0.0, 0.0)),
+        ];
+        let motion = Motion::Blend1D {
+            parameter: "speed".to_owned(),
+            points: vec![(0.0, 0), (1.0, 1), (4.0, 2)],
+        };
+        let mut graph = AnimationGraph::new(clips, Vec::new(), rest(1))
+            .with_layer(
+                Layer::new("base").with_state(State::new("move", motion)),
+            );
+        graph.set_float("speed", 0.5);
+        assert!(near(graph.update(0.1)[0].disp, vec3(0.5, 0.0, 0.0)));
+        graph.set_float("speed", 2.5);
+        assert!(near(graph.update(0.1)[0].disp, vec3(2.0, 0.0, 0.0)));
+    }
+
+    fn locomotion() -> AnimationGraph {
+        let clips = vec![
+            holding("Idle", vec3(0.0, 0.0, 0.0)),
+            holding("Walk", vec3(2.0, 0.0, 0.0)),
+            holding("Jump", vec3(0.0, 5.0, 0.0)),
+        ];
+        let base = Layer::new("base")
+            .with_state(State::clip("idle", 0))
+            .with_state(State::clip("walk", 1))
+            .with_state(State::clip("jump", 2))
+            .with_transition(
+                Transition::new("idle", "walk")
+                    .when(Condition::IsTrue("moving".to_owned()))
+                    .fade(1.0),
+            )
+            .with_transition(
+                Transition::new("walk", "idle")
+                    .when(Condition::IsFalse("moving".to_owned())),
+            )
+            .with_transition(
+                Transition::from_any("jump")
+                    .when(Condition::Triggered("jump".to_owned())),
+            )
+            .with_transition(Transition::new("jump", "idle").exit_time(1.0));
+        AnimationGraph::new(clips, Vec::new(), rest(1)).with_layer(base)
+    }
+
+    #[test]
+    fn test_cross_fade() {
+        let mut graph = locomotion();
+        assert!(near(graph.update(0.1)[0].disp, Vec3::zero()));
+        assert_eq!(graph.layer("base").unwrap().current_state(), Some("idle"));
+
+        graph.set_bool("moving", true);
+        assert!(near(graph.update(0.5)[0].disp, vec3(1.0, 0.0, 0.0)));
+        let base = graph.layer("base").unwrap();
+        assert_eq!(base.current_state(), Some("walk"));
+        assert!(base.is_fading());
+        assert!(near(graph.update(0.25)[0].disp, vec3(1.5, 0.0, 0.0)));
+        assert!(near(graph.update(0.25)[0].disp, vec3(2.0, 0.0, 0.0)));
+        assert!(!graph.layer("base").unwrap().is_fading());
+
+        // without a fade the switch is immediate
+        graph.set_bool("moving", false);
+        assert!(near(graph.update(0.1)[0].disp, Vec3::zero()));
+    }
+
+    #[test]
+    fn test_triggers_and_exit_time() {
+        let mut graph = locomotion();
+        graph.update(0.1);
+        graph.set_trigger("jump");
+        assert!(near(graph.update(0.5)[0].disp, vec3(0.0, 5.0, 0.0)));
+        assert_eq!(graph.parameter("jump"), Some(Parameter::Trigger(false)));
+        // the jump plays through before returning to idle
+        graph.update(0.4);
+        assert_eq!(graph.layer("base").unwrap().current_state(), Some("jump"));
+        graph.update(0.2);
+        graph.update(0.1);
+        assert_eq!(graph.layer("base").unwrap().current_state(), Some("idle"));
+    }
+
+    #[test]
+    fn test_masked_layers() {
+        let clips = vec![
+            moving("Base", &[0, 1], vec3(1.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0)),
+            moving("Wave", &[0, 1], vec3(0.0, 1.0, 0.0), vec3(0.0, 3.0, 0.0)),
+            holding("Override", vec3(0.0, 0.0, 4.0)),
+        ];
+        let upper = BoneMask::empty(2).with_node(1, 1.0);
+        let mut graph = AnimationGraph::new(clips, Vec::new(), rest(2))
+            .with_layer(Layer::new("base").with_state(State::clip("base", 0)))
+            .with_layer(
+                Layer::new("wave")
+                    .with_state(State::clip("wave", 1))
+                    .with_mask(upper)
+                    .with_weight(0.5)
+                    .additive(),
+            );
+        let pose = graph.update(0.5);
+        assert!(near(pose[0].disp, vec3(1.0, 0.0, 0.0)));
+        // half of the wave's rise from its first frame
+        assert!(near(pose[1].disp, vec3(1.0, 0.5, 0.0)));
+
+        graph.layers.push(
+            Layer::new("override")
+                .with_state(State::clip("override", 2))
+                .with_mask(BoneMask::empty(2).with_node(0, 1.0)),
+        );
+        let pose = graph.update(0.0);
+        assert!(near(pose[0].disp, vec3(0.0, 0.0, 4.0)));
+        assert!(near(pose[1].disp, vec3(1.0, 0.5, 0.0)));
+    }
+
+    #[test]
+    fn test_update_animation_graphs() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let entity = world.components.alloc_entity();
+        world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(
+                *entity,
+                TransformComponent {
+                    parent: None,
+                    transform: rest(1)[0],
+                },
+            );
+        let mut graph = locomotion();
+        graph.targets = vec![Some(entity)];
+        graph.set_bool("moving", true);
+        world
+            .components
+            .get_components::<AnimationGraph>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(*entity, graph);
+
+        update_animation_graphs(&world.components, 0.5);
+        let transforms = world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap();
+        let transforms = transforms.read().unwrap();
+        let transform = transforms[*entity].as_ref().unwrap().transform;
+        assert!(near(transform.disp, vec3(1.0, 0.0, 0.0)));
+    }
+}
@@ -0,0 +1,593 @@
+//! Inverse kinematics, bending animated skeletons towards targets.
+//!
+//! Constraints run after animation and physics have posed the joints,
+//! and rotate the joints' `TransformComponent`s so that chains reach
+//! their targets. Each constraint has a weight blending between the
+//! animated pose and the solved one. The joints of a chain are listed
+//! from the root down, each a child of the one before.
+
+use super::{slerp_shortest, NodePose};
+use crate::game::{component::*, TryGetComponent};
+use crate::math::*;
+use cgmath::*;
+
+/// Shortest bones and distances the solvers work with
+const EPSILON: f32 = 1e-5;
+
+/// Transform from an entity's space to world space, composed from its
+/// parents' transforms
+pub fn world_pose(
+    transforms: &ComponentList<TransformComponent>,
+    entity: Entity,
+) -> Option<NodePose> {
+    let mut component = transforms[*entity].as_ref()?;
+    let mut pose = component.transform;
+    while let Some(parent) = component.parent {
+        component = match transforms[*parent] {
+            Some(ref c) => c,
+            None => break,
+        };
+        pose = component.transform.concat(&pose);
+    }
+    Some(pose)
+}
+
+/// World rotation of an entity's parent
+fn parent_rotation(
+    transforms: &ComponentList<TransformComponent>,
+    entity: Entity,
+) -> Quaternion<f32> {
+    transforms[*entity]
+        .as_ref()
+        .and_then(|t| t.parent)
+        .and_then(|parent| world_pose(transforms, parent))
+        .map_or_else(Quaternion::one, |pose| pose.rot)
+}
+
+fn direction(v: Vec3) -> Option<Vec3> {
+    if v.magnitude2() > EPSILON * EPSILON {
+        Some(v.normalize())
+    } else {
+        None
+    }
+}
+
+/// Any direction at right angles to `v`
+fn perpendicular(v: Vec3) -> Vec3 {
+    let other = if v.x.abs() < 0.9 {
+        Vec3::unit_x()
+    } else {
+        Vec3::unit_y()
+    };
+    v.cross(other).normalize()
+}
+
+/// Blends a joint's local rotation towards a new local rotation
+fn blend_rotation(
+    transforms: &mut ComponentList<TransformComponent>,
+    entity: Entity,
+    rotation: Quaternion<f32>,
+    weight: f32,
+) {
+    if let Some(ref mut component) = transforms[*entity] {
+        let rot = component.transform.rot;
+        component.transform.rot =
+            slerp_shortest(rot, rotation.normalize(), weight.max(0.0).min(1.0));
+    }
+}
+
+/// Blends a joint's local rotation towards the one that gives it a
+/// world rotation
+fn blend_world_rotation(
+    transforms: &mut ComponentList<TransformComponent>,
+    entity: Entity,
+    rotation: Quaternion<f32>,
+    weight: f32,
+) {
+    let parent = parent_rotation(transforms, entity);
+    blend_rotation(transforms, entity, parent.invert() * rotation, weight);
+}
+
+/// Analytic solver for a limb of two bones, such as a leg or arm
+#[derive(Debug, Clone, PartialEq)]
+pub struct TwoBoneIk {
+    /// Upper joint, such as a hip or shoulder
+    pub root: Entity,
+    /// Middle joint, such as a knee or elbow
+    pub mid: Entity,
+    /// End joint, such as an ankle or wrist
+    pub end: Entity,
+    /// World position the end joint reaches for
+    pub target: Vec3,
+    /// World position the middle joint bends towards, or None to keep the
+    /// animated bend direction
+    pub pole: Option<Vec3>,
+    pub weight: f32,
+}
+
+impl TwoBoneIk {
+    pub fn new(root: Entity, mid: Entity, end: Entity, target: Vec3) -> Self {
+        TwoBoneIk {
+            root,
+            mid,
+            end,
+            target,
+            pole: None,
+            weight: 1.0,
+        }
+    }
+
+    pub fn with_pole(mut self, pole: Vec3) -> Self {
+        self.pole = Some(pole);
+        self
+    }
+
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    fn solve(
+        &self,
+        transforms: &mut ComponentList<TransformComponent>,
+    ) -> Option<()> {
+        let root = world_pose(transforms, self.root)?;
+        let mid = world_pose(transforms, self.mid)?;
+        let (a, b) = (root.disp, mid.disp);
+        let c = world_pose(transforms, self.end)?.disp;
+        let (upper, lower) = ((b - a).magnitude(), (c - b).magnitude());
+        if upper < EPSILON || lower < EPSILON {
+            return None;
+        }
+        // targets out of reach stretch the limb straight towards them
+        let reach = (self.target - a)
+            .magnitude()
+            .max((upper - lower).abs() + EPSILON)
+            .min(upper + lower - EPSILON);
+        let to_target = direction(self.target - a)?;
+
+        // bend the middle joint until the limb spans the reach
+        let normal = direction((b - a).cross(c - b))
+            .or_else(|| direction((self.pole? - a).cross(b - a)))
+            .unwrap_or_else(|| perpendicular(b - a));
+        let current = (a - b).angle(c - b).0;
+        let cos = (upper * upper + lower * lower - reach * reach)
+            / (2.0 * upper * lower);
+        let desired = cos.max(-1.0).min(1.0).acos();
+        let bend = Quaternion::from_axis_angle(normal, Rad(current - desired));
+        let bent_end = b + bend.rotate_vector(c - b);
+
+        // swing the limb onto the target, then twist it around the line
+        // to the target so the middle joint faces the pole
+        let swing = Quaternion::from_arc(bent_end - a, to_target, None);
+        let twist = self
+            .pole
+            .and_then(|pole| {
+                let flatten =
+                    |v: Vec3| direction(v - to_target * v.dot(to_target));
+                let bent = flatten(swing.rotate_vector(b - a))?;
+                let pole = flatten(pole - a)?;
+                Some(Quaternion::from_arc(bent, pole, Some(to_target)))
+            })
+            .unwrap_or_else(Quaternion::one);
+        let turn = twist * swing;
+
+        let root_rotation = turn * root.rot;
+        let mid_rotation = turn * bend * mid.rot;
+        let mid_parent = turn * parent_rotation(transforms, self.mid);
+        blend_world_rotation(transforms, self.root, root_rotation, self.weight);
+        blend_rotation(
+            transforms,
+            self.mid,
+            mid_parent.invert() * mid_rotation,
+            self.weight,
+        );
+        Some(())
+    }
+}
+
+/// Turns a joint to aim one of its axes at a target, such as a head
+/// tracking something or a gun aiming
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookAtIk {
+    pub joint: Entity,
+    /// Axis to point at the target, in the joint's own space
+    pub forward: Vec3,
+    /// World position to look at
+    pub target: Vec3,
+    /// Furthest the joint turns away from its animated aim
+    pub limit: Rad<f32>,
+    pub weight: f32,
+}
+
+impl LookAtIk {
+    pub fn new(joint: Entity, forward: Vec3, target: Vec3) -> Self {
+        LookAtIk {
+            joint,
+            forward,
+            target,
+            limit: Rad(std::f32::consts::PI),
+            weight: 1.0,
+        }
+    }
+
+    pub fn with_limit(mut self, limit: Rad<f32>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    fn solve(
+        &self,
+        transforms: &mut ComponentList<TransformComponent>,
+    ) -> Option<()> {
+        let pose = world_pose(transforms, self.joint)?;
+        let aim = direction(pose.rot.rotate_vector(self.forward))?;
+        let to_target = direction(self.target - pose.disp)?;
+        let mut turn = Quaternion::from_arc(aim, to_target, None);
+        let angle = aim.angle(to_target);
+        if angle > self.limit {
+            turn = Quaternion::one().slerp(turn, self.limit.0 / angle.0);
+        }
+        blend_world_rotation(
+            transforms,
+            self.joint,
+            turn * pose.rot,
+            self.weight,
+        );
+        Some(())
+    }
+}
+
+/// Iterative solver for chains of any length, such as spines and tails
+#[derive(Debug, Clone, PartialEq)]
+pub struct FabrikIk {
+    /// Joints from the root of the chain to its tip
+    pub joints: Vec<Entity>,
+    /// World position the tip reaches for
+    pub target: Vec3,
+    pub iterations: usize,
+    /// Distance from the target close enough to stop iterating
+    pub tolerance: f32,
+    pub weight: f32,
+}
+
+impl FabrikIk {
+    pub fn new(joints: Vec<Entity>, target: Vec3) -> Self {
+        FabrikIk {
+            joints,
+            target,
+            iterations: 10,
+            tolerance: 1e-3,
+            weight: 1.0,
+        }
+    }
+
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Joint positions reaching for the target, keeping the bone lengths
+    fn reach(&self, mut points: Vec<Vec3>) -> Vec<Vec3> {
+        let lengths: Vec<f32> = points
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).magnitude())
+            .collect();
+        let root = points[0];
+        // moves `to` along the line from `from` until the bone fits
+        let place = |from: Vec3, to: Vec3, length: f32| {
+            from + direction(to - from).unwrap_or_else(Vec3::zero) * length
+        };
+        if (self.target - root).magnitude() >= lengths.iter().sum::<f32>() {
+            for (i, &length) in lengths.iter().enumerate() {
+                points[i + 1] = place(points[i], self.target, length);
+            }
+            return points;
+        }
+        let tip = points.len() - 1;
+        for _ in 0..self.iterations {
+            if (points[tip] - self.target).magnitude() < self.tolerance {
+                break;
+            }
+            points[tip] = self.target;
+            for (i, &length) in lengths.iter().enumerate().rev() {
+                points[i] = place(points[i + 1], points[i], length);
+            }
+            points[0] = root;
+            for (i, &length) in lengths.iter().enumerate() {
+                points[i + 1] = place(points[i], points[i + 1], length);
+            }
+        }
+        points
+    }
+
+    fn solve(
+        &self,
+        transforms: &mut ComponentList<TransformComponent>,
+    ) -> Option<()> {
+        if self.joints.len() < 2 {
+            return None;
+        }
+        let points = self
+            .joints
+            .iter()
+            .map(|&joint| world_pose(transforms, joint).map(|p| p.disp))
+            .collect::<Option<Vec<_>>>()?;
+        let points = self.reach(points);
+        // turn each bone onto its solved direction from the root down,
+        // as turning a joint moves the ones after it
+        for (i, pair) in self.joints.windows(2).enumerate() {
+            let pose = world_pose(transforms, pair[0])?;
+            let child = world_pose(transforms, pair[1])?.disp;
+            let turn = match (
+                direction(child - pose.disp),
+                direction(points[i + 1] - pose.disp),
+            ) {
+                (Some(bone), Some(solved)) => {
+                    Quaternion::from_arc(bone, solved, None)
+                }
+                _ => continue,
+            };
+            blend_world_rotation(
+                transforms,
+                pair[0],
+                turn * pose.rot,
+                self.weight,
+            );
+        }
+        Some(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IkConstraint {
+    TwoBone(TwoBoneIk),
+    LookAt(LookAtIk),
+    Fabrik(FabrikIk),
+}
+
+impl IkConstraint {
+    /// Moves the constraint's target, which gameplay code does every
+    /// frame to track something
+    pub fn set_target(&mut self, target: Vec3) {
+        match *self {
+            IkConstraint::TwoBone(ref mut ik) => ik.target = target,
+            IkConstraint::LookAt(ref mut ik) => ik.target = target,
+            IkConstraint::Fabrik(ref mut ik) => ik.target = target,
+        }
+    }
+
+    pub fn set_weight(&mut self, weight: f32) {
+        match *self {
+            IkConstraint::TwoBone(ref mut ik) => ik.weight = weight,
+            IkConstraint::LookAt(ref mut ik) => ik.weight = weight,
+            IkConstraint::Fabrik(ref mut ik) => ik.weight = weight,
+        }
+    }
+
+    /// Rotates the constrained joints towards the target. Constraints
+    /// with missing joints are skipped.
+    pub fn solve(&self, transforms: &mut ComponentList<TransformComponent>) {
+        match *self {
+            IkConstraint::TwoBone(ref ik) => ik.solve(transforms),
+            IkConstraint::LookAt(ref ik) => ik.solve(transforms),
+            IkConstraint::Fabrik(ref ik) => ik.solve(transforms),
+        };
+    }
+}
+
+/// IK constraints on the joints of an entity's skeleton, solved in order
+/// after animation
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IkConstraints {
+    pub constraints: Vec<IkConstraint>,
+}
+
+impl Component for IkConstraints {}
+
+impl IkConstraints {
+    pub fn new(constraints: Vec<IkConstraint>) -> Self {
+        IkConstraints { constraints }
+    }
+}
+
+/// Solves every entity's IK constraints on its joints' transforms
+pub fn solve_ik<CS: TryGetComponent>(components: &ComponentManager<CS>) {
+    let (constraints, transforms) = match (
+        components.get_components::<IkConstraints>(),
+        components.get_components::<TransformComponent>(),
+    ) {
+        (Some(constraints), Some(transforms)) => (constraints, transforms),
+        _ => return,
+    };
+    let constraints = constraints.read().expect("poisoned RwLock");
+    let mut transforms = transforms.write().expect("poisoned RwLock");
+    for entity in components.entities() {
+        if let Some(ref constraints) = constraints[*entity] {
+            for constraint in &constraints.constraints {
+                constraint.solve(&mut transforms);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::test_util::*;
+
+    fn near(a: Vec3, b: Vec3) -> bool {
+        (a - b).magnitude() < 1e-3
+    }
+
+    /// Spawns a chain of joints, each offset from the one before
+    fn chain(
+        world: &mut MockWorld,
+        root: Vec3,
+        offsets: &[Vec3],
+    ) -> Vec<Entity> {
+        let transforms = world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap();
+        let mut joints: Vec<Entity> = Vec::new();
+        for &disp in Some(root).iter().chain(offsets) {
+            let entity = world.components.alloc_entity();
+            transforms.write().unwrap().insert(
+                *entity,
+                TransformComponent {
+                    parent: joints.last().cloned(),
+                    transform: Decomposed {
+                        scale: 1.0,
+                        rot: Quaternion::one(),
+                        disp,
+                    },
+                },
+            );
+            joints.push(entity);
+        }
+        joints
+    }
+
+    fn solve(world: &mut MockWorld, constraint: IkConstraint) {
+        let entity = world.components.alloc_entity();
+        world
+            .components
+            .get_components::<IkConstraints>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(*entity, IkConstraints::new(vec![constraint]));
+        solve_ik(&world.components);
+    }
+
+    fn position(world: &MockWorld, entity: Entity) -> Vec3 {
+        let transforms = world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap();
+        let transforms = transforms.read().unwrap();
+        world_pose(&transforms, entity).unwrap().disp
+    }
+
+    fn leg(world: &mut MockWorld) -> Vec<Entity> {
+        let down = vec3(0.0, -1.0, 0.0);
+        chain(world, vec3(0.0, 2.0, 0.0), &[down, down])
+    }
+
+    #[test]
+    fn test_two_bone() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let joints = leg(&mut world);
+        let target = vec3(0.3, 0.5, 0.2);
+        let ik = TwoBoneIk::new(joints[0], joints[1], joints[2], target)
+            .with_pole(vec3(0.0, 1.0, 5.0));
+        solve(&mut world, IkConstraint::TwoBone(ik));
+
+        assert!(near(position(&world, joints[2]), target));
+        let knee = position(&world, joints[1]);
+        assert!((knee - vec3(0.0, 2.0, 0.0)).magnitude() - 1.0 < 1e-4);
+        assert!(knee.z > 0.2, "knee at {:?} should bend forwards", knee);
+    }
+
+    #[test]
+    fn test_two_bone_out_of_reach() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let joints = leg(&mut world);
+        let ik = TwoBoneIk::new(
+            joints[0],
+            joints[1],
+            joints[2],
+            vec3(4.0, 2.0, 0.0),
+        );
+        solve(&mut world, IkConstraint::TwoBone(ik));
+        assert!(near(position(&world, joints[2]), vec3(2.0, 2.0, 0.0)));
+    }
+
+    #[test]
+    fn test_two_bone_weight() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let joints = leg(&mut world);
+        let ik = TwoBoneIk::new(
+            joints[0],
+            joints[1],
+            joints[2],
+            vec3(2.0, 2.0, 0.0),
+        );
+        solve(
+            &mut world,
+            IkConstraint::TwoBone(ik.clone().with_weight(0.0)),
+        );
+        assert!(near(position(&world, joints[2]), vec3(0.0, 0.0, 0.0)));
+        // half way from pointing down to pointing sideways
+        solve(&mut world, IkConstraint::TwoBone(ik.with_weight(0.5)));
+        let half = std::f32::consts::FRAC_1_SQRT_2 * 2.0;
+        assert!(near(
+            position(&world, joints[2]),
+            vec3(half, 2.0 - half, 0.0)
+        ));
+    }
+
+    #[test]
+    fn test_look_at() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let head = chain(&mut world, vec3(0.0, 2.0, 0.0), &[])[0];
+        let look = LookAtIk::new(head, Vec3::unit_z(), vec3(5.0, 2.0, 0.0))
+            .with_limit(Deg(45.0).into());
+        solve(&mut world, IkConstraint::LookAt(look.clone()));
+        let aim = |world: &MockWorld| {
+            let transforms = world
+                .components
+                .get_components::<TransformComponent>()
+                .unwrap();
+            let transforms = transforms.read().unwrap();
+            let pose = world_pose(&transforms, head).unwrap();
+            pose.rot.rotate_vector(Vec3::unit_z())
+        };
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        assert!(near(aim(&world), vec3(diagonal, 0.0, diagonal)));
+
+        solve(
+            &mut world,
+            IkConstraint::LookAt(look.with_limit(Deg(180.0).into())),
+        );
+        assert!(near(aim(&world), Vec3::unit_x()));
+    }
+
+    #[test]
+    fn test_fabrik() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let up = Vec3::unit_y();
+        let joints = chain(&mut world, Vec3::zero(), &[up, up, up]);
+        let target = vec3(1.5, 1.5, 0.5);
+        let ik = FabrikIk::new(joints.clone(), target).with_iterations(50);
+        solve(&mut world, IkConstraint::Fabrik(ik));
+
+        assert!(
+            (position(&world, joints[3]) - target).magnitude() < 1e-2,
+            "tip at {:?}",
+            position(&world, joints[3])
+        );
+        for pair in joints.windows(2) {
+            let length = (position(&world, pair[1])
+                - position(&world, pair[0]))
+            .magnitude();
+            assert!((length - 1.0).abs() < 1e-4);
+        }
+    }
+}
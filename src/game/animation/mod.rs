@@ -0,0 +1,293 @@
+//! Keyframe animation of entity transforms.
+//!
+//! Models imported from glTF carry their animations as `AnimationClip`s,
+//! whose channels target the model's nodes by index. `spawn_model` gives
+//! the root entity of an animated model an `AnimationPlayer`, which maps
+//! those node indices to the spawned entities and writes sampled values
+//! into their `TransformComponent`s on each update.
+//!
+//! Skinned meshes get a `SkinComponent` listing their joint entities,
+//! whose joint palette is recomputed from the joints' world transforms
+//! after animations and physics have moved them.
+//!
+//! Meshes with morph targets get a `MorphWeights` component, which
+//! weights channels animate like transforms.
+//!
+//! For more than playing one clip at a time, an `AnimationGraph` blends
+//! clips by layered state machines that gameplay code drives through
+//! parameters.
+//!
+//! IK constraints then bend the animated joints to reach targets, such as
+//! feet planted on the ground.
+
+pub mod clip;
+pub mod graph;
+pub mod ik;
+pub mod morph;
+pub mod skin;
+
+pub use self::clip::*;
+pub use self::graph::*;
+pub use self::ik::*;
+pub use self::morph::*;
+pub use self::skin::*;
+
+use super::{component::*, TryGetComponent};
+use std::sync::Arc;
+
+/// What happens when playback reaches the end of a clip
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlaybackMode {
+    /// Starts over from the beginning
+    Loop,
+    /// Holds the last frame
+    Clamp,
+    /// Plays backwards to the beginning, then forwards again
+    PingPong,
+}
+
+/// Plays one of a set of clips on the entities they animate
+#[derive(Debug, Clone)]
+pub struct AnimationPlayer {
+    pub clips: Vec<Arc<AnimationClip>>,
+    /// Entity animated by each node index the clips target, or None for
+    /// nodes that weren't spawned
+    pub targets: Vec<Option<Entity>>,
+    /// Clip being played, by index into `clips`
+    pub current: Option<usize>,
+    /// Seconds of playback since the clip started, scaled by speed
+    pub time: f32,
+    /// Playback rate, where negative speeds play backwards
+    pub speed: f32,
+    pub mode: PlaybackMode,
+    pub paused: bool,
+}
+
+impl Component for AnimationPlayer {}
+
+/// Maps unbounded playback time into a clip of the given duration
+pub fn clip_time(time: f32, duration: f32, mode: PlaybackMode) -> f32 {
+    if duration <= 0.0 {
+        return 0.0;
+    }
+    let wrap = |t: f32, period: f32| ((t % period) + period) % period;
+    match mode {
+        PlaybackMode::Loop => wrap(time, duration),
+        PlaybackMode::Clamp => time.max(0.0).min(duration),
+        PlaybackMode::PingPong => {
+            let t = wrap(time, duration * 2.0);
+            if t > duration {
+                duration * 2.0 - t
+            } else {
+                t
+            }
+        }
+    }
+}
+
+impl AnimationPlayer {
+    /// Creates a stopped player
+    pub fn new(
+        clips: Vec<Arc<AnimationClip>>,
+        targets: Vec<Option<Entity>>,
+    ) -> Self {
+        AnimationPlayer {
+            clips,
+            targets,
+            current: None,
+            time: 0.0,
+            speed: 1.0,
+            mode: PlaybackMode::Loop,
+            paused: false,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: PlaybackMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Plays a clip from the start
+    pub fn play(&mut self, clip: usize) {
+        if clip < self.clips.len() {
+            self.current = Some(clip);
+            self.time = 0.0;
+            self.paused = false;
+        }
+    }
+
+    /// Plays the clip with the given name. Returns whether it was found.
+    pub fn play_named(&mut self, name: &str) -> bool {
+        let index = self
+            .clips
+            .iter()
+            .position(|c| c.name.as_ref().map(String::as_str) == Some(name));
+        if let Some(index) = index {
+            self.play(index);
+        }
+        index.is_some()
+    }
+
+    /// Stops playback. Transforms keep their last sampled values.
+    pub fn stop(&mut self) {
+        self.current = None;
+        self.time = 0.0;
+    }
+
+    pub fn clip(&self) -> Option<&Arc<AnimationClip>> {
+        self.current.and_then(|i| self.clips.get(i))
+    }
+
+    /// Position in the current clip, in seconds
+    pub fn clip_time(&self) -> f32 {
+        self.clip()
+            .map_or(0.0, |c| clip_time(self.time, c.duration(), self.mode))
+    }
+
+    /// Whether a clamped clip has played through to its end
+    pub fn is_finished(&self) -> bool {
+        match (self.clip(), self.mode) {
+            (Some(clip), PlaybackMode::Clamp) => {
+                let end = if self.speed < 0.0 {
+                    0.0
+                } else {
+                    clip.duration()
+                };
+                self.clip_time() == end
+            }
+            (Some(_), _) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// Advances playback by `dt` seconds of real time
+    pub fn advance(&mut self, dt: f32) {
+        if self.paused || self.current.is_none() {
+            return;
+        }
+        let time = self.time + dt * self.speed;
+        let duration = self.clip().map_or(0.0, |c| c.duration());
+        self.time = match self.mode {
+            // don't keep counting past the ends, so reversing the speed
+            // plays back straight away
+            PlaybackMode::Clamp => clip_time(time, duration, self.mode),
+            // wrapped, so long playback doesn't lose f32 precision
+            PlaybackMode::Loop if duration > 0.0 => time.rem_euclid(duration),
+            PlaybackMode::PingPong if duration > 0.0 => {
+                time.rem_euclid(duration * 2.0)
+            }
+            _ => time,
+        };
+    }
+}
+
+/// Advances every AnimationPlayer and writes the sampled values into its
+/// targets' transforms and morph weights
+pub fn update_animations<CS: TryGetComponent>(
+    components: &ComponentManager<CS>,
+    dt: f32,
+) {
+    let (players, transforms) = match (
+        components.get_components::<AnimationPlayer>(),
+        components.get_components::<TransformComponent>(),
+    ) {
+        (Some(players), Some(transforms)) => (players, transforms),
+        _ => return,
+    };
+    let morph_weights = components.get_components::<MorphWeights>();
+    let mut players = players.write().expect("poisoned RwLock");
+    let mut transforms = transforms.write().expect("poisoned RwLock");
+    let mut morph_weights = morph_weights
+        .as_ref()
+        .map(|m| m.write().expect("poisoned RwLock"));
+    for entity in components.entities() {
+        let player = match players[*entity] {
+            Some(ref mut player) => player,
+            None => continue,
+        };
+        player.advance(dt);
+        let clip = match player.clip() {
+            Some(clip) => clip,
+            None => continue,
+        };
+        for (node, value) in clip.sample(player.clip_time()) {
+            let target = match player.targets.get(node) {
+                Some(&Some(target)) => target,
+                _ => continue,
+            };
+            if let ChannelValue::Weights(weights) = value {
+                let target =
+                    morph_weights.as_mut().and_then(|m| m[*target].as_mut());
+                if let Some(morph) = target {
+                    morph.weights = weights;
+                }
+            } else if let Some(ref mut transform) = transforms[*target] {
+                value.apply(&mut transform.transform);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::*;
+    use cgmath::*;
+
+    #[test]
+    fn test_clip_time() {
+        use self::PlaybackMode::*;
+        let cases = [
+            (Loop, 2.5, 0.5),
+            (Loop, -0.5, 1.5),
+            (Clamp, 2.5, 2.0),
+            (Clamp, -1.0, 0.0),
+            (PingPong, 2.5, 1.5),
+            (PingPong, 4.5, 0.5),
+            (PingPong, -0.5, 0.5),
+        ];
+        for &(mode, time, expected) in cases.iter() {
+            let t = clip_time(time, 2.0, mode);
+            assert!(
+                (t - expected).abs() < 1e-5,
+                "{:?} at {} gave {}",
+                mode,
+                time,
+                t
+            );
+        }
+        assert_eq!(clip_time(3.0, 0.0, Loop), 0.0);
+    }
+
+    #[test]
+    fn test_looping_time_wraps() {
+        let channel = Channel {
+            node: 0,
+            interpolation: Interpolation::Linear,
+            times: vec![0.0, 2.0],
+            keyframes: Keyframes::Translation(vec![
+                Vec3::zero(),
+                Vec3::unit_x(),
+            ]),
+        };
+        let clip = Arc::new(AnimationClip::new(None, vec![channel]));
+        for &(mode, period) in
+            [(PlaybackMode::Loop, 2.0), (PlaybackMode::PingPong, 4.0)].iter()
+        {
+            let mut player = AnimationPlayer::new(vec![clip.clone()], vec![])
+                .with_mode(mode);
+            player.play(0);
+            // an hour and a quarter second, in steps f32 adds exactly
+            for _ in 0..14_401 {
+                player.advance(0.25);
+            }
+            assert!(player.time >= 0.0 && player.time < period);
+            assert_eq!(player.clip_time(), 0.25, "{:?}", mode);
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Morph target weights of entities with blend-shaped meshes.
+
+use crate::game::component::*;
+
+/// Weights blending the morph targets of an entity's mesh into it.
+/// Set them directly, or let an AnimationPlayer drive them with the
+/// weights channels of its clips.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MorphWeights {
+    /// Weight of each morph target, in the order of the mesh's targets
+    pub weights: Vec<f32>,
+}
+
+impl Component for MorphWeights {}
+
+impl MorphWeights {
+    pub fn new(weights: Vec<f32>) -> Self {
+        MorphWeights { weights }
+    }
+
+    /// Sets the weight of one target, growing the weights if needed
+    pub fn set(&mut self, target: usize, weight: f32) {
+        if target >= self.weights.len() {
+            self.weights.resize(target + 1, 0.0);
+        }
+        self.weights[target] = weight;
+    }
+}
+
+/// Morph weights applying to an entity's mesh. Nodes with several
+/// primitives spawn a child entity per primitive, which share the
+/// weights of the node's entity.
+pub fn morph_weights<'a>(
+    weights: &'a ComponentList<MorphWeights>,
+    transforms: &ComponentList<TransformComponent>,
+    entity: Entity,
+) -> Option<&'a [f32]> {
+    if let Some(ref own) = weights[*entity] {
+        return Some(&own.weights);
+    }
+    let parent = transforms[*entity].as_ref()?.parent?;
+    weights[*parent].as_ref().map(|w| w.weights.as_slice())
+}
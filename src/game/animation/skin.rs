@@ -0,0 +1,79 @@
+//! Skinned meshes, deformed by the transforms of joint entities.
+
+use crate::game::{
+    built_in_components::world_matrix, component::*, TryGetComponent,
+};
+use crate::math::*;
+use cgmath::*;
+use std::sync::Arc;
+
+/// Deforms the mesh of its entity by the poses of a skin's joints
+#[derive(Debug, Clone)]
+pub struct SkinComponent {
+    /// Joint entities, in the order the mesh's joint indices refer to.
+    /// Joints that weren't spawned hold their bind pose.
+    pub joints: Vec<Option<Entity>>,
+    /// Transforms from mesh space into each joint's space in the bind pose
+    pub inverse_bind_matrices: Arc<Vec<Mat4>>,
+    /// Joint matrices taking bind pose vertices to their posed positions
+    /// in mesh space, updated by `update_skins`
+    pub palette: Vec<Mat4>,
+}
+
+impl Component for SkinComponent {}
+
+impl SkinComponent {
+    pub fn new(
+        joints: Vec<Option<Entity>>,
+        inverse_bind_matrices: Arc<Vec<Mat4>>,
+    ) -> Self {
+        SkinComponent {
+            palette: vec![Mat4::identity(); joints.len()],
+            joints,
+            inverse_bind_matrices,
+        }
+    }
+
+    /// Whether the palette is too large for the vertex shaders, and the
+    /// mesh has to be skinned on the cpu
+    pub fn needs_cpu_skinning(&self) -> bool {
+        self.palette.len() > crate::renderer::MAX_GPU_JOINTS
+    }
+}
+
+/// Computes the joint palette of every skin from the world transforms of
+/// its joints and of the skinned entity
+pub fn update_skins<CS: TryGetComponent>(components: &ComponentManager<CS>) {
+    let (skins, transforms) = match (
+        components.get_components::<SkinComponent>(),
+        components.get_components::<TransformComponent>(),
+    ) {
+        (Some(skins), Some(transforms)) => (skins, transforms),
+        _ => return,
+    };
+    let mut skins = skins.write().expect("poisoned RwLock");
+    let transforms = transforms.read().expect("poisoned RwLock");
+    for entity in components.entities() {
+        let skin = match skins[*entity] {
+            Some(ref mut skin) => skin,
+            None => continue,
+        };
+        let to_mesh = world_matrix(&transforms, entity)
+            .and_then(|m| m.invert())
+            .unwrap_or_else(Mat4::identity);
+        let SkinComponent {
+            ref joints,
+            ref inverse_bind_matrices,
+            ref mut palette,
+        } = *skin;
+        palette.clear();
+        palette.extend(joints.iter().zip(inverse_bind_matrices.iter()).map(
+            |(&joint, inverse_bind)| {
+                match joint.and_then(|j| world_matrix(&transforms, j)) {
+                    Some(joint) => to_mesh * joint * inverse_bind,
+                    None => Mat4::identity(),
+                }
+            },
+        ));
+    }
+}
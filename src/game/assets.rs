@@ -0,0 +1,624 @@
+//! Loads assets without blocking the frame. Files are decoded on a pool
+//! of worker threads, then uploaded to the renderer on the main thread,
+//! a frame's upload budget at a time. Models upload a mesh or texture at
+//! a time, and spawn once the last of them is uploaded.
+
+use super::{
+    component::Entity,
+    resource::{MeshHandle, ResourceError, TextureHandle},
+    scene::{model_textures, spawn_uploaded_model},
+    world::EntityWorld,
+    TryGetComponent,
+};
+use crate::renderer::{model::Model, Mesh, Renderer};
+use log::*;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Default time uploads may take each frame
+pub const DEFAULT_UPLOAD_BUDGET: Duration = Duration::from_millis(4);
+
+/// Progress of an asset
+#[derive(Debug)]
+pub enum LoadState<T> {
+    /// Decoding on a worker thread, or waiting for its upload
+    Loading,
+    Loaded(T),
+    Failed(ResourceError),
+}
+
+impl<T> LoadState<T> {
+    pub fn is_loading(&self) -> bool {
+        matches!(self, LoadState::Loading)
+    }
+
+    pub fn loaded(&self) -> Option<&T> {
+        match self {
+            LoadState::Loaded(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Refers to an asset which loads into a `T`. Returned as soon as the
+/// load starts.
+pub struct AssetHandle<T> {
+    id: usize,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> AssetHandle<T> {
+    fn new(id: usize) -> Self {
+        AssetHandle {
+            id,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for AssetHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for AssetHandle<T> {}
+
+impl<T> PartialEq for AssetHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for AssetHandle<T> {}
+
+impl<T> Hash for AssetHandle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for AssetHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AssetHandle({})", self.id)
+    }
+}
+
+/// What an asset loads into: a texture or mesh in the world's
+/// resources, or the root entity of a spawned model
+pub trait Asset: Sized {
+    fn states(assets: &AssetServer) -> &HashMap<usize, LoadState<Self>>;
+}
+
+impl Asset for TextureHandle {
+    fn states(assets: &AssetServer) -> &HashMap<usize, LoadState<Self>> {
+        &assets.textures
+    }
+}
+
+impl Asset for MeshHandle {
+    fn states(assets: &AssetServer) -> &HashMap<usize, LoadState<Self>> {
+        &assets.meshes
+    }
+}
+
+impl Asset for Entity {
+    fn states(assets: &AssetServer) -> &HashMap<usize, LoadState<Self>> {
+        &assets.models
+    }
+}
+
+/// An asset decoded by a worker, ready to upload
+enum Decoded {
+    Texture(gltf::image::Data),
+    Mesh(Mesh),
+    Model(Box<Model>),
+}
+
+/// A piece of upload work. A frame's budget is spent an item at a time.
+enum Upload {
+    Texture(usize, String, gltf::image::Data),
+    Mesh(usize, String, Mesh),
+    /// One of a model's images, by index
+    ModelTexture(usize, usize),
+    /// One of a model's meshes, by index
+    ModelMesh(usize, usize),
+    /// Spawns a model, queued after its textures and meshes
+    SpawnModel(usize),
+}
+
+/// A model whose textures and meshes are uploading
+struct ModelUpload {
+    name: String,
+    model: Box<Model>,
+    textures: HashMap<usize, TextureHandle>,
+    meshes: Vec<MeshHandle>,
+}
+
+struct Finished {
+    id: usize,
+    name: String,
+    result: Result<Decoded, failure::Error>,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Loads textures, meshes and models on worker threads.
+/// Loads return a handle right away; `update` uploads the decoded assets
+/// and should be called once a frame on the main thread.
+pub struct AssetServer {
+    jobs: Option<Sender<Job>>,
+    finished_tx: Sender<Finished>,
+    finished: Receiver<Finished>,
+    /// Set when the server drops, so workers skip the queued jobs
+    cancelled: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<()>>,
+    /// Upload work of the decoded assets, oldest first
+    uploads: VecDeque<Upload>,
+    /// Models with uploads queued, by id
+    model_uploads: HashMap<usize, ModelUpload>,
+    textures: HashMap<usize, LoadState<TextureHandle>>,
+    meshes: HashMap<usize, LoadState<MeshHandle>>,
+    models: HashMap<usize, LoadState<Entity>>,
+    next_id: usize,
+    upload_budget: Duration,
+}
+
+impl fmt::Debug for AssetServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AssetServer")
+            .field("workers", &self.workers.len())
+            .field("pending", &self.pending())
+            .field("upload_budget", &self.upload_budget)
+            .finish()
+    }
+}
+
+impl AssetServer {
+    /// Starts a server decoding on the given number of worker threads
+    pub fn new(threads: usize) -> Self {
+        let (jobs, job_rx) = mpsc::channel::<Job>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let workers = (0..threads.max(1))
+            .map(|i| {
+                let job_rx = job_rx.clone();
+                let cancelled = cancelled.clone();
+                thread::Builder::new()
+                    .name(format!("asset worker {}", i))
+                    .spawn(move || loop {
+                        let job = job_rx.lock().expect("poisoned Mutex").recv();
+                        match job {
+                            Ok(_) if cancelled.load(Ordering::Relaxed) => {}
+                            Ok(job) => job(),
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("could not spawn asset worker")
+            })
+            .collect();
+        let (finished_tx, finished) = mpsc::channel();
+        AssetServer {
+            jobs: Some(jobs),
+            finished_tx,
+            finished,
+            cancelled,
+            workers,
+            uploads: VecDeque::new(),
+            model_uploads: HashMap::new(),
+            textures: HashMap::new(),
+            meshes: HashMap::new(),
+            models: HashMap::new(),
+            next_id: 0,
+            upload_budget: DEFAULT_UPLOAD_BUDGET,
+        }
+    }
+
+    /// Sets how long uploads may take each frame. At least one asset is
+    /// uploaded per frame, however long it takes.
+    pub fn set_upload_budget(&mut self, budget: Duration) {
+        self.upload_budget = budget;
+    }
+
+    pub fn with_upload_budget(mut self, budget: Duration) -> Self {
+        self.set_upload_budget(budget);
+        self
+    }
+
+    pub fn upload_budget(&self) -> Duration {
+        self.upload_budget
+    }
+
+    /// Loads a glTF model, spawning it as entities once uploaded. Loads
+    /// into the model's root entity.
+    pub fn load_model<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> AssetHandle<Entity> {
+        let path = path.as_ref().to_path_buf();
+        let id = self.spawn_job(path.display().to_string(), move || {
+            Model::from_gltf(&path).map(|model| Decoded::Model(Box::new(model)))
+        });
+        self.models.insert(id, LoadState::Loading);
+        AssetHandle::new(id)
+    }
+
+    /// Loads an image file as an RGBA texture
+    pub fn load_texture<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> AssetHandle<TextureHandle> {
+        let path = path.as_ref().to_path_buf();
+        let id = self.spawn_job(path.display().to_string(), move || {
+            let image = image::open(&path)?.to_rgba();
+            let (width, height) = image.dimensions();
+            Ok(Decoded::Texture(gltf::image::Data {
+                pixels: image.into_raw(),
+                format: gltf::image::Format::R8G8B8A8,
+                width,
+                height,
+            }))
+        });
+        self.textures.insert(id, LoadState::Loading);
+        AssetHandle::new(id)
+    }
+
+    /// Builds a mesh on a worker thread, such as a generated or
+    /// custom-decoded one
+    pub fn load_mesh_with<F>(
+        &mut self,
+        name: &str,
+        build: F,
+    ) -> AssetHandle<MeshHandle>
+    where
+        F: FnOnce() -> Result<Mesh, failure::Error> + Send + 'static,
+    {
+        let id = self
+            .spawn_job(name.to_string(), move || build().map(Decoded::Mesh));
+        self.meshes.insert(id, LoadState::Loading);
+        AssetHandle::new(id)
+    }
+
+    fn spawn_job<F>(&mut self, name: String, decode: F) -> usize
+    where
+        F: FnOnce() -> Result<Decoded, failure::Error> + Send + 'static,
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        let finished_tx = self.finished_tx.clone();
+        let job: Job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(decode))
+                .unwrap_or_else(|_| Err(format_err!("decoding panicked")));
+            // the server may have dropped while decoding
+            let _ = finished_tx.send(Finished { id, name, result });
+        });
+        self.jobs
+            .as_ref()
+            .expect("asset server is shutting down")
+            .send(job)
+            .expect("asset workers have stopped");
+        id
+    }
+
+    /// The asset's progress, or None if the handle is from another server
+    pub fn state<T: Asset>(
+        &self,
+        handle: AssetHandle<T>,
+    ) -> Option<&LoadState<T>> {
+        T::states(self).get(&handle.id)
+    }
+
+    /// The loaded value of the asset, if it has finished loading
+    pub fn get<T: Asset + Copy>(&self, handle: AssetHandle<T>) -> Option<T> {
+        self.state(handle).and_then(LoadState::loaded).cloned()
+    }
+
+    /// Number of assets still loading
+    pub fn pending(&self) -> usize {
+        let textures = self.textures.values().filter(|s| s.is_loading());
+        let meshes = self.meshes.values().filter(|s| s.is_loading());
+        let models = self.models.values().filter(|s| s.is_loading());
+        textures.count() + meshes.count() + models.count()
+    }
+
+    /// Uploads decoded assets to the renderer until the frame's upload
+    /// budget is spent. Returns the number of items uploaded: a texture,
+    /// a mesh, or the spawn of a model whose parts are uploaded.
+    pub fn update<R, CS>(
+        &mut self,
+        world: &mut EntityWorld<R, CS>,
+        renderer: &mut R,
+    ) -> usize
+    where
+        R: Renderer,
+        CS: TryGetComponent,
+    {
+        let start = Instant::now();
+        self.receive_finished();
+        let mut uploaded = 0;
+        while let Some(upload) = self.uploads.pop_front() {
+            self.upload(upload, world, renderer);
+            uploaded += 1;
+            if start.elapsed() >= self.upload_budget {
+                break;
+            }
+        }
+        uploaded
+    }
+
+    fn upload<R, CS>(
+        &mut self,
+        upload: Upload,
+        world: &mut EntityWorld<R, CS>,
+        renderer: &mut R,
+    ) where
+        R: Renderer,
+        CS: TryGetComponent,
+    {
+        let (id, result) = match upload {
+            Upload::Texture(id, name, image) => {
+                let result = renderer.create_texture(&image).map(|texture| {
+                    let handle = world.resources.insert_texture(texture);
+                    self.textures.insert(id, LoadState::Loaded(handle));
+                });
+                (id, result.map_err(|e| (name, e)))
+            }
+            Upload::Mesh(id, name, mesh) => {
+                let result = renderer.create_mesh(&mesh).map(|mesh| {
+                    let handle = world.resources.insert_mesh(mesh);
+                    self.meshes.insert(id, LoadState::Loaded(handle));
+                });
+                (id, result.map_err(|e| (name, e)))
+            }
+            Upload::ModelTexture(id, index) => {
+                // a failed part drops the rest of its model's uploads
+                let upload = match self.model_uploads.get_mut(&id) {
+                    Some(upload) => upload,
+                    None => return,
+                };
+                let image = &upload.model.imports().images[index];
+                let result = renderer.create_texture(image).map(|texture| {
+                    let handle = world.resources.insert_texture(texture);
+                    upload.textures.insert(index, handle);
+                });
+                (id, result.map_err(|e| (upload.name.clone(), e)))
+            }
+            Upload::ModelMesh(id, index) => {
+                let upload = match self.model_uploads.get_mut(&id) {
+                    Some(upload) => upload,
+                    None => return,
+                };
+                let mesh = &upload.model.meshes[index].mesh;
+                let result = renderer.create_mesh(mesh).map(|mesh| {
+                    upload.meshes.push(world.resources.insert_mesh(mesh));
+                });
+                (id, result.map_err(|e| (upload.name.clone(), e)))
+            }
+            Upload::SpawnModel(id) => {
+                let upload = match self.model_uploads.remove(&id) {
+                    Some(upload) => upload,
+                    None => return,
+                };
+                let result = spawn_uploaded_model(
+                    world,
+                    &upload.model,
+                    &upload.textures,
+                    &upload.meshes,
+                )
+                .map(|root| {
+                    self.models.insert(id, LoadState::Loaded(root));
+                });
+                (id, result.map_err(|e| (upload.name, e)))
+            }
+        };
+        if let Err((name, e)) = result {
+            warn!("could not upload {}: {}", name, e);
+            self.model_uploads.remove(&id);
+            self.fail(id, ResourceError::UploadError(name, e));
+        }
+    }
+
+    /// Queues the assets the workers have decoded for upload
+    fn receive_finished(&mut self) {
+        while let Ok(Finished { id, name, result }) = self.finished.try_recv() {
+            match result {
+                Ok(decoded) => self.queue_uploads(id, name, decoded),
+                Err(e) => {
+                    warn!("could not load {}: {}", name, e);
+                    self.fail(id, ResourceError::LoadError(name, e));
+                }
+            }
+        }
+    }
+
+    fn queue_uploads(&mut self, id: usize, name: String, decoded: Decoded) {
+        match decoded {
+            Decoded::Texture(image) => {
+                self.uploads.push_back(Upload::Texture(id, name, image))
+            }
+            Decoded::Mesh(mesh) => {
+                self.uploads.push_back(Upload::Mesh(id, name, mesh))
+            }
+            Decoded::Model(model) => {
+                for index in model_textures(&model) {
+                    self.uploads.push_back(Upload::ModelTexture(id, index));
+                }
+                for index in 0..model.meshes.len() {
+                    self.uploads.push_back(Upload::ModelMesh(id, index));
+                }
+                self.uploads.push_back(Upload::SpawnModel(id));
+                let meshes = Vec::with_capacity(model.meshes.len());
+                self.model_uploads.insert(
+                    id,
+                    ModelUpload {
+                        name,
+                        model,
+                        textures: HashMap::new(),
+                        meshes,
+                    },
+                );
+            }
+        }
+    }
+
+    fn fail(&mut self, id: usize, error: ResourceError) {
+        if let Some(state) = self.textures.get_mut(&id) {
+            *state = LoadState::Failed(error);
+        } else if let Some(state) = self.meshes.get_mut(&id) {
+            *state = LoadState::Failed(error);
+        } else {
+            self.models.insert(id, LoadState::Failed(error));
+        }
+    }
+}
+
+impl Default for AssetServer {
+    /// A server with a worker per cpu
+    fn default() -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        AssetServer::new(threads)
+    }
+}
+
+impl Drop for AssetServer {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        // closing the job queue stops the workers once they're idle
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::test_util::*;
+
+    fn load_all(
+        assets: &mut AssetServer,
+        world: &mut MockWorld,
+        renderer: &mut MockRenderer,
+    ) {
+        let timeout = Instant::now() + Duration::from_secs(60);
+        while assets.pending() > 0 {
+            assert!(Instant::now() < timeout, "assets took too long to load");
+            assets.update(world, renderer);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn quad() -> Result<Mesh, failure::Error> {
+        use genmesh::generators::IcoSphere;
+        Ok(Mesh::from_genmesh(IcoSphere::subdivide(1)))
+    }
+
+    #[test]
+    fn test_load_model_and_texture() {
+        let mut renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let mut assets = AssetServer::new(2);
+        let model = assets.load_model("assets/models/AnimatedProp.gltf");
+        let texture =
+            assets.load_texture("assets/Textures/Maskonaive/posx.jpg");
+        // handles come back before anything has loaded
+        assert!(assets.state(model).unwrap().is_loading());
+        assert_eq!(assets.pending(), 2);
+
+        load_all(&mut assets, &mut world, &mut renderer);
+        let root = assets.get(model).expect("model should have loaded");
+        assert!(world.components.entities().any(|e| e == root));
+        let texture = assets.get(texture).expect("texture should have loaded");
+        assert!(world.resources.textures.contains_key(&texture));
+    }
+
+    #[test]
+    fn test_failed_load() {
+        let mut renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let mut assets = AssetServer::new(1);
+        let missing = assets.load_model("assets/models/Missing.gltf");
+        let broken =
+            assets.load_mesh_with("broken", || Err(format_err!("no vertices")));
+        let panicked =
+            assets.load_mesh_with("panicked", || panic!("bad mesh data"));
+
+        load_all(&mut assets, &mut world, &mut renderer);
+        match assets.state(missing) {
+            Some(LoadState::Failed(ResourceError::LoadError(name, _))) => {
+                assert_eq!(name, "assets/models/Missing.gltf")
+            }
+            other => panic!("expected a LoadError, got {:?}", other),
+        }
+        for &mesh in &[broken, panicked] {
+            match assets.state(mesh) {
+                Some(LoadState::Failed(ResourceError::LoadError(..))) => {}
+                other => panic!("expected a LoadError, got {:?}", other),
+            }
+        }
+        assert!(world.resources.meshes.is_empty());
+    }
+
+    #[test]
+    fn test_upload_budget() {
+        let mut renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let mut assets =
+            AssetServer::new(2).with_upload_budget(Duration::from_secs(0));
+        let meshes: Vec<_> = (0..3)
+            .map(|_| assets.load_mesh_with("quad", quad))
+            .collect();
+        while assets.uploads.len() < 3 {
+            thread::sleep(Duration::from_millis(1));
+            assets.receive_finished();
+        }
+
+        // a zero budget still uploads one asset a frame
+        assert_eq!(assets.update(&mut world, &mut renderer), 1);
+        assert_eq!(assets.pending(), 2);
+        assert_eq!(assets.update(&mut world, &mut renderer), 1);
+        assert_eq!(assets.update(&mut world, &mut renderer), 1);
+        assert_eq!(assets.update(&mut world, &mut renderer), 0);
+        for mesh in meshes {
+            let handle = assets.get(mesh).expect("mesh should have loaded");
+            assert!(world.resources.meshes.contains_key(&handle));
+        }
+    }
+
+    #[test]
+    fn test_model_uploads_in_parts() {
+        let mut renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let mut assets =
+            AssetServer::new(1).with_upload_budget(Duration::from_secs(0));
+        let model = assets.load_model("assets/models/DamagedHelmet.glb");
+        while assets.uploads.is_empty() {
+            thread::sleep(Duration::from_millis(1));
+            assets.receive_finished();
+        }
+
+        // five textures and a mesh, then the spawn
+        for uploaded in 1..7 {
+            assert_eq!(assets.update(&mut world, &mut renderer), 1);
+            assert!(assets.state(model).unwrap().is_loading());
+            let resources =
+                world.resources.textures.len() + world.resources.meshes.len();
+            assert_eq!(resources, uploaded);
+        }
+        assert_eq!(world.components.entities().count(), 0);
+        assert_eq!(assets.update(&mut world, &mut renderer), 1);
+        let root = assets.get(model).expect("model should have spawned");
+        assert!(world.components.entities().any(|e| e == root));
+        assert_eq!(assets.update(&mut world, &mut renderer), 0);
+    }
+}
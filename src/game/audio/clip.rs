@@ -0,0 +1,184 @@
+//! Decoded audio clips.
+
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+
+#[derive(Fail, Debug)]
+pub enum AudioError {
+    #[fail(display = "could not read audio clip: {}", _0)]
+    Io(std::io::Error),
+    #[fail(display = "could not decode wav clip: {}", _0)]
+    Wav(hound::Error),
+    #[fail(display = "could not decode ogg clip: {}", _0)]
+    Ogg(lewton::VorbisError),
+    #[fail(display = "unsupported audio clip format '{}'", _0)]
+    UnsupportedFormat(String),
+    #[fail(display = "could not open audio device: {}", _0)]
+    Device(String),
+}
+
+/// Sound decoded to floating point samples
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioClip {
+    /// Samples in [-1, 1], interleaved by channel
+    pub samples: Vec<f32>,
+    pub channels: u16,
+    /// Frames per second
+    pub sample_rate: u32,
+}
+
+impl AudioClip {
+    pub fn new(samples: Vec<f32>, channels: u16, sample_rate: u32) -> Self {
+        AudioClip {
+            samples,
+            channels: channels.max(1),
+            sample_rate,
+        }
+    }
+
+    /// Decodes a WAV file of integer or float samples
+    pub fn from_wav<R: Read>(reader: R) -> Result<Self, AudioError> {
+        let reader = hound::WavReader::new(reader).map_err(AudioError::Wav)?;
+        let spec = reader.spec();
+        let samples = match spec.sample_format {
+            hound::SampleFormat::Float => {
+                reader.into_samples::<f32>().collect::<Result<Vec<_>, _>>()
+            }
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .into_samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 * scale))
+                    .collect::<Result<Vec<_>, _>>()
+            }
+        }
+        .map_err(AudioError::Wav)?;
+        Ok(AudioClip::new(samples, spec.channels, spec.sample_rate))
+    }
+
+    /// Decodes an Ogg Vorbis file
+    pub fn from_ogg<R: Read + Seek>(reader: R) -> Result<Self, AudioError> {
+        let mut stream = lewton::inside_ogg::OggStreamReader::new(reader)
+            .map_err(AudioError::Ogg)?;
+        let mut samples = Vec::new();
+        while let Some(packet) =
+            stream.read_dec_packet_itl().map_err(AudioError::Ogg)?
+        {
+            samples.extend(packet.into_iter().map(|s| f32::from(s) / 32768.0));
+        }
+        Ok(AudioClip::new(
+            samples,
+            u16::from(stream.ident_hdr.audio_channels),
+            stream.ident_hdr.audio_sample_rate,
+        ))
+    }
+
+    /// Loads a .wav or .ogg file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, AudioError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase)
+            .unwrap_or_default();
+        let open =
+            || File::open(path).map(BufReader::new).map_err(AudioError::Io);
+        match extension.as_str() {
+            "wav" => AudioClip::from_wav(open()?),
+            "ogg" => AudioClip::from_ogg(open()?),
+            _ => Err(AudioError::UnsupportedFormat(extension)),
+        }
+    }
+
+    /// Number of samples in each channel
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    /// Length of the clip, in seconds
+    pub fn duration(&self) -> f32 {
+        self.frames() as f32 / self.sample_rate as f32
+    }
+
+    /// A channel's value part way through the clip, interpolating
+    /// between frames. Channels past the clip's last repeat it.
+    pub fn sample(&self, frame: f64, channel: usize) -> f32 {
+        let frames = self.frames();
+        if frames == 0 || frame < 0.0 {
+            return 0.0;
+        }
+        let channels = self.channels as usize;
+        let channel = channel.min(channels - 1);
+        let index = frame as usize;
+        if index >= frames {
+            return 0.0;
+        }
+        let a = self.samples[index * channels + channel];
+        let b = if index + 1 < frames {
+            self.samples[(index + 1) * channels + channel]
+        } else {
+            a
+        };
+        let t = (frame - index as f64) as f32;
+        a + (b - a) * t
+    }
+
+    /// Mean of the channels of a frame
+    pub fn sample_mono(&self, frame: f64) -> f32 {
+        let channels = self.channels as usize;
+        (0..channels).map(|c| self.sample(frame, c)).sum::<f32>()
+            / channels as f32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_decode_wav() {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut bytes = Cursor::new(Vec::new());
+        {
+            let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+            for &sample in &[0i16, 16384, -16384, 32767] {
+                writer.write_sample(sample).unwrap();
+            }
+            writer.finalize().unwrap();
+        }
+        bytes.set_position(0);
+        let clip = AudioClip::from_wav(bytes).unwrap();
+        assert_eq!(clip.channels, 2);
+        assert_eq!(clip.frames(), 2);
+        assert_eq!(clip.samples[..3], [0.0, 0.5, -0.5]);
+        assert_eq!(clip.duration(), 2.0 / 8000.0);
+        // half way between the frames of the left channel
+        assert_eq!(clip.sample(0.5, 0), -0.25);
+        assert_eq!(clip.sample_mono(0.0), 0.25);
+        assert_eq!(clip.sample(2.0, 0), 0.0);
+    }
+
+    #[test]
+    fn test_bad_clips() {
+        let garbage = Cursor::new(vec![1u8, 2, 3, 4]);
+        assert!(match AudioClip::from_ogg(garbage) {
+            Err(AudioError::Ogg(_)) => true,
+            _ => false,
+        });
+        assert!(match AudioClip::from_wav(Cursor::new(vec![0u8; 8])) {
+            Err(AudioError::Wav(_)) => true,
+            _ => false,
+        });
+        assert!(match AudioClip::load("music.mp3") {
+            Err(AudioError::UnsupportedFormat(ref e)) => e == "mp3",
+            _ => false,
+        });
+    }
+}
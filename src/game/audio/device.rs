@@ -0,0 +1,88 @@
+//! Playback of the mixer on an SDL audio device.
+
+use super::{AudioError, Mixer, OUTPUT_CHANNELS};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Frames per second requested from the device
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+struct MixerCallback {
+    mixer: Arc<Mutex<Mixer>>,
+}
+
+impl AudioCallback for MixerCallback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        match self.mixer.lock() {
+            Ok(mut mixer) => mixer.mix(out),
+            Err(_) => {
+                for sample in out.iter_mut() {
+                    *sample = 0.0;
+                }
+            }
+        }
+    }
+}
+
+/// An open playback device, which mixes on SDL's audio thread.
+/// Under SDL's dummy driver, chosen by setting `SDL_AUDIODRIVER=dummy`,
+/// the device mixes on time but plays nothing, so playback runs without
+/// sound hardware.
+pub struct AudioOutput {
+    device: AudioDevice<MixerCallback>,
+    driver: &'static str,
+}
+
+impl fmt::Debug for AudioOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AudioOutput")
+            .field("driver", &self.driver)
+            .finish()
+    }
+}
+
+impl AudioOutput {
+    /// Opens the default playback device and starts playing the mixer,
+    /// switching the mixer to the device's sample rate
+    pub fn open(
+        sdl: &sdl2::Sdl,
+        mixer: Arc<Mutex<Mixer>>,
+    ) -> Result<Self, AudioError> {
+        let audio = sdl.audio().map_err(AudioError::Device)?;
+        let desired = AudioSpecDesired {
+            freq: Some(DEFAULT_SAMPLE_RATE as i32),
+            channels: Some(OUTPUT_CHANNELS as u8),
+            samples: Some(1024),
+        };
+        let device = audio
+            .open_playback(None, &desired, |spec| {
+                mixer
+                    .lock()
+                    .expect("poisoned Mutex")
+                    .set_sample_rate(spec.freq as u32);
+                MixerCallback { mixer }
+            })
+            .map_err(AudioError::Device)?;
+        device.resume();
+        Ok(AudioOutput {
+            device,
+            driver: audio.current_audio_driver(),
+        })
+    }
+
+    /// Name of the SDL audio driver playing the device
+    pub fn driver(&self) -> &'static str {
+        self.driver
+    }
+
+    pub fn pause(&self) {
+        self.device.pause();
+    }
+
+    pub fn resume(&self) {
+        self.device.resume();
+    }
+}
@@ -0,0 +1,257 @@
+//! Software mixing of playing clips into stereo output.
+
+use super::AudioClip;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Channels of mixed output, interleaved left then right
+pub const OUTPUT_CHANNELS: usize = 2;
+
+/// How a voice plays, set by the game on each update
+#[derive(Debug, Clone)]
+pub struct VoiceSettings {
+    pub clip: Arc<AudioClip>,
+    /// Left and right volume
+    pub gains: [f32; 2],
+    /// Playback rate, where 2 plays an octave higher
+    pub pitch: f32,
+    pub looping: bool,
+    /// Whether to mix the clip's channels down before applying the
+    /// gains, as positioned sounds do
+    pub mono: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Voice {
+    settings: VoiceSettings,
+    /// Gains reached at the end of the last mix, ramped towards the
+    /// settings' gains to avoid clicks
+    gains: [f32; 2],
+    /// Frame of the clip being played
+    position: f64,
+    finished: bool,
+}
+
+/// Plays clips on voices identified by the game, such as by entity
+#[derive(Debug)]
+pub struct Mixer {
+    sample_rate: u32,
+    voices: HashMap<usize, Voice>,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32) -> Self {
+        Mixer {
+            sample_rate,
+            voices: HashMap::new(),
+        }
+    }
+
+    /// Output frames per second
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Starts a voice, or updates a playing one. Changing a voice's clip
+    /// plays the new clip from the start.
+    pub fn set_voice(&mut self, id: usize, settings: VoiceSettings) {
+        match self.voices.get_mut(&id) {
+            Some(ref mut voice)
+                if Arc::ptr_eq(&voice.settings.clip, &settings.clip) =>
+            {
+                voice.settings = settings;
+            }
+            _ => {
+                let gains = settings.gains;
+                self.voices.insert(
+                    id,
+                    Voice {
+                        settings,
+                        gains,
+                        position: 0.0,
+                        finished: false,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Plays a voice's clip again from the start
+    pub fn restart_voice(&mut self, id: usize) {
+        if let Some(voice) = self.voices.get_mut(&id) {
+            voice.position = 0.0;
+            voice.finished = false;
+        }
+    }
+
+    pub fn stop_voice(&mut self, id: usize) {
+        self.voices.remove(&id);
+    }
+
+    /// Stops the voices whose ids fail the predicate
+    pub fn retain_voices<F: FnMut(usize) -> bool>(&mut self, mut keep: F) {
+        self.voices.retain(|&id, _| keep(id));
+    }
+
+    /// Whether a voice is playing, and hasn't reached the end of a clip
+    /// that doesn't loop
+    pub fn is_playing(&self, id: usize) -> bool {
+        self.voices.get(&id).map_or(false, |v| !v.finished)
+    }
+
+    /// Seconds into its clip a voice has played
+    pub fn voice_time(&self, id: usize) -> Option<f32> {
+        self.voices.get(&id).map(|v| {
+            (v.position / f64::from(v.settings.clip.sample_rate)) as f32
+        })
+    }
+
+    pub fn voice_count(&self) -> usize {
+        self.voices.len()
+    }
+
+    /// Mixes the playing voices into interleaved stereo samples,
+    /// overwriting `out`, and advances them
+    pub fn mix(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = 0.0;
+        }
+        let frames = out.len() / OUTPUT_CHANNELS;
+        if frames == 0 || self.sample_rate == 0 {
+            return;
+        }
+        for voice in self.voices.values_mut() {
+            if voice.finished {
+                continue;
+            }
+            let VoiceSettings {
+                ref clip,
+                gains: target,
+                pitch,
+                looping,
+                mono,
+            } = voice.settings;
+            let length = clip.frames() as f64;
+            if length == 0.0 {
+                voice.finished = true;
+                continue;
+            }
+            let step = f64::from(clip.sample_rate)
+                / f64::from(self.sample_rate)
+                * f64::from(pitch.max(0.0));
+            let start = voice.gains;
+            for (i, frame) in out.chunks_mut(OUTPUT_CHANNELS).enumerate() {
+                let t = (i + 1) as f32 / frames as f32;
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    let gain =
+                        start[channel] + (target[channel] - start[channel]) * t;
+                    let value = if mono {
+                        clip.sample_mono(voice.position)
+                    } else {
+                        clip.sample(voice.position, channel)
+                    };
+                    *sample += value * gain;
+                }
+                voice.position += step;
+                if voice.position >= length {
+                    if looping {
+                        voice.position %= length;
+                    } else {
+                        voice.finished = true;
+                        break;
+                    }
+                }
+            }
+            voice.gains = target;
+        }
+        for sample in out.iter_mut() {
+            *sample = sample.max(-1.0).min(1.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings(clip: &Arc<AudioClip>, gains: [f32; 2]) -> VoiceSettings {
+        VoiceSettings {
+            clip: clip.clone(),
+            gains,
+            pitch: 1.0,
+            looping: false,
+            mono: true,
+        }
+    }
+
+    #[test]
+    fn test_mix() {
+        let clip = Arc::new(AudioClip::new(vec![0.5; 4], 1, 100));
+        let mut mixer = Mixer::new(100);
+        mixer.set_voice(0, settings(&clip, [1.0, 0.5]));
+        mixer.set_voice(1, settings(&clip, [0.5, 0.0]));
+        let mut out = [0.0; 4];
+        mixer.mix(&mut out);
+        assert_eq!(out, [0.75, 0.25, 0.75, 0.25]);
+        assert_eq!(mixer.voice_time(0), Some(0.02));
+
+        // plays out the end of the clip, then stops
+        let mut out = [0.0; 8];
+        mixer.mix(&mut out);
+        assert_eq!(out, [0.75, 0.25, 0.75, 0.25, 0.0, 0.0, 0.0, 0.0]);
+        assert!(!mixer.is_playing(0));
+        mixer.restart_voice(0);
+        assert!(mixer.is_playing(0));
+    }
+
+    #[test]
+    fn test_gain_ramps_and_clipping() {
+        let clip = Arc::new(AudioClip::new(vec![1.0; 100], 1, 100));
+        let mut mixer = Mixer::new(100);
+        mixer.set_voice(0, settings(&clip, [0.0, 0.0]));
+        mixer.set_voice(0, settings(&clip, [1.0, 4.0]));
+        let mut out = [0.0; 8];
+        mixer.mix(&mut out);
+        assert_eq!(out, [0.25, 1.0, 0.5, 1.0, 0.75, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_pitch_and_looping() {
+        let clip = Arc::new(AudioClip::new(vec![0.0, 1.0], 1, 100));
+        let mut mixer = Mixer::new(200);
+        let mut looping = settings(&clip, [1.0, 1.0]);
+        looping.looping = true;
+        mixer.set_voice(0, looping);
+        let mut out = [0.0; 10];
+        mixer.mix(&mut out);
+        // resampled to twice the rate, interpolating between frames and
+        // holding the last one until the loop wraps
+        let left: Vec<f32> = out.iter().step_by(2).cloned().collect();
+        assert_eq!(left, [0.0, 0.5, 1.0, 1.0, 0.0]);
+        assert!(mixer.is_playing(0));
+
+        mixer.retain_voices(|id| id != 0);
+        assert_eq!(mixer.voice_count(), 0);
+    }
+
+    #[test]
+    fn test_stereo_clips() {
+        let clip = Arc::new(AudioClip::new(vec![0.2, 0.6], 2, 100));
+        let mut mixer = Mixer::new(100);
+        let mut stereo = settings(&clip, [1.0, 1.0]);
+        stereo.mono = false;
+        mixer.set_voice(0, stereo);
+        let mut out = [0.0; 2];
+        mixer.mix(&mut out);
+        assert_eq!(out, [0.2, 0.6]);
+
+        mixer.set_voice(1, settings(&clip, [1.0, 1.0]));
+        mixer.stop_voice(0);
+        mixer.mix(&mut out);
+        assert!((out[0] - 0.4).abs() < 1e-6 && (out[1] - 0.4).abs() < 1e-6);
+    }
+}
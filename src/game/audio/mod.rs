@@ -0,0 +1,417 @@
+//! Sound playback positioned by entity transforms.
+//!
+//! Entities with an `AudioSource` play a clip. Spatial sources fade with
+//! distance from the listener, pan between the ears, and shift in pitch
+//! as they and the listener move, all computed from their
+//! `TransformComponent`s. The listener is the entity with an
+//! `AudioListener`, usually a camera, or failing that the active camera
+//! drawn last.
+//!
+//! `AudioSystem::update` turns the sources into voices of a software
+//! `Mixer`, which an `AudioOutput` plays on an SDL audio device. The
+//! mixer can also be run by hand, without a device.
+
+mod clip;
+mod device;
+mod mixer;
+mod spatial;
+
+pub use self::clip::*;
+pub use self::device::*;
+pub use self::mixer::*;
+pub use self::spatial::*;
+
+use super::{animation::world_pose, component::*, TryGetComponent};
+use crate::math::*;
+use cgmath::*;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Plays a clip from its entity
+#[derive(Debug, Clone)]
+pub struct AudioSource {
+    pub clip: Arc<AudioClip>,
+    pub volume: f32,
+    pub looping: bool,
+    /// Whether the sound is positioned at the entity, or plays at equal
+    /// volume in both ears, as music and interface sounds do
+    pub spatial: bool,
+    pub attenuation: Attenuation,
+    /// Playback rate, before Doppler shift
+    pub pitch: f32,
+    /// Whether the clip is playing. Cleared when a clip that doesn't loop
+    /// finishes.
+    pub playing: bool,
+    restart: bool,
+    last_position: Option<Vec3>,
+}
+
+impl Component for AudioSource {}
+
+impl AudioSource {
+    /// A spatial source, which starts playing straight away
+    pub fn new(clip: Arc<AudioClip>) -> Self {
+        AudioSource {
+            clip,
+            volume: 1.0,
+            looping: false,
+            spatial: true,
+            attenuation: Attenuation::default(),
+            pitch: 1.0,
+            playing: true,
+            restart: false,
+            last_position: None,
+        }
+    }
+
+    pub fn with_volume(mut self, volume: f32) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    pub fn with_spatial(mut self, spatial: bool) -> Self {
+        self.spatial = spatial;
+        self
+    }
+
+    pub fn with_attenuation(mut self, attenuation: Attenuation) -> Self {
+        self.attenuation = attenuation;
+        self
+    }
+
+    /// Plays the clip from the start
+    pub fn play(&mut self) {
+        self.playing = true;
+        self.restart = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+}
+
+/// Hears sounds from its entity, usually a camera
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioListener {
+    /// Volume of everything the listener hears
+    pub volume: f32,
+}
+
+impl Component for AudioListener {}
+
+impl Default for AudioListener {
+    fn default() -> Self {
+        AudioListener { volume: 1.0 }
+    }
+}
+
+/// Feeds the world's audio sources to a mixer
+pub struct AudioSystem {
+    mixer: Arc<Mutex<Mixer>>,
+    output: Option<AudioOutput>,
+    listener: Listener,
+    volume: f32,
+    last_listener_position: Option<Vec3>,
+}
+
+impl fmt::Debug for AudioSystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AudioSystem")
+            .field("output", &self.output)
+            .field("listener", &self.listener)
+            .finish()
+    }
+}
+
+impl Default for AudioSystem {
+    fn default() -> Self {
+        AudioSystem::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+impl AudioSystem {
+    /// Creates a system mixing at `sample_rate`, without an output device
+    pub fn new(sample_rate: u32) -> Self {
+        AudioSystem {
+            mixer: Arc::new(Mutex::new(Mixer::new(sample_rate))),
+            output: None,
+            listener: Listener::default(),
+            volume: 1.0,
+            last_listener_position: None,
+        }
+    }
+
+    /// Starts playing the mixer on the default SDL audio device
+    pub fn open_output(&mut self, sdl: &sdl2::Sdl) -> Result<(), AudioError> {
+        self.output = Some(AudioOutput::open(sdl, self.mixer.clone())?);
+        Ok(())
+    }
+
+    pub fn output(&self) -> Option<&AudioOutput> {
+        self.output.as_ref()
+    }
+
+    /// The mixer, shared with the output device's audio thread
+    pub fn mixer(&self) -> &Arc<Mutex<Mixer>> {
+        &self.mixer
+    }
+
+    /// The listener as of the last update
+    pub fn listener(&self) -> &Listener {
+        &self.listener
+    }
+
+    /// Finds the listener's entity: the one with an AudioListener, or
+    /// else the active camera with the highest priority
+    fn listener_entity<CS: TryGetComponent>(
+        components: &ComponentManager<CS>,
+    ) -> Option<(Entity, f32)> {
+        if let Some(listeners) = components.get_components::<AudioListener>() {
+            let listeners = listeners.read().expect("poisoned RwLock");
+            let found = components.entities().find_map(|entity| {
+                listeners[*entity].as_ref().map(|l| (entity, l.volume))
+            });
+            if found.is_some() {
+                return found;
+            }
+        }
+        let cameras = components.get_components::<CameraComponent>()?;
+        let cameras = cameras.read().expect("poisoned RwLock");
+        components
+            .entities()
+            .filter_map(|entity| match cameras[*entity] {
+                Some(ref camera) if camera.active => {
+                    Some((entity, camera.priority))
+                }
+                _ => None,
+            })
+            .max_by_key(|&(_, priority)| priority)
+            .map(|(entity, _)| (entity, 1.0))
+    }
+
+    /// Moves the listener and updates a voice for every playing source.
+    /// Velocities for the Doppler shift come from how far transforms
+    /// moved over `dt` seconds.
+    pub fn update<CS: TryGetComponent>(
+        &mut self,
+        components: &ComponentManager<CS>,
+        dt: f32,
+    ) {
+        let (sources, transforms) = match (
+            components.get_components::<AudioSource>(),
+            components.get_components::<TransformComponent>(),
+        ) {
+            (Some(sources), Some(transforms)) => (sources, transforms),
+            _ => return,
+        };
+        let listener = Self::listener_entity(components);
+        let mut sources = sources.write().expect("poisoned RwLock");
+        let transforms = transforms.read().expect("poisoned RwLock");
+        let velocity = |position: Vec3, last: Option<Vec3>| match last {
+            Some(last) if dt > 0.0 => (position - last) / dt,
+            _ => Vec3::zero(),
+        };
+
+        let pose = listener.and_then(|(e, _)| world_pose(&transforms, e));
+        self.volume = listener.map_or(1.0, |(_, volume)| volume);
+        self.listener = match pose {
+            Some(pose) => Listener {
+                position: pose.disp,
+                rotation: pose.rot,
+                velocity: velocity(pose.disp, self.last_listener_position),
+            },
+            None => Listener::default(),
+        };
+        self.last_listener_position = pose.map(|p| p.disp);
+
+        let mut mixer = self.mixer.lock().expect("poisoned Mutex");
+        let mut live = HashSet::new();
+        for entity in components.entities() {
+            let source = match sources[*entity] {
+                Some(ref mut source) => source,
+                None => continue,
+            };
+            let id = entity.index();
+            let position = world_pose(&transforms, entity)
+                .map_or_else(Vec3::zero, |p| p.disp);
+            let source_velocity = velocity(position, source.last_position);
+            source.last_position = Some(position);
+            if !source.playing {
+                continue;
+            }
+            // a clip that doesn't loop has played out
+            if mixer.voice_time(id).is_some()
+                && !mixer.is_playing(id)
+                && !source.restart
+            {
+                source.playing = false;
+                continue;
+            }
+            let volume = source.volume * self.volume;
+            let (gains, pitch) = if source.spatial {
+                let distance = (position - self.listener.position).magnitude();
+                let gain = volume * source.attenuation.gain(distance);
+                let [left, right] = self.listener.pan(position);
+                let doppler = self.listener.doppler(position, source_velocity);
+                ([left * gain, right * gain], source.pitch * doppler)
+            } else {
+                ([volume, volume], source.pitch)
+            };
+            mixer.set_voice(
+                id,
+                VoiceSettings {
+                    clip: source.clip.clone(),
+                    gains,
+                    pitch,
+                    looping: source.looping,
+                    mono: source.spatial,
+                },
+            );
+            if source.restart {
+                mixer.restart_voice(id);
+                source.restart = false;
+            }
+            live.insert(id);
+        }
+        mixer.retain_voices(|id| live.contains(&id));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::test_util::*;
+
+    fn spawn(world: &mut MockWorld, position: Vec3) -> Entity {
+        let entity = world.components.alloc_entity();
+        world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(
+                *entity,
+                TransformComponent {
+                    parent: None,
+                    transform: Decomposed {
+                        scale: 1.0,
+                        rot: Quaternion::one(),
+                        disp: position,
+                    },
+                },
+            );
+        entity
+    }
+
+    fn add<C: Component + Send + Sync>(
+        world: &MockWorld,
+        entity: Entity,
+        c: C,
+    ) {
+        world
+            .components
+            .get_components::<C>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(*entity, c);
+    }
+
+    fn mix(audio: &AudioSystem, frames: usize) -> Vec<f32> {
+        let mut out = vec![0.0; frames * OUTPUT_CHANNELS];
+        audio.mixer().lock().unwrap().mix(&mut out);
+        out
+    }
+
+    #[test]
+    fn test_spatial_sources() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let clip = Arc::new(AudioClip::new(vec![0.5; 100], 1, 100));
+        let listener = spawn(&mut world, Vec3::zero());
+        add(&world, listener, AudioListener::default());
+        let right = spawn(&mut world, vec3(2.0, 0.0, 0.0));
+        add(&world, right, AudioSource::new(clip.clone()));
+
+        let mut audio = AudioSystem::new(100);
+        audio.update(&world.components, 1.0 / 60.0);
+        let out = mix(&audio, 1);
+        // half volume at twice the minimum distance, all in the right ear
+        assert!(out[0].abs() < 1e-6);
+        assert!((out[1] - 0.25).abs() < 1e-6);
+
+        // music plays in both ears regardless of position
+        let music = spawn(&mut world, vec3(-50.0, 0.0, 0.0));
+        add(
+            &world,
+            music,
+            AudioSource::new(clip.clone())
+                .with_spatial(false)
+                .with_volume(0.5),
+        );
+        audio.update(&world.components, 1.0 / 60.0);
+        let out = mix(&audio, 1);
+        assert!((out[0] - 0.25).abs() < 1e-6);
+        assert!((out[1] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_doppler_from_movement() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let clip = Arc::new(AudioClip::new(vec![0.5; 1000], 1, 100));
+        let listener = spawn(&mut world, Vec3::zero());
+        add(&world, listener, AudioListener::default());
+        let car = spawn(&mut world, vec3(0.0, 0.0, -100.0));
+        add(&world, car, AudioSource::new(clip).with_looping(true));
+
+        let mut audio = AudioSystem::new(100);
+        audio.update(&world.components, 0.1);
+        mix(&audio, 10);
+        let before = audio.mixer().lock().unwrap().voice_time(car.index());
+        assert_eq!(before, Some(0.1));
+
+        // approaching at a tenth of the speed of sound
+        {
+            let transforms = world
+                .components
+                .get_components::<TransformComponent>()
+                .unwrap();
+            let mut transforms = transforms.write().unwrap();
+            transforms[*car].as_mut().unwrap().transform.disp.z += 3.43;
+        }
+        audio.update(&world.components, 0.1);
+        mix(&audio, 10);
+        let after = audio.mixer().lock().unwrap().voice_time(car.index());
+        let pitched = after.unwrap() - 0.1;
+        assert!((pitched - 0.1 / 0.9).abs() < 1e-4, "{}", pitched);
+    }
+
+    #[test]
+    fn test_finished_sources_stop() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let clip = Arc::new(AudioClip::new(vec![0.5; 2], 1, 100));
+        let beep = spawn(&mut world, Vec3::zero());
+        add(&world, beep, AudioSource::new(clip).with_spatial(false));
+
+        let mut audio = AudioSystem::new(100);
+        audio.update(&world.components, 0.0);
+        mix(&audio, 4);
+        audio.update(&world.components, 0.0);
+        let sources = world.components.get_components::<AudioSource>().unwrap();
+        assert!(!sources.read().unwrap()[*beep].as_ref().unwrap().playing);
+        assert_eq!(audio.mixer().lock().unwrap().voice_count(), 0);
+
+        sources.write().unwrap()[*beep].as_mut().unwrap().play();
+        audio.update(&world.components, 0.0);
+        assert!(audio.mixer().lock().unwrap().is_playing(beep.index()));
+    }
+}
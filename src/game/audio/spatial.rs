@@ -0,0 +1,140 @@
+//! Distance attenuation, stereo panning and Doppler shift of positioned
+//! sounds.
+
+use crate::math::*;
+use cgmath::*;
+
+/// Speed of sound in air, in units per second, taking units as meters
+pub const SPEED_OF_SOUND: f32 = 343.0;
+
+/// How a sound fades with distance from the listener
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attenuation {
+    /// Distance within which the sound plays at full volume
+    pub min_distance: f32,
+    /// Distance beyond which the sound stops fading
+    pub max_distance: f32,
+    /// How quickly the sound fades, where 1 halves the volume at double
+    /// the minimum distance
+    pub rolloff: f32,
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Attenuation {
+            min_distance: 1.0,
+            max_distance: 100.0,
+            rolloff: 1.0,
+        }
+    }
+}
+
+impl Attenuation {
+    /// Volume of the sound at a distance, falling off with the inverse of
+    /// the distance between the minimum and maximum distances
+    pub fn gain(&self, distance: f32) -> f32 {
+        let min = self.min_distance.max(1e-3);
+        let distance = distance.max(min).min(self.max_distance.max(min));
+        min / (min + self.rolloff * (distance - min))
+    }
+}
+
+/// Where sounds are heard from, looking down its -Z axis
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Listener {
+    pub position: Vec3,
+    pub rotation: Quaternion<f32>,
+    /// Units per second
+    pub velocity: Vec3,
+}
+
+impl Default for Listener {
+    fn default() -> Self {
+        Listener {
+            position: Vec3::zero(),
+            rotation: Quaternion::one(),
+            velocity: Vec3::zero(),
+        }
+    }
+}
+
+impl Listener {
+    /// Left and right gains of a sound at `position`, panned with equal
+    /// power so it keeps its loudness as it moves across the listener
+    pub fn pan(&self, position: Vec3) -> [f32; 2] {
+        let local = self
+            .rotation
+            .invert()
+            .rotate_vector(position - self.position);
+        let distance = local.magnitude();
+        let side = if distance > 1e-5 {
+            local.x / distance
+        } else {
+            0.0
+        };
+        let angle = (side + 1.0) * std::f32::consts::FRAC_PI_4;
+        [angle.cos(), angle.sin()]
+    }
+
+    /// Ratio the pitch of a sound is shifted by as it and the listener move
+    /// towards or away from each other
+    pub fn doppler(&self, position: Vec3, velocity: Vec3) -> f32 {
+        let to_source = position - self.position;
+        if to_source.magnitude2() < 1e-10 {
+            return 1.0;
+        }
+        let to_source = to_source.normalize();
+        // speeds along the line between them, kept below the speed of sound
+        let limit = SPEED_OF_SOUND * 0.9;
+        let listener = self.velocity.dot(to_source).max(-limit).min(limit);
+        let source = velocity.dot(to_source).max(-limit).min(limit);
+        (SPEED_OF_SOUND + listener) / (SPEED_OF_SOUND + source)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_attenuation() {
+        let attenuation = Attenuation::default();
+        assert_eq!(attenuation.gain(0.5), 1.0);
+        assert_eq!(attenuation.gain(2.0), 0.5);
+        assert_eq!(attenuation.gain(1000.0), attenuation.gain(100.0));
+    }
+
+    #[test]
+    fn test_pan() {
+        let listener = Listener::default();
+        let [left, right] = listener.pan(vec3(5.0, 0.0, 0.0));
+        assert!(left.abs() < 1e-6 && (right - 1.0).abs() < 1e-6);
+        let [left, right] = listener.pan(vec3(0.0, 0.0, -5.0));
+        assert!((left - right).abs() < 1e-6);
+        assert!((left * left + right * right - 1.0).abs() < 1e-5);
+
+        // turned to face +X, the sound is ahead and the left is at -Z
+        let turned = Listener {
+            rotation: Quaternion::from_angle_y(Deg(-90.0)),
+            ..listener
+        };
+        let [left, right] = turned.pan(vec3(0.0, 0.0, -5.0));
+        assert!((left - 1.0).abs() < 1e-5 && right.abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_doppler() {
+        let listener = Listener::default();
+        let position = vec3(0.0, 0.0, -10.0);
+        assert_eq!(listener.doppler(position, Vec3::zero()), 1.0);
+        let approaching = listener.doppler(position, vec3(0.0, 0.0, 34.3));
+        assert!((approaching - 1.0 / 0.9).abs() < 1e-5);
+        let receding = listener.doppler(position, vec3(0.0, 0.0, -34.3));
+        assert!(receding < 1.0);
+        let chasing = Listener {
+            velocity: vec3(0.0, 0.0, -34.3),
+            ..listener
+        };
+        assert!((chasing.doppler(position, Vec3::zero()) - 1.1).abs() < 1e-5);
+    }
+}
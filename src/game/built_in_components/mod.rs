@@ -1,11 +1,16 @@
 use super::component::*;
 use super::resource::{MeshHandle, ResourceManager, TextureHandle};
 use crate::math::*;
-use crate::renderer::{material::Material, mesh::RenderMesh};
+use crate::renderer::{
+    camera::{ClearSettings, Projection, Viewport},
+    material::Material,
+    mesh::RenderMesh,
+};
 use cgmath::*;
 use std::fmt::{self, Debug};
 use std::sync::Arc;
 
+/// An entity's transform, relative to its parent
 #[derive(Debug, Clone)]
 pub struct TransformComponent {
     pub parent: Option<Entity>,
@@ -16,6 +21,32 @@ impl Component for TransformComponent {
     // const MASK: ComponentMask = ComponentMask::TRANSFORM;
 }
 
+impl TransformComponent {
+    /// The local transform as a matrix, relative to the parent
+    pub fn matrix(&self) -> Mat4 {
+        self.transform.into()
+    }
+}
+
+/// Composes an entity's transform with those of its parents, giving the
+/// entity's model-to-world matrix. Returns None if the entity has no
+/// TransformComponent.
+pub fn world_matrix(
+    transforms: &ComponentList<TransformComponent>,
+    entity: Entity,
+) -> Option<Mat4> {
+    let mut component = transforms[*entity].as_ref()?;
+    let mut matrix = component.matrix();
+    while let Some(parent) = component.parent {
+        component = match transforms[*parent] {
+            Some(ref c) => c,
+            None => break,
+        };
+        matrix = component.matrix() * matrix;
+    }
+    Some(matrix)
+}
+
 impl Default for TransformComponent {
     fn default() -> Self {
         TransformComponent {
@@ -44,3 +75,30 @@ pub struct MeshComponent {
 impl Component for MeshComponent {
     // const MASK: ComponentMask = ComponentMask::MESH;
 }
+
+/// A camera drawn by the renderer. Views the scene down the -Z axis of
+/// its entity's TransformComponent.
+#[derive(Debug, Clone)]
+pub struct CameraComponent {
+    pub projection: Projection,
+    pub viewport: Viewport,
+    pub clear: ClearSettings,
+    /// Cameras are drawn in ascending priority, so higher priority
+    /// cameras draw over lower ones
+    pub priority: i32,
+    pub active: bool,
+}
+
+impl Component for CameraComponent {}
+
+impl Default for CameraComponent {
+    fn default() -> Self {
+        CameraComponent {
+            projection: Projection::default(),
+            viewport: Viewport::full(),
+            clear: ClearSettings::default(),
+            priority: 0,
+            active: true,
+        }
+    }
+}
@@ -1,19 +1,243 @@
 use super::*;
 use crate::math::*;
 use cgmath::*;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Per-frame input for camera controllers
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CameraInput {
+    /// Movement axes, each in [-1, 1]
+    pub movement: Vec2,
+    /// Mouse movement this frame in pixels, with up as positive y.
+    /// Zero unless the look button is held.
+    pub look: Vec2,
+    /// Zoom axis in [-1, 1], positive zooming in
+    pub zoom: f32,
+    /// Whether jump was pressed this frame
+    pub jump: bool,
+}
+
+impl CameraInput {
+    /// Reads the "move_x", "move_y", "look_x", "look_y" and "zoom" axes,
+    /// and the "mouselook" and "jump" actions
+    pub fn from_input_map(input: &InputMap) -> Self {
+        let look = if input.action_pressed("mouselook") {
+            input.axis2("look_x", "look_y")
+        } else {
+            Vec2::zero()
+        };
+        CameraInput {
+            movement: input.axis2("move_x", "move_y"),
+            look,
+            zoom: input.axis("zoom"),
+            jump: input.action_just_pressed("jump"),
+        }
+    }
+}
+
+impl Default for CameraInput {
+    fn default() -> Self {
+        CameraInput {
+            movement: Vec2::zero(),
+            look: Vec2::zero(),
+            zoom: 0.0,
+            jump: false,
+        }
+    }
+}
+
+/// Common interface for camera controllers.
+/// Look input is measured in pixels and applied without scaling by the
+/// frame time, so sensitivity does not depend on frame rate. Held inputs
+/// and damping are scaled by `dt`.
+pub trait CameraController: fmt::Debug + Send + Sync {
+    /// Advances the controller by `dt` seconds
+    fn update(&mut self, input: &CameraInput, dt: f32);
+
+    /// The camera's placement in the world, the inverse of its view
+    /// transform. Used to drive a camera entity's TransformComponent.
+    fn world_transform(&self) -> Decomposed<Vec3, Quaternion<f32>>;
+
+    /// The view matrix, transforming world space to eye space
+    fn view(&self) -> Mat4 {
+        let transform = self.world_transform();
+        Mat4::from(transform.inverse_transform().unwrap_or(transform))
+    }
+
+    fn position(&self) -> Point3<f32> {
+        Point3::from_vec(self.world_transform().disp)
+    }
+
+    /// Direction the camera looks in
+    fn front(&self) -> Vec3 {
+        self.world_transform().rot * -Vec3::unit_z()
+    }
+
+    /// Takes the tunables of the controller's section of the config
+    fn apply_config(&mut self, _config: &CameraConfig) {}
+}
+
+/// Fraction of the remaining distance a damped value covers in `dt`
+/// seconds. Exponential, so the result does not depend on frame rate.
+/// A damping of zero or less disables smoothing.
+pub fn damping_factor(damping: f32, dt: f32) -> f32 {
+    if damping <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-damping * dt).exp()
+    }
+}
+
+#[derive(Fail, Debug)]
+pub enum CameraConfigError {
+    #[fail(display = "could not parse camera config: {}", _0)]
+    Parse(toml::de::Error),
+    #[fail(display = "could not read camera config: {}", _0)]
+    Io(std::io::Error),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FpsCameraConfig {
+    /// Movement speed, in units per second
+    pub speed: f32,
+    /// Look rotation, in radians per pixel
+    pub sensitivity: f32,
+    /// How quickly velocity catches up to the movement input
+    pub damping: f32,
+}
+
+impl Default for FpsCameraConfig {
+    fn default() -> Self {
+        FpsCameraConfig {
+            speed: 9.0,
+            sensitivity: 0.002,
+            damping: 15.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OrbitCameraConfig {
+    /// Look rotation, in radians per pixel
+    pub sensitivity: f32,
+    /// Rotation from the movement keys, in radians per second
+    pub key_speed: f32,
+    /// Zoom rate, as a fraction of the distance per second
+    pub zoom_speed: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Pitch limits, in degrees
+    pub min_pitch: f32,
+    pub max_pitch: f32,
+    pub damping: f32,
+}
+
+impl Default for OrbitCameraConfig {
+    fn default() -> Self {
+        OrbitCameraConfig {
+            sensitivity: 0.005,
+            key_speed: 1.5,
+            zoom_speed: 1.5,
+            min_distance: 0.5,
+            max_distance: 100.0,
+            min_pitch: -85.0,
+            max_pitch: 85.0,
+            damping: 12.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArcballCameraConfig {
+    /// Rotation, in radians per pixel dragged
+    pub sensitivity: f32,
+    /// Zoom rate, as a fraction of the distance per second
+    pub zoom_speed: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub damping: f32,
+}
+
+impl Default for ArcballCameraConfig {
+    fn default() -> Self {
+        ArcballCameraConfig {
+            sensitivity: 0.005,
+            zoom_speed: 1.5,
+            min_distance: 0.5,
+            max_distance: 100.0,
+            damping: 12.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FollowCameraConfig {
+    /// Distance behind the target
+    pub distance: f32,
+    /// Height of the camera above the target
+    pub height: f32,
+    /// Height above the target the camera looks at
+    pub look_height: f32,
+    /// Look rotation around the target, in radians per pixel
+    pub sensitivity: f32,
+    pub damping: f32,
+}
+
+impl Default for FollowCameraConfig {
+    fn default() -> Self {
+        FollowCameraConfig {
+            distance: 6.0,
+            height: 2.0,
+            look_height: 1.0,
+            sensitivity: 0.005,
+            damping: 6.0,
+        }
+    }
+}
+
+/// Tunables for every camera controller, loaded from a TOML file with
+/// `[fps]`, `[orbit]`, `[arcball]` and `[follow]` tables. Missing values
+/// use the defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    pub fps: FpsCameraConfig,
+    pub orbit: OrbitCameraConfig,
+    pub arcball: ArcballCameraConfig,
+    pub follow: FollowCameraConfig,
+}
+
+impl CameraConfig {
+    pub fn from_toml_str(source: &str) -> Result<Self, CameraConfigError> {
+        toml::from_str(source).map_err(CameraConfigError::Parse)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CameraConfigError> {
+        let source = fs::read_to_string(path).map_err(CameraConfigError::Io)?;
+        CameraConfig::from_toml_str(&source)
+    }
+}
+
 ///
-/// Constructs the camera view matrix for the scene.
+/// Free-fly camera, moved with the movement axes and turned with
+/// mouselook. Constructs the camera view matrix for the scene.
 #[derive(Debug)]
 pub struct FpsCameraComponent {
+    pub config: FpsCameraConfig,
     pos: Point3<f32>,
+    velocity: Vec3,
     front: Vec3,
     up: Vec3,
     right: Vec3,
     world_up: Vec3,
     yaw: Rad<f32>,
     pitch: Rad<f32>,
-    speed: f32,
-    mouse_sensitivity: f32,
     transform: Mat4,
 }
 
@@ -28,13 +252,13 @@ impl FpsCameraComponent {
         let world_up = up;
         let zero = vec3(0.0, 0.0, 0.0);
         let mut cmp = FpsCameraComponent {
+            config: FpsCameraConfig::default(),
             pos: position,
+            velocity: zero,
             up,
             world_up,
             yaw,
             pitch,
-            speed: 9.0,
-            mouse_sensitivity: 0.1,
             // other fields given default values
             transform: Mat4::identity(),
             front: zero,
@@ -47,6 +271,11 @@ impl FpsCameraComponent {
         cmp
     }
 
+    pub fn with_config(mut self, config: FpsCameraConfig) -> Self {
+        self.config = config;
+        self
+    }
+
     /// Set front, up, and right vectors to appropriate values
     fn update_vectors(&mut self) {
         let Rad(yaw) = self.yaw;
@@ -70,25 +299,50 @@ impl FpsCameraComponent {
         &self.transform
     }
 
-    pub fn input_move(
-        &mut self,
-        wasd_axis: Vec2,
-        dt: f64,
-        _input: &InputSources,
-    ) {
-        use cgmath::prelude::*;
-        let move_direction =
-            (wasd_axis.x * self.right + wasd_axis.y * self.front).normalize();
-        let delta_position = move_direction * self.speed * dt as f32;
-        self.pos += delta_position;
-        self.update_vectors();
+    pub fn position(&self) -> Point3<f32> {
+        self.pos
+    }
+
+    /// Moves the camera without changing where it looks
+    pub fn set_position(&mut self, position: Point3<f32>) {
+        self.pos = position;
         self.build_transform();
     }
 
-    pub fn mouselook(&mut self, mouse_offset: Vec2, dt: f64) {
-        use cgmath::*;
-        let mut mouse_offset = mouse_offset;
-        mouse_offset *= self.mouse_sensitivity * dt as f32;
+    pub fn front(&self) -> Vec3 {
+        self.front
+    }
+
+    pub fn world_transform(&self) -> Decomposed<Vec3, Quaternion<f32>> {
+        let rotation = Mat3::from_cols(self.right, self.up, -self.front);
+        Decomposed {
+            scale: 1.0,
+            rot: rotation.into(),
+            disp: self.pos.to_vec(),
+        }
+    }
+
+    /// Accelerates towards the movement input, then moves by the
+    /// camera's velocity
+    pub fn input_move(&mut self, wasd_axis: Vec2, dt: f32) {
+        let target_velocity = if wasd_axis.magnitude2() > 0.0 {
+            (wasd_axis.x * self.right + wasd_axis.y * self.front).normalize()
+                * self.config.speed
+        } else {
+            Vec3::zero()
+        };
+        let t = damping_factor(self.config.damping, dt);
+        self.velocity += (target_velocity - self.velocity) * t;
+        if target_velocity == Vec3::zero() && self.velocity.magnitude() < 1e-3 {
+            self.velocity = Vec3::zero();
+        }
+        self.pos += self.velocity * dt;
+        self.build_transform();
+    }
+
+    /// Turns the camera by a mouse offset, in pixels
+    pub fn mouselook(&mut self, mouse_offset: Vec2) {
+        let mouse_offset = mouse_offset * self.config.sensitivity;
         self.yaw += Rad(mouse_offset.x);
         self.pitch += Rad(mouse_offset.y);
         self.pitch = if self.pitch < Deg(-89.0).into() {
@@ -102,3 +356,104 @@ impl FpsCameraComponent {
         self.build_transform();
     }
 }
+
+impl CameraController for FpsCameraComponent {
+    fn apply_config(&mut self, config: &CameraConfig) {
+        self.config = config.fps;
+    }
+
+    fn update(&mut self, input: &CameraInput, dt: f32) {
+        if input.look.magnitude2() > 0.0 {
+            self.mouselook(input.look);
+        }
+        self.input_move(input.movement, dt);
+    }
+
+    fn world_transform(&self) -> Decomposed<Vec3, Quaternion<f32>> {
+        FpsCameraComponent::world_transform(self)
+    }
+
+    fn view(&self) -> Mat4 {
+        self.transform
+    }
+
+    fn position(&self) -> Point3<f32> {
+        self.pos
+    }
+
+    fn front(&self) -> Vec3 {
+        self.front
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn fps_camera() -> FpsCameraComponent {
+        FpsCameraComponent::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Vec3::unit_y(),
+            Rad(-PI / 2.0),
+            Rad(0.0),
+        )
+    }
+
+    #[test]
+    fn test_mouselook_ignores_frame_rate() {
+        let look = CameraInput {
+            look: Vec2::new(120.0, 30.0),
+            ..CameraInput::default()
+        };
+        let mut slow = fps_camera();
+        slow.update(&look, 1.0 / 30.0);
+        let mut fast = fps_camera();
+        fast.update(&look, 1.0 / 144.0);
+        assert!((slow.front() - fast.front()).magnitude() < 1e-6);
+        assert!((slow.front() - fps_camera().front()).magnitude() > 0.1);
+    }
+
+    #[test]
+    fn test_movement_ignores_frame_rate() {
+        let walk = CameraInput {
+            movement: Vec2::new(0.0, 1.0),
+            ..CameraInput::default()
+        };
+        let mut positions = Vec::new();
+        for &frames in &[30, 144] {
+            let mut camera = fps_camera();
+            for _ in 0..frames {
+                camera.update(&walk, 1.0 / frames as f32);
+            }
+            positions.push(camera.position());
+        }
+        let travelled = positions[0].distance(Point3::new(0.0, 0.0, 5.0));
+        assert!(travelled > 5.0 && travelled < 9.0);
+        assert!(positions[0].distance(positions[1]) < 0.2);
+    }
+
+    #[test]
+    fn test_camera_config() {
+        let config = CameraConfig::from_toml_str(
+            r#"
+            [fps]
+            speed = 3.0
+
+            [follow]
+            distance = 10.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.fps.speed, 3.0);
+        assert_eq!(
+            config.fps.sensitivity,
+            FpsCameraConfig::default().sensitivity
+        );
+        assert_eq!(config.follow.distance, 10.0);
+        assert_eq!(config.orbit, OrbitCameraConfig::default());
+
+        let file = CameraConfig::load("assets/config/camera.toml").unwrap();
+        assert_eq!(file, CameraConfig::default());
+    }
+}
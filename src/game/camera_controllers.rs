@@ -0,0 +1,535 @@
+//! Orbit, arcball, follow and walking camera controllers.
+//! The first three keep a goal placement, moved directly by input, and
+//! ease their current placement towards the goal using their config's
+//! damping.
+
+use super::camera::*;
+use super::physics::{character::CharacterController, trimesh::TriangleMesh};
+use crate::math::*;
+use cgmath::*;
+use std::sync::Arc;
+
+fn clamp(value: f32, min: f32, max: f32) -> f32 {
+    value.max(min).min(max)
+}
+
+/// Scales a distance by the zoom axis, zooming in for positive values
+fn zoomed(distance: f32, zoom: f32, zoom_speed: f32, dt: f32) -> f32 {
+    distance * (-zoom * zoom_speed * dt).exp()
+}
+
+fn look_at_transform(
+    eye: Point3<f32>,
+    center: Point3<f32>,
+    up: Vec3,
+) -> Decomposed<Vec3, Quaternion<f32>> {
+    let front = (center - eye).normalize();
+    let right = front.cross(up).normalize();
+    let up = right.cross(front);
+    Decomposed {
+        scale: 1.0,
+        rot: Mat3::from_cols(right, up, -front).into(),
+        disp: eye.to_vec(),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Orbit {
+    yaw: Rad<f32>,
+    pitch: Rad<f32>,
+    distance: f32,
+}
+
+/// Orbits a target point at a distance, turning around the world's up
+/// axis. Suited to model viewers.
+#[derive(Debug)]
+pub struct OrbitCamera {
+    pub config: OrbitCameraConfig,
+    pub target: Point3<f32>,
+    current: Orbit,
+    goal: Orbit,
+}
+
+impl OrbitCamera {
+    pub fn new(
+        target: Point3<f32>,
+        distance: f32,
+        config: OrbitCameraConfig,
+    ) -> Self {
+        let orbit = Orbit {
+            yaw: Rad(std::f32::consts::PI / 2.0),
+            pitch: Rad(0.0),
+            distance: clamp(distance, config.min_distance, config.max_distance),
+        };
+        OrbitCamera {
+            config,
+            target,
+            current: orbit,
+            goal: orbit,
+        }
+    }
+
+    /// Sets the orbit angles and distance the camera eases towards
+    pub fn set_goal(&mut self, yaw: Rad<f32>, pitch: Rad<f32>, distance: f32) {
+        self.goal = Orbit {
+            yaw,
+            pitch,
+            distance,
+        };
+        self.clamp_goal();
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.current.distance
+    }
+
+    fn clamp_goal(&mut self) {
+        let config = &self.config;
+        let pitch: Rad<f32> = Deg(clamp(
+            Deg::from(self.goal.pitch).0,
+            config.min_pitch,
+            config.max_pitch,
+        ))
+        .into();
+        self.goal.pitch = pitch;
+        self.goal.distance =
+            clamp(self.goal.distance, config.min_distance, config.max_distance);
+    }
+}
+
+impl CameraController for OrbitCamera {
+    fn apply_config(&mut self, config: &CameraConfig) {
+        self.config = config.orbit;
+    }
+
+    fn update(&mut self, input: &CameraInput, dt: f32) {
+        let config = self.config;
+        let turn = input.look * config.sensitivity
+            + input.movement * config.key_speed * dt;
+        self.goal.yaw += Rad(turn.x);
+        self.goal.pitch += Rad(turn.y);
+        self.goal.distance =
+            zoomed(self.goal.distance, input.zoom, config.zoom_speed, dt);
+        self.clamp_goal();
+
+        let t = damping_factor(config.damping, dt);
+        self.current.yaw += (self.goal.yaw - self.current.yaw) * t;
+        self.current.pitch += (self.goal.pitch - self.current.pitch) * t;
+        self.current.distance +=
+            (self.goal.distance - self.current.distance) * t;
+    }
+
+    fn world_transform(&self) -> Decomposed<Vec3, Quaternion<f32>> {
+        let Orbit {
+            yaw: Rad(yaw),
+            pitch: Rad(pitch),
+            distance,
+        } = self.current;
+        let offset = vec3(
+            pitch.cos() * yaw.cos(),
+            pitch.sin(),
+            pitch.cos() * yaw.sin(),
+        ) * distance;
+        look_at_transform(self.target + offset, self.target, Vec3::unit_y())
+    }
+}
+
+/// Rotates the camera around a target as if dragging a ball under the
+/// cursor. Unlike `OrbitCamera`, rotation is not tied to the world's up
+/// axis, so the view can roll and pass over the poles.
+#[derive(Debug)]
+pub struct ArcballCamera {
+    pub config: ArcballCameraConfig,
+    pub target: Point3<f32>,
+    rotation: Quaternion<f32>,
+    goal_rotation: Quaternion<f32>,
+    distance: f32,
+    goal_distance: f32,
+}
+
+impl ArcballCamera {
+    pub fn new(
+        target: Point3<f32>,
+        distance: f32,
+        config: ArcballCameraConfig,
+    ) -> Self {
+        let distance =
+            clamp(distance, config.min_distance, config.max_distance);
+        ArcballCamera {
+            config,
+            target,
+            rotation: Quaternion::one(),
+            goal_rotation: Quaternion::one(),
+            distance,
+            goal_distance: distance,
+        }
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+}
+
+impl CameraController for ArcballCamera {
+    fn apply_config(&mut self, config: &CameraConfig) {
+        self.config = config.arcball;
+    }
+
+    fn update(&mut self, input: &CameraInput, dt: f32) {
+        let config = self.config;
+        let drag = input.look;
+        if drag.magnitude2() > 0.0 {
+            // the scene turns with the drag, so the camera turns the
+            // opposite way about the same axis
+            let view_axis = vec3(-drag.y, drag.x, 0.0).normalize();
+            let axis = self.goal_rotation * view_axis;
+            let angle = Rad(-drag.magnitude() * config.sensitivity);
+            self.goal_rotation = (Quaternion::from_axis_angle(axis, angle)
+                * self.goal_rotation)
+                .normalize();
+        }
+        self.goal_distance = clamp(
+            zoomed(self.goal_distance, input.zoom, config.zoom_speed, dt),
+            config.min_distance,
+            config.max_distance,
+        );
+
+        let t = damping_factor(config.damping, dt);
+        self.rotation = self.rotation.slerp(self.goal_rotation, t).normalize();
+        self.distance += (self.goal_distance - self.distance) * t;
+    }
+
+    fn world_transform(&self) -> Decomposed<Vec3, Quaternion<f32>> {
+        let offset = self.rotation * vec3(0.0, 0.0, self.distance);
+        Decomposed {
+            scale: 1.0,
+            rot: self.rotation,
+            disp: self.target.to_vec() + offset,
+        }
+    }
+}
+
+/// Third-person camera which trails behind a moving target, smoothing
+/// out the target's motion. Mouselook swings the camera around the
+/// target.
+#[derive(Debug)]
+pub struct FollowCamera {
+    pub config: FollowCameraConfig,
+    target: Point3<f32>,
+    heading: Rad<f32>,
+    yaw_offset: Rad<f32>,
+    goal_yaw_offset: Rad<f32>,
+    position: Point3<f32>,
+    look_at: Point3<f32>,
+}
+
+impl FollowCamera {
+    /// Creates a camera placed behind the target. `heading` is the
+    /// target's facing yaw, measured like `FpsCameraComponent`'s yaw.
+    pub fn new(
+        target: Point3<f32>,
+        heading: Rad<f32>,
+        config: FollowCameraConfig,
+    ) -> Self {
+        let mut camera = FollowCamera {
+            config,
+            target,
+            heading,
+            yaw_offset: Rad(0.0),
+            goal_yaw_offset: Rad(0.0),
+            position: target,
+            look_at: target,
+        };
+        camera.position = camera.desired_position();
+        camera.look_at = camera.desired_look_at();
+        camera
+    }
+
+    /// Sets the position and heading the camera follows. Should be
+    /// called each frame, before update.
+    pub fn set_target(&mut self, target: Point3<f32>, heading: Rad<f32>) {
+        self.target = target;
+        self.heading = heading;
+    }
+
+    pub fn target(&self) -> Point3<f32> {
+        self.target
+    }
+
+    fn desired_position(&self) -> Point3<f32> {
+        let Rad(yaw) = self.heading + self.yaw_offset;
+        let behind = -vec3(yaw.cos(), 0.0, yaw.sin());
+        self.target
+            + behind * self.config.distance
+            + Vec3::unit_y() * self.config.height
+    }
+
+    fn desired_look_at(&self) -> Point3<f32> {
+        self.target + Vec3::unit_y() * self.config.look_height
+    }
+}
+
+impl CameraController for FollowCamera {
+    fn apply_config(&mut self, config: &CameraConfig) {
+        self.config = config.follow;
+    }
+
+    fn update(&mut self, input: &CameraInput, dt: f32) {
+        self.goal_yaw_offset += Rad(input.look.x * self.config.sensitivity);
+        let t = damping_factor(self.config.damping, dt);
+        self.yaw_offset += (self.goal_yaw_offset - self.yaw_offset) * t;
+
+        let desired_position = self.desired_position();
+        let desired_look_at = self.desired_look_at();
+        self.position += (desired_position - self.position) * t;
+        self.look_at += (desired_look_at - self.look_at) * t;
+    }
+
+    fn world_transform(&self) -> Decomposed<Vec3, Quaternion<f32>> {
+        look_at_transform(self.position, self.look_at, Vec3::unit_y())
+    }
+}
+
+/// First-person camera which walks a CharacterController through level
+/// geometry, rather than flying through it. Looks around like
+/// `FpsCameraComponent`, using its config's sensitivity.
+#[derive(Debug)]
+pub struct WalkCamera {
+    pub character: CharacterController,
+    pub level: Arc<TriangleMesh>,
+    /// Height of the camera above the character's feet
+    pub eye_height: f32,
+    look: FpsCameraComponent,
+}
+
+impl WalkCamera {
+    pub fn new(
+        character: CharacterController,
+        level: Arc<TriangleMesh>,
+        yaw: Rad<f32>,
+        config: FpsCameraConfig,
+    ) -> Self {
+        let eye_height = character.config.height * 0.9;
+        let look = FpsCameraComponent::new(
+            character.position() + Vec3::unit_y() * eye_height,
+            Vec3::unit_y(),
+            yaw,
+            Rad(0.0),
+        )
+        .with_config(config);
+        WalkCamera {
+            character,
+            level,
+            eye_height,
+            look,
+        }
+    }
+}
+
+impl CameraController for WalkCamera {
+    fn apply_config(&mut self, config: &CameraConfig) {
+        self.look.apply_config(config);
+    }
+
+    fn update(&mut self, input: &CameraInput, dt: f32) {
+        if input.look.magnitude2() > 0.0 {
+            self.look.mouselook(input.look);
+        }
+        if input.jump {
+            self.character.jump();
+        }
+        self.character.input_move(
+            input.movement,
+            self.look.front(),
+            dt,
+            &self.level,
+        );
+        self.look.set_position(
+            self.character.position() + Vec3::unit_y() * self.eye_height,
+        );
+    }
+
+    fn world_transform(&self) -> Decomposed<Vec3, Quaternion<f32>> {
+        self.look.world_transform()
+    }
+
+    fn view(&self) -> Mat4 {
+        *self.look.transform()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn drag(x: f32, y: f32) -> CameraInput {
+        CameraInput {
+            look: Vec2::new(x, y),
+            ..CameraInput::default()
+        }
+    }
+
+    /// Feeds `input` on the first frame, then idles until one second
+    /// has passed
+    fn settle<C: CameraController>(
+        camera: &mut C,
+        input: &CameraInput,
+        fps: u32,
+    ) {
+        let dt = 1.0 / fps as f32;
+        camera.update(input, dt);
+        for _ in 1..fps {
+            camera.update(&CameraInput::default(), dt);
+        }
+    }
+
+    #[test]
+    fn test_orbit_keeps_distance() {
+        let target = Point3::new(1.0, 2.0, 3.0);
+        let mut camera =
+            OrbitCamera::new(target, 5.0, OrbitCameraConfig::default());
+        settle(&mut camera, &drag(200.0, 100.0), 60);
+        assert!((camera.position().distance(target) - 5.0).abs() < 1e-3);
+        let to_target = (target - camera.position()).normalize();
+        assert!(camera.front().dot(to_target) > 0.999);
+    }
+
+    #[test]
+    fn test_orbit_damping_ignores_frame_rate() {
+        let config = OrbitCameraConfig::default();
+        let mut slow = OrbitCamera::new(Point3::origin(), 5.0, config);
+        let mut fast = OrbitCamera::new(Point3::origin(), 5.0, config);
+        settle(&mut slow, &drag(150.0, -40.0), 30);
+        settle(&mut fast, &drag(150.0, -40.0), 144);
+        assert!(slow.position().distance(fast.position()) < 1e-2);
+    }
+
+    #[test]
+    fn test_orbit_limits() {
+        let mut camera = OrbitCamera::new(
+            Point3::origin(),
+            5.0,
+            OrbitCameraConfig::default(),
+        );
+        settle(&mut camera, &drag(0.0, 10_000.0), 60);
+        let config = camera.config;
+        let elevation = (camera.position().y / camera.distance()).asin();
+        let pitch = Deg::from(Rad(elevation)).0;
+        assert!(pitch <= config.max_pitch + 0.01);
+
+        let zoom_in = CameraInput {
+            zoom: 1.0,
+            ..CameraInput::default()
+        };
+        for _ in 0..600 {
+            camera.update(&zoom_in, 1.0 / 60.0);
+        }
+        assert!((camera.distance() - config.min_distance).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_arcball_drag() {
+        let mut camera = ArcballCamera::new(
+            Point3::origin(),
+            5.0,
+            ArcballCameraConfig::default(),
+        );
+        assert!(camera.position().distance(Point3::new(0.0, 0.0, 5.0)) < 1e-5);
+        // dragging right turns the scene right, moving the camera left
+        settle(&mut camera, &drag(100.0, 0.0), 60);
+        assert!(camera.position().x < -1.0);
+        assert!(
+            (camera.position().distance(Point3::origin()) - 5.0).abs() < 1e-3
+        );
+
+        // dragging carries the camera over the pole, turning it upside down
+        settle(&mut camera, &drag(0.0, -700.0), 60);
+        let up = camera.world_transform().rot * Vec3::unit_y();
+        assert!(up.y < 0.0);
+        let to_target = (Point3::origin() - camera.position()).normalize();
+        assert!(camera.front().dot(to_target) > 0.999);
+    }
+
+    #[test]
+    fn test_follow_trails_target() {
+        use std::f32::consts::PI;
+        let config = FollowCameraConfig::default();
+        let mut camera = FollowCamera::new(Point3::origin(), Rad(0.0), config);
+        // facing +x, so the camera sits behind on -x
+        assert!(camera.position().x < -config.distance + 1e-3);
+
+        // the camera lags behind a moving target, then catches up
+        camera.set_target(Point3::new(0.0, 0.0, 10.0), Rad(PI / 2.0));
+        camera.update(&CameraInput::default(), 1.0 / 60.0);
+        let lagging = camera.position();
+        assert!(lagging.distance(Point3::new(0.0, 2.0, 4.0)) > 1.0);
+        for _ in 0..300 {
+            camera.update(&CameraInput::default(), 1.0 / 60.0);
+        }
+        let expected = Point3::new(0.0, config.height, 10.0 - config.distance);
+        assert!(camera.position().distance(expected) < 1e-2);
+    }
+
+    #[test]
+    fn test_walk_camera_stops_at_walls() {
+        use std::f32::consts::PI;
+        let p = Point3::new;
+        let mut level = TriangleMesh::new();
+        let floor = [p(-5.0, 0.0, -5.0), p(-5.0, 0.0, 5.0), p(5.0, 0.0, 5.0)];
+        level.add_triangle(Triangle::new(floor[0], floor[1], floor[2]));
+        level.add_triangle(Triangle::new(floor[0], floor[2], p(5.0, 0.0, -5.0)));
+        let wall = [p(-5.0, 0.0, -2.0), p(5.0, 0.0, -2.0), p(5.0, 3.0, -2.0)];
+        level.add_triangle(Triangle::new(wall[0], wall[1], wall[2]));
+        level.add_triangle(Triangle::new(wall[0], wall[2], p(-5.0, 3.0, -2.0)));
+
+        // facing -z, towards the wall
+        let mut camera = WalkCamera::new(
+            CharacterController::new(Point3::origin()),
+            Arc::new(level),
+            Rad(-PI / 2.0),
+            FpsCameraConfig::default(),
+        );
+        let walk = CameraInput {
+            movement: Vec2::new(0.0, 1.0),
+            ..CameraInput::default()
+        };
+        for _ in 0..120 {
+            camera.update(&walk, 1.0 / 60.0);
+        }
+        let position = camera.position();
+        let radius = camera.character.config.radius;
+        assert!((position.z - (-2.0 + radius)).abs() < 0.01, "at {:?}", position);
+        assert!((position.y - camera.eye_height).abs() < 0.01);
+        assert!(camera.character.is_grounded());
+    }
+
+    #[test]
+    fn test_apply_config() {
+        let mut config = CameraConfig::default();
+        config.orbit.sensitivity = 0.01;
+        config.arcball.zoom_speed = 3.0;
+        config.follow.distance = 10.0;
+
+        let mut orbit = OrbitCamera::new(
+            Point3::origin(),
+            5.0,
+            OrbitCameraConfig::default(),
+        );
+        orbit.apply_config(&config);
+        assert_eq!(orbit.config, config.orbit);
+        let mut arcball = ArcballCamera::new(
+            Point3::origin(),
+            5.0,
+            ArcballCameraConfig::default(),
+        );
+        arcball.apply_config(&config);
+        assert_eq!(arcball.config, config.arcball);
+        let mut follow = FollowCamera::new(
+            Point3::origin(),
+            Rad(0.0),
+            FollowCameraConfig::default(),
+        );
+        follow.apply_config(&config);
+        assert_eq!(follow.config, config.follow);
+    }
+}
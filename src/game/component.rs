@@ -9,7 +9,7 @@ use slsengine_entityalloc::*;
 use std::{
     any::Any,
     ops::Deref,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
 };
 
 pub enum StoreType {
@@ -61,6 +61,8 @@ where  S: TryGetComponent {
     pub masks: IndexArray<BitSet>,
     custom_store: S,
     id_table: ComponentIdGen,
+    /// Indices of entities flagged by `mark_spawned`
+    spawned: Mutex<BitSet>,
 }
 
 impl<S> ComponentManager<S> where S: TryGetComponent {
@@ -74,6 +76,7 @@ impl<S> ComponentManager<S> where S: TryGetComponent {
             masks: IndexArray::with_capacity(capacity),
             custom_store,
             id_table,
+            spawned: Mutex::new(BitSet::new()),
         }
     }
 
@@ -96,17 +99,48 @@ impl<S> ComponentManager<S> where S: TryGetComponent {
 
     pub fn alloc_entity(&mut self) -> Entity {
         let idx = self.entity_alloc.allocate();
-        Entity(idx)
+        let entity = Entity(idx);
+        self.mark_spawned(entity);
+        entity
     }
     pub fn dealloc_entity(&mut self, entity: Entity) {
         self.entity_alloc.deallocate(entity.0);
         self.masks.remove(entity.0);
+        self.mark_spawned(entity);
     }
 
     pub fn entities<'a>(&'a self) -> impl Iterator<Item = Entity> + 'a {
         self.entity_alloc.iter_live().map(|i| Entity(i))
     }
 
+    /// The live entity at an index, if there is one
+    pub fn entity_at(&self, index: usize) -> Option<Entity> {
+        self.entity_alloc.live_at(index).map(Entity)
+    }
+
+    //-- Change tracking
+
+    /// Flags an entity which was spawned or despawned, so the spatial
+    /// index visits its slot. `alloc_entity` and `dealloc_entity` flag
+    /// entities themselves; code allocating them another way, as
+    /// scripts do, should flag them. Writes to transforms and meshes
+    /// need no flag, since their stores track their own writes.
+    pub fn mark_spawned(&self, entity: Entity) {
+        self.spawned
+            .lock()
+            .expect("poisoned Mutex")
+            .add(entity.index() as u32);
+    }
+
+    /// Takes the indices of the entities flagged since the last call.
+    /// An index's entity may since have been despawned.
+    pub fn take_spawned(&self) -> Vec<usize> {
+        let mut spawned = self.spawned.lock().expect("poisoned Mutex");
+        let indices = (&*spawned).iter().map(|i| i as usize).collect();
+        spawned.clear();
+        indices
+    }
+
     //-- Component retreival
 
     /// returns runtime registered store
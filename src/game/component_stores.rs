@@ -1,6 +1,6 @@
 use super::component::{Component, ComponentList};
 use mopa::{self, mopafy};
-use std::any::TypeId;
+use std::any::{Any, TypeId};
 use std::collections::HashMap;
 use std::fmt;
 use std::ops::Deref;
@@ -51,17 +51,95 @@ impl TryGetComponent for NullComponentStore {
 
 }
 
+/// A component store whose component lists are registered at runtime
+#[derive(Default)]
+pub struct AnyComponentStore {
+    stores: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl fmt::Debug for AnyComponentStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AnyComponentStore")
+            .field("n_stores", &self.stores.len())
+            .finish()
+    }
+}
+
+impl AnyComponentStore {
+    pub fn new() -> Self {
+        AnyComponentStore::default()
+    }
+
+    /// Creates a store with the engine's built-in components registered
+    pub fn with_built_in_components() -> Self {
+        use super::animation::{
+            AnimationGraph, AnimationPlayer, IkConstraints, MorphWeights,
+            SkinComponent,
+        };
+        use super::audio::{AudioListener, AudioSource};
+        use super::built_in_components::*;
+        use super::physics::{Collider, RigidBody};
+        use super::sequencer::SequencePlayer;
+        let mut store = AnyComponentStore::new();
+        store.register::<TransformComponent>();
+        store.register::<MeshComponent>();
+        store.register::<MaterialComponent>();
+        store.register::<CameraComponent>();
+        store.register::<RigidBody>();
+        store.register::<Collider>();
+        store.register::<AnimationPlayer>();
+        store.register::<AnimationGraph>();
+        store.register::<SkinComponent>();
+        store.register::<MorphWeights>();
+        store.register::<IkConstraints>();
+        store.register::<SequencePlayer>();
+        store.register::<AudioSource>();
+        store.register::<AudioListener>();
+        store
+    }
+
+    /// Registers a component list for C, returning the existing list
+    /// if one is already registered
+    pub fn register<C: Component + Send + Sync>(&mut self) -> Arc<Storage<C>> {
+        self.stores
+            .entry(TypeId::of::<C>())
+            .or_insert_with(|| Box::new(Arc::new(Storage::<C>::new())))
+            .downcast_ref::<Arc<Storage<C>>>()
+            .cloned()
+            .expect("component store should match its TypeId")
+    }
+}
+
+impl TryGetComponent for AnyComponentStore {
+    fn try_get_component<C: Component>(&self) -> Option<Arc<Storage<C>>> {
+        self.stores
+            .get(&TypeId::of::<C>())
+            .and_then(|store| store.downcast_ref::<Arc<Storage<C>>>())
+            .cloned()
+    }
+}
+
 
 #[cfg(test)]
 mod test {
- 
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Dummy(u32);
+    impl Component for Dummy {}
+
     #[test]
     fn test_any_store() {
-        unimplemented!()
+        let mut store = AnyComponentStore::new();
+        assert!(store.try_get_component::<Dummy>().is_none());
+        let registered = store.register::<Dummy>();
+        let fetched = store
+            .try_get_component::<Dummy>()
+            .expect("Dummy should be registered");
+        assert!(Arc::ptr_eq(&registered, &fetched));
+        assert!(Arc::ptr_eq(&registered, &store.register::<Dummy>()));
     }
 
-    
-
 }
 /// Generates unique bitset mask values for a componenet
 pub struct ComponentIdGen {
@@ -0,0 +1,107 @@
+//! Frustum culling of mesh entities, run for each camera before drawing.
+
+use super::{component::*, world::EntityWorld, TryGetComponent};
+use crate::math::*;
+use crate::renderer::{RenderMesh, Renderer};
+use cgmath::*;
+
+/// Returns the entities with a mesh whose world space bounds intersect the
+/// frustum of `view_projection`, a camera's projection times its view.
+/// Candidates come from the world's spatial index, so entities spawned or
+/// moved since the last update may be missed.
+/// Entities without a TransformComponent are placed at the origin.
+pub fn visible_entities<R, CS>(
+    world: &EntityWorld<R, CS>,
+    view_projection: &Mat4,
+) -> Vec<Entity>
+where
+    R: Renderer,
+    CS: TryGetComponent,
+{
+    let (meshes, transforms) = match (
+        world.components.get_components::<MeshComponent>(),
+        world.components.get_components::<TransformComponent>(),
+    ) {
+        (Some(meshes), Some(transforms)) => (meshes, transforms),
+        _ => return Vec::new(),
+    };
+    let meshes = meshes.read().expect("poisoned RwLock");
+    let transforms = transforms.read().expect("poisoned RwLock");
+    let frustum = Frustum::from_matrix(view_projection);
+
+    // the index's boxes are loose; test the tight bounds of its candidates
+    let mut visible: Vec<Entity> = world
+        .spatial
+        .query_frustum(&frustum)
+        .into_iter()
+        .filter(|&entity| {
+            let mesh = match meshes[*entity]
+                .as_ref()
+                .and_then(|m| world.resources.meshes.get(&m.mesh))
+            {
+                Some(mesh) => mesh,
+                None => return false,
+            };
+            let model = world_matrix(&transforms, entity)
+                .unwrap_or_else(Mat4::identity);
+            mesh.bounds().transform(&model).intersects_frustum(&frustum)
+        })
+        .collect();
+    visible.sort_by_key(|entity| entity.index());
+    visible
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::test_util::*;
+    use crate::renderer::Mesh;
+    use genmesh::generators::IcoSphere;
+
+    #[test]
+    fn test_visible_entities() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let sphere = world
+            .resources
+            .insert_mesh(Mesh::from_genmesh(IcoSphere::new()));
+        let meshes =
+            world.components.get_components::<MeshComponent>().unwrap();
+        let transforms = world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap();
+        let mut spawn = |x: f32, z: f32| {
+            let entity = world.components.alloc_entity();
+            meshes
+                .write()
+                .unwrap()
+                .insert(*entity, MeshComponent { mesh: sphere });
+            transforms.write().unwrap().insert(
+                *entity,
+                TransformComponent {
+                    parent: None,
+                    transform: Decomposed {
+                        scale: 1.0,
+                        rot: Quaternion::one(),
+                        disp: vec3(x, 0.0, z),
+                    },
+                },
+            );
+            entity
+        };
+        let ahead = spawn(0.0, -10.0);
+        let behind = spawn(0.0, 20.0);
+        let far_left = spawn(-40.0, -10.0);
+        world.update_spatial_index();
+
+        let projection = renderer.camera().projection;
+        let view = world.main_camera.view();
+        let visible = visible_entities(&world, &(projection * view));
+        assert_eq!(visible, vec![ahead]);
+        assert!(!visible.contains(&behind) && !visible.contains(&far_left));
+
+        let views = world.render_views((800, 800), projection);
+        assert_eq!(views[0].visible, vec![ahead]);
+    }
+}
@@ -0,0 +1,572 @@
+//! Named input actions and axes, bound to keys and mouse buttons.
+//!
+//! Bindings are loaded from a TOML file such as:
+//!
+//! ```toml
+//! [actions]
+//! quit = ["Escape"]
+//! recompile_shaders = ["Left Alt+R"]
+//! mouselook = ["MouseLeft"]
+//!
+//! [axes.move_x]
+//! positive = ["D"]
+//! negative = ["A"]
+//!
+//! [axes.look_y]
+//! mouse = "y"
+//! scale = -1.0
+//! ```
+//!
+//! Key names are SDL scancode names. Buttons joined by `+` form a chord,
+//! which is only pressed while all of its buttons are held.
+
+use super::world::InputState;
+use crate::math::*;
+use sdl2::keyboard::Scancode;
+use sdl2::mouse::MouseButton;
+use sdl2::EventPump;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+#[derive(Fail, Debug)]
+pub enum InputMapError {
+    #[fail(display = "unknown input '{}' bound to '{}'", input, binding)]
+    UnknownInput { binding: String, input: String },
+    #[fail(display = "could not parse input bindings: {}", _0)]
+    Parse(toml::de::Error),
+    #[fail(display = "could not serialize input bindings: {}", _0)]
+    Serialize(toml::ser::Error),
+    #[fail(display = "could not read input bindings: {}", _0)]
+    Io(std::io::Error),
+}
+
+/// A key or mouse button which can be bound to actions and axes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Button {
+    Key(Scancode),
+    Mouse(MouseButton),
+}
+
+const MOUSE_BUTTON_NAMES: &[(&str, MouseButton)] = &[
+    ("MouseLeft", MouseButton::Left),
+    ("MouseMiddle", MouseButton::Middle),
+    ("MouseRight", MouseButton::Right),
+    ("MouseX1", MouseButton::X1),
+    ("MouseX2", MouseButton::X2),
+];
+
+impl Button {
+    /// Parses a mouse button name, such as "MouseLeft", or an SDL
+    /// scancode name, such as "W" or "Left Alt"
+    pub fn from_name(name: &str) -> Option<Button> {
+        let name = name.trim();
+        MOUSE_BUTTON_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|&(_, b)| Button::Mouse(b))
+            .or_else(|| Scancode::from_name(name).map(Button::Key))
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Button::Key(scancode) => scancode.name().to_owned(),
+            Button::Mouse(button) => MOUSE_BUTTON_NAMES
+                .iter()
+                .find(|(_, b)| *b == button)
+                .map(|(n, _)| (*n).to_owned())
+                .unwrap_or_else(|| "MouseUnknown".to_owned()),
+        }
+    }
+}
+
+/// Provides the held state of buttons for a single frame
+pub trait ButtonSource {
+    fn is_down(&self, button: Button) -> bool;
+}
+
+/// Engine-owned copy of the keys and mouse buttons held during a frame.
+/// Filled from SDL by the main loop, or built by hand in tests and replays.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputSnapshot {
+    pub keys: HashSet<Scancode>,
+    pub mouse_buttons: HashSet<MouseButton>,
+}
+
+impl InputSnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Samples the held keys and mouse buttons from SDL
+    pub fn from_event_pump(event_pump: &EventPump) -> Self {
+        InputSnapshot {
+            keys: event_pump.keyboard_state().pressed_scancodes().collect(),
+            mouse_buttons: event_pump
+                .mouse_state()
+                .pressed_mouse_buttons()
+                .collect(),
+        }
+    }
+
+    /// A snapshot with the given buttons held
+    pub fn with_buttons<I>(buttons: I) -> Self
+    where
+        I: IntoIterator<Item = Button>,
+    {
+        let mut snapshot = InputSnapshot::new();
+        for button in buttons {
+            snapshot.press(button);
+        }
+        snapshot
+    }
+
+    pub fn press(&mut self, button: Button) {
+        match button {
+            Button::Key(scancode) => {
+                self.keys.insert(scancode);
+            }
+            Button::Mouse(button) => {
+                self.mouse_buttons.insert(button);
+            }
+        }
+    }
+
+    pub fn release(&mut self, button: Button) {
+        match button {
+            Button::Key(scancode) => {
+                self.keys.remove(&scancode);
+            }
+            Button::Mouse(button) => {
+                self.mouse_buttons.remove(&button);
+            }
+        }
+    }
+}
+
+impl ButtonSource for InputSnapshot {
+    fn is_down(&self, button: Button) -> bool {
+        match button {
+            Button::Key(scancode) => self.keys.contains(&scancode),
+            Button::Mouse(button) => self.mouse_buttons.contains(&button),
+        }
+    }
+}
+
+/// A set of buttons which must all be held to trigger a binding
+pub type Chord = Vec<Button>;
+
+fn parse_chord(binding: &str, chord: &str) -> Result<Chord, InputMapError> {
+    chord
+        .split('+')
+        .map(|name| {
+            Button::from_name(name).ok_or_else(|| InputMapError::UnknownInput {
+                binding: binding.to_owned(),
+                input: name.to_owned(),
+            })
+        })
+        .collect()
+}
+
+fn chord_name(chord: &[Button]) -> String {
+    let names: Vec<String> = chord.iter().map(|b| b.name()).collect();
+    names.join("+")
+}
+
+fn chord_down<S: ButtonSource>(chord: &[Button], sources: &S) -> bool {
+    !chord.is_empty() && chord.iter().all(|&b| sources.is_down(b))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MouseAxis {
+    X,
+    Y,
+}
+
+/// Binding of a named axis. The axis value is the difference of
+/// its positive and negative buttons, plus mouse motion along
+/// `mouse`, multiplied by `scale`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AxisBinding {
+    pub positive: Vec<Chord>,
+    pub negative: Vec<Chord>,
+    pub mouse: Option<MouseAxis>,
+    pub scale: f32,
+}
+
+impl AxisBinding {
+    pub fn buttons(positive: Vec<Chord>, negative: Vec<Chord>) -> Self {
+        AxisBinding {
+            positive,
+            negative,
+            mouse: None,
+            scale: 1.0,
+        }
+    }
+
+    pub fn mouse(axis: MouseAxis, scale: f32) -> Self {
+        AxisBinding {
+            positive: Vec::new(),
+            negative: Vec::new(),
+            mouse: Some(axis),
+            scale,
+        }
+    }
+
+    fn evaluate<S: ButtonSource>(&self, sources: &S, mouse_delta: Vec2) -> f32 {
+        let mut value = 0.0;
+        if self.positive.iter().any(|c| chord_down(c, sources)) {
+            value += 1.0;
+        }
+        if self.negative.iter().any(|c| chord_down(c, sources)) {
+            value -= 1.0;
+        }
+        value += match self.mouse {
+            Some(MouseAxis::X) => mouse_delta.x,
+            Some(MouseAxis::Y) => mouse_delta.y,
+            None => 0.0,
+        };
+        value * self.scale
+    }
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// Serialized form of an axis binding
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AxisConfig {
+    #[serde(default)]
+    pub positive: Vec<String>,
+    #[serde(default)]
+    pub negative: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mouse: Option<MouseAxis>,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+
+/// Serialized form of an InputMap, as stored in a bindings file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BindingsConfig {
+    #[serde(default)]
+    pub actions: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub axes: HashMap<String, AxisConfig>,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+struct ActionState {
+    pressed: bool,
+    was_pressed: bool,
+}
+
+/// Maps named actions and axes to physical inputs, and tracks
+/// their state from frame to frame.
+#[derive(Debug, Clone)]
+pub struct InputMap {
+    actions: HashMap<String, Vec<Chord>>,
+    axes: HashMap<String, AxisBinding>,
+    action_states: HashMap<String, ActionState>,
+    axis_values: HashMap<String, f32>,
+}
+
+impl InputMap {
+    /// Creates an input map with no bindings
+    pub fn new() -> Self {
+        InputMap {
+            actions: HashMap::new(),
+            axes: HashMap::new(),
+            action_states: HashMap::new(),
+            axis_values: HashMap::new(),
+        }
+    }
+
+    pub fn from_config(config: &BindingsConfig) -> Result<Self, InputMapError> {
+        let mut map = InputMap::new();
+        for (name, chords) in &config.actions {
+            let chords = chords
+                .iter()
+                .map(|c| parse_chord(name, c))
+                .collect::<Result<Vec<_>, _>>()?;
+            map.set_action(name, chords);
+        }
+        for (name, axis) in &config.axes {
+            let parse_all = |chords: &[String]| {
+                chords
+                    .iter()
+                    .map(|c| parse_chord(name, c))
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            map.set_axis(
+                name,
+                AxisBinding {
+                    positive: parse_all(&axis.positive)?,
+                    negative: parse_all(&axis.negative)?,
+                    mouse: axis.mouse,
+                    scale: axis.scale,
+                },
+            );
+        }
+        Ok(map)
+    }
+
+    pub fn from_toml_str(source: &str) -> Result<Self, InputMapError> {
+        let config: BindingsConfig =
+            toml::from_str(source).map_err(InputMapError::Parse)?;
+        InputMap::from_config(&config)
+    }
+
+    /// Loads bindings from a TOML file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, InputMapError> {
+        let source = fs::read_to_string(path).map_err(InputMapError::Io)?;
+        InputMap::from_toml_str(&source)
+    }
+
+    pub fn to_config(&self) -> BindingsConfig {
+        let names = |chords: &[Chord]| -> Vec<String> {
+            chords.iter().map(|c| chord_name(c)).collect()
+        };
+        BindingsConfig {
+            actions: self
+                .actions
+                .iter()
+                .map(|(name, chords)| (name.clone(), names(chords)))
+                .collect(),
+            axes: self
+                .axes
+                .iter()
+                .map(|(name, axis)| {
+                    let config = AxisConfig {
+                        positive: names(&axis.positive),
+                        negative: names(&axis.negative),
+                        mouse: axis.mouse,
+                        scale: axis.scale,
+                    };
+                    (name.clone(), config)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_toml_string(&self) -> Result<String, InputMapError> {
+        toml::to_string(&self.to_config()).map_err(InputMapError::Serialize)
+    }
+
+    /// Replaces all bindings of an action
+    pub fn set_action(&mut self, name: &str, chords: Vec<Chord>) {
+        self.actions.insert(name.to_owned(), chords);
+    }
+
+    /// Adds a binding to an action, keeping its existing bindings
+    pub fn bind_action(&mut self, name: &str, chord: Chord) {
+        self.actions
+            .entry(name.to_owned())
+            .or_insert_with(Vec::new)
+            .push(chord);
+    }
+
+    pub fn unbind_action(&mut self, name: &str) {
+        self.actions.remove(name);
+        self.action_states.remove(name);
+    }
+
+    pub fn action_bindings(&self, name: &str) -> Option<&[Chord]> {
+        self.actions.get(name).map(|c| c.as_slice())
+    }
+
+    pub fn set_axis(&mut self, name: &str, axis: AxisBinding) {
+        self.axes.insert(name.to_owned(), axis);
+    }
+
+    pub fn unbind_axis(&mut self, name: &str) {
+        self.axes.remove(name);
+        self.axis_values.remove(name);
+    }
+
+    pub fn axis_binding(&self, name: &str) -> Option<&AxisBinding> {
+        self.axes.get(name)
+    }
+
+    /// Samples the current frame's inputs. Should be called once per frame,
+    /// after events are handled.
+    pub fn update<S: ButtonSource>(
+        &mut self,
+        sources: &S,
+        input_state: &InputState,
+    ) {
+        let mouse_delta = input_state.mousepos - input_state.last_mousepos;
+        for (name, chords) in &self.actions {
+            let pressed = chords.iter().any(|c| chord_down(c, sources));
+            let state = self
+                .action_states
+                .entry(name.clone())
+                .or_insert_with(ActionState::default);
+            state.was_pressed = state.pressed;
+            state.pressed = pressed;
+        }
+        for (name, axis) in &self.axes {
+            self.axis_values
+                .insert(name.clone(), axis.evaluate(sources, mouse_delta));
+        }
+    }
+
+    fn action_state(&self, name: &str) -> ActionState {
+        self.action_states.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Returns true while any binding of the action is held
+    pub fn action_pressed(&self, name: &str) -> bool {
+        self.action_state(name).pressed
+    }
+
+    /// Returns true on the frame the action was first pressed
+    pub fn action_just_pressed(&self, name: &str) -> bool {
+        let state = self.action_state(name);
+        state.pressed && !state.was_pressed
+    }
+
+    /// Returns true on the frame the action was released
+    pub fn action_just_released(&self, name: &str) -> bool {
+        let state = self.action_state(name);
+        !state.pressed && state.was_pressed
+    }
+
+    /// Returns the axis value for this frame, or 0 for unbound axes
+    pub fn axis(&self, name: &str) -> f32 {
+        self.axis_values.get(name).cloned().unwrap_or(0.0)
+    }
+
+    /// Combines two axes into a vector
+    pub fn axis2(&self, x_name: &str, y_name: &str) -> Vec2 {
+        Vec2::new(self.axis(x_name), self.axis(y_name))
+    }
+}
+
+impl Default for InputMap {
+    /// The engine's built-in bindings: WASD movement, Space to jump, left
+    /// mouse mouselook, Escape to quit, and Alt+R to recompile shaders
+    fn default() -> Self {
+        use self::Button::*;
+        let mut map = InputMap::new();
+        map.set_action("quit", vec![vec![Key(Scancode::Escape)]]);
+        map.set_action(
+            "recompile_shaders",
+            vec![vec![Key(Scancode::LAlt), Key(Scancode::R)]],
+        );
+        map.set_action("log_camera", vec![vec![Key(Scancode::Y)]]);
+        map.set_action("mouselook", vec![vec![Mouse(MouseButton::Left)]]);
+        map.set_action("jump", vec![vec![Key(Scancode::Space)]]);
+        map.set_axis(
+            "move_x",
+            AxisBinding::buttons(
+                vec![vec![Key(Scancode::D)]],
+                vec![vec![Key(Scancode::A)]],
+            ),
+        );
+        map.set_axis(
+            "move_y",
+            AxisBinding::buttons(
+                vec![vec![Key(Scancode::W)]],
+                vec![vec![Key(Scancode::S)]],
+            ),
+        );
+        map.set_axis(
+            "zoom",
+            AxisBinding::buttons(
+                vec![vec![Key(Scancode::E)]],
+                vec![vec![Key(Scancode::Q)]],
+            ),
+        );
+        map.set_axis("look_x", AxisBinding::mouse(MouseAxis::X, 1.0));
+        map.set_axis("look_y", AxisBinding::mouse(MouseAxis::Y, -1.0));
+        map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_bindings() {
+        let map = InputMap::from_toml_str(
+            r#"
+            [actions]
+            jump = ["Space", "MouseRight"]
+            reload = ["Left Alt+R"]
+
+            [axes.move]
+            positive = ["D"]
+            negative = ["A"]
+
+            [axes.look]
+            mouse = "y"
+            scale = -0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            map.action_bindings("jump").unwrap(),
+            &[
+                vec![Button::Key(Scancode::Space)],
+                vec![Button::Mouse(MouseButton::Right)]
+            ][..]
+        );
+        assert_eq!(
+            map.action_bindings("reload").unwrap(),
+            &[vec![Button::Key(Scancode::LAlt), Button::Key(Scancode::R)]][..]
+        );
+        let axis = map.axis_binding("move").unwrap();
+        assert_eq!(axis.positive, vec![vec![Button::Key(Scancode::D)]]);
+        assert_eq!(axis.scale, 1.0);
+        let look = map.axis_binding("look").unwrap();
+        assert_eq!(look.mouse, Some(MouseAxis::Y));
+        assert_eq!(look.scale, -0.5);
+    }
+
+    #[test]
+    fn test_unknown_input() {
+        let res = InputMap::from_toml_str(
+            r#"
+            [actions]
+            jump = ["NotAKey"]
+            "#,
+        );
+        match res {
+            Err(InputMapError::UnknownInput { binding, input }) => {
+                assert_eq!(binding, "jump");
+                assert_eq!(input, "NotAKey");
+            }
+            other => panic!("expected UnknownInput, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_default_bindings_file() {
+        let path = crate::platform_system::asset_path()
+            .join("assets/config/input_bindings.toml");
+        let map = InputMap::load(&path).unwrap();
+        assert_eq!(map.to_config(), InputMap::default().to_config());
+    }
+
+    #[test]
+    fn test_rebind() {
+        let mut map = InputMap::default();
+        map.set_action("quit", vec![vec![Button::Key(Scancode::Q)]]);
+        map.bind_action("quit", vec![Button::Mouse(MouseButton::X1)]);
+        let reloaded =
+            InputMap::from_toml_str(&map.to_toml_string().unwrap()).unwrap();
+        assert_eq!(
+            reloaded.action_bindings("quit").unwrap(),
+            &[
+                vec![Button::Key(Scancode::Q)],
+                vec![Button::Mouse(MouseButton::X1)]
+            ][..]
+        );
+        assert_eq!(reloaded.axis("move_x"), 0.0);
+        assert!(!reloaded.action_pressed("quit"));
+    }
+}
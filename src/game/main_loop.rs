@@ -1,18 +1,149 @@
 use crate::{game, renderer};
 use sdl2::event::{Event, WindowEvent};
-use sdl2::keyboard::Keycode;
 use sdl2::video::Window;
 use std::{
     cell::RefCell,
+    collections::VecDeque,
+    thread,
     time::{Duration, Instant},
 };
 
+/// Number of frames FrameStats keeps by default
+pub const DEFAULT_STATS_WINDOW: usize = 240;
+
+/// How long before a frame's deadline the limiter stops sleeping and
+/// spins, since sleeps can overshoot by a millisecond or more
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+/// Frame times over a rolling window of recent frames
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameStats {
+    frame_times: VecDeque<Duration>,
+    window: usize,
+    frame_count: u64,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        FrameStats::new(DEFAULT_STATS_WINDOW)
+    }
+}
+
+impl FrameStats {
+    /// Creates stats over the last `window` frames
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        FrameStats {
+            frame_times: VecDeque::with_capacity(window),
+            window,
+            frame_count: 0,
+        }
+    }
+
+    pub fn record(&mut self, frame_time: Duration) {
+        if self.frame_times.len() == self.window {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+        self.frame_count += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.frame_times.clear();
+    }
+
+    /// Frames recorded since the stats were created
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Recorded frame times in the window, oldest first
+    pub fn frame_times(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.frame_times.iter().cloned()
+    }
+
+    pub fn last(&self) -> Option<Duration> {
+        self.frame_times.back().cloned()
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.frame_times.iter().min().cloned()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.frame_times.iter().max().cloned()
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        let total: Duration = self.frame_times.iter().sum();
+        Some(total / self.frame_times.len() as u32)
+    }
+
+    /// Frames per second over the window
+    pub fn fps(&self) -> Option<f64> {
+        self.average().map(fps_of)
+    }
+
+    /// Frames per second over the slowest `percent` of frames in the
+    /// window, counting at least one frame. `low_fps(1.0)` gives the
+    /// "1% low" frame rate.
+    pub fn low_fps(&self, percent: f64) -> Option<f64> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.frame_times().collect();
+        sorted.sort_unstable_by(|a, b| b.cmp(a));
+        let count = (sorted.len() as f64 * percent / 100.0).ceil() as usize;
+        let slowest = &sorted[..count.max(1).min(sorted.len())];
+        let total: Duration = slowest.iter().sum();
+        Some(fps_of(total / slowest.len() as u32))
+    }
+
+    /// The "1% low" frame rate
+    pub fn one_percent_low(&self) -> Option<f64> {
+        self.low_fps(1.0)
+    }
+}
+
+fn fps_of(frame_time: Duration) -> f64 {
+    let seconds = frame_time.as_secs_f64();
+    if seconds > 0.0 {
+        1.0 / seconds
+    } else {
+        std::f64::INFINITY
+    }
+}
+
+/// Sleeps until shortly before `deadline`, then spins until it passes
+fn wait_until(deadline: Instant) {
+    let now = Instant::now();
+    if deadline <= now {
+        return;
+    }
+    let remaining = deadline - now;
+    if remaining > SPIN_MARGIN {
+        thread::sleep(remaining - SPIN_MARGIN);
+    }
+    while Instant::now() < deadline {
+        thread::yield_now();
+    }
+}
+
 /// State object for main loop information, such as
 /// Event handlers and frame timers.
 #[derive(Debug)]
 pub struct MainLoopState {
     pub is_running: bool,
     pub last_time: Instant,
+    /// Shortest time between ticks, if the frame rate is capped
+    target_frame_time: Option<Duration>,
+    stats: FrameStats,
+    /// Whether to pass SDL's input to the world. Off while a replay feeds
+    /// it instead.
+    live_input: bool,
 }
 
 pub struct FrameTick {
@@ -38,16 +169,56 @@ impl MainLoopState {
         eprintln!("starting loop {:?}", self);
     }
 
+    /// Caps the frame rate, or uncaps it with None. Independent of
+    /// vsync, which also bounds the frame rate.
+    pub fn set_target_fps(&mut self, fps: Option<f64>) {
+        self.target_frame_time = fps
+            .filter(|&fps| fps > 0.0)
+            .map(|fps| Duration::from_nanos((1e9 / fps) as u64));
+    }
+
+    pub fn with_target_fps(mut self, fps: Option<f64>) -> Self {
+        self.set_target_fps(fps);
+        self
+    }
+
+    /// Stops or resumes passing SDL's input to the world, such as while
+    /// a replay is fed to it
+    pub fn set_live_input(&mut self, live_input: bool) {
+        self.live_input = live_input;
+    }
+
+    pub fn live_input(&self) -> bool {
+        self.live_input
+    }
+
+    pub fn target_fps(&self) -> Option<f64> {
+        self.target_frame_time.map(fps_of)
+    }
+
+    /// Times of the recent frames
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
     /// updates time on game loop clock. Returns a FrameTick struct, which provides
-    /// the delta time as a duration, as well as the last_time value tick_frame reset
+    /// the delta time as a duration, as well as the last_time value tick_frame reset.
+    /// With a target frame rate, first waits until the frame has taken
+    /// its share of a second.
     pub fn tick_frame(&mut self) -> FrameTick {
         let last_time = self.last_time;
+        if let Some(frame_time) = self.target_frame_time {
+            wait_until(last_time + frame_time);
+        }
         let now = Instant::now();
         let delta = now - last_time;
         self.last_time = now;
+        self.stats.record(delta);
         FrameTick { delta, last_time }
     }
 
+    /// Polls SDL events, then passes a snapshot of the frame's input and
+    /// the frame stats to the world
     pub fn handle_events<R: renderer::Renderer, CS: game::TryGetComponent>(
         &mut self,
         window: &Window,
@@ -55,6 +226,18 @@ impl MainLoopState {
         renderer: &R,
         world: &mut game::EntityWorld<R, CS>,
     ) {
+        self.poll_events(window, event_pump, renderer, world);
+    }
+
+    /// Like `handle_events`, also returning the frame's events so apps
+    /// can handle them too
+    pub fn poll_events<R: renderer::Renderer, CS: game::TryGetComponent>(
+        &mut self,
+        window: &Window,
+        event_pump: &RefCell<sdl2::EventPump>,
+        renderer: &R,
+        world: &mut game::EntityWorld<R, CS>,
+    ) -> Vec<Event> {
         use cgmath::*;
         if world.input_state.is_none() {
             let ep = event_pump.borrow();
@@ -66,13 +249,10 @@ impl MainLoopState {
                 last_mousepos: mousepos,
             });
         }
-        for event in event_pump.borrow_mut().poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => {
+        let events: Vec<Event> = event_pump.borrow_mut().poll_iter().collect();
+        for event in &events {
+            match *event {
+                Event::Quit { .. } => {
                     self.is_running = false;
                 }
 
@@ -90,25 +270,26 @@ impl MainLoopState {
                         world.input_state = Some(input_state);
                     }
                 }
-                Event::KeyDown {
-                    keycode,
-                    repeat,
-                    keymod,
-                    ..
-                } => {
-                    if let Some(code) = keycode {
-                        use sdl2::keyboard::{Keycode, Mod};
-                        if code == Keycode::R
-                            && !repeat
-                            && keymod.contains(Mod::LALTMOD)
-                        {
-                            renderer.flag_shader_recompile();
-                        }
-                    }
-                }
                 _ => {}
             }
         }
+
+        world.frame_stats.clone_from(&self.stats);
+        if !self.live_input {
+            return events;
+        }
+        if let Some(input_state) = world.input_state.clone() {
+            let snapshot =
+                game::InputSnapshot::from_event_pump(&event_pump.borrow());
+            world.set_input(snapshot, input_state);
+        }
+        if world.input.action_just_pressed("quit") {
+            self.is_running = false;
+        }
+        if world.input.action_just_pressed("recompile_shaders") {
+            renderer.flag_shader_recompile();
+        }
+        events
     }
 }
 
@@ -117,6 +298,49 @@ impl Default for MainLoopState {
         MainLoopState {
             is_running: false,
             last_time: Instant::now(),
+            target_frame_time: None,
+            stats: FrameStats::default(),
+            live_input: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_frame_stats() {
+        let mut stats = FrameStats::new(4);
+        assert_eq!(stats.fps(), None);
+        for &frame in &[100, 10, 20, 10, 10] {
+            stats.record(ms(frame));
+        }
+        // the first frame has left the window
+        assert_eq!(stats.frame_count(), 5);
+        assert_eq!(stats.min(), Some(ms(10)));
+        assert_eq!(stats.max(), Some(ms(20)));
+        assert_eq!(stats.last(), Some(ms(10)));
+        assert_eq!(stats.average(), Some(Duration::from_micros(12_500)));
+        assert_eq!(stats.fps(), Some(80.0));
+        assert_eq!(stats.one_percent_low(), Some(50.0));
+        assert!((stats.low_fps(50.0).unwrap() - 1.0 / 0.015).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_target_fps() {
+        let mut main_loop = MainLoopState::new().with_target_fps(Some(100.0));
+        assert!((main_loop.target_fps().unwrap() - 100.0).abs() < 1e-6);
+        main_loop.start();
+        for _ in 0..3 {
+            assert!(main_loop.tick_frame().delta >= ms(10));
         }
+        assert_eq!(main_loop.stats().frame_count(), 3);
+        main_loop.set_target_fps(None);
+        assert_eq!(main_loop.target_fps(), None);
     }
 }
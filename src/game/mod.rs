@@ -1,22 +1,42 @@
+pub mod animation;
+pub mod assets;
+pub mod audio;
 pub mod built_in_components;
 pub mod camera;
+pub mod camera_controllers;
 pub mod component;
 pub mod component_stores;
+pub mod culling;
+pub mod input;
 pub mod main_loop;
+pub mod physics;
+pub mod picking;
+pub mod replay;
 pub mod resource;
+pub mod scene;
+pub mod script;
+pub mod sequencer;
+pub mod simulation;
+pub mod spatial;
 pub mod system;
+#[cfg(test)]
+pub(crate) mod test_util;
 pub mod timer;
 pub mod world;
 
 pub use self::{
     camera::*,
+    camera_controllers::{ArcballCamera, FollowCamera, OrbitCamera},
+    input::{InputMap, InputSnapshot},
+    scene::spawn_model,
     timer::*,
-    world::{EntityWorld, InputSources, InputState},
+    simulation::Simulation,
+    world::{EntityWorld, InputState},
 };
 pub mod prelude {
     pub use super::component::Component;
     pub use super::component_stores::{GetComponent, Storage, TryGetComponent};
-    pub use super::main_loop::{FrameTick, MainLoopState};
+    pub use super::main_loop::{FrameStats, FrameTick, MainLoopState};
     pub use super::resource::{ResourceFetcher, ResourceResult};
     pub use super::system::EntitySystem;
 }
@@ -0,0 +1,643 @@
+//! Kinematic capsule character controller.
+//!
+//! The controller isn't simulated by the physics world: it moves by its
+//! own velocity in small substeps, pushing its capsule out of the level's
+//! triangles after each. How a push is applied depends on the slope of the
+//! surface. Walkable ground lifts the character straight up so it doesn't
+//! slide down gentle slopes, steeper surfaces push it sideways like walls,
+//! and ceilings push along their normal.
+
+use super::trimesh::TriangleMesh;
+use crate::game::camera::damping_factor;
+use crate::math::*;
+use cgmath::*;
+
+/// Most times the capsule is pushed out of the geometry per substep
+const MAX_ITERATIONS: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CharacterConfig {
+    pub radius: f32,
+    /// Height of the capsule, from the feet to the top of the head
+    pub height: f32,
+    /// Steepest walkable slope, in degrees
+    pub max_slope: f32,
+    /// Tallest ledge the character walks up without jumping
+    pub step_height: f32,
+    /// Walking speed, in units per second
+    pub speed: f32,
+    /// How quickly velocity catches up to the movement input
+    pub damping: f32,
+    /// Fraction of `damping` applied while in the air
+    pub air_control: f32,
+    /// Downwards acceleration, in units per second squared
+    pub gravity: f32,
+    /// Upwards speed when jumping, in units per second
+    pub jump_speed: f32,
+}
+
+impl Default for CharacterConfig {
+    fn default() -> Self {
+        CharacterConfig {
+            radius: 0.3,
+            height: 1.8,
+            max_slope: 50.0,
+            step_height: 0.35,
+            speed: 5.0,
+            damping: 15.0,
+            air_control: 0.2,
+            gravity: 9.81,
+            jump_speed: 5.0,
+        }
+    }
+}
+
+/// How far, and in which direction, to push a capsule out of a triangle
+#[derive(Debug, Copy, Clone)]
+struct Penetration {
+    normal: Vec3,
+    depth: f32,
+    /// The deepest point of the triangle in the capsule
+    point: Point3<f32>,
+}
+
+fn penetration(capsule: &Capsule, triangle: &Triangle) -> Option<Penetration> {
+    let (on_segment, on_triangle) =
+        triangle.closest_points_to_segment(capsule.a, capsule.b);
+    let offset = on_segment - on_triangle;
+    let distance2 = offset.magnitude2();
+    let r = capsule.radius;
+    if distance2 >= r * r {
+        return None;
+    }
+    if distance2 > 1e-10 {
+        let distance = distance2.sqrt();
+        return Some(Penetration {
+            normal: offset / distance,
+            depth: r - distance,
+            point: on_triangle,
+        });
+    }
+    // the segment passes through the triangle: push its deeper end back
+    // out on the side its middle is on
+    let mut normal = triangle.normal();
+    let middle = capsule.a.midpoint(capsule.b);
+    if (middle - triangle.a).dot(normal) < 0.0 {
+        normal = -normal;
+    }
+    let deepest = (capsule.a - triangle.a)
+        .dot(normal)
+        .min((capsule.b - triangle.a).dot(normal));
+    Some(Penetration {
+        normal,
+        depth: r - deepest,
+        point: on_triangle,
+    })
+}
+
+/// A surface the capsule was pushed out of
+#[derive(Debug, Copy, Clone)]
+struct Touch {
+    /// Direction the capsule was pushed out along
+    normal: Vec3,
+    /// Normal of the walkable surface, if the capsule stands on it
+    ground: Option<Vec3>,
+}
+
+/// Outcome of trying to step up onto an obstacle
+enum Step {
+    /// Something at step height blocks the way too
+    Blocked,
+    /// The way is clear at step height, but the move didn't carry the
+    /// character onto walkable ground
+    Clear,
+    /// Where the character landed, and the surfaces touched on the way
+    Landed(Point3<f32>, Vec<Touch>),
+}
+
+/// Moves a capsule through static geometry, keeping track of whether it
+/// stands on walkable ground
+#[derive(Debug, Clone)]
+pub struct CharacterController {
+    pub config: CharacterConfig,
+    /// Bottom of the capsule
+    position: Point3<f32>,
+    velocity: Vec3,
+    ground_normal: Option<Vec3>,
+}
+
+impl CharacterController {
+    pub fn new(position: Point3<f32>) -> Self {
+        CharacterController {
+            config: CharacterConfig::default(),
+            position,
+            velocity: Vec3::zero(),
+            ground_normal: None,
+        }
+    }
+
+    pub fn with_config(mut self, config: CharacterConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Position of the character's feet
+    pub fn position(&self) -> Point3<f32> {
+        self.position
+    }
+
+    /// Moves the character without colliding, e.g. to respawn it
+    pub fn set_position(&mut self, position: Point3<f32>) {
+        self.position = position;
+        self.ground_normal = None;
+    }
+
+    pub fn velocity(&self) -> Vec3 {
+        self.velocity
+    }
+
+    pub fn set_velocity(&mut self, velocity: Vec3) {
+        self.velocity = velocity;
+    }
+
+    /// Whether the character stood on walkable ground after its last
+    /// move
+    pub fn is_grounded(&self) -> bool {
+        self.ground_normal.is_some()
+    }
+
+    /// Normal of the ground the character stands on
+    pub fn ground_normal(&self) -> Option<Vec3> {
+        self.ground_normal
+    }
+
+    /// The character's collision capsule
+    pub fn capsule(&self) -> Capsule {
+        self.capsule_at(self.position)
+    }
+
+    fn capsule_at(&self, position: Point3<f32>) -> Capsule {
+        let r = self.config.radius;
+        let top = (self.config.height - r).max(r);
+        Capsule::new(
+            position + Vec3::unit_y() * r,
+            position + Vec3::unit_y() * top,
+            r,
+        )
+    }
+
+    /// Jumps if the character is on the ground. Returns whether it did.
+    pub fn jump(&mut self) -> bool {
+        if !self.is_grounded() {
+            return false;
+        }
+        self.velocity.y = self.config.jump_speed;
+        self.ground_normal = None;
+        true
+    }
+
+    /// Accelerates towards the movement input, relative to the direction
+    /// `forward` flattened onto the ground plane, then moves through the
+    /// level by the character's velocity
+    pub fn input_move(
+        &mut self,
+        wasd_axis: Vec2,
+        forward: Vec3,
+        dt: f32,
+        level: &TriangleMesh,
+    ) {
+        let forward = vec3(forward.x, 0.0, forward.z);
+        let forward = if forward.magnitude2() > 1e-8 {
+            forward.normalize()
+        } else {
+            -Vec3::unit_z()
+        };
+        let right = forward.cross(Vec3::unit_y());
+        let target_velocity = if wasd_axis.magnitude2() > 0.0 {
+            (wasd_axis.x * right + wasd_axis.y * forward).normalize()
+                * self.config.speed
+        } else {
+            Vec3::zero()
+        };
+        let damping = if self.is_grounded() {
+            self.config.damping
+        } else {
+            self.config.damping * self.config.air_control
+        };
+        let t = damping_factor(damping, dt);
+        let mut horizontal = vec3(self.velocity.x, 0.0, self.velocity.z);
+        horizontal += (target_velocity - horizontal) * t;
+        if target_velocity == Vec3::zero() && horizontal.magnitude() < 1e-3 {
+            horizontal = Vec3::zero();
+        }
+        self.velocity.x = horizontal.x;
+        self.velocity.z = horizontal.z;
+        self.velocity.y -= self.config.gravity * dt;
+        self.move_and_slide(dt, level);
+    }
+
+    /// Moves by the character's velocity for `dt` seconds, sliding along
+    /// walls, stepping up ledges and following the ground down slopes.
+    /// Velocity into the surfaces met is removed.
+    pub fn move_and_slide(&mut self, dt: f32, level: &TriangleMesh) {
+        let was_grounded = self.is_grounded();
+        let start = self.position;
+        let horizontal = vec3(self.velocity.x, 0.0, self.velocity.z) * dt;
+
+        let mut position = start;
+        let mut touches = Vec::new();
+        self.move_by(&mut position, horizontal, level, &mut touches);
+        let blocked = touches.iter().any(|t| self.is_wall(t));
+        // walls below step height don't stop the character, even when this
+        // move is too short to get on top of them
+        let mut steppable = false;
+        if was_grounded && blocked {
+            let distance = |p: Point3<f32>| {
+                vec3(p.x - start.x, 0.0, p.z - start.z).magnitude()
+            };
+            match self.step_up(start, horizontal, level) {
+                Step::Landed(stepped, step_touches)
+                    if distance(stepped) > distance(position) + 1e-4 =>
+                {
+                    position = stepped;
+                    touches = step_touches;
+                }
+                Step::Blocked => (),
+                _ => steppable = true,
+            }
+        }
+
+        let fall = Vec3::unit_y() * (self.velocity.y * dt);
+        self.move_by(&mut position, fall, level, &mut touches);
+
+        // stick to the ground when walking down slopes and stairs
+        let on_ground = touches.iter().any(|t| t.ground.is_some());
+        if was_grounded && !on_ground && self.velocity.y <= 0.0 {
+            let mut probe = position;
+            let mut probe_touches = Vec::new();
+            let down = -Vec3::unit_y() * self.config.step_height;
+            self.move_by(&mut probe, down, level, &mut probe_touches);
+            if probe_touches.iter().any(|t| t.ground.is_some()) {
+                position = probe;
+                touches.extend(probe_touches);
+            }
+        }
+
+        for touch in &touches {
+            if !(steppable && self.is_wall(touch)) {
+                self.clip_velocity(touch);
+            }
+        }
+        self.ground_normal = touches.iter().filter_map(|t| t.ground).fold(
+            None,
+            |best: Option<Vec3>, n| match best {
+                Some(best) if best.y >= n.y => Some(best),
+                _ => Some(n),
+            },
+        );
+        self.position = position;
+    }
+
+    fn is_walkable(&self, normal: Vec3) -> bool {
+        normal.y >= Deg(self.config.max_slope).cos()
+    }
+
+    /// Too steep to walk on, but not a ceiling
+    fn is_wall(&self, touch: &Touch) -> bool {
+        touch.ground.is_none()
+            && touch.normal.y > -Deg(self.config.max_slope).cos()
+    }
+
+    /// Climbs `step_height`, moves, then drops back down
+    fn step_up(
+        &self,
+        start: Point3<f32>,
+        horizontal: Vec3,
+        level: &TriangleMesh,
+    ) -> Step {
+        let mut position = start;
+        let mut touches = Vec::new();
+        let up = Vec3::unit_y() * self.config.step_height;
+        self.move_by(&mut position, up, level, &mut touches);
+        let lifted = position.y - start.y;
+        if lifted < 1e-4 {
+            return Step::Blocked;
+        }
+        touches.clear();
+        self.move_by(&mut position, horizontal, level, &mut touches);
+        if touches.iter().any(|t| self.is_wall(t)) {
+            return Step::Blocked;
+        }
+        let mut down_touches = Vec::new();
+        let down = -Vec3::unit_y() * lifted;
+        self.move_by(&mut position, down, level, &mut down_touches);
+        if !down_touches.iter().any(|t| t.ground.is_some()) {
+            return Step::Clear;
+        }
+        touches.extend(down_touches);
+        Step::Landed(position, touches)
+    }
+
+    /// Moves in substeps no longer than half the radius, so thin
+    /// geometry can't be skipped, resolving overlaps after each
+    fn move_by(
+        &self,
+        position: &mut Point3<f32>,
+        displacement: Vec3,
+        level: &TriangleMesh,
+        touches: &mut Vec<Touch>,
+    ) {
+        let max_step = self.config.radius * 0.5;
+        let steps = (displacement.magnitude() / max_step).ceil().max(1.0);
+        let step = displacement / steps;
+        for _ in 0..steps as usize {
+            *position += step;
+            self.depenetrate(position, level, touches);
+        }
+    }
+
+    fn depenetrate(
+        &self,
+        position: &mut Point3<f32>,
+        level: &TriangleMesh,
+        touches: &mut Vec<Touch>,
+    ) {
+        for _ in 0..MAX_ITERATIONS {
+            let nearby = level.query_aabb(&self.capsule_at(*position).aabb());
+            let mut pushed = false;
+            for triangle in nearby {
+                let capsule = self.capsule_at(*position);
+                if let Some(hit) = penetration(&capsule, triangle) {
+                    let touch = self.touch(&hit, *position, level);
+                    *position += self.push_out(&hit, &touch);
+                    touches.push(touch);
+                    pushed = true;
+                }
+            }
+            if !pushed {
+                break;
+            }
+        }
+    }
+
+    /// Works out whether a contact supports the character. Contacts
+    /// with the rounded bottom of the capsule on the edge of a ledge have
+    /// steep normals, so the surface just past the edge is checked too.
+    fn touch(
+        &self,
+        hit: &Penetration,
+        position: Point3<f32>,
+        level: &TriangleMesh,
+    ) -> Touch {
+        let n = hit.normal;
+        let ground = if self.is_walkable(n) {
+            Some(n)
+        } else if n.y > 0.1
+            && hit.point.y - position.y <= self.config.step_height
+        {
+            let flat = vec3(n.x, 0.0, n.z).normalize();
+            let eps = self.config.radius * 0.05;
+            let probe = Ray::new(
+                hit.point - flat * eps + Vec3::unit_y() * eps,
+                -Vec3::unit_y(),
+            );
+            level
+                .raycast(&probe, eps * 2.0)
+                .map(|(triangle, _)| {
+                    let normal = triangle.normal();
+                    if normal.y < 0.0 {
+                        -normal
+                    } else {
+                        normal
+                    }
+                })
+                .filter(|&normal| self.is_walkable(normal))
+        } else {
+            None
+        };
+        Touch { normal: n, ground }
+    }
+
+    fn push_out(&self, hit: &Penetration, touch: &Touch) -> Vec3 {
+        let n = hit.normal;
+        if touch.ground.is_some() {
+            return Vec3::unit_y() * (hit.depth / n.y);
+        }
+        let flat = vec3(n.x, 0.0, n.z);
+        if n.y > 0.0 && flat.magnitude2() > 1e-8 {
+            flat * (hit.depth / flat.magnitude2())
+        } else {
+            n * hit.depth
+        }
+    }
+
+    fn clip_velocity(&mut self, touch: &Touch) {
+        if touch.ground.is_some() {
+            self.velocity.y = self.velocity.y.max(0.0);
+            return;
+        }
+        // steep slopes only stop horizontal movement, so they can't be
+        // climbed by walking into them
+        let n = touch.normal;
+        let n = if n.y > 0.0 {
+            let flat = vec3(n.x, 0.0, n.z);
+            if flat.magnitude2() < 1e-8 {
+                return;
+            }
+            flat.normalize()
+        } else {
+            n
+        };
+        let into = self.velocity.dot(n);
+        if into < 0.0 {
+            self.velocity -= n * into;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn quad(level: &mut TriangleMesh, corners: [Point3<f32>; 4]) {
+        let [a, b, c, d] = corners;
+        level.add_triangle(Triangle::new(a, b, c));
+        level.add_triangle(Triangle::new(a, c, d));
+    }
+
+    /// A floor at y = 0, with a wall at z = -5, a 0.3 high step starting
+    /// at x = 5, and a 60 degree ramp rising from x = -5
+    fn level() -> TriangleMesh {
+        let p = Point3::new;
+        let mut level = TriangleMesh::new();
+        quad(
+            &mut level,
+            [
+                p(-5.0, 0.0, -10.0),
+                p(-5.0, 0.0, 10.0),
+                p(5.0, 0.0, 10.0),
+                p(5.0, 0.0, -10.0),
+            ],
+        );
+        quad(
+            &mut level,
+            [
+                p(-5.0, 0.0, -5.0),
+                p(5.0, 0.0, -5.0),
+                p(5.0, 3.0, -5.0),
+                p(-5.0, 3.0, -5.0),
+            ],
+        );
+        // step: riser and top
+        quad(
+            &mut level,
+            [
+                p(5.0, 0.0, -10.0),
+                p(5.0, 0.0, 10.0),
+                p(5.0, 0.3, 10.0),
+                p(5.0, 0.3, -10.0),
+            ],
+        );
+        quad(
+            &mut level,
+            [
+                p(5.0, 0.3, -10.0),
+                p(5.0, 0.3, 10.0),
+                p(15.0, 0.3, 10.0),
+                p(15.0, 0.3, -10.0),
+            ],
+        );
+        let rise = 60f32.to_radians().tan() * 5.0;
+        quad(
+            &mut level,
+            [
+                p(-5.0, 0.0, -10.0),
+                p(-10.0, rise, -10.0),
+                p(-10.0, rise, 10.0),
+                p(-5.0, 0.0, 10.0),
+            ],
+        );
+        level
+    }
+
+    fn walk(
+        character: &mut CharacterController,
+        level: &TriangleMesh,
+        forward: Vec3,
+        seconds: f32,
+    ) {
+        let dt = 1.0 / 60.0;
+        for _ in 0..(seconds / dt) as usize {
+            character.input_move(Vec2::new(0.0, 1.0), forward, dt, level);
+        }
+    }
+
+    fn settle(character: &mut CharacterController, level: &TriangleMesh) {
+        for _ in 0..120 {
+            character.input_move(
+                Vec2::zero(),
+                Vec3::unit_z(),
+                1.0 / 60.0,
+                level,
+            );
+        }
+    }
+
+    #[test]
+    fn test_lands_on_ground() {
+        let level = level();
+        let mut character =
+            CharacterController::new(Point3::new(0.0, 2.0, 0.0));
+        assert!(!character.is_grounded());
+        settle(&mut character, &level);
+        assert!(character.is_grounded());
+        assert!(character.position().y.abs() < 0.01);
+        assert_eq!(character.velocity(), Vec3::zero());
+        assert_eq!(character.ground_normal(), Some(Vec3::unit_y()));
+
+        assert!(character.jump());
+        assert!(!character.jump());
+        character.input_move(Vec2::zero(), Vec3::unit_z(), 1.0 / 60.0, &level);
+        assert!(character.position().y > 0.05);
+        assert!(!character.is_grounded());
+        settle(&mut character, &level);
+        assert!(character.is_grounded());
+    }
+
+    #[test]
+    fn test_slides_along_walls() {
+        let level = level();
+        let mut character =
+            CharacterController::new(Point3::new(0.0, 0.0, -3.0));
+        settle(&mut character, &level);
+        // walk diagonally into the wall
+        walk(&mut character, &level, vec3(1.0, 0.0, -1.0), 1.0);
+        let position = character.position();
+        assert!(position.z >= -5.0 + 0.3 - 0.01, "at {:?}", position);
+        assert!(position.z < -4.6);
+        assert!(position.x > 2.0, "should slide along x, at {:?}", position);
+        assert!(position.y.abs() < 0.01);
+        assert!(character.velocity().z.abs() < 1e-3);
+        assert!(character.is_grounded());
+    }
+
+    #[test]
+    fn test_steps_up_ledges() {
+        let level = level();
+        let mut character =
+            CharacterController::new(Point3::new(3.0, 0.0, 0.0));
+        settle(&mut character, &level);
+        walk(&mut character, &level, Vec3::unit_x(), 1.0);
+        let position = character.position();
+        assert!(position.x > 6.0, "stuck at {:?}", position);
+        assert!((position.y - 0.3).abs() < 0.01);
+        assert!(character.is_grounded());
+
+        // a step taller than step_height blocks the character
+        let mut short = CharacterController::new(Point3::new(3.0, 0.0, 0.0))
+            .with_config(CharacterConfig {
+                step_height: 0.1,
+                ..CharacterConfig::default()
+            });
+        settle(&mut short, &level);
+        walk(&mut short, &level, Vec3::unit_x(), 1.0);
+        assert!(short.position().x < 5.0 - 0.25, "at {:?}", short.position());
+        assert!(short.position().y.abs() < 0.01);
+    }
+
+    #[test]
+    fn test_slope_limit() {
+        let level = level();
+        let mut character =
+            CharacterController::new(Point3::new(-3.0, 0.0, 0.0));
+        settle(&mut character, &level);
+        walk(&mut character, &level, -Vec3::unit_x(), 2.0);
+        // the 60 degree ramp is too steep to walk up
+        let position = character.position();
+        assert!(position.x > -5.5 && position.y < 0.5, "at {:?}", position);
+
+        // placed on the ramp, it slides off
+        let mut sliding = CharacterController::new(Point3::new(-7.0, 5.0, 0.0));
+        for _ in 0..240 {
+            sliding.input_move(
+                Vec2::zero(),
+                Vec3::unit_z(),
+                1.0 / 60.0,
+                &level,
+            );
+        }
+        assert!(sliding.position().y < 0.1, "at {:?}", sliding.position());
+        assert!(sliding.is_grounded());
+
+        // a gentler limit lets it walk up
+        let mut climber = CharacterController::new(Point3::new(-3.0, 0.0, 0.0))
+            .with_config(CharacterConfig {
+                max_slope: 65.0,
+                ..CharacterConfig::default()
+            });
+        settle(&mut climber, &level);
+        walk(&mut climber, &level, -Vec3::unit_x(), 2.0);
+        assert!(climber.position().y > 1.0, "at {:?}", climber.position());
+    }
+}
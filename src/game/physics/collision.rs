@@ -0,0 +1,311 @@
+//! Narrowphase contact generation between world space collider shapes.
+
+use crate::math::*;
+use cgmath::*;
+
+/// A collider's shape placed in world space
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WorldShape {
+    Sphere(Sphere),
+    Capsule(Capsule),
+    Box(Obb),
+}
+
+impl WorldShape {
+    pub fn aabb(&self) -> Aabb {
+        match self {
+            WorldShape::Sphere(sphere) => sphere.aabb(),
+            WorldShape::Capsule(capsule) => capsule.aabb(),
+            WorldShape::Box(obb) => obb.aabb(),
+        }
+    }
+}
+
+/// The deepest point of overlap between two shapes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Contact {
+    /// Unit normal pointing from the first shape towards the second
+    pub normal: Vec3,
+    /// How far the shapes overlap along the normal
+    pub depth: f32,
+    /// World space contact point, between the two surfaces
+    pub point: Point3<f32>,
+}
+
+impl Contact {
+    fn flipped(self) -> Contact {
+        Contact {
+            normal: -self.normal,
+            ..self
+        }
+    }
+}
+
+/// Finds the contact between two shapes, if they overlap
+pub fn collide(a: &WorldShape, b: &WorldShape) -> Option<Contact> {
+    use self::WorldShape::*;
+    match (a, b) {
+        (Sphere(a), Sphere(b)) => sphere_sphere(a, b),
+        (Sphere(a), Capsule(b)) => sphere_capsule(a, b),
+        (Sphere(a), Box(b)) => sphere_box(a, b),
+        (Capsule(a), Sphere(b)) => sphere_capsule(b, a).map(Contact::flipped),
+        (Capsule(a), Capsule(b)) => capsule_capsule(a, b),
+        (Capsule(a), Box(b)) => capsule_box(a, b),
+        (Box(a), Sphere(b)) => sphere_box(b, a).map(Contact::flipped),
+        (Box(a), Capsule(b)) => capsule_box(b, a).map(Contact::flipped),
+        (Box(a), Box(b)) => box_box(a, b),
+    }
+}
+
+pub fn sphere_sphere(a: &Sphere, b: &Sphere) -> Option<Contact> {
+    let offset = b.center - a.center;
+    let distance = offset.magnitude();
+    let depth = a.radius + b.radius - distance;
+    if depth < 0.0 {
+        return None;
+    }
+    // concentric spheres push apart along an arbitrary axis
+    let normal = if distance > 1e-6 {
+        offset / distance
+    } else {
+        Vec3::unit_y()
+    };
+    Some(Contact {
+        normal,
+        depth,
+        point: a.center + normal * (a.radius - depth * 0.5),
+    })
+}
+
+pub fn sphere_capsule(a: &Sphere, b: &Capsule) -> Option<Contact> {
+    let center = b.segment_point(a.center);
+    sphere_sphere(a, &Sphere::new(center, b.radius))
+}
+
+pub fn capsule_capsule(a: &Capsule, b: &Capsule) -> Option<Contact> {
+    let (pa, pb) = closest_points_between_segments(a.a, a.b, b.a, b.b);
+    sphere_sphere(&Sphere::new(pa, a.radius), &Sphere::new(pb, b.radius))
+}
+
+pub fn sphere_box(a: &Sphere, b: &Obb) -> Option<Contact> {
+    let closest = b.closest_point(a.center);
+    let offset = closest - a.center;
+    let distance2 = offset.magnitude2();
+    if distance2 > a.radius * a.radius {
+        return None;
+    }
+    if distance2 > 1e-12 {
+        let distance = distance2.sqrt();
+        let depth = a.radius - distance;
+        return Some(Contact {
+            normal: offset / distance,
+            depth,
+            point: closest - offset / distance * (depth * 0.5),
+        });
+    }
+    // the center is inside the box: push out through the nearest face
+    let local = b.to_local(a.center);
+    let (axis, face_distance) = (0..3)
+        .map(|i| (i, b.half_extents[i] - local[i].abs()))
+        .fold((0, std::f32::INFINITY), |best, candidate| {
+            if candidate.1 < best.1 {
+                candidate
+            } else {
+                best
+            }
+        });
+    let outward = b.axes[axis] * local[axis].signum();
+    Some(Contact {
+        normal: -outward,
+        depth: a.radius + face_distance,
+        point: a.center,
+    })
+}
+
+pub fn capsule_box(a: &Capsule, b: &Obb) -> Option<Contact> {
+    // walk towards the segment point closest to the box
+    let mut point = a.segment_point(b.center);
+    for _ in 0..3 {
+        point = a.segment_point(b.closest_point(point));
+    }
+    sphere_box(&Sphere::new(point, a.radius), b)
+}
+
+/// Separating axis test, pushing out along the axis of least overlap
+pub fn box_box(a: &Obb, b: &Obb) -> Option<Contact> {
+    let mut axes = Vec::with_capacity(15);
+    axes.extend_from_slice(&a.axes);
+    axes.extend_from_slice(&b.axes);
+    for ea in &a.axes {
+        for eb in &b.axes {
+            let cross = ea.cross(*eb);
+            if cross.magnitude2() > 1e-6 {
+                axes.push(cross.normalize());
+            }
+        }
+    }
+    let offset = b.center - a.center;
+    let mut best: Option<(f32, Vec3)> = None;
+    for (i, &axis) in axes.iter().enumerate() {
+        let distance = offset.dot(axis);
+        let overlap = a.projected_radius(axis) + b.projected_radius(axis)
+            - distance.abs();
+        if overlap < 0.0 {
+            return None;
+        }
+        // prefer face axes over nearly equal edge axes, which are less
+        // stable for resting contact
+        let bias = if i < 6 { 0.0 } else { 1e-3 };
+        if best.map_or(true, |(o, _)| overlap + bias < o) {
+            let normal = if distance < 0.0 { -axis } else { axis };
+            best = Some((overlap, normal));
+        }
+    }
+    let (depth, normal) = best?;
+    // use the smaller of the two touching features for the contact point,
+    // or the smaller box's if both are faces
+    let (count_a, feature_a) = support_feature(a, normal);
+    let (count_b, feature_b) = support_feature(b, -normal);
+    let volume = |obb: &Obb| {
+        let h = obb.half_extents;
+        h.x * h.y * h.z
+    };
+    let point =
+        if count_a < count_b || (count_a == count_b && volume(a) < volume(b)) {
+            feature_a - normal * (depth * 0.5)
+        } else {
+            feature_b + normal * (depth * 0.5)
+        };
+    Some(Contact {
+        normal,
+        depth,
+        point,
+    })
+}
+
+/// The centroid and vertex count of the box's vertex, edge, or face
+/// furthest along `direction`
+fn support_feature(obb: &Obb, direction: Vec3) -> (usize, Point3<f32>) {
+    let corners = obb.corners();
+    let furthest = corners
+        .iter()
+        .map(|c| c.to_vec().dot(direction))
+        .fold(std::f32::NEG_INFINITY, f32::max);
+    let tolerance = 1e-3 * (1.0 + obb.half_extents.magnitude());
+    let feature: Vec<_> = corners
+        .iter()
+        .filter(|c| c.to_vec().dot(direction) >= furthest - tolerance)
+        .cloned()
+        .collect();
+    (feature.len(), Point3::centroid(&feature))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cube(center: Point3<f32>, half: f32) -> Obb {
+        Obb::new(
+            center,
+            [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()],
+            vec3(half, half, half),
+        )
+    }
+
+    fn near(a: Vec3, b: Vec3) -> bool {
+        (a - b).magnitude() < 1e-4
+    }
+
+    #[test]
+    fn test_sphere_contacts() {
+        let a = Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0);
+        let b = Sphere::new(Point3::new(1.5, 0.0, 0.0), 1.0);
+        let contact = sphere_sphere(&a, &b).unwrap();
+        assert!(near(contact.normal, Vec3::unit_x()));
+        assert!((contact.depth - 0.5).abs() < 1e-6);
+        assert!(near(contact.point.to_vec(), vec3(0.75, 0.0, 0.0)));
+        assert!(sphere_sphere(
+            &a,
+            &Sphere::new(Point3::new(2.5, 0.0, 0.0), 1.0)
+        )
+        .is_none());
+
+        // resting on top of a box
+        let ground = cube(Point3::new(0.0, -1.0, 0.0), 1.0);
+        let ball =
+            WorldShape::Sphere(Sphere::new(Point3::new(0.3, 0.9, 0.0), 1.0));
+        let contact = collide(&WorldShape::Box(ground), &ball).unwrap();
+        assert!(near(contact.normal, Vec3::unit_y()));
+        assert!((contact.depth - 0.1).abs() < 1e-5);
+        // sunk into the box, it still pushes out through the top
+        let sunk = Sphere::new(Point3::new(0.0, -0.2, 0.0), 0.5);
+        let contact = sphere_box(&sunk, &ground).unwrap();
+        assert!(near(contact.normal, -Vec3::unit_y()));
+        assert!((contact.depth - 0.7).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_capsule_contacts() {
+        let standing = Capsule::new(
+            Point3::new(0.0, 0.5, 0.0),
+            Point3::new(0.0, 1.5, 0.0),
+            0.5,
+        );
+        let ground = cube(Point3::new(0.0, -1.0, 0.0), 1.0);
+        let contact = capsule_box(&standing, &ground).unwrap();
+        assert!(near(contact.normal, -Vec3::unit_y()));
+        assert!(contact.depth.abs() < 1e-5);
+
+        let lying = Capsule::new(
+            Point3::new(-1.0, 0.4, 0.0),
+            Point3::new(1.0, 0.4, 0.0),
+            0.5,
+        );
+        let contact = capsule_box(&lying, &ground).unwrap();
+        assert!((contact.depth - 0.1).abs() < 1e-5);
+
+        let other = Capsule::new(
+            Point3::new(0.8, 0.0, -1.0),
+            Point3::new(0.8, 0.0, 1.0),
+            0.5,
+        );
+        let contact = collide(
+            &WorldShape::Capsule(
+                standing
+                    .transform(&Mat4::from_translation(vec3(0.0, -1.0, 0.0))),
+            ),
+            &WorldShape::Capsule(other),
+        )
+        .unwrap();
+        assert!(near(contact.normal, Vec3::unit_x()));
+        assert!((contact.depth - 0.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_box_contacts() {
+        let ground = cube(Point3::new(0.0, -5.0, 0.0), 5.0);
+        let crate_box = cube(Point3::new(1.0, 0.4, 2.0), 0.5);
+        let contact = box_box(&ground, &crate_box).unwrap();
+        assert!(near(contact.normal, Vec3::unit_y()));
+        assert!((contact.depth - 0.1).abs() < 1e-5);
+        // the crate's bottom face is the smaller feature, so the contact
+        // sits under its center rather than the ground's
+        assert!(near(contact.point.to_vec(), vec3(1.0, -0.05, 2.0)));
+
+        let apart = cube(Point3::new(1.0, 0.6, 2.0), 0.5);
+        assert!(box_box(&ground, &apart).is_none());
+
+        // a corner of a rotated box pointing into the ground
+        let tilted = crate_box.transform(
+            &(Mat4::from_translation(vec3(0.0, 0.3, 0.0))
+                * Mat4::from_translation(crate_box.center.to_vec())
+                * Mat4::from_angle_z(Deg(45.0))
+                * Mat4::from_translation(-crate_box.center.to_vec())),
+        );
+        let contact = box_box(&ground, &tilted).unwrap();
+        assert!(near(contact.normal, Vec3::unit_y()));
+        let corner_depth = 0.5 * 2f32.sqrt() - 0.7;
+        assert!((contact.depth - corner_depth).abs() < 1e-4);
+        assert!((contact.point.x - 1.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,909 @@
+//! Rigid body dynamics on a fixed timestep.
+//!
+//! Entities with a `RigidBody` and a `TransformComponent` are simulated;
+//! entities with only a `Collider` are static geometry. Each step
+//! integrates velocities (semi-implicit Euler), finds overlapping pairs
+//! with a sweep and prune broadphase, generates contacts, and resolves
+//! them with sequential impulses before integrating positions and writing
+//! them back to the transforms. Bodies are simulated in their parent's
+//! space, so they should be root entities.
+//!
+//! Player characters are moved by a kinematic `CharacterController`
+//! against static `TriangleMesh` level geometry instead.
+
+pub mod character;
+pub mod collision;
+pub mod trimesh;
+
+use self::collision::{collide, Contact, WorldShape};
+use super::{component::*, timer::FixedTimestep, TryGetComponent};
+use crate::math::*;
+use cgmath::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// An entity's local transform
+type Pose = Decomposed<Vec3, Quaternion<f32>>;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColliderShape {
+    Sphere {
+        radius: f32,
+    },
+    /// A box with the given half extents along each local axis
+    Box {
+        half_extents: Vec3,
+    },
+    /// A capsule along the local y axis, with `half_height` from its
+    /// center to each hemisphere's center
+    Capsule {
+        half_height: f32,
+        radius: f32,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Collider {
+    pub shape: ColliderShape,
+    /// Offset of the shape from the entity's origin, in local space
+    pub offset: Vec3,
+    /// Triggers report overlaps as events, without colliding
+    pub trigger: bool,
+    /// Bounciness, from 0 to 1. The larger of two colliders' is used.
+    pub restitution: f32,
+    pub friction: f32,
+}
+
+impl Component for Collider {}
+
+impl Collider {
+    pub fn new(shape: ColliderShape) -> Self {
+        Collider {
+            shape,
+            offset: Vec3::zero(),
+            trigger: false,
+            restitution: 0.2,
+            friction: 0.5,
+        }
+    }
+
+    pub fn sphere(radius: f32) -> Self {
+        Collider::new(ColliderShape::Sphere { radius })
+    }
+
+    pub fn cuboid(half_extents: Vec3) -> Self {
+        Collider::new(ColliderShape::Box { half_extents })
+    }
+
+    pub fn capsule(half_height: f32, radius: f32) -> Self {
+        Collider::new(ColliderShape::Capsule {
+            half_height,
+            radius,
+        })
+    }
+
+    /// The shape placed by an entity's transform
+    pub fn world_shape(&self, transform: &Pose) -> WorldShape {
+        let scale = transform.scale;
+        let center = Point3::from_vec(transform.transform_vector(self.offset))
+            + transform.disp;
+        let rot = transform.rot;
+        match self.shape {
+            ColliderShape::Sphere { radius } => {
+                WorldShape::Sphere(Sphere::new(center, radius * scale))
+            }
+            ColliderShape::Box { half_extents } => WorldShape::Box(Obb::new(
+                center,
+                [
+                    rot.rotate_vector(Vec3::unit_x()),
+                    rot.rotate_vector(Vec3::unit_y()),
+                    rot.rotate_vector(Vec3::unit_z()),
+                ],
+                half_extents * scale,
+            )),
+            ColliderShape::Capsule {
+                half_height,
+                radius,
+            } => {
+                let axis =
+                    rot.rotate_vector(Vec3::unit_y()) * half_height * scale;
+                WorldShape::Capsule(Capsule::new(
+                    center - axis,
+                    center + axis,
+                    radius * scale,
+                ))
+            }
+        }
+    }
+
+    /// Principal moments of inertia of the shape with the given mass.
+    /// Capsules are approximated by their bounding box.
+    fn inertia(&self, mass: f32, scale: f32) -> Vec3 {
+        let box_inertia = |h: Vec3| {
+            let h2 = h.mul_element_wise(h);
+            vec3(h2.y + h2.z, h2.x + h2.z, h2.x + h2.y) * (mass / 3.0)
+        };
+        match self.shape {
+            ColliderShape::Sphere { radius } => {
+                let r = radius * scale;
+                let i = 0.4 * mass * r * r;
+                vec3(i, i, i)
+            }
+            ColliderShape::Box { half_extents } => {
+                box_inertia(half_extents * scale)
+            }
+            ColliderShape::Capsule {
+                half_height,
+                radius,
+            } => {
+                box_inertia(vec3(radius, half_height + radius, radius) * scale)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RigidBody {
+    pub mass: f32,
+    pub velocity: Vec3,
+    /// Rotation axis scaled by speed in radians per second
+    pub angular_velocity: Vec3,
+    /// Fraction of linear velocity lost per second, roughly
+    pub linear_damping: f32,
+    pub angular_damping: f32,
+    pub gravity_scale: f32,
+    /// Kinematic bodies move by their velocity, but aren't affected by
+    /// gravity or pushed by collisions
+    pub kinematic: bool,
+}
+
+impl Component for RigidBody {}
+
+impl Default for RigidBody {
+    fn default() -> Self {
+        RigidBody {
+            mass: 1.0,
+            velocity: Vec3::zero(),
+            angular_velocity: Vec3::zero(),
+            linear_damping: 0.05,
+            angular_damping: 0.05,
+            gravity_scale: 1.0,
+            kinematic: false,
+        }
+    }
+}
+
+impl RigidBody {
+    pub fn new(mass: f32) -> Self {
+        RigidBody {
+            mass,
+            ..RigidBody::default()
+        }
+    }
+
+    pub fn kinematic(velocity: Vec3) -> Self {
+        RigidBody {
+            velocity,
+            kinematic: true,
+            ..RigidBody::default()
+        }
+    }
+
+    fn inverse_mass(&self) -> f32 {
+        if self.kinematic || self.mass <= 0.0 {
+            0.0
+        } else {
+            1.0 / self.mass
+        }
+    }
+
+    /// Changes the body's velocity by an impulse through its center
+    pub fn apply_impulse(&mut self, impulse: Vec3) {
+        self.velocity += impulse * self.inverse_mass();
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ContactPhase {
+    /// The pair started touching this step
+    Began,
+    Persisted,
+    /// The pair stopped touching this step
+    Ended,
+}
+
+/// A contact or trigger overlap between two entities, reported once per
+/// physics step. `a` always has the lower entity index.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CollisionEvent {
+    pub a: Entity,
+    pub b: Entity,
+    pub phase: ContactPhase,
+    pub trigger: bool,
+    /// Unit normal pointing from `a` towards `b`
+    pub normal: Vec3,
+    pub point: Point3<f32>,
+    /// Normal impulse applied to separate the pair this step
+    pub impulse: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct PhysicsConfig {
+    pub gravity: Vec3,
+    pub timestep: Duration,
+    /// Most steps run in one update
+    pub max_steps: u32,
+    /// Impulse solver iterations per step
+    pub iterations: usize,
+    /// Penetration depth left uncorrected, to keep resting contacts stable
+    pub slop: f32,
+    /// Fraction of the remaining penetration corrected each step
+    pub correction: f32,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        PhysicsConfig {
+            gravity: vec3(0.0, -9.81, 0.0),
+            timestep: Duration::from_micros(16_667),
+            max_steps: 5,
+            iterations: 10,
+            slop: 0.005,
+            correction: 0.4,
+        }
+    }
+}
+
+/// Simulation state of an entity during a step
+struct Body {
+    entity: Entity,
+    transform: Pose,
+    velocity: Vec3,
+    angular_velocity: Vec3,
+    inverse_mass: f32,
+    /// Inverse principal moments of inertia, in local space
+    inverse_inertia: Vec3,
+    has_body: bool,
+    collider: Option<Collider>,
+    shape: Option<WorldShape>,
+}
+
+impl Body {
+    fn is_dynamic(&self) -> bool {
+        self.inverse_mass > 0.0
+    }
+
+    /// Applies the world space inverse inertia tensor to a vector
+    fn inverse_inertia_world(&self, v: Vec3) -> Vec3 {
+        let rot = self.transform.rot;
+        let local = rot.conjugate().rotate_vector(v);
+        rot.rotate_vector(local.mul_element_wise(self.inverse_inertia))
+    }
+
+    fn velocity_at(&self, offset: Vec3) -> Vec3 {
+        self.velocity + self.angular_velocity.cross(offset)
+    }
+
+    fn apply_impulse(&mut self, impulse: Vec3, offset: Vec3) {
+        self.velocity += impulse * self.inverse_mass;
+        self.angular_velocity +=
+            self.inverse_inertia_world(offset.cross(impulse));
+    }
+}
+
+/// A contact being resolved by the solver
+struct Constraint {
+    a: usize,
+    b: usize,
+    contact: Contact,
+    /// Contact point relative to each body's origin
+    ra: Vec3,
+    rb: Vec3,
+    tangents: [Vec3; 2],
+    /// Normal velocity the solver aims for, from restitution
+    target: f32,
+    friction: f32,
+    normal_impulse: f32,
+    tangent_impulses: [f32; 2],
+}
+
+impl Constraint {
+    fn effective_mass(&self, bodies: &[Body], direction: Vec3) -> f32 {
+        let (a, b) = (&bodies[self.a], &bodies[self.b]);
+        let angular = |body: &Body, r: Vec3| {
+            body.inverse_inertia_world(r.cross(direction))
+                .cross(r)
+                .dot(direction)
+        };
+        a.inverse_mass
+            + b.inverse_mass
+            + angular(a, self.ra)
+            + angular(b, self.rb)
+    }
+
+    fn relative_velocity(&self, bodies: &[Body]) -> Vec3 {
+        bodies[self.b].velocity_at(self.rb)
+            - bodies[self.a].velocity_at(self.ra)
+    }
+
+    fn apply(&self, bodies: &mut [Body], impulse: Vec3) {
+        bodies[self.a].apply_impulse(-impulse, self.ra);
+        bodies[self.b].apply_impulse(impulse, self.rb);
+    }
+
+    fn solve(&mut self, bodies: &mut [Body]) {
+        let n = self.contact.normal;
+        let k = self.effective_mass(bodies, n);
+        if k <= 0.0 {
+            return;
+        }
+        let vn = self.relative_velocity(bodies).dot(n);
+        let previous = self.normal_impulse;
+        self.normal_impulse = (previous + (self.target - vn) / k).max(0.0);
+        self.apply(bodies, n * (self.normal_impulse - previous));
+
+        let max_friction = self.friction * self.normal_impulse;
+        for i in 0..2 {
+            let t = self.tangents[i];
+            let kt = self.effective_mass(bodies, t);
+            if kt <= 0.0 {
+                continue;
+            }
+            let vt = self.relative_velocity(bodies).dot(t);
+            let previous = self.tangent_impulses[i];
+            self.tangent_impulses[i] =
+                (previous - vt / kt).max(-max_friction).min(max_friction);
+            self.apply(bodies, t * (self.tangent_impulses[i] - previous));
+        }
+    }
+}
+
+/// Two unit vectors perpendicular to `n` and each other
+fn tangents(n: Vec3) -> [Vec3; 2] {
+    let helper = if n.x.abs() < 0.9 {
+        Vec3::unit_x()
+    } else {
+        Vec3::unit_y()
+    };
+    let t1 = n.cross(helper).normalize();
+    [t1, n.cross(t1)]
+}
+
+fn seconds(duration: Duration) -> f32 {
+    duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1e-9
+}
+
+#[derive(Debug)]
+pub struct PhysicsWorld {
+    pub config: PhysicsConfig,
+    timestep: FixedTimestep,
+    events: Vec<CollisionEvent>,
+    /// Pairs touching at the end of the last step, by entity index
+    touching: HashMap<(usize, usize), CollisionEvent>,
+}
+
+impl Default for PhysicsWorld {
+    fn default() -> Self {
+        PhysicsWorld::new(PhysicsConfig::default())
+    }
+}
+
+impl PhysicsWorld {
+    pub fn new(config: PhysicsConfig) -> Self {
+        PhysicsWorld {
+            timestep: FixedTimestep::new(config.timestep, config.max_steps),
+            config,
+            events: Vec::new(),
+            touching: HashMap::new(),
+        }
+    }
+
+    /// Collision and trigger events from the steps run by the last update
+    pub fn events(&self) -> &[CollisionEvent] {
+        &self.events
+    }
+
+    /// Runs as many fixed steps as fit in the frame's time. Returns the
+    /// number of steps run.
+    pub fn update<CS: TryGetComponent>(
+        &mut self,
+        delta: Duration,
+        components: &ComponentManager<CS>,
+    ) -> u32 {
+        self.events.clear();
+        self.timestep.step = self.config.timestep;
+        self.timestep.max_steps = self.config.max_steps;
+        let steps = self.timestep.advance(delta);
+        for _ in 0..steps {
+            self.step(components);
+        }
+        steps
+    }
+
+    /// Advances the simulation by one fixed step
+    pub fn step<CS: TryGetComponent>(
+        &mut self,
+        components: &ComponentManager<CS>,
+    ) {
+        let (transforms, rigid_bodies, colliders) = match (
+            components.get_components::<TransformComponent>(),
+            components.get_components::<RigidBody>(),
+            components.get_components::<Collider>(),
+        ) {
+            (Some(t), Some(r), Some(c)) => (t, r, c),
+            _ => return,
+        };
+        let mut transforms = transforms.write().expect("poisoned RwLock");
+        let mut rigid_bodies = rigid_bodies.write().expect("poisoned RwLock");
+        let colliders = colliders.read().expect("poisoned RwLock");
+        let dt = seconds(self.config.timestep);
+
+        let mut bodies: Vec<Body> = components
+            .entities()
+            .filter_map(|entity| {
+                let transform = match transforms[*entity] {
+                    Some(ref t) if t.parent.is_none() => t.transform,
+                    _ => return None,
+                };
+                let rigid_body = rigid_bodies[*entity].as_ref();
+                let collider = colliders[*entity].clone();
+                if rigid_body.is_none() && collider.is_none() {
+                    return None;
+                }
+                let inverse_mass =
+                    rigid_body.map_or(0.0, RigidBody::inverse_mass);
+                let inverse_inertia = match (&collider, rigid_body) {
+                    (Some(collider), Some(body)) if inverse_mass > 0.0 => {
+                        let inertia =
+                            collider.inertia(body.mass, transform.scale);
+                        let invert =
+                            |i: f32| if i > 0.0 { 1.0 / i } else { 0.0 };
+                        vec3(
+                            invert(inertia.x),
+                            invert(inertia.y),
+                            invert(inertia.z),
+                        )
+                    }
+                    _ => Vec3::zero(),
+                };
+                Some(Body {
+                    entity,
+                    transform,
+                    velocity: rigid_body.map_or(Vec3::zero(), |b| b.velocity),
+                    angular_velocity: rigid_body
+                        .map_or(Vec3::zero(), |b| b.angular_velocity),
+                    inverse_mass,
+                    inverse_inertia,
+                    has_body: rigid_body.is_some(),
+                    collider,
+                    shape: None,
+                })
+            })
+            .collect();
+
+        // forces
+        for body in bodies.iter_mut().filter(|b| b.is_dynamic()) {
+            let rb = rigid_bodies[*body.entity].as_ref().unwrap();
+            body.velocity += self.config.gravity * (rb.gravity_scale * dt);
+            body.velocity /= 1.0 + rb.linear_damping * dt;
+            body.angular_velocity /= 1.0 + rb.angular_damping * dt;
+        }
+        for body in bodies.iter_mut() {
+            body.shape = body
+                .collider
+                .as_ref()
+                .map(|c| c.world_shape(&body.transform));
+        }
+
+        let mut constraints = Vec::new();
+        let mut touching = HashMap::new();
+        for (a, b) in broadphase(&bodies) {
+            let (body_a, body_b) = (&bodies[a], &bodies[b]);
+            let contact = match collide(
+                body_a.shape.as_ref().unwrap(),
+                body_b.shape.as_ref().unwrap(),
+            ) {
+                Some(contact) => contact,
+                None => continue,
+            };
+            let (ca, cb) = (
+                body_a.collider.as_ref().unwrap(),
+                body_b.collider.as_ref().unwrap(),
+            );
+            let trigger = ca.trigger || cb.trigger;
+            let key = (body_a.entity.index(), body_b.entity.index());
+            touching.insert(
+                key,
+                CollisionEvent {
+                    a: body_a.entity,
+                    b: body_b.entity,
+                    phase: ContactPhase::Began,
+                    trigger,
+                    normal: contact.normal,
+                    point: contact.point,
+                    impulse: 0.0,
+                },
+            );
+            if trigger || !(body_a.is_dynamic() || body_b.is_dynamic()) {
+                continue;
+            }
+            let ra = contact.point - Point3::from_vec(body_a.transform.disp);
+            let rb = contact.point - Point3::from_vec(body_b.transform.disp);
+            let mut constraint = Constraint {
+                a,
+                b,
+                contact,
+                ra,
+                rb,
+                tangents: tangents(contact.normal),
+                target: 0.0,
+                friction: (ca.friction * cb.friction).sqrt(),
+                normal_impulse: 0.0,
+                tangent_impulses: [0.0; 2],
+            };
+            // only bounce off impacts faster than resting contact jitter
+            let vn = constraint.relative_velocity(&bodies).dot(contact.normal);
+            if vn < -1.0 {
+                constraint.target = -ca.restitution.max(cb.restitution) * vn;
+            }
+            constraints.push(constraint);
+        }
+
+        for _ in 0..self.config.iterations {
+            for constraint in constraints.iter_mut() {
+                constraint.solve(&mut bodies);
+            }
+        }
+
+        // integrate positions
+        for body in bodies.iter_mut().filter(|b| b.has_body) {
+            let t = &mut body.transform;
+            t.disp += body.velocity * dt;
+            let w = body.angular_velocity;
+            let spin = Quaternion::from_sv(0.0, w) * t.rot * (0.5 * dt);
+            t.rot = (t.rot + spin).normalize();
+        }
+        // push apart remaining penetration
+        for constraint in &constraints {
+            let (a, b) = (constraint.a, constraint.b);
+            let inverse_mass = bodies[a].inverse_mass + bodies[b].inverse_mass;
+            let depth = constraint.contact.depth - self.config.slop;
+            if depth <= 0.0 || inverse_mass <= 0.0 {
+                continue;
+            }
+            let push = constraint.contact.normal
+                * (depth * self.config.correction / inverse_mass);
+            let (ia, ib) = (bodies[a].inverse_mass, bodies[b].inverse_mass);
+            bodies[a].transform.disp -= push * ia;
+            bodies[b].transform.disp += push * ib;
+        }
+
+        for constraint in &constraints {
+            let key = (
+                bodies[constraint.a].entity.index(),
+                bodies[constraint.b].entity.index(),
+            );
+            if let Some(event) = touching.get_mut(&key) {
+                event.impulse += constraint.normal_impulse;
+            }
+        }
+        for body in bodies.iter().filter(|b| b.has_body) {
+            if let Some(ref mut transform) = transforms[*body.entity] {
+                transform.transform = body.transform;
+            }
+            if let Some(ref mut rb) = rigid_bodies[*body.entity] {
+                rb.velocity = body.velocity;
+                rb.angular_velocity = body.angular_velocity;
+            }
+        }
+        self.report(touching);
+    }
+
+    /// Emits events for this step's touching pairs and the pairs which
+    /// stopped touching
+    fn report(
+        &mut self,
+        mut touching: HashMap<(usize, usize), CollisionEvent>,
+    ) {
+        for (key, event) in touching.iter_mut() {
+            if self.touching.contains_key(key) {
+                event.phase = ContactPhase::Persisted;
+            }
+            self.events.push(*event);
+        }
+        for (key, event) in self.touching.iter() {
+            if !touching.contains_key(key) {
+                self.events.push(CollisionEvent {
+                    phase: ContactPhase::Ended,
+                    impulse: 0.0,
+                    ..*event
+                });
+            }
+        }
+        self.touching = touching;
+    }
+}
+
+/// Sweep and prune along x: returns pairs of bodies with colliders whose
+/// boxes overlap, where at least one has a RigidBody. The lower entity
+/// index comes first in each pair.
+fn broadphase(bodies: &[Body]) -> Vec<(usize, usize)> {
+    let mut boxes: Vec<(usize, Aabb)> = bodies
+        .iter()
+        .enumerate()
+        .filter_map(|(i, b)| b.shape.map(|s| (i, s.aabb())))
+        .collect();
+    boxes.sort_by(|a, b| a.1.min.x.total_cmp(&b.1.min.x));
+
+    let mut pairs = Vec::new();
+    for (i, &(a, ref box_a)) in boxes.iter().enumerate() {
+        for &(b, ref box_b) in boxes[i + 1..].iter() {
+            if box_b.min.x > box_a.max.x {
+                break;
+            }
+            if !(bodies[a].has_body || bodies[b].has_body)
+                || !box_a.intersects(box_b)
+            {
+                continue;
+            }
+            if bodies[a].entity.index() < bodies[b].entity.index() {
+                pairs.push((a, b));
+            } else {
+                pairs.push((b, a));
+            }
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::test_util::*;
+
+    fn spawn(
+        world: &mut MockWorld,
+        position: Vec3,
+        body: Option<RigidBody>,
+        collider: Collider,
+    ) -> Entity {
+        let entity = world.components.alloc_entity();
+        let components = &world.components;
+        components
+            .get_components::<TransformComponent>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(
+                *entity,
+                TransformComponent {
+                    parent: None,
+                    transform: Decomposed {
+                        scale: 1.0,
+                        rot: Quaternion::one(),
+                        disp: position,
+                    },
+                },
+            );
+        if let Some(body) = body {
+            components
+                .get_components::<RigidBody>()
+                .unwrap()
+                .write()
+                .unwrap()
+                .insert(*entity, body);
+        }
+        components
+            .get_components::<Collider>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(*entity, collider);
+        entity
+    }
+
+    fn transform(world: &MockWorld, entity: Entity) -> Pose {
+        let transforms = world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap();
+        let transforms = transforms.read().unwrap();
+        transforms[*entity].as_ref().unwrap().transform
+    }
+
+    fn velocity(world: &MockWorld, entity: Entity) -> Vec3 {
+        let bodies = world.components.get_components::<RigidBody>().unwrap();
+        let bodies = bodies.read().unwrap();
+        bodies[*entity].as_ref().unwrap().velocity
+    }
+
+    fn ground(world: &mut MockWorld) -> Entity {
+        spawn(
+            world,
+            vec3(0.0, -1.0, 0.0),
+            None,
+            Collider::cuboid(vec3(20.0, 1.0, 20.0)),
+        )
+    }
+
+    fn run(world: &mut MockWorld, seconds: f32) -> Vec<CollisionEvent> {
+        let mut events = Vec::new();
+        let steps = (seconds * 60.0) as usize;
+        for _ in 0..steps {
+            world.sim.physics.step(&world.sim.components);
+            events.extend_from_slice(world.physics.events());
+            world.physics.events.clear();
+        }
+        events
+    }
+
+    #[test]
+    fn test_sphere_comes_to_rest_on_ground() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let ground = ground(&mut world);
+        let ball = spawn(
+            &mut world,
+            vec3(0.0, 3.0, 0.0),
+            Some(RigidBody::new(1.0)),
+            Collider::sphere(0.5),
+        );
+        let events = run(&mut world, 3.0);
+        let position = transform(&world, ball).disp;
+        assert!((position.y - 0.5).abs() < 0.02, "ball at {:?}", position);
+        assert!(velocity(&world, ball).magnitude() < 0.05);
+        assert_eq!(transform(&world, ground).disp, vec3(0.0, -1.0, 0.0));
+
+        let began: Vec<_> = events
+            .iter()
+            .filter(|e| e.phase == ContactPhase::Began)
+            .collect();
+        assert!(!began.is_empty());
+        assert_eq!((began[0].a, began[0].b), (ground, ball));
+        assert!((began[0].normal - Vec3::unit_y()).magnitude() < 1e-3);
+        assert!(began[0].impulse > 0.0);
+        assert_eq!(events.last().unwrap().phase, ContactPhase::Persisted);
+    }
+
+    #[test]
+    fn test_box_rests_flat() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        ground(&mut world);
+        let crate_box = spawn(
+            &mut world,
+            vec3(0.0, 1.0, 0.0),
+            Some(RigidBody::new(2.0)),
+            Collider::cuboid(vec3(0.5, 0.5, 0.5)),
+        );
+        run(&mut world, 3.0);
+        let t = transform(&world, crate_box);
+        assert!((t.disp.y - 0.5).abs() < 0.02, "box at {:?}", t.disp);
+        assert!(t.disp.x.abs() < 0.01 && t.disp.z.abs() < 0.01);
+        // didn't tip over
+        let up = t.rot.rotate_vector(Vec3::unit_y());
+        assert!(up.dot(Vec3::unit_y()) > 0.999, "up is {:?}", up);
+    }
+
+    #[test]
+    fn test_elastic_collision_swaps_velocities() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        world.physics.config.gravity = Vec3::zero();
+        let body = |velocity| RigidBody {
+            velocity,
+            linear_damping: 0.0,
+            ..RigidBody::new(1.0)
+        };
+        let bouncy = |radius| Collider {
+            restitution: 1.0,
+            friction: 0.0,
+            ..Collider::sphere(radius)
+        };
+        let left = spawn(
+            &mut world,
+            vec3(-2.0, 0.0, 0.0),
+            Some(body(vec3(4.0, 0.0, 0.0))),
+            bouncy(0.5),
+        );
+        let right = spawn(
+            &mut world,
+            vec3(2.0, 0.0, 0.0),
+            Some(body(vec3(-4.0, 0.0, 0.0))),
+            bouncy(0.5),
+        );
+        run(&mut world, 1.0);
+        assert!(
+            (velocity(&world, left) - vec3(-4.0, 0.0, 0.0)).magnitude() < 0.1
+        );
+        assert!(
+            (velocity(&world, right) - vec3(4.0, 0.0, 0.0)).magnitude() < 0.1
+        );
+    }
+
+    #[test]
+    fn test_trigger_reports_without_colliding() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        world.physics.config.gravity = Vec3::zero();
+        let zone = spawn(
+            &mut world,
+            Vec3::zero(),
+            None,
+            Collider {
+                trigger: true,
+                ..Collider::cuboid(vec3(1.0, 1.0, 1.0))
+            },
+        );
+        let ball = spawn(
+            &mut world,
+            vec3(-3.0, 0.0, 0.0),
+            Some(RigidBody {
+                velocity: vec3(3.0, 0.0, 0.0),
+                linear_damping: 0.0,
+                ..RigidBody::new(1.0)
+            }),
+            Collider::sphere(0.25),
+        );
+        let events = run(&mut world, 2.0);
+        assert!(
+            (velocity(&world, ball) - vec3(3.0, 0.0, 0.0)).magnitude() < 1e-4
+        );
+        let phases: Vec<_> = events
+            .iter()
+            .filter(|e| e.phase != ContactPhase::Persisted)
+            .map(|e| (e.a, e.b, e.trigger, e.phase))
+            .collect();
+        assert_eq!(
+            phases,
+            vec![
+                (zone, ball, true, ContactPhase::Began),
+                (zone, ball, true, ContactPhase::Ended)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_update_runs_fixed_steps() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let ball = spawn(
+            &mut world,
+            Vec3::zero(),
+            Some(RigidBody::new(1.0)),
+            Collider::sphere(0.5),
+        );
+        let frame = Duration::from_millis(30);
+        assert_eq!(world.sim.physics.update(frame, &world.sim.components), 1);
+        assert_eq!(world.sim.physics.update(frame, &world.sim.components), 2);
+        // three steps of gravity, whatever the frame times were
+        let mut expected = RigidBody::new(1.0);
+        let dt = seconds(world.physics.config.timestep);
+        for _ in 0..3 {
+            expected.velocity += vec3(0.0, -9.81, 0.0) * dt;
+            expected.velocity /= 1.0 + expected.linear_damping * dt;
+        }
+        assert!(
+            (velocity(&world, ball) - expected.velocity).magnitude() < 1e-5
+        );
+    }
+
+    #[test]
+    fn test_nan_position_does_not_panic() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        spawn(
+            &mut world,
+            vec3(std::f32::NAN, 0.0, 0.0),
+            Some(RigidBody::new(1.0)),
+            Collider::sphere(0.5),
+        );
+        spawn(
+            &mut world,
+            Vec3::zero(),
+            Some(RigidBody::new(1.0)),
+            Collider::sphere(0.5),
+        );
+        run(&mut world, 0.1);
+    }
+}
@@ -0,0 +1,141 @@
+//! Static collision geometry built from mesh triangles.
+
+use crate::game::{component::*, resource::ResourceManager, TryGetComponent};
+use crate::math::*;
+use crate::renderer::{Mesh, RenderMesh, Renderer};
+use cgmath::*;
+
+/// World space triangles, indexed by a BVH for overlap queries
+#[derive(Debug)]
+pub struct TriangleMesh {
+    triangles: Vec<Triangle>,
+    bvh: DynamicBvh<usize>,
+}
+
+impl Default for TriangleMesh {
+    fn default() -> Self {
+        TriangleMesh::new()
+    }
+}
+
+impl TriangleMesh {
+    pub fn new() -> Self {
+        TriangleMesh {
+            triangles: Vec::new(),
+            // the geometry never moves, so leaves don't need a margin
+            bvh: DynamicBvh::new(0.0),
+        }
+    }
+
+    /// Collision geometry for a mesh placed by a model matrix
+    pub fn from_mesh(mesh: &Mesh, model: &Mat4) -> Self {
+        let mut trimesh = TriangleMesh::new();
+        trimesh.add_mesh(mesh, model);
+        trimesh
+    }
+
+    /// Collision geometry for every entity with a MeshComponent, as
+    /// currently placed
+    pub fn from_entities<R, CS>(
+        components: &ComponentManager<CS>,
+        resources: &ResourceManager<R>,
+    ) -> Self
+    where
+        R: Renderer,
+        CS: TryGetComponent,
+    {
+        let mut trimesh = TriangleMesh::new();
+        let (meshes, transforms) = match (
+            components.get_components::<MeshComponent>(),
+            components.get_components::<TransformComponent>(),
+        ) {
+            (Some(meshes), Some(transforms)) => (meshes, transforms),
+            _ => return trimesh,
+        };
+        let meshes = meshes.read().expect("poisoned RwLock");
+        let transforms = transforms.read().expect("poisoned RwLock");
+        for entity in components.entities() {
+            let mesh = match meshes[*entity]
+                .as_ref()
+                .and_then(|m| resources.meshes.get(&m.mesh))
+            {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            let model = world_matrix(&transforms, entity)
+                .unwrap_or_else(Mat4::identity);
+            trimesh.add_mesh(mesh.mesh(), &model);
+        }
+        trimesh
+    }
+
+    pub fn add_mesh(&mut self, mesh: &Mesh, model: &Mat4) {
+        let position =
+            |i: u32| Point3::from(mesh.vertices[i as usize].position);
+        for tri in mesh.triangle_indices().filter(|t| t.len() == 3) {
+            let triangle = Triangle::new(
+                position(tri[0]),
+                position(tri[1]),
+                position(tri[2]),
+            )
+            .transform(model);
+            // degenerate triangles have no normal to push out along
+            if triangle.area() > 1e-8 {
+                self.add_triangle(triangle);
+            }
+        }
+    }
+
+    pub fn add_triangle(&mut self, triangle: Triangle) {
+        self.bvh.insert(triangle.aabb(), self.triangles.len());
+        self.triangles.push(triangle);
+    }
+
+    pub fn len(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+
+    pub fn aabb(&self) -> Option<Aabb> {
+        self.triangles
+            .iter()
+            .map(Triangle::aabb)
+            .fold(None, |acc, aabb| {
+                Some(acc.map_or(aabb, |acc: Aabb| acc.union(&aabb)))
+            })
+    }
+
+    /// Triangles whose bounding boxes overlap `aabb`
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<&Triangle> {
+        self.bvh
+            .query_aabb(aabb)
+            .into_iter()
+            .map(|&i| &self.triangles[i])
+            .collect()
+    }
+
+    /// The nearest triangle hit by a ray within `max_distance`
+    pub fn raycast(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+    ) -> Option<(&Triangle, TriangleHit)> {
+        self.bvh
+            .query_ray(ray, max_distance)
+            .into_iter()
+            .filter_map(|(_, &i)| {
+                let triangle = &self.triangles[i];
+                ray.intersect_triangle(triangle)
+                    .filter(|hit| hit.distance <= max_distance)
+                    .map(|hit| (triangle, hit))
+            })
+            .min_by(|a, b| a.1.distance.total_cmp(&b.1.distance))
+    }
+}
@@ -0,0 +1,363 @@
+//! Ray casts against mesh entities, for mouse picking and line of sight.
+//!
+//! Candidate entities come from the world's spatial index. Rays are tested
+//! against each candidate's world space bounding sphere and box, and only
+//! entities whose bounds are hit are tested triangle by triangle.
+
+use super::{component::*, world::EntityWorld, TryGetComponent};
+use crate::math::*;
+use crate::renderer::{Mesh, RenderMesh, Renderer};
+use cgmath::*;
+
+/// The nearest intersection of a ray with a mesh entity
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RayHit {
+    pub entity: Entity,
+    /// Distance along the ray to the hit
+    pub distance: f32,
+    /// World space hit position
+    pub position: Point3<f32>,
+    /// World space normal, interpolated from the triangle's vertex normals
+    pub normal: Vec3,
+    /// Weights of the hit triangle's vertices
+    pub barycentrics: Vec3,
+    /// Indices of the hit triangle's vertices into the mesh
+    pub triangle: [u32; 3],
+}
+
+/// Unprojects a mouse position, in window pixels from the top left, into a
+/// world space ray from the camera through the cursor.
+pub fn screen_ray(
+    mousepos: Point2<f32>,
+    screen_size: (u32, u32),
+    projection: &Mat4,
+    view: &Mat4,
+) -> Option<Ray> {
+    let (width, height) = screen_size;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let ndc = Point2::new(
+        2.0 * mousepos.x / width as f32 - 1.0,
+        1.0 - 2.0 * mousepos.y / height as f32,
+    );
+    Ray::from_ndc(ndc, &(projection * view))
+}
+
+/// Tests a world space ray against the triangles of a mesh placed by
+/// `model`. Returns the nearest hit within `max_distance`.
+fn raycast_mesh(
+    mesh: &Mesh,
+    model: &Mat4,
+    ray: &Ray,
+    max_distance: f32,
+) -> Option<(TriangleHit, [u32; 3])> {
+    let position = |i: u32| {
+        model.transform_point(Point3::from(mesh.vertices[i as usize].position))
+    };
+    let mut nearest: Option<(TriangleHit, [u32; 3])> = None;
+    for tri in mesh.triangle_indices().filter(|tri| tri.len() == 3) {
+        let triangle =
+            Triangle::new(position(tri[0]), position(tri[1]), position(tri[2]));
+        let hit = match ray.intersect_triangle(&triangle) {
+            Some(hit) => hit,
+            None => continue,
+        };
+        let limit = nearest.map_or(max_distance, |(n, _)| n.distance);
+        if hit.distance <= limit {
+            nearest = Some((hit, [tri[0], tri[1], tri[2]]));
+        }
+    }
+    nearest
+}
+
+/// Interpolates the vertex normals of a hit triangle into world space,
+/// falling back to the face normal if they cancel out.
+fn hit_normal(
+    mesh: &Mesh,
+    model: &Mat4,
+    triangle: [u32; 3],
+    barycentrics: Vec3,
+) -> Vec3 {
+    let vertices: Vec<_> = triangle
+        .iter()
+        .map(|&i| mesh.vertices[i as usize])
+        .collect();
+    let local = Vec3::from(vertices[0].normal) * barycentrics.x
+        + Vec3::from(vertices[1].normal) * barycentrics.y
+        + Vec3::from(vertices[2].normal) * barycentrics.z;
+    let normal_matrix = model
+        .invert()
+        .map(|m| m.transpose())
+        .unwrap_or_else(Mat4::identity);
+    let normal = normal_matrix.transform_vector(local);
+    if normal.magnitude2() > 1e-12 {
+        return normal.normalize();
+    }
+    let position = |i: usize| Point3::from(vertices[i].position);
+    Triangle::new(position(0), position(1), position(2))
+        .transform(model)
+        .normal()
+}
+
+/// Casts a world space ray against every entity with a MeshComponent,
+/// returning the nearest hit within `max_distance`. Uses the spatial
+/// index, so entities spawned or moved since the last update may be
+/// missed. Entities without a TransformComponent are placed at the origin.
+pub fn raycast<R, CS>(
+    world: &EntityWorld<R, CS>,
+    ray: &Ray,
+    max_distance: f32,
+) -> Option<RayHit>
+where
+    R: Renderer,
+    CS: TryGetComponent,
+{
+    let (meshes, transforms) = match (
+        world.components.get_components::<MeshComponent>(),
+        world.components.get_components::<TransformComponent>(),
+    ) {
+        (Some(meshes), Some(transforms)) => (meshes, transforms),
+        _ => return None,
+    };
+    let meshes = meshes.read().expect("poisoned RwLock");
+    let transforms = transforms.read().expect("poisoned RwLock");
+
+    // entities whose tight bounds the ray enters, nearest first
+    let mut candidates: Vec<_> = world
+        .spatial
+        .query_ray(ray, max_distance)
+        .into_iter()
+        .filter_map(|(_, entity)| {
+            let mesh = meshes[*entity]
+                .as_ref()
+                .and_then(|m| world.resources.meshes.get(&m.mesh))?;
+            let model = world_matrix(&transforms, entity)
+                .unwrap_or_else(Mat4::identity);
+            let bounds = mesh.bounds().transform(&model);
+            ray.intersect_sphere(&bounds.sphere)?;
+            let entry = ray.intersect_aabb(&bounds.aabb)?;
+            if entry > max_distance {
+                return None;
+            }
+            Some((entry, entity, mesh, model))
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut nearest: Option<RayHit> = None;
+    for (entry, entity, mesh, model) in candidates {
+        let limit = nearest.map_or(max_distance, |n| n.distance);
+        if entry > limit {
+            break;
+        }
+        let mesh = mesh.mesh();
+        if let Some((hit, triangle)) = raycast_mesh(mesh, &model, ray, limit) {
+            nearest = Some(RayHit {
+                entity,
+                distance: hit.distance,
+                position: ray.at(hit.distance),
+                normal: hit_normal(mesh, &model, triangle, hit.barycentrics),
+                barycentrics: hit.barycentrics,
+                triangle,
+            });
+        }
+    }
+    nearest
+}
+
+/// Returns true if no mesh entity blocks the segment between two points.
+/// Hits on the `ignore` entities, such as the looker, don't count.
+pub fn line_of_sight<R, CS>(
+    world: &EntityWorld<R, CS>,
+    from: Point3<f32>,
+    to: Point3<f32>,
+    ignore: &[Entity],
+) -> bool
+where
+    R: Renderer,
+    CS: TryGetComponent,
+{
+    let distance = from.distance(to);
+    if distance < std::f32::EPSILON {
+        return true;
+    }
+    let mut ray = Ray::between(from, to);
+    let mut remaining = distance;
+    // step past ignored entities and keep casting
+    while let Some(hit) = raycast(world, &ray, remaining) {
+        if !ignore.contains(&hit.entity) {
+            return false;
+        }
+        let step = hit.distance + 1e-4;
+        ray.origin = ray.at(step);
+        remaining -= step;
+        if remaining <= 0.0 {
+            break;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::test_util::*;
+    use crate::renderer::Vertex;
+
+    /// A 2x2 quad in the xy plane facing +z
+    fn quad() -> Mesh {
+        let vertex = |x: f32, y: f32| Vertex {
+            position: [x, y, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            ..Vertex::default()
+        };
+        Mesh {
+            vertices: vec![
+                vertex(-1.0, -1.0),
+                vertex(1.0, -1.0),
+                vertex(1.0, 1.0),
+                vertex(-1.0, 1.0),
+            ],
+            indices: vec![0, 1, 2, 0, 2, 3],
+            morph_targets: Vec::new(),
+        }
+    }
+
+    fn spawn_quad<R: Renderer, CS: TryGetComponent>(
+        world: &mut EntityWorld<R, CS>,
+        mesh: crate::game::resource::MeshHandle,
+        disp: Vec3,
+    ) -> Entity {
+        let entity = world.components.alloc_entity();
+        world
+            .components
+            .get_components::<MeshComponent>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(*entity, MeshComponent { mesh });
+        world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(
+                *entity,
+                TransformComponent {
+                    parent: None,
+                    transform: Decomposed {
+                        scale: 1.0,
+                        rot: Quaternion::one(),
+                        disp,
+                    },
+                },
+            );
+        world.update_spatial_index();
+        entity
+    }
+
+    #[test]
+    fn test_raycast_nearest_hit() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let mesh = world.resources.insert_mesh(quad());
+        let far = spawn_quad(&mut world, mesh, vec3(0.0, 0.0, -10.0));
+        let near = spawn_quad(&mut world, mesh, vec3(0.5, 0.0, -5.0));
+
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), -Vec3::unit_z());
+        let hit = raycast(&world, &ray, 100.0).unwrap();
+        assert_eq!(hit.entity, near);
+        assert!((hit.distance - 5.0).abs() < 1e-5);
+        assert!(
+            (hit.position - Point3::new(0.0, 0.0, -5.0)).magnitude() < 1e-5
+        );
+        assert!((hit.normal - Vec3::unit_z()).magnitude() < 1e-5);
+        let b = hit.barycentrics;
+        assert!((b.x + b.y + b.z - 1.0).abs() < 1e-5);
+        // barycentrics reconstruct the local hit point from the triangle
+        let quad = quad();
+        let local: Vec3 = hit
+            .triangle
+            .iter()
+            .zip(&[b.x, b.y, b.z])
+            .map(|(&i, &w)| Vec3::from(quad.vertices[i as usize].position) * w)
+            .sum();
+        assert!((local - vec3(-0.5, 0.0, 0.0)).magnitude() < 1e-5);
+
+        // the near quad starts at x = -0.5
+        let ray = Ray::new(Point3::new(-0.75, 0.0, 0.0), -Vec3::unit_z());
+        assert_eq!(raycast(&world, &ray, 100.0).unwrap().entity, far);
+        assert_eq!(raycast(&world, &ray, 9.0), None);
+        let up = Ray::new(Point3::origin(), Vec3::unit_y());
+        assert_eq!(raycast(&world, &up, 100.0), None);
+    }
+
+    #[test]
+    fn test_mouse_pick() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let mesh = world.resources.insert_mesh(quad());
+        let position = world.main_camera.position();
+        let front = world.main_camera.front();
+        let target =
+            spawn_quad(&mut world, mesh, (position + front * 5.0).to_vec());
+        let projection = renderer.camera().projection;
+
+        // the center of the screen looks along the camera's front
+        let center = screen_ray(
+            Point2::new(400.0, 300.0),
+            (800, 600),
+            &projection,
+            &world.main_camera.view(),
+        )
+        .unwrap();
+        assert!((center.direction - front).magnitude() < 1e-4);
+
+        world.input_state = Some(crate::game::InputState {
+            mousepos: Point2::new(400.0, 300.0),
+            last_mousepos: Point2::new(400.0, 300.0),
+        });
+        let hit = world.pick((800, 600), &projection).unwrap();
+        assert_eq!(hit.entity, target);
+        world.input_state = Some(crate::game::InputState {
+            mousepos: Point2::new(0.0, 0.0),
+            last_mousepos: Point2::new(0.0, 0.0),
+        });
+        assert_eq!(world.pick((800, 600), &projection), None);
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let mesh = world.resources.insert_mesh(quad());
+        let wall = spawn_quad(&mut world, mesh, vec3(0.0, 0.0, -5.0));
+        let from = Point3::origin();
+        assert!(!line_of_sight(
+            &world,
+            from,
+            Point3::new(0.0, 0.0, -10.0),
+            &[]
+        ));
+        assert!(line_of_sight(
+            &world,
+            from,
+            Point3::new(0.0, 0.0, -4.0),
+            &[]
+        ));
+        assert!(line_of_sight(
+            &world,
+            from,
+            Point3::new(5.0, 0.0, -10.0),
+            &[]
+        ));
+        assert!(line_of_sight(
+            &world,
+            from,
+            Point3::new(0.0, 0.0, -10.0),
+            &[wall]
+        ));
+    }
+}
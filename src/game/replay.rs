@@ -0,0 +1,463 @@
+//! Recording and deterministic playback of per-frame input.
+//!
+//! A recording stores the simulation's rng seed, followed by one `InputFrame`
+//! per frame: the frame delta, mouse positions, and held keys and mouse
+//! buttons. Playing it back feeds each frame into the simulation's input
+//! map and `Simulation::update` in place of SDL.
+
+use super::input::InputSnapshot;
+use super::simulation::{InputState, Simulation};
+use super::TryGetComponent;
+use cgmath::Point2;
+use sdl2::keyboard::Scancode;
+use sdl2::mouse::MouseButton;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+const MAGIC: &[u8; 4] = b"SLSR";
+const VERSION: u32 = 3;
+
+/// Environment variable naming a file `app::run` records the input to
+pub const RECORD_INPUT_VAR: &str = "SLSENGINE_RECORD_INPUT";
+/// Environment variable naming a recording to play back in place of live
+/// input
+pub const REPLAY_INPUT_VAR: &str = "SLSENGINE_REPLAY_INPUT";
+
+#[derive(Fail, Debug)]
+pub enum ReplayError {
+    #[fail(display = "io error: {}", _0)]
+    Io(io::Error),
+    #[fail(display = "invalid input recording: {}", _0)]
+    InvalidFormat(String),
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+/// Input for a single frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputFrame {
+    pub delta: Duration,
+    pub mousepos: Point2<f32>,
+    pub last_mousepos: Point2<f32>,
+    pub input: InputSnapshot,
+}
+
+/// SDL mouse button bitmask, as `SDL_BUTTON(button)`
+fn mouse_button_mask(button: MouseButton) -> u8 {
+    match button {
+        MouseButton::Unknown => 0,
+        button => 1 << (button as u8 - 1),
+    }
+}
+
+impl InputFrame {
+    pub fn new(
+        delta: Duration,
+        input: &InputSnapshot,
+        input_state: &InputState,
+    ) -> Self {
+        InputFrame {
+            delta,
+            mousepos: input_state.mousepos,
+            last_mousepos: input_state.last_mousepos,
+            input: input.clone(),
+        }
+    }
+
+    pub fn input_state(&self) -> InputState {
+        InputState {
+            mousepos: self.mousepos,
+            last_mousepos: self.last_mousepos,
+        }
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        // full precision, so playback feeds the same deltas the live run
+        // updated with
+        w.write_all(&self.delta.as_secs().to_le_bytes())?;
+        w.write_all(&self.delta.subsec_nanos().to_le_bytes())?;
+        for v in &[
+            self.mousepos.x,
+            self.mousepos.y,
+            self.last_mousepos.x,
+            self.last_mousepos.y,
+        ] {
+            w.write_all(&v.to_bits().to_le_bytes())?;
+        }
+        let mouse_buttons = self
+            .input
+            .mouse_buttons
+            .iter()
+            .fold(0, |mask, &b| mask | mouse_button_mask(b));
+        // sorted so identical frames serialize identically
+        let mut keys: Vec<u16> =
+            self.input.keys.iter().map(|&k| k as u16).collect();
+        keys.sort();
+        // SDL has fewer than 512 scancodes, so the count always fits
+        w.write_all(&[mouse_buttons])?;
+        w.write_all(&(keys.len() as u16).to_le_bytes())?;
+        for key in keys {
+            w.write_all(&key.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(r: &mut R) -> Result<Self, ReplayError> {
+        let secs = read_u64(r)?;
+        let nanos = read_u32(r)?;
+        if nanos >= 1_000_000_000 {
+            return Err(ReplayError::InvalidFormat(format!(
+                "bad frame delta nanoseconds {}",
+                nanos
+            )));
+        }
+        let mut coords = [0f32; 4];
+        for c in coords.iter_mut() {
+            *c = f32::from_bits(read_u32(r)?);
+        }
+        let mut mouse_buttons = [0u8; 1];
+        r.read_exact(&mut mouse_buttons)?;
+        let mut n_keys = [0u8; 2];
+        r.read_exact(&mut n_keys)?;
+        let mut input = InputSnapshot::new();
+        input.mouse_buttons = (1..=5)
+            .map(MouseButton::from_ll)
+            .filter(|&b| mouse_buttons[0] & mouse_button_mask(b) != 0)
+            .collect();
+        for _ in 0..u16::from_le_bytes(n_keys) {
+            let mut buf = [0u8; 2];
+            r.read_exact(&mut buf)?;
+            let code = u16::from_le_bytes(buf) as i32;
+            let key = Scancode::from_i32(code).ok_or_else(|| {
+                ReplayError::InvalidFormat(format!("bad scancode {}", code))
+            })?;
+            input.keys.insert(key);
+        }
+        Ok(InputFrame {
+            delta: Duration::new(secs, nanos),
+            mousepos: Point2::new(coords[0], coords[1]),
+            last_mousepos: Point2::new(coords[2], coords[3]),
+            input,
+        })
+    }
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// A seed and sequence of input frames
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputRecording {
+    pub seed: u64,
+    pub frames: Vec<InputFrame>,
+}
+
+impl InputRecording {
+    pub fn new(seed: u64) -> Self {
+        InputRecording {
+            seed,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&self.seed.to_le_bytes())?;
+        w.write_all(&(self.frames.len() as u32).to_le_bytes())?;
+        for frame in &self.frames {
+            frame.write(w)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self, ReplayError> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(ReplayError::InvalidFormat(
+                "not an input recording".to_owned(),
+            ));
+        }
+        let version = read_u32(r)?;
+        if version != VERSION {
+            return Err(ReplayError::InvalidFormat(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        let seed = read_u64(r)?;
+        let n_frames = read_u32(r)? as usize;
+        let frames = (0..n_frames)
+            .map(|_| InputFrame::read(r))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(InputRecording { seed, frames })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ReplayError> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ReplayError> {
+        InputRecording::read(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+/// Captures input frames from a running game
+#[derive(Debug)]
+pub struct InputRecorder {
+    recording: InputRecording,
+}
+
+impl InputRecorder {
+    /// Starts recording. Reseeds the simulation's rng with its current
+    /// seed, matching the reseed `InputReplay::start` performs on playback.
+    pub fn new<CS: TryGetComponent>(sim: &mut Simulation<CS>) -> Self {
+        let seed = sim.seed();
+        sim.reseed(seed);
+        InputRecorder {
+            recording: InputRecording::new(seed),
+        }
+    }
+
+    /// Records a frame. Should be called after the main loop handles
+    /// events and ticks the frame, before the simulation updates.
+    pub fn record(
+        &mut self,
+        delta: Duration,
+        input: &InputSnapshot,
+        input_state: &InputState,
+    ) {
+        let frame = InputFrame::new(delta, input, input_state);
+        self.recording.frames.push(frame);
+    }
+
+    pub fn recording(&self) -> &InputRecording {
+        &self.recording
+    }
+
+    pub fn finish(self) -> InputRecording {
+        self.recording
+    }
+}
+
+/// Plays back a recording into a simulation, one frame per step
+#[derive(Debug)]
+pub struct InputReplay {
+    recording: InputRecording,
+    cursor: usize,
+}
+
+impl InputReplay {
+    pub fn new(recording: InputRecording) -> Self {
+        InputReplay {
+            recording,
+            cursor: 0,
+        }
+    }
+
+    /// Loads the recording named by `SLSENGINE_REPLAY_INPUT`, if it's set
+    pub fn from_env() -> Result<Option<Self>, ReplayError> {
+        match env::var_os(REPLAY_INPUT_VAR) {
+            Some(path) => {
+                Ok(Some(InputReplay::new(InputRecording::load(path)?)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Reseeds the simulation with the recording's seed. Should be called
+    /// before the first step, once the simulation is set up.
+    pub fn start<CS: TryGetComponent>(&mut self, sim: &mut Simulation<CS>) {
+        self.cursor = 0;
+        sim.reseed(self.recording.seed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.cursor >= self.recording.frames.len()
+    }
+
+    /// Feeds the next recorded frame's input to the simulation, without
+    /// updating it. Returns the frame's delta to update it with, or None
+    /// once the recording has no more frames.
+    pub fn feed<CS: TryGetComponent>(
+        &mut self,
+        sim: &mut Simulation<CS>,
+    ) -> Option<Duration> {
+        let frame = self.recording.frames.get(self.cursor)?;
+        self.cursor += 1;
+        sim.set_input(frame.input.clone(), frame.input_state());
+        Some(frame.delta)
+    }
+
+    /// Feeds the next recorded frame to the simulation and updates it.
+    /// Returns false once the recording has no more frames.
+    pub fn step<CS: TryGetComponent>(
+        &mut self,
+        sim: &mut Simulation<CS>,
+    ) -> bool {
+        match self.feed(sim) {
+            Some(delta) => {
+                sim.update(delta);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Steps through every remaining frame
+    pub fn run<CS: TryGetComponent>(&mut self, sim: &mut Simulation<CS>) {
+        while self.step(sim) {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::input::Button;
+    use crate::game::test_util::*;
+
+    fn walk_recording() -> InputRecording {
+        walk_recording_with_delta(Duration::from_micros(16_667))
+    }
+
+    fn walk_recording_with_delta(delta: Duration) -> InputRecording {
+        let mut recording = InputRecording::new(42);
+        let origin = Point2::new(100.0, 100.0);
+        for i in 0..30 {
+            let mousepos = Point2::new(100.0 + i as f32, 100.0);
+            recording.frames.push(InputFrame {
+                delta,
+                mousepos,
+                last_mousepos: if i == 0 {
+                    origin
+                } else {
+                    Point2::new(99.0 + i as f32, 100.0)
+                },
+                input: InputSnapshot::with_buttons(vec![
+                    Button::Key(Scancode::W),
+                    Button::Key(Scancode::D),
+                    Button::Mouse(MouseButton::Left),
+                ]),
+            });
+        }
+        recording
+    }
+
+    #[test]
+    fn test_recording_roundtrip() {
+        let recording = walk_recording();
+        let mut bytes = Vec::new();
+        recording.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 20 + 30 * (12 + 16 + 3 + 4));
+        let read = InputRecording::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, recording);
+    }
+
+    #[test]
+    fn test_invalid_recording() {
+        match InputRecording::read(&mut &b"nope"[..]) {
+            Err(ReplayError::InvalidFormat(_)) => {}
+            other => panic!("expected InvalidFormat, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_replay_is_deterministic() {
+        use rand::Rng;
+        let renderer = MockRenderer::new();
+        let play = || {
+            let mut world = mock_world(&renderer);
+            let mut replay = InputReplay::new(walk_recording());
+            replay.start(&mut world);
+            replay.run(&mut world);
+            assert!(replay.is_finished());
+            let roll: u32 = world.rng.gen();
+            (world.main_camera.view(), roll)
+        };
+        let (first_view, first_roll) = play();
+        let (second_view, second_roll) = play();
+        assert_eq!(first_view, second_view);
+        assert_eq!(first_roll, second_roll);
+
+        let still = mock_world(&renderer);
+        assert_ne!(first_view, still.main_camera.view());
+    }
+
+    #[test]
+    fn test_sub_microsecond_deltas() {
+        use crate::game::component_stores::AnyComponentStore;
+        // a 60Hz frame, which isn't a whole number of microseconds
+        let delta = Duration::new(0, 16_666_667);
+        let recording = walk_recording_with_delta(delta);
+        let mut bytes = Vec::new();
+        recording.write(&mut bytes).unwrap();
+        let read = InputRecording::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, recording);
+
+        // the live run, updating with the deltas as they were recorded
+        let mut live =
+            Simulation::new(AnyComponentStore::with_built_in_components());
+        let mut recorder = InputRecorder::new(&mut live);
+        for frame in &recording.frames {
+            live.set_input(frame.input.clone(), frame.input_state());
+            recorder.record(
+                frame.delta,
+                &live.input_snapshot,
+                live.input_state.as_ref().unwrap(),
+            );
+            live.update(frame.delta);
+        }
+        let mut bytes = Vec::new();
+        recorder.finish().write(&mut bytes).unwrap();
+
+        let mut replayed =
+            Simulation::new(AnyComponentStore::with_built_in_components());
+        let mut replay = InputReplay::new(
+            InputRecording::read(&mut bytes.as_slice()).unwrap(),
+        );
+        replay.start(&mut replayed);
+        replay.run(&mut replayed);
+        assert_eq!(replayed.clock.game_time(), live.clock.game_time());
+        assert_eq!(replayed.clock.game_time(), delta * 30);
+        assert_eq!(replayed.main_camera.view(), live.main_camera.view());
+    }
+
+    #[test]
+    fn test_replay_without_renderer() {
+        use crate::game::component_stores::AnyComponentStore;
+        let play = || {
+            let mut sim =
+                Simulation::new(AnyComponentStore::with_built_in_components());
+            let mut replay = InputReplay::new(walk_recording());
+            replay.start(&mut sim);
+            replay.run(&mut sim);
+            sim.main_camera.view()
+        };
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let mut replay = InputReplay::new(walk_recording());
+        replay.start(&mut world);
+        replay.run(&mut world);
+        assert_eq!(play(), world.main_camera.view());
+    }
+}
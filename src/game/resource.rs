@@ -11,6 +11,10 @@ pub struct TextureHandle(pub usize);
 pub enum ResourceError {
     #[fail(display = "failed to fetch resource")]
     FetchError(failure::Error),
+    #[fail(display = "failed to load '{}': {}", _0, _1)]
+    LoadError(String, failure::Error),
+    #[fail(display = "failed to upload '{}': {}", _0, _1)]
+    UploadError(String, failure::Error),
 }
 
 pub type ResourceResult<T> = Result<T, ResourceError>;
@@ -19,12 +23,30 @@ pub type ResourceResult<T> = Result<T, ResourceError>;
 pub struct ResourceManager<R: Renderer> {
     pub textures: HashMap<TextureHandle, R::Texture>,
     pub meshes: HashMap<MeshHandle, R::Mesh>,
+    next_texture: usize,
+    next_mesh: usize,
 }
 
 impl<R: Renderer> ResourceManager<R> {
     pub fn new() -> Self {
         ResourceManager::default()
     }
+
+    /// Stores a texture under a newly allocated handle
+    pub fn insert_texture(&mut self, texture: R::Texture) -> TextureHandle {
+        let handle = TextureHandle(self.next_texture);
+        self.next_texture += 1;
+        self.textures.insert(handle, texture);
+        handle
+    }
+
+    /// Stores a mesh under a newly allocated handle
+    pub fn insert_mesh(&mut self, mesh: R::Mesh) -> MeshHandle {
+        let handle = MeshHandle(self.next_mesh);
+        self.next_mesh += 1;
+        self.meshes.insert(handle, mesh);
+        handle
+    }
 }
 
 impl<R: Renderer> Default for ResourceManager<R> {
@@ -32,6 +54,8 @@ impl<R: Renderer> Default for ResourceManager<R> {
         ResourceManager {
             textures: HashMap::new(),
             meshes: HashMap::new(),
+            next_texture: 0,
+            next_mesh: 0,
         }
     }
 }
@@ -0,0 +1,529 @@
+use super::{
+    animation::{AnimationPlayer, MorphWeights, SkinComponent},
+    component::*,
+    resource::{MeshHandle, TextureHandle},
+    world::EntityWorld,
+    TryGetComponent,
+};
+use crate::math::*;
+use crate::renderer::{material::Material, model::Model, Renderer};
+use cgmath::*;
+use std::{collections::HashMap, sync::Arc};
+
+fn identity_transform() -> Decomposed<Vec3, Quaternion<f32>> {
+    Decomposed {
+        scale: 1.0,
+        rot: Quaternion::one(),
+        disp: Vec3::zero(),
+    }
+}
+
+/// Instantiates a model as a tree of entities.
+/// Creates a root entity, one child entity per node of the model's
+/// default scene, and uploads the model's meshes and textures into the
+/// world's resource manager.
+/// Nodes with more than one primitive get a child entity per primitive.
+/// Meshes of skinned nodes get a SkinComponent whose joints are the
+/// entities of the skin's joint nodes, and nodes whose meshes have morph
+/// targets get MorphWeights starting at the mesh's default weights.
+/// Animated models get an AnimationPlayer on the root entity, looping
+/// the first animation.
+/// Returns the root entity of the model.
+pub fn spawn_model<R, CS>(
+    world: &mut EntityWorld<R, CS>,
+    model: &Model,
+    renderer: &mut R,
+) -> Result<Entity, failure::Error>
+where
+    R: Renderer,
+    CS: TryGetComponent,
+{
+    let images = &model.imports().images;
+    let mut textures: HashMap<usize, TextureHandle> = HashMap::new();
+    for index in model_textures(model) {
+        let texture = renderer.create_texture(&images[index])?;
+        textures.insert(index, world.resources.insert_texture(texture));
+    }
+    let mut meshes: Vec<MeshHandle> = Vec::with_capacity(model.meshes.len());
+    for mesh_data in &model.meshes {
+        let mesh = renderer.create_mesh(&mesh_data.mesh)?;
+        meshes.push(world.resources.insert_mesh(mesh));
+    }
+    spawn_uploaded_model(world, model, &textures, &meshes)
+}
+
+/// Indices of the model's images its materials use, in the order they're
+/// first used
+pub fn model_textures(model: &Model) -> Vec<usize> {
+    let n_images = model.imports().images.len();
+    let mut indices = Vec::new();
+    for material in model.materials.values() {
+        let maps = [
+            material.albedo_map,
+            material.metallic_roughness_map,
+            material.emissive_map,
+            material.normal_map,
+            material.occlusion_map,
+        ];
+        for &index in maps.iter().filter_map(Option::as_ref) {
+            if index < n_images && !indices.contains(&index) {
+                indices.push(index);
+            }
+        }
+    }
+    indices
+}
+
+/// Like `spawn_model`, for a model whose textures and meshes are already
+/// uploaded. `textures` maps the indices from `model_textures` to their
+/// textures, and `meshes` holds a mesh for each of the model's meshes.
+pub fn spawn_uploaded_model<R, CS>(
+    world: &mut EntityWorld<R, CS>,
+    model: &Model,
+    texture_handles: &HashMap<usize, TextureHandle>,
+    mesh_handles: &[MeshHandle],
+) -> Result<Entity, failure::Error>
+where
+    R: Renderer,
+    CS: TryGetComponent,
+{
+    if mesh_handles.len() != model.meshes.len() {
+        return Err(format_err!(
+            "expected {} meshes, got {}",
+            model.meshes.len(),
+            mesh_handles.len()
+        ));
+    }
+    let transform_store = world
+        .components
+        .get_components::<TransformComponent>()
+        .ok_or_else(|| format_err!("TransformComponent is not registered"))?;
+    let mesh_store = world
+        .components
+        .get_components::<MeshComponent>()
+        .ok_or_else(|| format_err!("MeshComponent is not registered"))?;
+    let material_store = world
+        .components
+        .get_components::<MaterialComponent>()
+        .ok_or_else(|| format_err!("MaterialComponent is not registered"))?;
+
+    let materials: HashMap<Option<usize>, Material<TextureHandle>> = model
+        .materials
+        .iter()
+        .map(|(&index, material)| {
+            let material = material
+                .transform_textures(|tex, _| texture_handles.get(tex).cloned());
+            (index, material)
+        })
+        .collect();
+
+    let skin_store = if model.skins.is_empty() {
+        None
+    } else {
+        let store = world
+            .components
+            .get_components::<SkinComponent>()
+            .ok_or_else(|| format_err!("SkinComponent is not registered"))?;
+        Some(store)
+    };
+    let morph_store =
+        if model.meshes.iter().all(|m| m.default_weights.is_empty()) {
+            None
+        } else {
+            let store = world
+                .components
+                .get_components::<MorphWeights>()
+                .ok_or_else(|| format_err!("MorphWeights is not registered"))?;
+            Some(store)
+        };
+    let inverse_bind_matrices: Vec<Arc<Vec<Mat4>>> = model
+        .skins
+        .iter()
+        .map(|skin| Arc::new(skin.inverse_bind_matrices.clone()))
+        .collect();
+
+    let mut transforms = transform_store.write().expect("poisoned RwLock");
+    let mut meshes = mesh_store.write().expect("poisoned RwLock");
+    let mut material_components =
+        material_store.write().expect("poisoned RwLock");
+    let mut skins = skin_store
+        .as_ref()
+        .map(|store| store.write().expect("poisoned RwLock"));
+    let mut morph_weights = morph_store
+        .as_ref()
+        .map(|store| store.write().expect("poisoned RwLock"));
+
+    // only the node trees of the default scene are spawned
+    let mut in_scene = vec![false; model.nodes.len()];
+    let mut stack = model.root_nodes.clone();
+    while let Some(i) = stack.pop() {
+        if !in_scene[i] {
+            in_scene[i] = true;
+            stack.extend_from_slice(&model.nodes[i].children);
+        }
+    }
+
+    let root = world.components.alloc_entity();
+    transforms.insert(
+        *root,
+        TransformComponent {
+            parent: None,
+            transform: identity_transform(),
+        },
+    );
+
+    // nodes outside the scene get no entity, leaving the node indices of
+    // animations and skins without a target
+    let node_entities: Vec<Option<Entity>> = in_scene
+        .iter()
+        .map(|&in_scene| {
+            if in_scene {
+                Some(world.components.alloc_entity())
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    for (node, &entity) in model.nodes.iter().zip(node_entities.iter()) {
+        let entity = match entity {
+            Some(entity) => entity,
+            None => continue,
+        };
+        // the parents of nodes in the scene are in it too
+        let parent = node.parent.and_then(|i| node_entities[i]).unwrap_or(root);
+        transforms.insert(
+            *entity,
+            TransformComponent {
+                parent: Some(parent),
+                transform: node.transform,
+            },
+        );
+        // the primitives of a mesh share its weights
+        let default_weights = node
+            .mesh_indices
+            .first()
+            .map(|&i| &model.meshes[i].default_weights)
+            .filter(|weights| !weights.is_empty());
+        if let (Some(weights), Some(store)) =
+            (default_weights, morph_weights.as_mut())
+        {
+            store.insert(*entity, MorphWeights::new(weights.clone()));
+        }
+
+        for &mesh_index in &node.mesh_indices {
+            let primitive_entity = if node.mesh_indices.len() == 1 {
+                entity
+            } else {
+                let e = world.components.alloc_entity();
+                transforms.insert(
+                    *e,
+                    TransformComponent {
+                        parent: Some(entity),
+                        transform: identity_transform(),
+                    },
+                );
+                e
+            };
+            let material = materials
+                .get(&model.meshes[mesh_index].material_index)
+                .cloned()
+                .unwrap_or_default();
+            meshes.insert(
+                *primitive_entity,
+                MeshComponent {
+                    mesh: mesh_handles[mesh_index],
+                },
+            );
+            material_components
+                .insert(*primitive_entity, MaterialComponent { material });
+            if let (Some(skin), Some(skins)) = (node.skin, skins.as_mut()) {
+                let joints = model.skins[skin]
+                    .joints
+                    .iter()
+                    .map(|&joint| node_entities[joint])
+                    .collect();
+                skins.insert(
+                    *primitive_entity,
+                    SkinComponent::new(
+                        joints,
+                        inverse_bind_matrices[skin].clone(),
+                    ),
+                );
+            }
+        }
+    }
+
+    if !model.animations.is_empty() {
+        let players = world
+            .components
+            .get_components::<AnimationPlayer>()
+            .ok_or_else(|| format_err!("AnimationPlayer is not registered"))?;
+        let mut player =
+            AnimationPlayer::new(model.animations.clone(), node_entities);
+        player.play(0);
+        players
+            .write()
+            .expect("poisoned RwLock")
+            .insert(*root, player);
+    }
+
+    Ok(root)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::test_util::*;
+
+    #[test]
+    fn test_spawn_model() {
+        let mut renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let model = Model::from_gltf("assets/models/DamagedHelmet.glb")
+            .expect("could not load DamagedHelmet.glb");
+        let root = spawn_model(&mut world, &model, &mut renderer).unwrap();
+
+        assert_eq!(world.components.entities().count(), 2);
+        assert_eq!(world.resources.meshes.len(), 1);
+        assert_eq!(world.resources.textures.len(), 5);
+
+        let helmet = world.components.entities().find(|&e| e != root).unwrap();
+        let transforms = world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap();
+        let transforms = transforms.read().unwrap();
+        assert_eq!(transforms[*root].as_ref().unwrap().parent, None);
+        assert_eq!(transforms[*helmet].as_ref().unwrap().parent, Some(root));
+
+        let meshes =
+            world.components.get_components::<MeshComponent>().unwrap();
+        let mesh_handle =
+            meshes.read().unwrap()[*helmet].as_ref().unwrap().mesh;
+        assert!(world.resources.meshes.contains_key(&mesh_handle));
+
+        let materials = world
+            .components
+            .get_components::<MaterialComponent>()
+            .unwrap();
+        let materials = materials.read().unwrap();
+        let material = &materials[*helmet].as_ref().unwrap().material;
+        let albedo = material.albedo_map.expect("helmet has an albedo map");
+        assert!(world.resources.textures.contains_key(&albedo));
+    }
+
+    #[test]
+    fn test_spawn_default_scene_only() {
+        let path = std::env::temp_dir().join("slsengine_two_scenes.gltf");
+        std::fs::write(
+            &path,
+            r#"{
+                "asset": {"version": "2.0"},
+                "scene": 0,
+                "scenes": [{"nodes": [0]}, {"nodes": [2]}],
+                "nodes": [
+                    {"name": "body", "children": [1]},
+                    {"name": "head"},
+                    {"name": "alternate_lod"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let model = Model::from_gltf(&path).unwrap();
+        spawn_model(&mut world, &model, &mut renderer).unwrap();
+
+        let transforms = world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap();
+        let transforms = transforms.read().unwrap();
+        // the root, body and head, but not the other scene's node
+        assert_eq!(world.components.entities().count(), 3);
+        assert!(world
+            .components
+            .entities()
+            .all(|e| transforms[*e].is_some()));
+    }
+
+    #[test]
+    fn test_spawn_animated_model() {
+        use crate::game::animation::{update_animations, PlaybackMode};
+        let mut renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let model = Model::from_gltf("assets/models/AnimatedProp.gltf")
+            .expect("could not load AnimatedProp.gltf");
+        let root = spawn_model(&mut world, &model, &mut renderer).unwrap();
+
+        let players = world
+            .components
+            .get_components::<AnimationPlayer>()
+            .unwrap();
+        let (node_root, prop) = {
+            let players = players.read().unwrap();
+            let player = players[*root].as_ref().expect("root has a player");
+            assert_eq!(player.current, Some(0));
+            (player.targets[0].unwrap(), player.targets[1].unwrap())
+        };
+        let transform = |entity: Entity| {
+            let transforms = world
+                .components
+                .get_components::<TransformComponent>()
+                .unwrap();
+            let transforms = transforms.read().unwrap();
+            transforms[*entity].as_ref().unwrap().transform
+        };
+
+        update_animations(&world.components, 0.5);
+        assert!((transform(node_root).disp.y - 1.0).abs() < 1e-5);
+        // stepped rotation holds the first keyframe until 1s
+        assert_eq!(transform(prop).rot, Quaternion::one());
+        update_animations(&world.components, 0.5);
+        let t = transform(prop);
+        let forward = t.rot.rotate_vector(-Vec3::unit_z());
+        assert!((forward - -Vec3::unit_x()).magnitude() < 1e-5);
+        // halfway through a smooth scale from 1 to 3
+        assert!((t.scale - 2.0).abs() < 1e-5);
+
+        // ping-pong at double speed: 1s in plus 1.5s of playback
+        {
+            let mut players = players.write().unwrap();
+            let player = players[*root].as_mut().unwrap();
+            player.mode = PlaybackMode::PingPong;
+            player.speed = 2.0;
+        }
+        update_animations(&world.components, 0.75);
+        assert!((transform(node_root).disp.y - 1.0).abs() < 1e-5);
+
+        {
+            let mut players = players.write().unwrap();
+            let player = players[*root].as_mut().unwrap();
+            assert!(player.play_named("Spin"));
+            assert!(!player.play_named("Walk"));
+            player.speed = 1.0;
+        }
+        update_animations(&world.components, 1.0);
+        let forward = transform(prop).rot.rotate_vector(-Vec3::unit_z());
+        assert!((forward - -Vec3::unit_x()).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn test_spawn_skinned_model() {
+        use crate::game::animation::{
+            update_animations, update_skins, PlaybackMode,
+        };
+        let mut renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let model = Model::from_gltf("assets/models/SkinnedStrip.gltf")
+            .expect("could not load SkinnedStrip.gltf");
+        let root = spawn_model(&mut world, &model, &mut renderer).unwrap();
+
+        let skins = world.components.get_components::<SkinComponent>().unwrap();
+        let (strip, hip) = {
+            let players = world
+                .components
+                .get_components::<AnimationPlayer>()
+                .unwrap();
+            let players = players.read().unwrap();
+            let targets = &players[*root].as_ref().unwrap().targets;
+            (targets[0].unwrap(), targets[1].unwrap())
+        };
+        {
+            let skins = skins.read().unwrap();
+            let skin = skins[*strip].as_ref().expect("strip is skinned");
+            assert_eq!(skin.joints.len(), 2);
+            assert_eq!(skin.joints[0], Some(hip));
+            assert!(!skin.needs_cpu_skinning());
+        }
+        let mesh = &model.meshes[0].mesh;
+        let skinned = || {
+            let skins = skins.read().unwrap();
+            mesh.skinned(&skins[*strip].as_ref().unwrap().palette)
+        };
+
+        // the bind pose leaves the mesh as it is
+        update_skins(&world.components);
+        for (posed, bound) in skinned().vertices.iter().zip(&mesh.vertices) {
+            assert_eq!(posed.position, bound.position);
+            assert_eq!(posed.weights, [0.0; 4]);
+        }
+
+        // bending the knee a quarter turn folds the top of the strip over
+        {
+            let players = world
+                .components
+                .get_components::<AnimationPlayer>()
+                .unwrap();
+            let mut players = players.write().unwrap();
+            players[*root].as_mut().unwrap().mode = PlaybackMode::Clamp;
+        }
+        update_animations(&world.components, 1.0);
+        update_skins(&world.components);
+        let posed: Vec<Vec3> = skinned()
+            .vertices
+            .iter()
+            .map(|v| Vec3::from(v.position))
+            .collect();
+        let near = |a: Vec3, b: Vec3| (a - b).magnitude() < 1e-5;
+        assert!(near(posed[0], vec3(-0.5, 0.0, 0.0)));
+        assert!(near(posed[3], vec3(0.25, 1.25, 0.0)));
+        assert!(near(posed[4], vec3(-1.0, 0.5, 0.0)));
+        assert!(near(posed[5], vec3(-1.0, 1.5, 0.0)));
+
+        // skins follow their joints, so moving the skinned entity itself
+        // leaves the vertices where they were in the world
+        {
+            let transforms = world
+                .components
+                .get_components::<TransformComponent>()
+                .unwrap();
+            let mut transforms = transforms.write().unwrap();
+            transforms[*strip].as_mut().unwrap().transform.disp =
+                vec3(5.0, 0.0, 0.0);
+        }
+        update_skins(&world.components);
+        let top = Vec3::from(skinned().vertices[5].position);
+        assert!(near(top + vec3(5.0, 0.0, 0.0), posed[5]));
+    }
+
+    #[test]
+    fn test_spawn_morphed_model() {
+        use crate::game::animation::update_animations;
+        let mut renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let model = Model::from_gltf("assets/models/MorphQuad.gltf")
+            .expect("could not load MorphQuad.gltf");
+        let root = spawn_model(&mut world, &model, &mut renderer).unwrap();
+        let face = world.components.entities().find(|&e| e != root).unwrap();
+
+        let weights =
+            world.components.get_components::<MorphWeights>().unwrap();
+        let face_weights = || {
+            let weights = weights.read().unwrap();
+            weights[*face].as_ref().unwrap().weights.clone()
+        };
+        assert_eq!(face_weights(), vec![0.5, 0.0]);
+
+        update_animations(&world.components, 0.5);
+        assert_eq!(face_weights(), vec![0.5, 0.25]);
+        update_animations(&world.components, 1.0);
+        assert_eq!(face_weights(), vec![0.5, 0.75]);
+
+        // gameplay code can drive the weights while nothing plays
+        {
+            let players = world
+                .components
+                .get_components::<AnimationPlayer>()
+                .unwrap();
+            players.write().unwrap()[*root].as_mut().unwrap().stop();
+        }
+        weights.write().unwrap()[*face]
+            .as_mut()
+            .unwrap()
+            .set(0, 1.0);
+        update_animations(&world.components, 0.5);
+        assert_eq!(face_weights(), vec![1.0, 0.75]);
+    }
+}
@@ -0,0 +1,204 @@
+//! Script types for the engine's math and built-in components.
+
+use crate::game::component::*;
+use crate::game::component_stores::{Storage, TryGetComponent};
+use crate::game::physics::RigidBody;
+use crate::math::*;
+use cgmath::*;
+use rhai::{Dynamic, Engine, FLOAT, INT};
+use std::sync::Arc;
+
+/// A component store scripts can read and write, holding components as
+/// script values
+pub trait BoundComponent: Send + Sync {
+    fn get(&self, entity: Entity) -> Option<Dynamic>;
+    /// Adds or replaces the entity's component. Fails if the value is of
+    /// another type.
+    fn set(&self, entity: Entity, value: Dynamic) -> Result<(), String>;
+    fn remove(&self, entity: Entity);
+}
+
+struct Bound<C: Component>(Arc<Storage<C>>);
+
+impl<C> BoundComponent for Bound<C>
+where
+    C: Component + Clone + Send + Sync,
+{
+    fn get(&self, entity: Entity) -> Option<Dynamic> {
+        let store = self.0.read().expect("poisoned RwLock");
+        store[*entity].clone().map(Dynamic::from)
+    }
+
+    fn set(&self, entity: Entity, value: Dynamic) -> Result<(), String> {
+        let type_name = value.type_name();
+        let component = value.try_cast::<C>().ok_or_else(|| {
+            format!(
+                "expected {}, found {}",
+                std::any::type_name::<C>(),
+                type_name
+            )
+        })?;
+        let mut store = self.0.write().expect("poisoned RwLock");
+        store.insert(*entity, component);
+        Ok(())
+    }
+
+    fn remove(&self, entity: Entity) {
+        let mut store = self.0.write().expect("poisoned RwLock");
+        store.remove(*entity);
+    }
+}
+
+/// Binds the world's store of `C` for scripts, if it has one
+pub(super) fn bind<C, CS>(
+    components: &ComponentManager<CS>,
+) -> Option<Arc<dyn BoundComponent>>
+where
+    C: Component + Clone + Send + Sync,
+    CS: TryGetComponent,
+{
+    let store = components.get_components::<C>()?;
+    Some(Arc::new(Bound(store)))
+}
+
+fn register_entity(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<Entity>("Entity")
+        .register_get("index", |e: &mut Entity| e.index() as INT)
+        .register_fn("==", |a: Entity, b: Entity| a == b)
+        .register_fn("!=", |a: Entity, b: Entity| a != b)
+        .register_fn("to_string", |e: &mut Entity| {
+            format!("Entity({})", e.index())
+        })
+        .register_fn("to_debug", |e: &mut Entity| format!("{:?}", e));
+}
+
+fn register_vec3(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<Vec3>("Vec3")
+        .register_fn("vec3", |x: FLOAT, y: FLOAT, z: FLOAT| vec3(x, y, z))
+        .register_get_set("x", |v: &mut Vec3| v.x, |v: &mut Vec3, x| v.x = x)
+        .register_get_set("y", |v: &mut Vec3| v.y, |v: &mut Vec3, y| v.y = y)
+        .register_get_set("z", |v: &mut Vec3| v.z, |v: &mut Vec3, z| v.z = z)
+        .register_fn("+", |a: Vec3, b: Vec3| a + b)
+        .register_fn("-", |a: Vec3, b: Vec3| a - b)
+        .register_fn("-", |a: Vec3| -a)
+        .register_fn("*", |a: Vec3, s: FLOAT| a * s)
+        .register_fn("*", |s: FLOAT, a: Vec3| a * s)
+        .register_fn("/", |a: Vec3, s: FLOAT| a / s)
+        .register_fn("==", |a: Vec3, b: Vec3| a == b)
+        .register_fn("dot", |a: Vec3, b: Vec3| a.dot(b))
+        .register_fn("cross", |a: Vec3, b: Vec3| a.cross(b))
+        .register_fn("length", |a: &mut Vec3| a.magnitude())
+        .register_fn("normalize", |a: Vec3| {
+            if a.magnitude2() > 0.0 {
+                a.normalize()
+            } else {
+                a
+            }
+        })
+        .register_fn("to_string", |a: &mut Vec3| {
+            format!("vec3({}, {}, {})", a.x, a.y, a.z)
+        })
+        .register_fn("to_debug", |a: &mut Vec3| format!("{:?}", a));
+}
+
+fn register_quat(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<Quaternion<f32>>("Quat")
+        .register_fn("quat", Quaternion::<f32>::one)
+        // rotations are in degrees, which designers find easier to read
+        .register_fn("quat_from_euler", |x: FLOAT, y: FLOAT, z: FLOAT| {
+            Quaternion::from(Euler::new(Deg(x), Deg(y), Deg(z)))
+        })
+        .register_fn("quat_from_axis_angle", |axis: Vec3, angle: FLOAT| {
+            Quaternion::from_axis_angle(axis.normalize(), Deg(angle))
+        })
+        .register_fn("*", |a: Quaternion<f32>, b: Quaternion<f32>| a * b)
+        .register_fn("*", |q: Quaternion<f32>, v: Vec3| q.rotate_vector(v))
+        .register_fn("slerp", |a: Quaternion<f32>, b, t: FLOAT| a.slerp(b, t))
+        .register_fn("to_debug", |q: &mut Quaternion<f32>| format!("{:?}", q));
+}
+
+fn register_transform(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<TransformComponent>("Transform")
+        .register_fn("transform", || TransformComponent {
+            parent: None,
+            transform: Decomposed {
+                scale: 1.0,
+                rot: Quaternion::one(),
+                disp: Vec3::zero(),
+            },
+        })
+        .register_get_set(
+            "position",
+            |t: &mut TransformComponent| t.transform.disp,
+            |t: &mut TransformComponent, p| t.transform.disp = p,
+        )
+        .register_get_set(
+            "rotation",
+            |t: &mut TransformComponent| t.transform.rot,
+            |t: &mut TransformComponent, r| t.transform.rot = r,
+        )
+        .register_get_set(
+            "scale",
+            |t: &mut TransformComponent| t.transform.scale,
+            |t: &mut TransformComponent, s| t.transform.scale = s,
+        )
+        // the parent is an Entity, or () for none
+        .register_get_set(
+            "parent",
+            |t: &mut TransformComponent| {
+                t.parent.map_or(Dynamic::UNIT, Dynamic::from)
+            },
+            |t: &mut TransformComponent, parent: Dynamic| {
+                t.parent = parent.try_cast::<Entity>();
+            },
+        )
+        .register_fn("to_debug", |t: &mut TransformComponent| {
+            format!("{:?}", t)
+        });
+}
+
+fn register_rigid_body(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<RigidBody>("RigidBody")
+        .register_fn("rigid_body", RigidBody::default)
+        .register_get_set(
+            "velocity",
+            |b: &mut RigidBody| b.velocity,
+            |b: &mut RigidBody, v| b.velocity = v,
+        )
+        .register_get_set(
+            "angular_velocity",
+            |b: &mut RigidBody| b.angular_velocity,
+            |b: &mut RigidBody, v| b.angular_velocity = v,
+        )
+        .register_get_set(
+            "mass",
+            |b: &mut RigidBody| b.mass,
+            |b: &mut RigidBody, m| b.mass = m,
+        )
+        .register_get_set(
+            "gravity_scale",
+            |b: &mut RigidBody| b.gravity_scale,
+            |b: &mut RigidBody, g| b.gravity_scale = g,
+        )
+        .register_get_set(
+            "kinematic",
+            |b: &mut RigidBody| b.kinematic,
+            |b: &mut RigidBody, k| b.kinematic = k,
+        )
+        .register_fn("to_debug", |b: &mut RigidBody| format!("{:?}", b));
+}
+
+/// Registers the math types and the script types of the built-in
+/// components
+pub(super) fn register_types(engine: &mut Engine) {
+    register_entity(engine);
+    register_vec3(engine);
+    register_quat(engine);
+    register_transform(engine);
+    register_rigid_body(engine);
+}
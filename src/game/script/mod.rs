@@ -0,0 +1,673 @@
+//! Gameplay logic written in Rhai scripts.
+//!
+//! A `ScriptSystem` runs one script file as an `EntitySystem`. The
+//! script's top level runs on the first update after it loads, and
+//! registers per-frame callbacks with `on_update`:
+//!
+//! ```rhai
+//! on_update(|dt| {
+//!     for entity in query(["Transform", "RigidBody"]) {
+//!         let body = get(entity, "RigidBody");
+//!         if action_just_pressed("jump") {
+//!             body.velocity += vec3(0.0, 5.0, 0.0);
+//!         }
+//!         body.velocity.x = axis("move_x") * 3.0;
+//!         set(entity, "RigidBody", body);
+//!     }
+//! });
+//! ```
+//!
+//! Scripts can also create entities with `spawn_entity()` and destroy
+//! them with `despawn_entity(entity)`, test components with
+//! `has(entity, name)`, and `remove(entity, name)` them.
+//! `get` returns a copy of a component, which `set` writes back.
+//! The built-in `Transform` and `RigidBody` components are available to
+//! every script; games add their own with `with_component`.
+//!
+//! Scripts loaded from files are reloaded when the file changes, running
+//! their top level again. If the changed script doesn't parse, the old
+//! one keeps running.
+
+mod bindings;
+
+pub use self::bindings::BoundComponent;
+
+use super::component::*;
+use super::input::InputMap;
+use super::physics::RigidBody;
+use super::system::{EntitySystem, SystemDispatch};
+use super::TryGetComponent;
+use log::*;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FnPtr, AST, FLOAT};
+use slsengine_entityalloc::GenerationalIndexAllocator;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+#[derive(Fail, Debug)]
+pub enum ScriptError {
+    #[fail(display = "could not read script: {}", _0)]
+    Io(std::io::Error),
+    #[fail(display = "could not parse script: {}", _0)]
+    Parse(rhai::ParseError),
+    #[fail(display = "script failed: {}", _0)]
+    Runtime(Box<EvalAltResult>),
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// The world as seen by a running script
+#[derive(Default)]
+struct ScriptContext {
+    components: HashMap<String, Arc<dyn BoundComponent>>,
+    /// The world's entity allocator, lent to the script while it runs
+    entities: Option<GenerationalIndexAllocator>,
+    spawned: Vec<Entity>,
+    despawned: Vec<Entity>,
+    input: InputMap,
+    dt: f32,
+    callbacks: Vec<FnPtr>,
+    /// Whether the top level of the script still has to run
+    needs_start: bool,
+    error: Option<ScriptError>,
+}
+
+impl ScriptContext {
+    fn is_alive(&self, entity: Entity) -> bool {
+        self.entities.as_ref().map_or(false, |alloc| {
+            entity.index() < alloc.capacity() && alloc.is_live(*entity)
+        })
+    }
+
+    fn store(&self, name: &str) -> ScriptResult<&Arc<dyn BoundComponent>> {
+        self.components
+            .get(name)
+            .ok_or_else(|| format!("no component named '{}'", name).into())
+    }
+
+    fn live_entities(&self) -> Vec<Entity> {
+        self.entities
+            .as_ref()
+            .map(|alloc| alloc.iter_live().map(Entity).collect())
+            .unwrap_or_default()
+    }
+
+    fn query(&self, names: &[String]) -> ScriptResult<Array> {
+        let stores = names
+            .iter()
+            .map(|name| self.store(name))
+            .collect::<ScriptResult<Vec<_>>>()?;
+        Ok(self
+            .live_entities()
+            .into_iter()
+            .filter(|&e| stores.iter().all(|s| s.get(e).is_some()))
+            .map(Dynamic::from)
+            .collect())
+    }
+}
+
+type Context = Arc<Mutex<ScriptContext>>;
+
+fn lock(context: &Context) -> MutexGuard<'_, ScriptContext> {
+    context.lock().expect("poisoned Mutex")
+}
+
+/// Registers the functions scripts use to reach the world
+fn register_world_api(engine: &mut Engine, context: &Context) {
+    let ctx = context.clone();
+    engine.register_fn("entities", move || -> Array {
+        let context = lock(&ctx);
+        context
+            .live_entities()
+            .into_iter()
+            .map(Dynamic::from)
+            .collect()
+    });
+    let ctx = context.clone();
+    engine.register_fn("query", move |name: &str| {
+        lock(&ctx).query(&[name.to_owned()])
+    });
+    let ctx = context.clone();
+    engine.register_fn("query", move |names: Array| {
+        let names: Vec<String> =
+            names.into_iter().map(|n| n.to_string()).collect();
+        lock(&ctx).query(&names)
+    });
+    let ctx = context.clone();
+    engine.register_fn("is_alive", move |entity: Entity| {
+        lock(&ctx).is_alive(entity)
+    });
+    let ctx = context.clone();
+    engine.register_fn(
+        "has",
+        move |entity: Entity, name: &str| -> ScriptResult<bool> {
+            let context = lock(&ctx);
+            let store = context.store(name)?;
+            Ok(context.is_alive(entity) && store.get(entity).is_some())
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn(
+        "get",
+        move |entity: Entity, name: &str| -> ScriptResult<Dynamic> {
+            let context = lock(&ctx);
+            let store = context.store(name)?;
+            if !context.is_alive(entity) {
+                return Ok(Dynamic::UNIT);
+            }
+            Ok(store.get(entity).unwrap_or(Dynamic::UNIT))
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn(
+        "set",
+        move |entity: Entity, name: &str, value: Dynamic| -> ScriptResult<()> {
+            let context = lock(&ctx);
+            let store = context.store(name)?;
+            if !context.is_alive(entity) {
+                return Err(format!("{:?} was despawned", entity).into());
+            }
+            store.set(entity, value).map_err(|e| e.into())
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn(
+        "remove",
+        move |entity: Entity, name: &str| -> ScriptResult<()> {
+            let context = lock(&ctx);
+            context.store(name)?.remove(entity);
+            Ok(())
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn("spawn_entity", move || -> ScriptResult<Entity> {
+        let mut context = lock(&ctx);
+        let alloc = context
+            .entities
+            .as_mut()
+            .ok_or("entities can only be spawned by a running script")?;
+        let entity = Entity(alloc.allocate());
+        context.spawned.push(entity);
+        Ok(entity)
+    });
+    let ctx = context.clone();
+    engine.register_fn("despawn_entity", move |entity: Entity| {
+        let mut context = lock(&ctx);
+        if !context.is_alive(entity) {
+            return;
+        }
+        for store in context.components.values() {
+            store.remove(entity);
+        }
+        if let Some(alloc) = context.entities.as_mut() {
+            alloc.deallocate(*entity);
+        }
+        context.despawned.push(entity);
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("action_pressed", move |name: &str| {
+        lock(&ctx).input.action_pressed(name)
+    });
+    let ctx = context.clone();
+    engine.register_fn("action_just_pressed", move |name: &str| {
+        lock(&ctx).input.action_just_pressed(name)
+    });
+    let ctx = context.clone();
+    engine.register_fn("action_just_released", move |name: &str| {
+        lock(&ctx).input.action_just_released(name)
+    });
+    let ctx = context.clone();
+    engine.register_fn("axis", move |name: &str| -> FLOAT {
+        lock(&ctx).input.axis(name)
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("on_update", move |callback: FnPtr| {
+        lock(&ctx).callbacks.push(callback);
+    });
+}
+
+/// Binds a world's store of a component type for scripts
+type Binder<CS> = fn(&ComponentManager<CS>) -> Option<Arc<dyn BoundComponent>>;
+
+/// Runs a script as an entity system
+pub struct ScriptSystem<CS: TryGetComponent> {
+    name: String,
+    /// The file the script was loaded from, and its modified time and
+    /// length when last read
+    source: Option<(PathBuf, Option<(SystemTime, u64)>)>,
+    engine: Engine,
+    ast: AST,
+    context: Context,
+    binders: Vec<(String, Binder<CS>)>,
+}
+
+impl<CS: TryGetComponent> fmt::Debug for ScriptSystem<CS> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let components: Vec<&str> =
+            self.binders.iter().map(|(name, _)| name.as_str()).collect();
+        f.debug_struct("ScriptSystem")
+            .field("name", &self.name)
+            .field("components", &components)
+            .finish()
+    }
+}
+
+fn file_version(path: &Path) -> Result<(SystemTime, u64), ScriptError> {
+    let metadata = fs::metadata(path).map_err(ScriptError::Io)?;
+    let modified = metadata.modified().map_err(ScriptError::Io)?;
+    Ok((modified, metadata.len()))
+}
+
+impl<CS: TryGetComponent> ScriptSystem<CS> {
+    /// Compiles a script which isn't backed by a file
+    pub fn from_source(name: &str, source: &str) -> Result<Self, ScriptError> {
+        let context = Context::default();
+        let mut engine = Engine::new();
+        engine.on_print(|text| info!("script: {}", text));
+        engine.on_debug(|text, _, pos| debug!("script at {}: {}", pos, text));
+        bindings::register_types(&mut engine);
+        register_world_api(&mut engine, &context);
+        let ast = engine.compile(source).map_err(ScriptError::Parse)?;
+        lock(&context).needs_start = true;
+        let system = ScriptSystem {
+            name: name.to_owned(),
+            source: None,
+            engine,
+            ast,
+            context,
+            binders: Vec::new(),
+        };
+        Ok(system
+            .with_component::<TransformComponent>("Transform")
+            .with_component::<RigidBody>("RigidBody"))
+    }
+
+    /// Loads a script file, which is reloaded when it changes
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScriptError> {
+        let path = path.as_ref();
+        let version = file_version(path)?;
+        let source = fs::read_to_string(path).map_err(ScriptError::Io)?;
+        let name = path.display().to_string();
+        let mut system = ScriptSystem::from_source(&name, &source)?;
+        system.source = Some((path.to_owned(), Some(version)));
+        Ok(system)
+    }
+
+    /// Makes a component available to the script by name. Games register
+    /// the component's fields as properties with `engine_mut`.
+    pub fn with_component<C>(mut self, name: &str) -> Self
+    where
+        C: Component + Clone + Send + Sync,
+    {
+        self.engine.register_type_with_name::<C>(name);
+        self.binders
+            .retain(|(existing, _)| existing.as_str() != name);
+        self.binders
+            .push((name.to_owned(), bindings::bind::<C, CS>));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The script engine, for registering more types and functions
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+
+    /// Recompiles the script from a new source. Its top level runs again
+    /// on the next update, replacing its callbacks.
+    pub fn set_source(&mut self, source: &str) -> Result<(), ScriptError> {
+        self.ast = self.engine.compile(source).map_err(ScriptError::Parse)?;
+        let mut context = lock(&self.context);
+        context.callbacks.clear();
+        context.needs_start = true;
+        Ok(())
+    }
+
+    /// Reloads the script if its file has changed since it was last read.
+    /// Returns whether it reloaded. A script which fails to parse isn't
+    /// retried until the file changes again.
+    pub fn reload_if_changed(&mut self) -> Result<bool, ScriptError> {
+        let path = match self.source {
+            Some((ref path, _)) => path.clone(),
+            None => return Ok(false),
+        };
+        let version = file_version(&path)?;
+        if let Some((_, ref mut last)) = self.source {
+            if *last == Some(version) {
+                return Ok(false);
+            }
+            *last = Some(version);
+        }
+        let source = fs::read_to_string(&path).map_err(ScriptError::Io)?;
+        self.set_source(&source)?;
+        Ok(true)
+    }
+
+    /// Sets the time step and input the script's callbacks see on the
+    /// next dispatch
+    pub fn begin_frame(&mut self, dt: f32, input: &InputMap) {
+        let mut context = lock(&self.context);
+        context.dt = dt;
+        context.input = input.clone();
+    }
+
+    /// Takes the error that stopped the script's last dispatch, if any
+    pub fn take_error(&self) -> Option<ScriptError> {
+        lock(&self.context).error.take()
+    }
+
+    fn run(&self) -> ScriptResult<()> {
+        let (start, dt) = {
+            let mut context = lock(&self.context);
+            (mem::replace(&mut context.needs_start, false), context.dt)
+        };
+        if start {
+            self.engine.run_ast(&self.ast)?;
+        }
+        let callbacks = lock(&self.context).callbacks.clone();
+        for callback in callbacks {
+            // whatever the callback returns is ignored
+            let _: Dynamic = callback.call(&self.engine, &self.ast, (dt,))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, CS: TryGetComponent> EntitySystem<'a, CS> for ScriptSystem<CS> {
+    const DISPATCH: SystemDispatch = SystemDispatch::Update;
+    /// The component stores the script can reach, by name
+    type Data = Vec<(String, Arc<dyn BoundComponent>)>;
+
+    fn dispatch(&self, manager: &mut ComponentManager<CS>, data: Self::Data) {
+        {
+            let mut context = lock(&self.context);
+            context.components = data.into_iter().collect();
+            // lent to the script so it can spawn and despawn entities
+            context.entities = Some(mem::replace(
+                &mut manager.entity_alloc,
+                GenerationalIndexAllocator::with_capacity(0),
+            ));
+        }
+        let result = self.run();
+
+        let mut context = lock(&self.context);
+        manager.entity_alloc =
+            context.entities.take().expect("entity allocator was taken");
+        for entity in context.spawned.drain(..) {
+            manager.mark_spawned(entity);
+        }
+        for entity in context.despawned.drain(..) {
+            manager.masks.remove(*entity);
+            manager.mark_spawned(entity);
+        }
+        context.components.clear();
+        if let Err(e) = result {
+            context.error = Some(ScriptError::Runtime(e));
+        }
+    }
+
+    fn prep_data<I>(
+        &self,
+        manager: &'a ComponentManager<CS>,
+        _entities: I,
+    ) -> Result<Self::Data, failure::Error>
+    where
+        I: Iterator<Item = Entity> + 'a,
+    {
+        Ok(self
+            .binders
+            .iter()
+            .filter_map(|(name, bind)| {
+                bind(manager).map(|store| (name.clone(), store))
+            })
+            .collect())
+    }
+}
+
+/// Reloads changed scripts, then runs each of them for a frame. Failing
+/// scripts are logged rather than stopping the game.
+pub fn run_scripts<CS: TryGetComponent>(
+    scripts: &mut [ScriptSystem<CS>],
+    components: &mut ComponentManager<CS>,
+    input: &InputMap,
+    dt: f32,
+) {
+    for script in scripts.iter_mut() {
+        match script.reload_if_changed() {
+            Ok(true) => info!("reloaded script {}", script.name()),
+            Ok(false) => {}
+            Err(e) => warn!("could not reload script {}: {}", script.name(), e),
+        }
+        script.begin_frame(dt, input);
+        let data = match script.prep_data(components, components.entities()) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("could not run script {}: {}", script.name(), e);
+                continue;
+            }
+        };
+        script.dispatch(components, data);
+        if let Some(e) = script.take_error() {
+            warn!("{}: {}", script.name(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::component_stores::AnyComponentStore;
+    use crate::game::input::{Button, InputSnapshot};
+    use crate::game::world::InputState;
+    use crate::math::*;
+    use cgmath::*;
+    use sdl2::keyboard::Scancode;
+    use std::io::Write;
+
+    type Manager = ComponentManager<AnyComponentStore>;
+
+    fn manager() -> Manager {
+        ComponentManager::new(AnyComponentStore::with_built_in_components())
+    }
+
+    fn run(script: &mut ScriptSystem<AnyComponentStore>, m: &mut Manager) {
+        run_with_input(script, m, &InputMap::new());
+    }
+
+    fn run_with_input(
+        script: &mut ScriptSystem<AnyComponentStore>,
+        m: &mut Manager,
+        input: &InputMap,
+    ) {
+        script.begin_frame(0.5, input);
+        let data = script.prep_data(m, m.entities()).unwrap();
+        script.dispatch(m, data);
+        if let Some(e) = script.take_error() {
+            panic!("{}", e);
+        }
+    }
+
+    fn position(m: &Manager, entity: Entity) -> Option<Vec3> {
+        let transforms = m.get_components::<TransformComponent>().unwrap();
+        let transforms = transforms.read().unwrap();
+        transforms[*entity].as_ref().map(|t| t.transform.disp)
+    }
+
+    #[test]
+    fn test_update_components() {
+        let mut m = manager();
+        let entity = m.alloc_entity();
+        let transform = TransformComponent {
+            parent: None,
+            transform: Decomposed {
+                scale: 1.0,
+                rot: Quaternion::one(),
+                disp: Vec3::zero(),
+            },
+        };
+        let body = RigidBody {
+            velocity: vec3(2.0, 0.0, 0.0),
+            ..RigidBody::default()
+        };
+        m.get_components::<TransformComponent>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(*entity, transform);
+        m.get_components::<RigidBody>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(*entity, body);
+
+        let mut script = ScriptSystem::from_source(
+            "mover",
+            r#"
+            on_update(|dt| {
+                for entity in query(["Transform", "RigidBody"]) {
+                    let t = get(entity, "Transform");
+                    let body = get(entity, "RigidBody");
+                    t.position += body.velocity * dt;
+                    t.position.y = 3.0;
+                    set(entity, "Transform", t);
+                }
+            });
+            "#,
+        )
+        .unwrap();
+        run(&mut script, &mut m);
+        assert_eq!(position(&m, entity), Some(vec3(1.0, 3.0, 0.0)));
+        run(&mut script, &mut m);
+        assert_eq!(position(&m, entity), Some(vec3(2.0, 3.0, 0.0)));
+    }
+
+    #[test]
+    fn test_spawn_and_despawn() {
+        let mut m = manager();
+        let mut script = ScriptSystem::from_source(
+            "spawner",
+            r#"
+            let entity = spawn_entity();
+            let t = transform();
+            t.position = vec3(1.0, 2.0, 3.0);
+            set(entity, "Transform", t);
+
+            on_update(|dt| {
+                if action_just_pressed("clear") {
+                    for entity in query("Transform") {
+                        despawn_entity(entity);
+                    }
+                }
+            });
+            "#,
+        )
+        .unwrap();
+        run(&mut script, &mut m);
+        let spawned: Vec<Entity> = m.entities().collect();
+        assert_eq!(spawned.len(), 1);
+        assert_eq!(position(&m, spawned[0]), Some(vec3(1.0, 2.0, 3.0)));
+
+        let mut input = InputMap::new();
+        input.bind_action("clear", vec![Button::Key(Scancode::C)]);
+        let snapshot =
+            InputSnapshot::with_buttons(vec![Button::Key(Scancode::C)]);
+        let state = InputState {
+            last_mousepos: Point2::new(0.0, 0.0),
+            mousepos: Point2::new(0.0, 0.0),
+        };
+        input.update(&snapshot, &state);
+        run_with_input(&mut script, &mut m, &input);
+        assert_eq!(m.entities().count(), 0);
+        assert_eq!(position(&m, spawned[0]), None);
+    }
+
+    #[test]
+    fn test_script_errors() {
+        assert!(match ScriptSystem::<AnyComponentStore>::from_source(
+            "broken",
+            "let x = ;"
+        ) {
+            Err(ScriptError::Parse(_)) => true,
+            _ => false,
+        });
+
+        let mut m = manager();
+        m.alloc_entity();
+        let mut script = ScriptSystem::from_source(
+            "wrong type",
+            r#"
+            for entity in entities() {
+                set(entity, "Transform", rigid_body());
+            }
+            "#,
+        )
+        .unwrap();
+        script.begin_frame(0.5, &InputMap::new());
+        let data = script.prep_data(&m, m.entities()).unwrap();
+        script.dispatch(&mut m, data);
+        assert!(match script.take_error() {
+            Some(ScriptError::Runtime(_)) => true,
+            _ => false,
+        });
+        // the world gets its entities back even though the script failed
+        assert_eq!(m.entities().count(), 1);
+    }
+
+    #[test]
+    fn test_hot_reload() {
+        let path = std::env::temp_dir()
+            .join(format!("slsengine_hot_reload_{}.rhai", std::process::id()));
+        let write = |source: &str| {
+            let mut file = fs::File::create(&path).unwrap();
+            file.write_all(source.as_bytes()).unwrap();
+        };
+        let script_at = |x: f32| {
+            format!(
+                r#"
+                let entity = spawn_entity();
+                on_update(|dt| {{
+                    let t = transform();
+                    t.position.x = {:.1};
+                    set(entity, "Transform", t);
+                }});
+                "#,
+                x
+            )
+        };
+
+        write(&script_at(1.0));
+        let mut m = manager();
+        let mut scripts = vec![ScriptSystem::load(&path).unwrap()];
+        let input = InputMap::new();
+        run_scripts(&mut scripts, &mut m, &input, 0.5);
+        let first = m.entities().next().unwrap();
+        assert_eq!(position(&m, first).unwrap().x, 1.0);
+
+        // the reloaded script starts over, spawning another entity
+        write(&script_at(20.0));
+        run_scripts(&mut scripts, &mut m, &input, 0.5);
+        assert_eq!(m.entities().count(), 2);
+        let second = m.entities().find(|&e| e != first).unwrap();
+        assert_eq!(position(&m, second).unwrap().x, 20.0);
+
+        // a script that doesn't parse leaves the old one running
+        write("on_update(|dt| {");
+        assert!(match scripts[0].reload_if_changed() {
+            Err(ScriptError::Parse(_)) => true,
+            _ => false,
+        });
+        assert!(!scripts[0].reload_if_changed().unwrap());
+        run_scripts(&mut scripts, &mut m, &input, 0.5);
+        assert_eq!(m.entities().count(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
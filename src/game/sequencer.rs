@@ -0,0 +1,595 @@
+//! Scripted camera moves for intros, trailers and cutscenes.
+//!
+//! A `CameraSequence` is a list of shots, each moving the camera along a
+//! spline path over its duration with eased timing, while aiming it at a
+//! look-at path or along a rotation spline. Named events fire at set
+//! times. Sequences are authored in TOML:
+//!
+//! ```toml
+//! looping = false
+//!
+//! [[shots]]
+//! duration = 4.0
+//! easing = "EaseInOut"
+//! path = { kind = "CatmullRom", points = [[0, 2, 10], [5, 3, 5], [0, 4, 0]] }
+//! look_at = { kind = "CatmullRom", points = [[0, 0, 0]] }
+//!
+//! [[shots]]
+//! duration = 2.0
+//! path = { kind = "Hermite", points = [[0, 4, 0], [0, 4, -5]], tangents = [[0, 0, -5], [0, 0, -5]] }
+//! # pitch, yaw and roll in degrees
+//! rotation = { kind = "CatmullRom", points = [[0, 0, 0], [-20, 90, 0]] }
+//!
+//! [[events]]
+//! time = 3.5
+//! name = "show_title"
+//! ```
+//!
+//! Shots without a look-at path or rotations face along their path. A
+//! `SequencePlayer` on a camera entity plays a sequence, writing the
+//! entity's `TransformComponent` and collecting the events fired during
+//! each update for gameplay code to act on.
+
+use super::{component::*, TryGetComponent};
+use crate::math::*;
+use cgmath::*;
+use std::{fs, path::Path, sync::Arc};
+
+#[derive(Fail, Debug)]
+pub enum SequenceError {
+    #[fail(display = "could not parse camera sequence: {}", _0)]
+    Parse(toml::de::Error),
+    #[fail(display = "could not read camera sequence: {}", _0)]
+    Io(std::io::Error),
+    #[fail(
+        display = "shot {} has a {:?} curve with a wrong number of points or tangents",
+        shot, kind
+    )]
+    BadCurve { shot: usize, kind: CurveKind },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CurveKind {
+    Bezier,
+    CatmullRom,
+    Hermite,
+}
+
+/// Points of a spline, built with `Spline::bezier`, `Spline::catmull_rom`
+/// or `Spline::hermite`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CurveConfig {
+    pub kind: CurveKind,
+    pub points: Vec<[f32; 3]>,
+    /// Tangent of each point of Hermite curves
+    #[serde(default)]
+    pub tangents: Vec<[f32; 3]>,
+}
+
+impl CurveConfig {
+    fn build<T: SplineValue>(
+        &self,
+        shot: usize,
+        point: impl Fn([f32; 3]) -> T,
+        tangent: impl Fn([f32; 3]) -> Vec3,
+    ) -> Result<Spline<T>, SequenceError> {
+        let points: Vec<T> = self.points.iter().cloned().map(point).collect();
+        let spline = match self.kind {
+            CurveKind::Bezier => Spline::bezier(&points),
+            CurveKind::CatmullRom => Spline::catmull_rom(&points),
+            CurveKind::Hermite => {
+                let tangents: Vec<Vec3> =
+                    self.tangents.iter().cloned().map(tangent).collect();
+                Spline::hermite(&points, &tangents)
+            }
+        };
+        spline.ok_or(SequenceError::BadCurve {
+            shot,
+            kind: self.kind,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShotConfig {
+    /// Length of the shot, in seconds
+    pub duration: f32,
+    #[serde(default)]
+    pub easing: Easing,
+    /// Positions of the camera
+    pub path: CurveConfig,
+    /// Positions the camera looks at
+    #[serde(default)]
+    pub look_at: Option<CurveConfig>,
+    /// Pitch, yaw and roll of the camera in degrees, with tangents in
+    /// degrees per shot
+    #[serde(default)]
+    pub rotation: Option<CurveConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventConfig {
+    /// Seconds from the start of the sequence
+    pub time: f32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SequenceConfig {
+    #[serde(default)]
+    pub looping: bool,
+    pub shots: Vec<ShotConfig>,
+    #[serde(default)]
+    pub events: Vec<EventConfig>,
+}
+
+/// How a shot turns the camera
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShotAim {
+    /// Looks at positions along a spline
+    LookAt(Spline<Vec3>),
+    /// Follows a spline of rotations
+    Rotation(Spline<Quaternion<f32>>),
+    /// Faces the direction of travel
+    AlongPath,
+}
+
+/// Rotation of a camera, which looks down its -Z axis, facing `front`
+fn look_rotation(front: Vec3) -> Option<Quaternion<f32>> {
+    let front = front.normalize();
+    let up = if front.y.abs() > 0.999 {
+        -Vec3::unit_z()
+    } else {
+        Vec3::unit_y()
+    };
+    let right = front.cross(up).normalize();
+    let up = right.cross(front);
+    let rotation: Quaternion<f32> = Mat3::from_cols(right, up, -front).into();
+    if rotation.s.is_finite() {
+        Some(rotation)
+    } else {
+        None
+    }
+}
+
+/// One continuous camera move
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shot {
+    pub duration: f32,
+    pub easing: Easing,
+    pub path: Spline<Vec3>,
+    pub aim: ShotAim,
+    path_length: ArcLength,
+}
+
+impl Shot {
+    pub fn new(
+        duration: f32,
+        easing: Easing,
+        path: Spline<Vec3>,
+        aim: ShotAim,
+    ) -> Self {
+        Shot {
+            duration,
+            easing,
+            path_length: path.arc_length(),
+            path,
+            aim,
+        }
+    }
+
+    /// Placement of the camera `time` seconds into the shot. The camera
+    /// travels the path at an even speed, eased over the shot.
+    pub fn pose(&self, time: f32) -> Decomposed<Vec3, Quaternion<f32>> {
+        let progress = if self.duration > 0.0 {
+            self.easing.apply(time / self.duration)
+        } else {
+            1.0
+        };
+        let param = self.path_length.param_at_fraction(progress);
+        let position = self.path.sample(param);
+        let rot = match self.aim {
+            ShotAim::LookAt(ref targets) => {
+                look_rotation(targets.sample(progress) - position)
+            }
+            ShotAim::Rotation(ref rotations) => {
+                Some(rotations.sample(progress))
+            }
+            ShotAim::AlongPath => {
+                let step = 1e-3;
+                let (from, to) = if param + step <= 1.0 {
+                    (position, self.path.sample(param + step))
+                } else {
+                    (self.path.sample(param - step), position)
+                };
+                look_rotation(to - from)
+            }
+        };
+        Decomposed {
+            scale: 1.0,
+            rot: rot.unwrap_or_else(Quaternion::one),
+            disp: position,
+        }
+    }
+}
+
+/// A named event at a time in a sequence
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceEvent {
+    pub time: f32,
+    pub name: String,
+}
+
+/// Shots played one after another, with timed events
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraSequence {
+    pub shots: Vec<Shot>,
+    pub events: Vec<SequenceEvent>,
+    /// Whether the sequence starts over after its last shot
+    pub looping: bool,
+}
+
+impl CameraSequence {
+    pub fn from_config(config: &SequenceConfig) -> Result<Self, SequenceError> {
+        let position = |p: [f32; 3]| vec3(p[0], p[1], p[2]);
+        let euler = |p: [f32; 3]| {
+            Quaternion::from(Euler::new(Deg(p[0]), Deg(p[1]), Deg(p[2])))
+        };
+        let degrees = |p: [f32; 3]| position(p) * std::f32::consts::PI / 180.0;
+        let shots = config
+            .shots
+            .iter()
+            .enumerate()
+            .map(|(i, shot)| {
+                let path = shot.path.build(i, position, position)?;
+                let aim = match (&shot.look_at, &shot.rotation) {
+                    (Some(look_at), _) => {
+                        ShotAim::LookAt(look_at.build(i, position, position)?)
+                    }
+                    (None, Some(rotation)) => {
+                        ShotAim::Rotation(rotation.build(i, euler, degrees)?)
+                    }
+                    (None, None) => ShotAim::AlongPath,
+                };
+                Ok(Shot::new(shot.duration, shot.easing, path, aim))
+            })
+            .collect::<Result<Vec<_>, SequenceError>>()?;
+        let events = config
+            .events
+            .iter()
+            .map(|e| SequenceEvent {
+                time: e.time,
+                name: e.name.clone(),
+            })
+            .collect();
+        Ok(CameraSequence {
+            shots,
+            events,
+            looping: config.looping,
+        })
+    }
+
+    pub fn from_toml_str(source: &str) -> Result<Self, SequenceError> {
+        let config: SequenceConfig =
+            toml::from_str(source).map_err(SequenceError::Parse)?;
+        CameraSequence::from_config(&config)
+    }
+
+    /// Loads a sequence from a TOML file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SequenceError> {
+        let source = fs::read_to_string(path).map_err(SequenceError::Io)?;
+        CameraSequence::from_toml_str(&source)
+    }
+
+    /// Total length of the shots, in seconds
+    pub fn duration(&self) -> f32 {
+        self.shots.iter().map(|s| s.duration).sum()
+    }
+
+    /// Placement of the camera `time` seconds into the sequence. Past
+    /// the end, looping sequences start over and others hold the end of
+    /// their last shot.
+    pub fn pose(&self, time: f32) -> Option<Decomposed<Vec3, Quaternion<f32>>> {
+        let duration = self.duration();
+        let mut time = if self.looping && duration > 0.0 {
+            ((time % duration) + duration) % duration
+        } else {
+            time.max(0.0)
+        };
+        let last = self.shots.len().checked_sub(1)?;
+        for (i, shot) in self.shots.iter().enumerate() {
+            if time < shot.duration || i == last {
+                return Some(shot.pose(time.min(shot.duration)));
+            }
+            time -= shot.duration;
+        }
+        None
+    }
+
+    /// Events at or after `from` and before `to`, in order. Looping
+    /// sequences repeat their events on every loop.
+    pub fn events_between(&self, from: f32, to: f32) -> Vec<&SequenceEvent> {
+        let duration = self.duration();
+        let mut fired: Vec<(f32, &SequenceEvent)> = Vec::new();
+        for event in &self.events {
+            if self.looping && duration > 0.0 {
+                let mut time = event.time
+                    + ((from - event.time) / duration).ceil() * duration;
+                while time < to {
+                    fired.push((time, event));
+                    time += duration;
+                }
+            } else if from <= event.time && event.time < to {
+                fired.push((event.time, event));
+            }
+        }
+        fired.sort_by(|a, b| a.0.total_cmp(&b.0));
+        fired.into_iter().map(|(_, event)| event).collect()
+    }
+}
+
+/// Plays a camera sequence on its entity
+#[derive(Debug, Clone)]
+pub struct SequencePlayer {
+    pub sequence: Arc<CameraSequence>,
+    /// Seconds into the sequence. Wraps around the duration of looping
+    /// sequences, so precision doesn't drift as playback goes on.
+    pub time: f32,
+    pub paused: bool,
+    fired: Vec<String>,
+}
+
+impl Component for SequencePlayer {}
+
+impl SequencePlayer {
+    pub fn new(sequence: Arc<CameraSequence>) -> Self {
+        SequencePlayer {
+            sequence,
+            time: 0.0,
+            paused: false,
+            fired: Vec::new(),
+        }
+    }
+
+    /// Whether a sequence that doesn't loop has played to its end
+    pub fn is_finished(&self) -> bool {
+        !self.sequence.looping && self.time >= self.sequence.duration()
+    }
+
+    /// Names of the events fired during the last update
+    pub fn fired(&self) -> &[String] {
+        &self.fired
+    }
+
+    /// Advances playback by `dt` seconds, collecting the events passed
+    pub fn advance(&mut self, dt: f32) {
+        self.fired.clear();
+        if self.paused || self.is_finished() {
+            return;
+        }
+        let (from, to) = (self.time, self.time + dt);
+        self.fired.extend(
+            self.sequence
+                .events_between(from, to)
+                .into_iter()
+                .map(|e| e.name.clone()),
+        );
+        let duration = self.sequence.duration();
+        self.time = if self.sequence.looping && duration > 0.0 {
+            to.rem_euclid(duration)
+        } else {
+            to
+        };
+    }
+}
+
+/// Advances every SequencePlayer and moves its entity to the sequence's
+/// camera placement. Runs after the controlled camera is synced, so a
+/// sequence on the controlled camera overrides it while it plays. Once
+/// a sequence finishes, its entity is left where the sequence ended.
+pub fn update_sequences<CS: TryGetComponent>(
+    components: &ComponentManager<CS>,
+    dt: f32,
+) {
+    let (players, transforms) = match (
+        components.get_components::<SequencePlayer>(),
+        components.get_components::<TransformComponent>(),
+    ) {
+        (Some(players), Some(transforms)) => (players, transforms),
+        _ => return,
+    };
+    let mut players = players.write().expect("poisoned RwLock");
+    let mut transforms = transforms.write().expect("poisoned RwLock");
+    for entity in components.entities() {
+        let player = match players[*entity] {
+            Some(ref mut player) => player,
+            None => continue,
+        };
+        // the update reaching the end still places the entity at it
+        let was_finished = player.is_finished();
+        player.advance(dt);
+        if was_finished {
+            continue;
+        }
+        let pose = match player.sequence.pose(player.time) {
+            Some(pose) => pose,
+            None => continue,
+        };
+        if let Some(ref mut transform) = transforms[*entity] {
+            transform.transform = pose;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::test_util::*;
+
+    const SEQUENCE: &str = r#"
+        [[shots]]
+        duration = 2.0
+        path = { kind = "CatmullRom", points = [[0, 0, 10], [10, 0, 10]] }
+        look_at = { kind = "CatmullRom", points = [[5, 0, 0]] }
+
+        [[shots]]
+        duration = 1.0
+        easing = "EaseInOut"
+        path = { kind = "Bezier", points = [[0, 5, 0], [0, 5, 0], [0, 5, -4], [0, 5, -4]] }
+        rotation = { kind = "CatmullRom", points = [[0, 0, 0], [0, 90, 0]] }
+
+        [[events]]
+        time = 0.0
+        name = "start"
+
+        [[events]]
+        time = 2.5
+        name = "cut"
+    "#;
+
+    fn near(a: Vec3, b: Vec3) -> bool {
+        (a - b).magnitude() < 1e-3
+    }
+
+    fn front(pose: &Decomposed<Vec3, Quaternion<f32>>) -> Vec3 {
+        pose.rot.rotate_vector(-Vec3::unit_z())
+    }
+
+    #[test]
+    fn test_sequence_poses() {
+        let sequence = CameraSequence::from_toml_str(SEQUENCE).unwrap();
+        assert_eq!(sequence.duration(), 3.0);
+
+        let pose = sequence.pose(1.0).unwrap();
+        assert!(near(pose.disp, vec3(5.0, 0.0, 10.0)));
+        assert!(near(front(&pose), -Vec3::unit_z()));
+        let pose = sequence.pose(0.0).unwrap();
+        let to_target = (vec3(5.0, 0.0, 0.0) - pose.disp).normalize();
+        assert!(near(front(&pose), to_target));
+
+        // the eased shot is half way through its path half way through
+        let pose = sequence.pose(2.5).unwrap();
+        assert!(near(pose.disp, vec3(0.0, 5.0, -2.0)), "{:?}", pose.disp);
+        let turned = Quaternion::from_angle_y(Deg(45.0));
+        assert!(pose.rot.dot(turned).abs() > 1.0 - 1e-4);
+
+        // holds the end without looping
+        let end = sequence.pose(10.0).unwrap();
+        assert!(near(end.disp, vec3(0.0, 5.0, -4.0)));
+    }
+
+    #[test]
+    fn test_sequence_events() {
+        let mut sequence = CameraSequence::from_toml_str(SEQUENCE).unwrap();
+        let names = |events: Vec<&SequenceEvent>| -> Vec<String> {
+            events.into_iter().map(|e| e.name.clone()).collect()
+        };
+        assert_eq!(names(sequence.events_between(0.0, 0.1)), ["start"]);
+        assert!(sequence.events_between(0.1, 2.5).is_empty());
+        assert_eq!(names(sequence.events_between(2.0, 4.0)), ["cut"]);
+
+        sequence.looping = true;
+        assert_eq!(
+            names(sequence.events_between(2.0, 6.0)),
+            ["cut", "start", "cut"]
+        );
+        let looped = sequence.pose(4.0).unwrap();
+        assert!(near(looped.disp, vec3(5.0, 0.0, 10.0)));
+    }
+
+    #[test]
+    fn test_bad_curves() {
+        let source = r#"
+            [[shots]]
+            duration = 1.0
+            path = { kind = "Hermite", points = [[0, 0, 0], [1, 0, 0]] }
+        "#;
+        match CameraSequence::from_toml_str(source) {
+            Err(SequenceError::BadCurve { shot: 0, .. }) => (),
+            other => panic!("expected a bad curve, got {:?}", other),
+        }
+        match CameraSequence::from_toml_str("[[shots]]\nduration = 1.0") {
+            Err(SequenceError::Parse(_)) => (),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_sequence_player() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let sequence = CameraSequence::from_toml_str(SEQUENCE).unwrap();
+        let camera = world.components.alloc_entity();
+        world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(*camera, TransformComponent::default());
+        let players =
+            world.components.get_components::<SequencePlayer>().unwrap();
+        players
+            .write()
+            .unwrap()
+            .insert(*camera, SequencePlayer::new(Arc::new(sequence)));
+
+        update_sequences(&world.components, 1.0);
+        assert_eq!(
+            players.read().unwrap()[*camera].as_ref().unwrap().fired(),
+            ["start"]
+        );
+        let transforms = world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap();
+        let position = transforms.read().unwrap()[*camera]
+            .as_ref()
+            .unwrap()
+            .transform
+            .disp;
+        assert!(near(position, vec3(5.0, 0.0, 10.0)));
+
+        update_sequences(&world.components, 2.0);
+        {
+            let players = players.read().unwrap();
+            let player = players[*camera].as_ref().unwrap();
+            assert_eq!(player.fired(), ["cut"]);
+            assert!(player.is_finished());
+        }
+        let end = transforms.read().unwrap()[*camera].clone().unwrap();
+        assert!(near(end.transform.disp, vec3(0.0, 5.0, -4.0)));
+
+        // a finished sequence lets go of the camera
+        let moved = vec3(1.0, 2.0, 3.0);
+        transforms.write().unwrap()[*camera]
+            .as_mut()
+            .unwrap()
+            .transform
+            .disp = moved;
+        update_sequences(&world.components, 1.0);
+        let position = transforms.read().unwrap()[*camera]
+            .as_ref()
+            .unwrap()
+            .transform
+            .disp;
+        assert_eq!(position, moved);
+        assert!(players.read().unwrap()[*camera]
+            .as_ref()
+            .unwrap()
+            .fired()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_looping_player_wraps_time() {
+        let mut sequence = CameraSequence::from_toml_str(SEQUENCE).unwrap();
+        sequence.looping = true;
+        let mut player = SequencePlayer::new(Arc::new(sequence));
+        player.advance(2.0);
+        player.advance(1.5);
+        assert_eq!(player.fired(), ["cut", "start"]);
+        assert!((player.time - 0.5).abs() < 1e-5);
+        assert!(!player.is_finished());
+    }
+}
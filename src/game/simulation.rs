@@ -0,0 +1,274 @@
+//! The part of the world which runs without a renderer: entities, input,
+//! cameras and the gameplay systems, but no GPU resources.
+
+use super::{
+    animation::{
+        solve_ik, update_animation_graphs, update_animations, update_skins,
+    },
+    audio::AudioSystem,
+    camera::*,
+    component::*,
+    input::{InputMap, InputSnapshot},
+    main_loop::FrameStats,
+    physics::PhysicsWorld,
+    picking,
+    script::{run_scripts, ScriptSystem},
+    sequencer::update_sequences,
+    timer::{duration_as_f64, GameClock, TimerEvent},
+    TryGetComponent,
+};
+use crate::math::*;
+use cgmath::*;
+use log::*;
+use rand::{rngs::StdRng, SeedableRng};
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputState {
+    pub last_mousepos: Point2<f32>,
+    pub mousepos: Point2<f32>,
+}
+
+/// Entities and the systems updating them. Needs no window, GPU or SDL
+/// video subsystem, so dedicated servers and batch simulations can run
+/// one on its own; `EntityWorld` adds the renderer's resources.
+pub struct Simulation<CS: TryGetComponent> {
+    pub input_state: Option<InputState>,
+    /// Buttons held during the current frame
+    pub input_snapshot: InputSnapshot,
+    pub input: InputMap,
+    pub main_camera: Box<dyn CameraController>,
+    /// Tunables of the camera controllers, applied to `main_camera`
+    pub camera_config: CameraConfig,
+    /// Camera entity whose transform follows `main_camera`
+    pub controlled_camera: Option<Entity>,
+    pub components: ComponentManager<CS>,
+    /// Rigid bodies and colliders, stepped on a fixed timestep
+    pub physics: PhysicsWorld,
+    /// Mixes the sounds of audio sources, heard from the listener
+    pub audio: AudioSystem,
+    /// Real and game time, and timers on game time
+    pub clock: GameClock,
+    /// Events of the timers which fired during the last update
+    pub timer_events: Vec<TimerEvent>,
+    /// Times of recent frames, copied from the main loop each frame
+    pub frame_stats: FrameStats,
+    /// Gameplay scripts, run at the start of each update
+    pub scripts: Vec<ScriptSystem<CS>>,
+    /// Random number generator for gameplay code. Seeded so input
+    /// replays reproduce the same results.
+    pub rng: StdRng,
+    seed: u64,
+}
+
+/// Expands a 64 bit seed into a seeded StdRng
+fn seeded_rng(seed: u64) -> StdRng {
+    let mut bytes = [0u8; 32];
+    for (i, chunk) in bytes.chunks_mut(8).enumerate() {
+        chunk.copy_from_slice(&seed.wrapping_add(i as u64).to_le_bytes());
+    }
+    StdRng::from_seed(bytes)
+}
+
+impl<CS: TryGetComponent> fmt::Debug for Simulation<CS> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Simulation")
+            .field(
+                "input_state",
+                &format_args!(
+                    "{}",
+                    if self.input_state.is_some() {
+                        "Some({{..}}"
+                    } else {
+                        "None"
+                    }
+                ),
+            )
+            .field("input", &self.input)
+            .field("main_camera", &format_args!("{{..}}"))
+            .field("camera_config", &self.camera_config)
+            .field("controlled_camera", &self.controlled_camera)
+            .field("components", &format_args!("{{..}}"))
+            .field("audio", &self.audio)
+            .field("clock", &self.clock)
+            .field("frame_stats", &self.frame_stats)
+            .field("scripts", &self.scripts)
+            .field("seed", &self.seed)
+            .finish()
+    }
+}
+
+impl<CS: TryGetComponent> Simulation<CS> {
+    pub fn new(component_store: CS) -> Self {
+        use std::f32::consts::PI;
+        let main_camera = Box::new(FpsCameraComponent::new(
+            Point3::new(0.0, 0.0, 5.0),
+            vec3(0.0, 1.0, 0.0),
+            Rad(-PI / 2.0),
+            Rad(0.0),
+        ));
+
+        let seed = rand::random();
+        Simulation {
+            main_camera,
+            camera_config: CameraConfig::default(),
+            controlled_camera: None,
+            input_state: None,
+            input_snapshot: InputSnapshot::new(),
+            input: InputMap::default(),
+            components: ComponentManager::new(component_store),
+            physics: PhysicsWorld::default(),
+            audio: AudioSystem::default(),
+            clock: GameClock::new(),
+            timer_events: Vec::new(),
+            frame_stats: FrameStats::default(),
+            scripts: Vec::new(),
+            rng: seeded_rng(seed),
+            seed,
+        }
+    }
+
+    /// The seed the rng was last seeded with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = seeded_rng(seed);
+    }
+
+    /// Sets the camera tunables, applying them to `main_camera`
+    pub fn set_camera_config(&mut self, config: CameraConfig) {
+        self.camera_config = config;
+        self.main_camera.apply_config(&self.camera_config);
+    }
+
+    /// Replaces `main_camera`, configuring the new camera from
+    /// `camera_config`
+    pub fn set_main_camera(&mut self, mut camera: Box<dyn CameraController>) {
+        camera.apply_config(&self.camera_config);
+        self.main_camera = camera;
+    }
+
+    /// Feeds a frame's input to the simulation and updates the input map.
+    /// The main loop passes a snapshot sampled from SDL; replays, tests
+    /// and headless runs can pass hand-built snapshots.
+    pub fn set_input(
+        &mut self,
+        snapshot: InputSnapshot,
+        input_state: InputState,
+    ) {
+        self.input.update(&snapshot, &input_state);
+        self.input_snapshot = snapshot;
+        self.input_state = Some(input_state);
+    }
+
+    /// Runs the systems from the current frame's input map, which
+    /// `set_input` sets. Without any input, as in a headless run, the
+    /// systems see no buttons held.
+    /// `delta` is real time; everything but the main camera runs on the
+    /// clock's game time, which stops while paused.
+    pub fn update(&mut self, delta: Duration) {
+        self.timer_events = self.clock.advance(delta);
+        let real_dt = duration_as_f64(delta) as f32;
+        let dt = self.clock.game_dt();
+
+        if self.input.action_pressed("log_camera") {
+            info!("Camera: {:?}", self.main_camera);
+        }
+        let camera_input = CameraInput::from_input_map(&self.input);
+        self.main_camera.update(&camera_input, real_dt);
+
+        if let Some(entity) = self.controlled_camera {
+            self.sync_controlled_camera(entity);
+        }
+        run_scripts(&mut self.scripts, &mut self.components, &self.input, dt);
+        update_sequences(&self.components, dt);
+        update_animations(&self.components, dt);
+        update_animation_graphs(&self.components, dt);
+        self.physics
+            .update(self.clock.game_delta(), &self.components);
+        solve_ik(&self.components);
+        update_skins(&self.components);
+        self.audio.update(&self.components, dt);
+        if let Some(input_state) = self.input_state.as_mut() {
+            input_state.last_mousepos = input_state.mousepos;
+        }
+    }
+
+    fn sync_controlled_camera(&self, entity: Entity) {
+        let transforms =
+            match self.components.get_components::<TransformComponent>() {
+                Some(transforms) => transforms,
+                None => return,
+            };
+        let mut transforms = transforms.write().expect("poisoned RwLock");
+        transforms.insert(
+            *entity,
+            TransformComponent {
+                parent: None,
+                transform: self.main_camera.world_transform(),
+            },
+        );
+    }
+
+    /// Creates a camera entity at the given transform
+    pub fn spawn_camera(
+        &mut self,
+        camera: CameraComponent,
+        transform: Decomposed<Vec3, Quaternion<f32>>,
+    ) -> Result<Entity, failure::Error> {
+        let transforms = self
+            .components
+            .get_components::<TransformComponent>()
+            .ok_or_else(|| {
+                format_err!("TransformComponent is not registered")
+            })?;
+        let cameras = self
+            .components
+            .get_components::<CameraComponent>()
+            .ok_or_else(|| format_err!("CameraComponent is not registered"))?;
+        let entity = self.components.alloc_entity();
+        transforms.write().expect("poisoned RwLock").insert(
+            *entity,
+            TransformComponent {
+                parent: None,
+                transform,
+            },
+        );
+        cameras
+            .write()
+            .expect("poisoned RwLock")
+            .insert(*entity, camera);
+        Ok(entity)
+    }
+
+    /// Spawns a camera entity driven by `main_camera`'s movement
+    pub fn spawn_controlled_camera(
+        &mut self,
+        camera: CameraComponent,
+    ) -> Result<Entity, failure::Error> {
+        let entity =
+            self.spawn_camera(camera, self.main_camera.world_transform())?;
+        self.controlled_camera = Some(entity);
+        Ok(entity)
+    }
+
+    /// The world space ray from `main_camera` through the mouse cursor,
+    /// if there is mouse input
+    pub fn mouse_ray(
+        &self,
+        screen_size: (u32, u32),
+        projection: &Mat4,
+    ) -> Option<Ray> {
+        let input_state = self.input_state.as_ref()?;
+        picking::screen_ray(
+            input_state.mousepos,
+            screen_size,
+            projection,
+            &self.main_camera.view(),
+        )
+    }
+}
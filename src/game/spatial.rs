@@ -0,0 +1,446 @@
+//! Spatial index over the world space bounds of mesh entities.
+//!
+//! `SpatialIndex` keeps a DynamicBvh leaf for every entity with a
+//! MeshComponent, boxed by its mesh bounds under the entity's world
+//! transform. The world refreshes it once per update, visiting only the
+//! entities whose transform or mesh was written since, the entities below
+//! them in the hierarchy, and entities spawned or despawned. The component
+//! stores track their own writes, so code moving entities needs no extra
+//! step. Entities which moved within their leaf's margin don't touch the
+//! tree.
+
+use super::{component::*, resource::ResourceManager, TryGetComponent};
+use crate::math::*;
+use crate::renderer::{RenderMesh, Renderer};
+use cgmath::*;
+use hibitset::{BitSet, BitSetLike};
+
+/// Leaves are grown by this much on every side
+const MARGIN: f32 = 0.2;
+
+#[derive(Debug)]
+struct Proxy {
+    entity: Entity,
+    id: ProxyId,
+    /// Indices of the entity's parents, whose moves move it too
+    ancestors: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct SpatialIndex {
+    bvh: DynamicBvh<Entity>,
+    /// Indexed entity and leaf, by entity index
+    proxies: Vec<Option<Proxy>>,
+    /// Indices of the indexed entities below each entity in the
+    /// hierarchy, by entity index
+    descendants: Vec<Vec<usize>>,
+    /// Indices of mesh entities whose mesh isn't loaded yet, retried on
+    /// every update
+    unresolved: Vec<usize>,
+    /// Whether the next update visits every entity, as a new index's
+    /// first update does
+    visit_all: bool,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        SpatialIndex::new()
+    }
+}
+
+impl SpatialIndex {
+    pub fn new() -> Self {
+        SpatialIndex {
+            bvh: DynamicBvh::new(MARGIN),
+            proxies: Vec::new(),
+            descendants: Vec::new(),
+            unresolved: Vec::new(),
+            visit_all: true,
+        }
+    }
+
+    /// Number of indexed entities
+    pub fn len(&self) -> usize {
+        self.bvh.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bvh.is_empty()
+    }
+
+    /// Syncs the index with the world bounds of the entities whose
+    /// transform or mesh was written since the last update, and the
+    /// entities below them. Entities that lost their mesh or were
+    /// deallocated are removed.
+    /// Takes the written indices from the transform and mesh stores, so
+    /// only one index should follow a world's stores.
+    pub fn update<R, CS>(
+        &mut self,
+        components: &ComponentManager<CS>,
+        resources: &ResourceManager<R>,
+    ) where
+        R: Renderer,
+        CS: TryGetComponent,
+    {
+        let (meshes, transforms) = match (
+            components.get_components::<MeshComponent>(),
+            components.get_components::<TransformComponent>(),
+        ) {
+            (Some(meshes), Some(transforms)) => (meshes, transforms),
+            _ => return,
+        };
+        let mut meshes = meshes.write().expect("poisoned RwLock");
+        let mut transforms = transforms.write().expect("poisoned RwLock");
+
+        let mut dirty = BitSet::new();
+        if self.visit_all {
+            for entity in components.entities() {
+                dirty.add(entity.index() as u32);
+            }
+            self.visit_all = false;
+        }
+        let written = transforms
+            .take_changed()
+            .into_iter()
+            .chain(meshes.take_changed())
+            .chain(components.take_spawned())
+            .chain(self.unresolved.drain(..))
+            .collect::<Vec<_>>();
+        for index in written {
+            dirty.add(index as u32);
+            if let Some(descendants) = self.descendants.get(index) {
+                for &descendant in descendants {
+                    dirty.add(descendant as u32);
+                }
+            }
+        }
+
+        for index in (&dirty).iter().map(|i| i as usize) {
+            let entity = match components.entity_at(index) {
+                Some(entity) => entity,
+                None => {
+                    self.remove_index(index);
+                    continue;
+                }
+            };
+            let handle = match meshes[*entity] {
+                Some(ref mesh) => &mesh.mesh,
+                None => {
+                    self.remove_index(index);
+                    continue;
+                }
+            };
+            let mesh = match resources.meshes.get(handle) {
+                Some(mesh) => mesh,
+                None => {
+                    self.remove_index(index);
+                    self.unresolved.push(index);
+                    continue;
+                }
+            };
+            let model = world_matrix(&transforms, entity)
+                .unwrap_or_else(Mat4::identity);
+            let aabb = mesh.bounds().aabb.transform(&model);
+            self.insert(entity, aabb);
+            self.set_ancestors(index, ancestors(&transforms, entity));
+        }
+    }
+
+    /// Indexes an entity with a world space box, or moves it if it's
+    /// already indexed
+    pub fn insert(&mut self, entity: Entity, aabb: Aabb) {
+        let index = entity.index();
+        if index >= self.proxies.len() {
+            self.proxies.resize_with(index + 1, || None);
+        }
+        match self.proxies[index] {
+            Some(ref proxy) if proxy.entity == entity => {
+                self.bvh.update(proxy.id, aabb);
+            }
+            _ => {
+                // a dead entity may have left its slot behind
+                self.remove_index(index);
+                let id = self.bvh.insert(aabb, entity);
+                self.proxies[index] = Some(Proxy {
+                    entity,
+                    id,
+                    ancestors: Vec::new(),
+                });
+            }
+        }
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(Some(proxy)) = self.proxies.get(entity.index()) {
+            if proxy.entity == entity {
+                self.remove_index(entity.index());
+            }
+        }
+    }
+
+    fn remove_index(&mut self, index: usize) {
+        if let Some(proxy) = self.proxies.get_mut(index).and_then(Option::take)
+        {
+            self.bvh.remove(proxy.id);
+            self.unlink(index, &proxy.ancestors);
+        }
+    }
+
+    /// Records the parents of an indexed entity, so their moves refresh it
+    fn set_ancestors(&mut self, index: usize, ancestors: Vec<usize>) {
+        let old = match self.proxies[index] {
+            Some(ref mut proxy) if proxy.ancestors != ancestors => {
+                std::mem::replace(&mut proxy.ancestors, ancestors.clone())
+            }
+            _ => return,
+        };
+        self.unlink(index, &old);
+        for ancestor in ancestors {
+            if ancestor >= self.descendants.len() {
+                self.descendants.resize_with(ancestor + 1, Vec::new);
+            }
+            self.descendants[ancestor].push(index);
+        }
+    }
+
+    fn unlink(&mut self, index: usize, ancestors: &[usize]) {
+        for &ancestor in ancestors {
+            if let Some(descendants) = self.descendants.get_mut(ancestor) {
+                descendants.retain(|&d| d != index);
+            }
+        }
+    }
+
+    /// Entities whose box overlaps `aabb`
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<Entity> {
+        self.bvh.query_aabb(aabb).into_iter().cloned().collect()
+    }
+
+    /// Entities whose box overlaps `sphere`
+    pub fn query_sphere(&self, sphere: &Sphere) -> Vec<Entity> {
+        self.bvh.query_sphere(sphere).into_iter().cloned().collect()
+    }
+
+    /// Entities whose box isn't entirely outside `frustum`
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<Entity> {
+        self.bvh
+            .query_frustum(frustum)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Entities whose box the ray enters within `max_distance`, with the
+    /// distance it enters at, nearest first
+    pub fn query_ray(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+    ) -> Vec<(f32, Entity)> {
+        self.bvh
+            .query_ray(ray, max_distance)
+            .into_iter()
+            .map(|(distance, &entity)| (distance, entity))
+            .collect()
+    }
+}
+
+/// Indices of an entity's parents, nearest first
+fn ancestors(
+    transforms: &ComponentList<TransformComponent>,
+    entity: Entity,
+) -> Vec<usize> {
+    let mut ancestors = Vec::new();
+    let mut parent = transforms[*entity].as_ref().and_then(|t| t.parent);
+    while let Some(entity) = parent {
+        // a cycle would otherwise never end
+        if ancestors.contains(&entity.index()) {
+            break;
+        }
+        ancestors.push(entity.index());
+        parent = transforms[*entity].as_ref().and_then(|t| t.parent);
+    }
+    ancestors
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::test_util::*;
+    use crate::renderer::Mesh;
+    use genmesh::generators::IcoSphere;
+
+    fn move_to<R: Renderer, CS: TryGetComponent>(
+        world: &mut crate::game::EntityWorld<R, CS>,
+        entity: Entity,
+        disp: Vec3,
+    ) {
+        world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(
+                *entity,
+                TransformComponent {
+                    parent: None,
+                    transform: Decomposed {
+                        scale: 1.0,
+                        rot: Quaternion::one(),
+                        disp,
+                    },
+                },
+            );
+    }
+
+    #[test]
+    fn test_spatial_index_tracks_entities() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let sphere = world
+            .resources
+            .insert_mesh(Mesh::from_genmesh(IcoSphere::new()));
+        let spawn = |world: &mut MockWorld, x: f32| {
+            let entity = world.components.alloc_entity();
+            world
+                .components
+                .get_components::<MeshComponent>()
+                .unwrap()
+                .write()
+                .unwrap()
+                .insert(*entity, MeshComponent { mesh: sphere });
+            move_to(world, entity, vec3(x, 0.0, 0.0));
+            entity
+        };
+        let entities: Vec<_> =
+            (0..20).map(|i| spawn(&mut world, i as f32 * 4.0)).collect();
+        world.update_spatial_index();
+        assert_eq!(world.spatial.len(), 20);
+
+        let around = |x: f32| Sphere::new(Point3::new(x, 0.0, 0.0), 0.5);
+        assert_eq!(world.spatial.query_sphere(&around(8.0)), vec![entities[2]]);
+
+        move_to(&mut world, entities[2], vec3(0.0, 10.0, 0.0));
+        world.update_spatial_index();
+        assert!(world.spatial.query_sphere(&around(8.0)).is_empty());
+        let region = Aabb::new(
+            Point3::new(-1.0, 9.0, -1.0),
+            Point3::new(1.0, 11.0, 1.0),
+        );
+        assert_eq!(world.spatial.query_aabb(&region), vec![entities[2]]);
+
+        let ray = Ray::new(Point3::new(-10.0, 0.0, 0.0), Vec3::unit_x());
+        let hits = world.spatial.query_ray(&ray, 15.0);
+        let hit_entities: Vec<_> = hits.iter().map(|&(_, e)| e).collect();
+        assert_eq!(hit_entities, vec![entities[0], entities[1]]);
+
+        world.components.dealloc_entity(entities[0]);
+        world.update_spatial_index();
+        assert_eq!(world.spatial.len(), 19);
+        let reused = spawn(&mut world, 0.0);
+        world.update_spatial_index();
+        assert_eq!(world.spatial.query_sphere(&around(0.0)), vec![reused]);
+    }
+
+    #[test]
+    fn test_moving_parent_moves_children() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let sphere = world
+            .resources
+            .insert_mesh(Mesh::from_genmesh(IcoSphere::new()));
+        let parent = world.components.alloc_entity();
+        move_to(&mut world, parent, vec3(0.0, 0.0, 0.0));
+        let child = world.components.alloc_entity();
+        world
+            .components
+            .get_components::<MeshComponent>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(*child, MeshComponent { mesh: sphere });
+        world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(
+                *child,
+                TransformComponent {
+                    parent: Some(parent),
+                    transform: Decomposed {
+                        scale: 1.0,
+                        rot: Quaternion::one(),
+                        disp: vec3(2.0, 0.0, 0.0),
+                    },
+                },
+            );
+        world.update_spatial_index();
+        let around = |x: f32| Sphere::new(Point3::new(x, 0.0, 0.0), 0.5);
+        assert_eq!(world.spatial.query_sphere(&around(2.0)), vec![child]);
+
+        // only the parent's transform is written
+        move_to(&mut world, parent, vec3(10.0, 0.0, 0.0));
+        world.update_spatial_index();
+        assert!(world.spatial.query_sphere(&around(2.0)).is_empty());
+        assert_eq!(world.spatial.query_sphere(&around(12.0)), vec![child]);
+    }
+
+    #[test]
+    fn test_unflagged_writes_are_indexed() {
+        use crate::game::{culling::visible_entities, picking::raycast};
+        use std::time::Duration;
+
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let sphere = world
+            .resources
+            .insert_mesh(Mesh::from_genmesh(IcoSphere::new()));
+        let meshes =
+            world.components.get_components::<MeshComponent>().unwrap();
+        let transforms = world
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap();
+        let entity = world.components.alloc_entity();
+        world.update(Duration::from_millis(10));
+        // the mesh arrives after the entity was first indexed
+        meshes
+            .write()
+            .unwrap()
+            .insert(*entity, MeshComponent { mesh: sphere });
+        transforms.write().unwrap().insert(
+            *entity,
+            TransformComponent {
+                parent: None,
+                transform: Decomposed {
+                    scale: 1.0,
+                    rot: Quaternion::one(),
+                    disp: vec3(0.0, 0.0, -10.0),
+                },
+            },
+        );
+        world.update(Duration::from_millis(10));
+
+        let view_projection =
+            renderer.camera().projection * world.main_camera.view();
+        let eye = world.main_camera.position();
+        let forward = Ray::new(eye, -Vec3::unit_z());
+        let backward = Ray::new(eye, Vec3::unit_z());
+        assert_eq!(visible_entities(&world, &view_projection), vec![entity]);
+        assert_eq!(raycast(&world, &forward, 100.0).unwrap().entity, entity);
+
+        // moved behind the camera through the store alone
+        transforms.write().unwrap()[*entity]
+            .as_mut()
+            .unwrap()
+            .transform
+            .disp = vec3(0.0, 0.0, 20.0);
+        world.update(Duration::from_millis(10));
+        assert!(visible_entities(&world, &view_projection).is_empty());
+        assert!(raycast(&world, &forward, 100.0).is_none());
+        assert_eq!(raycast(&world, &backward, 100.0).unwrap().entity, entity);
+    }
+}
@@ -0,0 +1,44 @@
+//! Helpers shared by the game module's unit tests
+
+use super::{component_stores::AnyComponentStore, EntityWorld};
+use crate::renderer::{camera::default_perspective, Camera, Mesh, Renderer};
+use std::cell::{Ref, RefCell};
+
+/// A renderer which keeps meshes on the cpu and never draws
+pub struct MockRenderer {
+    camera: RefCell<Camera>,
+}
+
+impl MockRenderer {
+    pub fn new() -> Self {
+        MockRenderer {
+            camera: RefCell::new(Camera::new(default_perspective())),
+        }
+    }
+}
+
+impl Renderer for MockRenderer {
+    type Texture = (u32, u32);
+    type Mesh = Mesh;
+
+    fn camera(&self) -> Ref<Camera> {
+        self.camera.borrow()
+    }
+
+    fn create_mesh(&self, mesh: &Mesh) -> Result<Mesh, failure::Error> {
+        Ok(mesh.clone())
+    }
+
+    fn create_texture(
+        &self,
+        image: &gltf::image::Data,
+    ) -> Result<(u32, u32), failure::Error> {
+        Ok((image.width, image.height))
+    }
+}
+
+pub type MockWorld = EntityWorld<MockRenderer, AnyComponentStore>;
+
+pub fn mock_world(renderer: &MockRenderer) -> MockWorld {
+    EntityWorld::new(renderer, AnyComponentStore::with_built_in_components())
+}
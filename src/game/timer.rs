@@ -1,3 +1,5 @@
+use log::*;
+use std::fmt;
 use std::time::*;
 
 #[derive(Clone, Debug)]
@@ -31,8 +33,9 @@ impl Timer {
         self.start_instant
     }
 
+    /// Time from the timer's start to `instant`
     pub fn dur_from_start(&self, instant: Instant) -> Duration {
-        self.start_instant.duration_since(instant)
+        instant.duration_since(self.start_instant)
     }
 
     pub fn tick(&mut self) -> Tick {
@@ -46,15 +49,404 @@ impl Timer {
     }
 }
 
-/// Converts a duration to a floating point number.
+/// Converts a duration to a floating point number of seconds.
 pub fn duration_as_f64(dur: Duration) -> f64 {
-    let sec: u64 = dur.as_secs() * 1000;
-    let milli = u64::from(dur.subsec_millis());
-    (sec + milli) as f64 / 1000f64
+    dur.as_secs() as f64 + f64::from(dur.subsec_nanos()) / 1e9
 }
 
 #[test]
 fn test_duration_as_f64() {
     let dur = Duration::from_secs(10);
     assert_eq!(duration_as_f64(dur), 10.0);
+    let dur = Duration::new(2, 250_500);
+    assert_eq!(duration_as_f64(dur), 2.0002505);
+}
+
+#[test]
+fn test_dur_from_start() {
+    let timer = Timer::new(Duration::from_millis(16));
+    let later = timer.start_instant() + Duration::from_millis(5);
+    assert_eq!(timer.dur_from_start(later), Duration::from_millis(5));
+}
+
+/// Accumulates frame time and hands it out in fixed size steps, for
+/// simulations that need a constant timestep regardless of frame rate.
+#[derive(Clone, Debug)]
+pub struct FixedTimestep {
+    pub step: Duration,
+    /// Most steps run for one frame. Time beyond them is dropped, so a
+    /// long stall doesn't cause a spiral of ever longer frames.
+    pub max_steps: u32,
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    pub fn new(step: Duration, max_steps: u32) -> Self {
+        FixedTimestep {
+            step,
+            max_steps,
+            accumulator: Duration::from_secs(0),
+        }
+    }
+
+    /// Adds a frame's time, returning the number of steps to run
+    pub fn advance(&mut self, delta: Duration) -> u32 {
+        self.accumulator += delta;
+        let mut steps = 0;
+        while self.accumulator >= self.step
+            && self.step > Duration::from_secs(0)
+        {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        if steps > self.max_steps {
+            self.accumulator = Duration::from_secs(0);
+            steps = self.max_steps;
+        }
+        steps
+    }
+
+    /// How far the leftover time is into the next step, from 0 to 1, for
+    /// interpolating between steps
+    pub fn alpha(&self) -> f32 {
+        duration_as_f64(self.accumulator) as f32
+            / duration_as_f64(self.step) as f32
+    }
+}
+
+#[test]
+fn test_fixed_timestep() {
+    let mut timestep = FixedTimestep::new(Duration::from_millis(10), 4);
+    assert_eq!(timestep.advance(Duration::from_millis(25)), 2);
+    assert!((timestep.alpha() - 0.5).abs() < 1e-6);
+    assert_eq!(timestep.advance(Duration::from_millis(5)), 1);
+    assert_eq!(timestep.alpha(), 0.0);
+    // a stall runs the most steps allowed and drops the rest
+    assert_eq!(timestep.advance(Duration::from_secs(1)), 4);
+    assert_eq!(timestep.alpha(), 0.0);
+}
+
+/// Identifies a timer scheduled on a GameClock
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+/// Fired by a GameClock when a timer without a callback comes due
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimerEvent {
+    pub id: TimerId,
+    pub name: String,
+    /// The game time the timer was due, which may be earlier than the
+    /// current game time
+    pub due: Duration,
+}
+
+type TimerCallback = Box<dyn FnMut(&TimerEvent) + Send>;
+
+struct ScheduledTimer {
+    id: TimerId,
+    name: String,
+    due: Duration,
+    /// Time between repeats, if the timer repeats
+    interval: Option<Duration>,
+    callback: Option<TimerCallback>,
+}
+
+impl fmt::Debug for ScheduledTimer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScheduledTimer")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("due", &self.due)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+/// Fastest game time may run, relative to real time
+pub const MAX_TIME_SCALE: f64 = 1000.0;
+
+/// The game's clock. Game time stops while paused and runs at the time
+/// scale, for slow motion, while real time follows the wall clock.
+///
+/// Timers run on game time, firing once or repeatedly. Each either
+/// calls a callback or is returned as a TimerEvent by `advance`.
+#[derive(Debug)]
+pub struct GameClock {
+    real_time: Duration,
+    game_time: Duration,
+    real_delta: Duration,
+    game_delta: Duration,
+    time_scale: f64,
+    paused: bool,
+    next_id: u64,
+    timers: Vec<ScheduledTimer>,
+}
+
+impl Default for GameClock {
+    fn default() -> Self {
+        GameClock::new()
+    }
+}
+
+impl GameClock {
+    pub fn new() -> Self {
+        GameClock {
+            real_time: Duration::from_secs(0),
+            game_time: Duration::from_secs(0),
+            real_delta: Duration::from_secs(0),
+            game_delta: Duration::from_secs(0),
+            time_scale: 1.0,
+            paused: false,
+            next_id: 0,
+            timers: Vec::new(),
+        }
+    }
+
+    /// Time advanced since the clock started, including while paused
+    pub fn real_time(&self) -> Duration {
+        self.real_time
+    }
+
+    /// Scaled time advanced while unpaused
+    pub fn game_time(&self) -> Duration {
+        self.game_time
+    }
+
+    /// Real time of the last advance
+    pub fn real_delta(&self) -> Duration {
+        self.real_delta
+    }
+
+    /// Game time of the last advance
+    pub fn game_delta(&self) -> Duration {
+        self.game_delta
+    }
+
+    /// Game time of the last advance, in seconds
+    pub fn game_dt(&self) -> f32 {
+        duration_as_f64(self.game_delta) as f32
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn time_scale(&self) -> f64 {
+        self.time_scale
+    }
+
+    /// Sets how fast game time runs, where 0.5 is half speed. Negative
+    /// scales are treated as 0 and scales above `MAX_TIME_SCALE` as the
+    /// maximum. NaN and infinite scales are ignored.
+    pub fn set_time_scale(&mut self, scale: f64) {
+        if !scale.is_finite() {
+            warn!("ignoring non-finite time scale {}", scale);
+            return;
+        }
+        self.time_scale = scale.max(0.0).min(MAX_TIME_SCALE);
+    }
+
+    fn schedule(
+        &mut self,
+        name: &str,
+        delay: Duration,
+        interval: Option<Duration>,
+        callback: Option<TimerCallback>,
+    ) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.timers.push(ScheduledTimer {
+            id,
+            name: name.to_owned(),
+            due: self.game_time + delay,
+            interval,
+            callback,
+        });
+        id
+    }
+
+    /// Fires an event once, `delay` of game time from now
+    pub fn after(&mut self, delay: Duration, name: &str) -> TimerId {
+        self.schedule(name, delay, None, None)
+    }
+
+    /// Fires an event every `interval` of game time, starting one
+    /// interval from now. A zero interval fires on every advance.
+    pub fn every(&mut self, interval: Duration, name: &str) -> TimerId {
+        self.schedule(name, interval, Some(interval), None)
+    }
+
+    /// Calls `callback` once, `delay` of game time from now
+    pub fn after_call<F>(&mut self, delay: Duration, callback: F) -> TimerId
+    where
+        F: FnMut(&TimerEvent) + Send + 'static,
+    {
+        self.schedule("", delay, None, Some(Box::new(callback)))
+    }
+
+    /// Calls `callback` every `interval` of game time
+    pub fn every_call<F>(&mut self, interval: Duration, callback: F) -> TimerId
+    where
+        F: FnMut(&TimerEvent) + Send + 'static,
+    {
+        self.schedule("", interval, Some(interval), Some(Box::new(callback)))
+    }
+
+    /// Stops a timer. Returns false if it had already finished.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        let count = self.timers.len();
+        self.timers.retain(|t| t.id != id);
+        self.timers.len() != count
+    }
+
+    pub fn is_scheduled(&self, id: TimerId) -> bool {
+        self.timers.iter().any(|t| t.id == id)
+    }
+
+    /// Game time until a timer next fires, such as the time left on a
+    /// cooldown
+    pub fn remaining(&self, id: TimerId) -> Option<Duration> {
+        let timer = self.timers.iter().find(|t| t.id == id)?;
+        Some(if timer.due > self.game_time {
+            timer.due - self.game_time
+        } else {
+            Duration::from_secs(0)
+        })
+    }
+
+    /// Advances the clock by a frame's real time, then fires the timers
+    /// that came due, in the order they were due. A repeating timer fires
+    /// once for each interval that passed. Returns the events of the
+    /// timers without callbacks.
+    pub fn advance(&mut self, real_delta: Duration) -> Vec<TimerEvent> {
+        self.real_delta = real_delta;
+        self.real_time += real_delta;
+        self.game_delta = if self.paused {
+            Duration::from_secs(0)
+        } else {
+            real_delta.mul_f64(self.time_scale)
+        };
+        self.game_time += self.game_delta;
+
+        let mut events = Vec::new();
+        // zero interval timers, which have fired for this advance
+        let mut fired_zero = Vec::new();
+        loop {
+            let now = self.game_time;
+            let next = self
+                .timers
+                .iter()
+                .enumerate()
+                .filter(|(_, t)| t.due <= now && !fired_zero.contains(&t.id))
+                .min_by_key(|(_, t)| (t.due, t.id))
+                .map(|(i, _)| i);
+            let index = match next {
+                Some(index) => index,
+                None => break,
+            };
+            let event = {
+                let timer = &self.timers[index];
+                TimerEvent {
+                    id: timer.id,
+                    name: timer.name.clone(),
+                    due: timer.due,
+                }
+            };
+            let timer = &mut self.timers[index];
+            match timer.callback {
+                Some(ref mut callback) => callback(&event),
+                None => events.push(event),
+            }
+            match timer.interval {
+                Some(interval) => {
+                    timer.due += interval;
+                    if interval == Duration::from_secs(0) {
+                        fired_zero.push(timer.id);
+                    }
+                }
+                None => {
+                    self.timers.remove(index);
+                }
+            }
+        }
+        events
+    }
+}
+
+#[test]
+fn test_game_clock_time() {
+    let mut clock = GameClock::new();
+    clock.advance(Duration::from_millis(100));
+    clock.set_time_scale(0.5);
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(clock.game_delta(), Duration::from_millis(50));
+    assert_eq!(clock.game_dt(), 0.05);
+    clock.pause();
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(clock.game_delta(), Duration::from_secs(0));
+    clock.resume();
+    clock.set_time_scale(-1.0);
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(clock.real_time(), Duration::from_millis(400));
+    assert_eq!(clock.game_time(), Duration::from_millis(150));
+
+    clock.set_time_scale(std::f64::INFINITY);
+    clock.set_time_scale(std::f64::NAN);
+    assert_eq!(clock.time_scale(), 0.0);
+    clock.set_time_scale(1e300);
+    assert_eq!(clock.time_scale(), MAX_TIME_SCALE);
+    clock.advance(Duration::from_secs(3600));
+}
+
+#[test]
+fn test_game_clock_timers() {
+    use std::sync::{Arc, Mutex};
+
+    let ms = Duration::from_millis;
+    let mut clock = GameClock::new();
+    let wave = clock.every(ms(100), "spawn_wave");
+    let cooldown = clock.after(ms(250), "cooldown");
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let recorded = calls.clone();
+    clock.after_call(ms(150), move |event| {
+        recorded.lock().unwrap().push(event.due);
+    });
+
+    let names = |events: Vec<TimerEvent>| -> Vec<String> {
+        events.into_iter().map(|e| e.name).collect()
+    };
+    assert!(clock.advance(ms(50)).is_empty());
+    assert_eq!(clock.remaining(cooldown), Some(ms(200)));
+    // the repeating timer fires for each interval that passed
+    assert_eq!(
+        names(clock.advance(ms(250))),
+        ["spawn_wave", "spawn_wave", "cooldown", "spawn_wave"]
+    );
+    assert_eq!(*calls.lock().unwrap(), [ms(150)]);
+    assert!(!clock.is_scheduled(cooldown));
+
+    // timers wait out pauses, and follow the time scale
+    clock.pause();
+    assert!(clock.advance(ms(500)).is_empty());
+    clock.resume();
+    clock.set_time_scale(0.5);
+    assert!(clock.advance(ms(80)).is_empty());
+    assert_eq!(names(clock.advance(ms(120))), ["spawn_wave"]);
+
+    assert!(clock.cancel(wave));
+    assert!(!clock.cancel(wave));
+    assert!(clock.advance(ms(1000)).is_empty());
+
+    let every_frame = clock.every(ms(0), "frame");
+    assert_eq!(names(clock.advance(ms(16))), ["frame"]);
+    assert_eq!(names(clock.advance(ms(16))), ["frame"]);
+    assert_eq!(clock.remaining(every_frame), Some(ms(0)));
 }
@@ -1,43 +1,33 @@
-
-
-use super::{camera::*, component::*, TryGetComponent, resource::ResourceManager};
+use super::{
+    component::*,
+    culling::visible_entities,
+    picking::{self, RayHit},
+    resource::ResourceManager,
+    simulation::Simulation,
+    spatial::SpatialIndex,
+    TryGetComponent,
+};
 use crate::math::*;
 use crate::renderer::*;
 use cgmath::*;
-use log::*;
-use sdl2::{keyboard::KeyboardState, mouse::MouseState, EventPump};
 use std::fmt;
+use std::ops::{Deref, DerefMut};
 use std::time::Duration;
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct InputState {
-    pub last_mousepos: Point2<f32>,
-    pub mousepos: Point2<f32>,
-}
-
-pub struct InputSources<'a> {
-    pub keyboard_state: KeyboardState<'a>,
-    pub mouse_state: MouseState,
-}
-
-impl<'a> InputSources<'a> {
-    pub fn from_event_pump(event_pump: &'a EventPump) -> Self {
-        InputSources {
-            keyboard_state: event_pump.keyboard_state(),
-            mouse_state: event_pump.mouse_state(),
-        }
-    }
-}
+pub use super::simulation::InputState;
 
+/// A simulation together with the renderer's resources for drawing it.
+/// Derefs to the simulation, so its entities, input and systems are
+/// reached through the world.
 pub struct EntityWorld<R, CS>
 where
     R: Renderer,
     CS: TryGetComponent
 {
-    pub input_state: Option<InputState>,
-    pub main_camera: FpsCameraComponent,
-    pub components: ComponentManager<CS>,
+    pub sim: Simulation<CS>,
     pub resources: ResourceManager<R>,
+    /// Bounds of the mesh entities, as of the last update
+    pub spatial: SpatialIndex,
 }
 
 impl<R, CS> fmt::Debug for EntityWorld<R, CS>
@@ -47,26 +37,35 @@ where
 
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use std::any::TypeId;
         f.debug_struct(&"EntityWorld<R>")
-            .field(
-                "input_state",
-                &format_args!(
-                    "{}",
-                    if self.input_state.is_some() {
-                        "Some({{..}}"
-                    } else {
-                        "None"
-                    }
-                ),
-            )
-            .field("main_camera", &format_args!("{{..}}"))
-            .field("components", &format_args!("{{..}}"))
+            .field("sim", &self.sim)
             .field("resources", &format_args!("{{..}}"))
             .finish()
     }
 }
 
+impl<R, CS> Deref for EntityWorld<R, CS>
+where
+    R: Renderer,
+    CS: TryGetComponent
+{
+    type Target = Simulation<CS>;
+
+    fn deref(&self) -> &Simulation<CS> {
+        &self.sim
+    }
+}
+
+impl<R, CS> DerefMut for EntityWorld<R, CS>
+where
+    R: Renderer,
+    CS: TryGetComponent
+{
+    fn deref_mut(&mut self) -> &mut Simulation<CS> {
+        &mut self.sim
+    }
+}
+
 impl<R, CS> EntityWorld<R, CS>
 where
     R: Renderer,
@@ -74,69 +73,243 @@ where
 
 {
     pub fn new(_renderer: &R, component_store: CS) -> Self {
-        use std::f32::consts::PI;
-        let main_camera = FpsCameraComponent::new(
-            Point3::new(0.0, 0.0, 5.0),
-            vec3(0.0, 1.0, 0.0),
-            Rad(-PI / 2.0),
-            Rad(0.0),
-        );
+        EntityWorld::from_simulation(Simulation::new(component_store))
+    }
 
+    /// Adds a renderer's resources to a simulation, such as one
+    /// which was set up headless
+    pub fn from_simulation(sim: Simulation<CS>) -> Self {
         EntityWorld {
-            main_camera,
-            input_state: None,
-            components: ComponentManager::new(component_store),
+            sim,
             resources: ResourceManager::new(),
+            spatial: SpatialIndex::new(),
         }
     }
 
-    pub fn update(&mut self, delta: Duration, input: InputSources) {
-        use sdl2::keyboard::Scancode;
-        let input_state = self
-            .input_state
-            .clone()
-            .expect("Event loop should have already populated input_state");
-        let mouse_offset = {
-            let mut m = input_state.mousepos - input_state.last_mousepos;
-            m.y *= -1.0;
-            m
-        };
-        let mut wasd_axis = Vec2::new(0.0, 0.0);
-        {
-            let InputSources { keyboard_state, .. } = &input;
+    /// Updates the simulation, then the spatial index.
+    /// `set_input` should have already been called for this frame.
+    pub fn update(&mut self, delta: Duration) {
+        self.sim.update(delta);
+        self.update_spatial_index();
+    }
 
-            if keyboard_state.is_scancode_pressed(Scancode::W) {
-                wasd_axis.y += 1.0;
-            }
-            if keyboard_state.is_scancode_pressed(Scancode::S) {
-                wasd_axis.y -= 1.0;
-            }
-            if keyboard_state.is_scancode_pressed(Scancode::D) {
-                wasd_axis.x += 1.0;
-            }
-            if keyboard_state.is_scancode_pressed(Scancode::A) {
-                wasd_axis.x -= 1.0;
-            }
+    /// Syncs the spatial index with the entities whose transforms or
+    /// meshes were written since it last ran. Runs on every update; call
+    /// it directly after spawning or moving entities outside of an update.
+    pub fn update_spatial_index(&mut self) {
+        self.spatial.update(&self.sim.components, &self.resources);
+    }
 
-            if keyboard_state.is_scancode_pressed(Scancode::Y) {
-                info!("Camera: {:?}", self.main_camera);
+    /// Resolves the active cameras for drawing, sorted by priority, and
+    /// culls the mesh entities each camera can see.
+    /// If no camera entities are active, falls back to a single
+    /// fullscreen view from `main_camera` with the given projection.
+    pub fn render_views(
+        &self,
+        framebuffer_size: (u32, u32),
+        default_projection: Mat4,
+    ) -> Vec<RenderView> {
+        let mut views = Vec::new();
+        if let (Some(cameras), Some(transforms)) = (
+            self.components.get_components::<CameraComponent>(),
+            self.components.get_components::<TransformComponent>(),
+        ) {
+            let cameras = cameras.read().expect("poisoned RwLock");
+            let transforms = transforms.read().expect("poisoned RwLock");
+            for entity in self.components.entities() {
+                let camera = match cameras[*entity] {
+                    Some(ref camera) if camera.active => camera,
+                    _ => continue,
+                };
+                let view = match world_matrix(&transforms, entity)
+                    .and_then(|m| m.invert())
+                {
+                    Some(view) => view,
+                    None => continue,
+                };
+                let aspect = camera.viewport.aspect(framebuffer_size);
+                views.push(RenderView {
+                    view,
+                    projection: camera.projection.matrix(aspect),
+                    viewport: camera.viewport,
+                    clear: camera.clear,
+                    priority: camera.priority,
+                    visible: Vec::new(),
+                });
             }
         }
-        if wasd_axis.magnitude() > 0.0 {
-            self.main_camera.input_move(
-                wasd_axis,
-                delta.as_millis() as f64 / 1000.0,
-                &input,
-            );
+        if views.is_empty() {
+            views.push(RenderView {
+                view: self.main_camera.view(),
+                projection: default_projection,
+                viewport: Viewport::full(),
+                clear: ClearSettings::default(),
+                priority: 0,
+                visible: Vec::new(),
+            });
         }
+        views.sort_by_key(|v| v.priority);
+        for view in &mut views {
+            view.visible =
+                visible_entities(self, &(view.projection * view.view));
+        }
+        views
+    }
+
+    /// Returns the nearest mesh entity under the mouse cursor
+    pub fn pick(
+        &self,
+        screen_size: (u32, u32),
+        projection: &Mat4,
+    ) -> Option<RayHit> {
+        let ray = self.mouse_ray(screen_size, projection)?;
+        picking::raycast(self, &ray, std::f32::INFINITY)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::input::{Button, InputSnapshot};
+    use crate::game::test_util::*;
+    use sdl2::{keyboard::Scancode, mouse::MouseButton};
 
-        if mouse_offset.magnitude() > 0.0 && input.mouse_state.left() {
-            self.main_camera
-                .mouselook(mouse_offset, delta.as_millis() as f64 / 1000.0);
+    fn still_mouse() -> InputState {
+        let mousepos = Point2::new(100.0, 100.0);
+        InputState {
+            mousepos,
+            last_mousepos: mousepos,
         }
-        if let Some(mut input_state) = self.input_state.clone() {
-            input_state.last_mousepos = input_state.mousepos;
-            self.input_state = Some(input_state);
+    }
+
+    #[test]
+    fn test_update_moves_camera() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let start = world.main_camera.position();
+        let front = world.main_camera.front();
+
+        let held = InputSnapshot::with_buttons(vec![Button::Key(Scancode::W)]);
+        world.set_input(held, still_mouse());
+        world.update(Duration::from_millis(500));
+
+        let moved = world.main_camera.position() - start;
+        assert!(moved.magnitude() > 0.0);
+        assert!(moved.normalize().dot(front) > 0.99);
+
+        // the camera slows to a stop once movement is released
+        world.set_input(InputSnapshot::new(), still_mouse());
+        world.update(Duration::from_millis(500));
+        world.update(Duration::from_millis(500));
+        let stopped = world.main_camera.position();
+        world.update(Duration::from_millis(500));
+        assert_eq!(world.main_camera.position(), stopped);
+    }
+
+    #[test]
+    fn test_mouselook_needs_button() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let front = world.main_camera.front();
+        let dragged = InputState {
+            mousepos: Point2::new(140.0, 100.0),
+            last_mousepos: Point2::new(100.0, 100.0),
+        };
+
+        world.set_input(InputSnapshot::new(), dragged.clone());
+        world.update(Duration::from_millis(16));
+        assert_eq!(world.main_camera.front(), front);
+
+        let held =
+            InputSnapshot::with_buttons(vec![Button::Mouse(MouseButton::Left)]);
+        world.set_input(held, dragged);
+        world.update(Duration::from_millis(16));
+        assert_ne!(world.main_camera.front(), front);
+    }
+
+    fn assert_mat_near(a: &Mat4, b: &Mat4) {
+        let (a, b): (&[f32; 16], &[f32; 16]) = (a.as_ref(), b.as_ref());
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert!((x - y).abs() < 1e-4, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_render_views_fallback() {
+        let renderer = MockRenderer::new();
+        let world = mock_world(&renderer);
+        let projection = renderer.camera().projection;
+        let views = world.render_views((800, 600), projection);
+        assert_eq!(views.len(), 1);
+        assert_eq!(views[0].view, world.main_camera.view());
+        assert_eq!(views[0].projection, projection);
+        assert_eq!(views[0].viewport, Viewport::full());
+    }
+
+    #[test]
+    fn test_split_screen_views() {
+        let renderer = MockRenderer::new();
+        let mut world = mock_world(&renderer);
+        let player_one = world
+            .spawn_controlled_camera(CameraComponent {
+                viewport: Viewport::grid(2, 1, 0),
+                ..CameraComponent::default()
+            })
+            .unwrap();
+        let minimap_transform = Decomposed {
+            scale: 1.0,
+            rot: Quaternion::from_angle_x(Deg(-90.0)),
+            disp: vec3(0.0, 50.0, 0.0),
+        };
+        world
+            .spawn_camera(
+                CameraComponent {
+                    projection: Projection::Orthographic {
+                        height: 20.0,
+                        near: 0.1,
+                        far: 100.0,
+                    },
+                    viewport: Viewport::new(0.8, 0.0, 0.2, 0.2),
+                    priority: 10,
+                    ..CameraComponent::default()
+                },
+                minimap_transform,
+            )
+            .unwrap();
+        world
+            .spawn_camera(
+                CameraComponent {
+                    viewport: Viewport::grid(2, 1, 1),
+                    priority: -1,
+                    ..CameraComponent::default()
+                },
+                minimap_transform,
+            )
+            .unwrap();
+
+        let views = world.render_views((800, 600), Mat4::identity());
+        let priorities: Vec<i32> = views.iter().map(|v| v.priority).collect();
+        assert_eq!(priorities, vec![-1, 0, 10]);
+        assert_mat_near(&views[1].view, &world.main_camera.view());
+        let expected = Projection::default().matrix(400.0 / 600.0);
+        assert_mat_near(&views[1].projection, &expected);
+
+        // the controlled camera follows main_camera as it moves
+        let held = InputSnapshot::with_buttons(vec![Button::Key(Scancode::W)]);
+        world.set_input(held, still_mouse());
+        world.update(Duration::from_millis(500));
+        let views = world.render_views((800, 600), Mat4::identity());
+        assert_mat_near(&views[1].view, &world.main_camera.view());
+
+        // deactivating a camera removes its view
+        let cameras = world
+            .components
+            .get_components::<CameraComponent>()
+            .unwrap();
+        if let Some(camera) = cameras.write().unwrap()[*player_one].as_mut() {
+            camera.active = false;
         }
+        let views = world.render_views((800, 600), Mat4::identity());
+        assert_eq!(views.len(), 2);
     }
 }
@@ -0,0 +1,329 @@
+//! Runs a simulation with no window, renderer or SDL video subsystem,
+//! for dedicated servers and batch simulations.
+
+use crate::game::input::InputSnapshot;
+use crate::game::main_loop::MainLoopState;
+use crate::game::replay::{InputReplay, ReplayError};
+use crate::game::simulation::{InputState, Simulation};
+use crate::game::TryGetComponent;
+use cgmath::Point2;
+use std::time::Duration;
+
+/// Default rate `HeadlessRunner` ticks at
+pub const DEFAULT_TICK_RATE: f64 = 60.0;
+
+/// Ticks a simulation on a simulated clock: each tick advances it by the
+/// same duration, however long the tick took to run. By default ticks
+/// run back to back, as fast as the systems allow; a realtime runner
+/// paces them to the tick rate instead. A replay's frames, while they
+/// last, take the place of the ticks.
+#[derive(Debug)]
+pub struct HeadlessRunner<CS: TryGetComponent> {
+    pub sim: Simulation<CS>,
+    tick: Duration,
+    /// Paces the ticks, if running in real time
+    main_loop: Option<MainLoopState>,
+    /// Feeds recorded input, if playing back a recording
+    replay: Option<InputReplay>,
+    ticks: u64,
+    simulated_time: Duration,
+}
+
+impl<CS: TryGetComponent> HeadlessRunner<CS> {
+    pub fn new(sim: Simulation<CS>) -> Self {
+        HeadlessRunner {
+            sim,
+            tick: tick_duration(DEFAULT_TICK_RATE),
+            main_loop: None,
+            replay: None,
+            ticks: 0,
+            simulated_time: Duration::from_secs(0),
+        }
+    }
+
+    /// Sets the ticks per second of simulated time. Panics if the rate
+    /// isn't positive.
+    pub fn with_tick_rate(mut self, rate: f64) -> Self {
+        assert!(rate > 0.0, "tick rate must be positive, got {}", rate);
+        self.tick = tick_duration(rate);
+        if let Some(main_loop) = self.main_loop.as_mut() {
+            main_loop.set_target_fps(Some(rate));
+        }
+        self
+    }
+
+    /// Paces ticks to real time, as a server does, rather than running
+    /// them as fast as possible
+    pub fn with_realtime(mut self, realtime: bool) -> Self {
+        self.main_loop = if realtime {
+            let rate = 1.0 / self.tick.as_secs_f64();
+            let mut main_loop =
+                MainLoopState::new().with_target_fps(Some(rate));
+            main_loop.start();
+            Some(main_loop)
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Plays back a recording: each tick feeds the next recorded frame's
+    /// input and runs for its recorded delta. Once the frames run out,
+    /// ticks run without input again.
+    pub fn with_replay(mut self, mut replay: InputReplay) -> Self {
+        replay.start(&mut self.sim);
+        self.replay = Some(replay);
+        self
+    }
+
+    /// Plays back the recording named by `SLSENGINE_REPLAY_INPUT`, if
+    /// it's set
+    pub fn with_replay_from_env(self) -> Result<Self, ReplayError> {
+        Ok(match InputReplay::from_env()? {
+            Some(replay) => self.with_replay(replay),
+            None => self,
+        })
+    }
+
+    /// Whether recorded frames are left to play back
+    pub fn is_replaying(&self) -> bool {
+        self.replay.as_ref().is_some_and(|r| !r.is_finished())
+    }
+
+    pub fn is_realtime(&self) -> bool {
+        self.main_loop.is_some()
+    }
+
+    /// Simulated time of one tick, outside of a replay
+    pub fn tick_duration(&self) -> Duration {
+        self.tick
+    }
+
+    /// Number of ticks run so far
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Simulated time run so far
+    pub fn simulated_time(&self) -> Duration {
+        self.simulated_time
+    }
+
+    /// Runs one tick, first waiting for its turn if running in real time
+    pub fn step(&mut self) {
+        if let Some(main_loop) = self.main_loop.as_mut() {
+            main_loop.tick_frame();
+            self.sim.frame_stats.clone_from(main_loop.stats());
+        }
+        let replayed = match self.replay.as_mut() {
+            Some(replay) => replay.feed(&mut self.sim),
+            None => None,
+        };
+        let delta = match replayed {
+            Some(delta) => delta,
+            None => {
+                if self.replay.take().is_some() {
+                    self.release_input();
+                }
+                self.tick
+            }
+        };
+        self.sim.update(delta);
+        self.ticks += 1;
+        self.simulated_time += delta;
+    }
+
+    /// Releases the buttons the last recorded frame held and stops the
+    /// mouse, so ticks after a replay run without input
+    fn release_input(&mut self) {
+        let mousepos = self
+            .sim
+            .input_state
+            .as_ref()
+            .map_or(Point2::new(0.0, 0.0), |state| state.mousepos);
+        self.sim.set_input(
+            InputSnapshot::new(),
+            InputState {
+                mousepos,
+                last_mousepos: mousepos,
+            },
+        );
+    }
+
+    /// Runs ticks until the replay's frames run out
+    pub fn run_replay(&mut self) {
+        while self.is_replaying() {
+            self.step();
+        }
+    }
+
+    /// Runs the given number of ticks
+    pub fn run_for(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+    /// Runs ticks for as long as `before_tick` returns true. It's called
+    /// before every tick, to feed input to the simulation or check for a
+    /// shutdown.
+    pub fn run_while<F>(&mut self, mut before_tick: F)
+    where
+        F: FnMut(&mut Simulation<CS>) -> bool,
+    {
+        while before_tick(&mut self.sim) {
+            self.step();
+        }
+    }
+
+    pub fn into_simulation(self) -> Simulation<CS> {
+        self.sim
+    }
+}
+
+fn tick_duration(rate: f64) -> Duration {
+    Duration::from_nanos((1e9 / rate) as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::built_in_components::TransformComponent;
+    use crate::game::component::Entity;
+    use crate::game::component_stores::AnyComponentStore;
+    use crate::game::physics::{Collider, RigidBody};
+    use crate::game::test_util::MockRenderer;
+    use crate::game::EntityWorld;
+    use cgmath::*;
+
+    fn headless() -> HeadlessRunner<AnyComponentStore> {
+        let sim =
+            Simulation::new(AnyComponentStore::with_built_in_components());
+        HeadlessRunner::new(sim).with_tick_rate(50.0)
+    }
+
+    fn spawn_ball(sim: &mut Simulation<AnyComponentStore>) -> Entity {
+        let entity = sim.components.alloc_entity();
+        let components = &sim.components;
+        let transform = TransformComponent {
+            parent: None,
+            transform: Decomposed {
+                scale: 1.0,
+                rot: Quaternion::one(),
+                disp: vec3(0.0, 10.0, 0.0),
+            },
+        };
+        components
+            .get_components::<TransformComponent>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(*entity, transform);
+        components
+            .get_components::<RigidBody>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(*entity, RigidBody::new(1.0));
+        components
+            .get_components::<Collider>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .insert(*entity, Collider::sphere(0.5));
+        entity
+    }
+
+    fn height(sim: &Simulation<AnyComponentStore>, entity: Entity) -> f32 {
+        let transforms = sim
+            .components
+            .get_components::<TransformComponent>()
+            .unwrap();
+        let transforms = transforms.read().unwrap();
+        transforms[*entity].as_ref().unwrap().transform.disp.y
+    }
+
+    #[test]
+    fn test_simulated_clock() {
+        let mut runner = headless();
+        let ball = spawn_ball(&mut runner.sim);
+        let timer = runner.sim.clock.after(Duration::from_millis(500), "half");
+        let mut fired_at = None;
+        runner.run_while(|sim| {
+            if sim.timer_events.iter().any(|e| e.id == timer) {
+                fired_at = Some(sim.clock.game_time());
+            }
+            sim.clock.game_time() < Duration::from_secs(1)
+        });
+        assert_eq!(runner.ticks(), 50);
+        assert_eq!(runner.simulated_time(), Duration::from_secs(1));
+        assert_eq!(fired_at, Some(Duration::from_millis(500)));
+        // a second of gravity, without a window or renderer
+        assert!(height(&runner.sim, ball) < 6.0);
+    }
+
+    #[test]
+    fn test_runs_are_deterministic() {
+        let run = || {
+            let mut runner = headless();
+            runner.sim.reseed(7);
+            let ball = spawn_ball(&mut runner.sim);
+            runner.run_for(30);
+            height(&runner.sim, ball)
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn test_world_from_simulation() {
+        let mut runner = headless();
+        let ball = spawn_ball(&mut runner.sim);
+        runner.run_for(10);
+        let sim = runner.into_simulation();
+        let expected = height(&sim, ball);
+
+        let world: EntityWorld<MockRenderer, _> =
+            EntityWorld::from_simulation(sim);
+        assert_eq!(height(&world, ball), expected);
+        assert_eq!(world.clock.game_time(), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_replay() {
+        use crate::game::input::{Button, InputSnapshot};
+        use crate::game::replay::{InputFrame, InputRecording};
+        use sdl2::keyboard::Scancode;
+
+        let mut recording = InputRecording::new(3);
+        for _ in 0..20 {
+            recording.frames.push(InputFrame {
+                delta: Duration::from_millis(10),
+                mousepos: Point2::new(0.0, 0.0),
+                last_mousepos: Point2::new(0.0, 0.0),
+                input: InputSnapshot::with_buttons(vec![Button::Key(
+                    Scancode::W,
+                )]),
+            });
+        }
+        let mut runner =
+            headless().with_replay(InputReplay::new(recording.clone()));
+        assert_eq!(runner.sim.seed(), 3);
+        let start = runner.sim.main_camera.position();
+        runner.run_replay();
+        assert!(!runner.is_replaying());
+        assert_eq!(runner.ticks(), 20);
+        assert_eq!(runner.simulated_time(), Duration::from_millis(200));
+        assert!(runner.sim.main_camera.position().distance(start) > 0.1);
+
+        // past the end of the replay, ticks run at the tick rate and
+        // without input
+        runner.step();
+        assert_eq!(runner.simulated_time(), Duration::from_millis(220));
+        assert!(runner.sim.input_snapshot.keys.is_empty());
+        // once its damped velocity settles, the camera stops
+        runner.run_for(50);
+        let stopped = runner.sim.main_camera.position();
+        runner.run_for(5);
+        assert!(runner.sim.main_camera.position().distance(stopped) < 1e-4);
+    }
+}
@@ -8,8 +8,10 @@ extern crate memoffset;
 #[macro_use]
 extern crate serde_derive;
 
+pub mod app;
 pub mod config;
 pub mod game;
+pub mod headless;
 pub mod platform_system;
 pub mod renderer;
 pub mod sdl_platform;
@@ -40,12 +42,4 @@ impl AppError {
     }
 }
 
-pub mod math {
-    use cgmath;
-    pub type Vec2 = cgmath::Vector2<f32>;
-    pub type Vec3 = cgmath::Vector3<f32>;
-    pub type Vec4 = cgmath::Vector4<f32>;
-
-    pub type Mat3 = cgmath::Matrix3<f32>;
-    pub type Mat4 = cgmath::Matrix4<f32>;
-}
+pub mod math;
@@ -0,0 +1,694 @@
+//! Bounding volumes, planes and view frustums.
+
+use super::{Mat3, Mat4, Obb, Vec3, Vec4};
+use cgmath::*;
+
+/// Axis aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Aabb { min, max }
+    }
+
+    /// The smallest box containing the points, or None if there are none
+    pub fn from_points<I>(points: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = Point3<f32>>,
+    {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Aabb::new(first, first), |aabb, p| Aabb {
+            min: Point3::new(
+                aabb.min.x.min(p.x),
+                aabb.min.y.min(p.y),
+                aabb.min.z.min(p.z),
+            ),
+            max: Point3::new(
+                aabb.max.x.max(p.x),
+                aabb.max.y.max(p.y),
+                aabb.max.z.max(p.z),
+            ),
+        }))
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    /// Half the size of the box along each axis
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// The eight corners of the box
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::new(a.x, a.y, a.z),
+            Point3::new(b.x, a.y, a.z),
+            Point3::new(a.x, b.y, a.z),
+            Point3::new(b.x, b.y, a.z),
+            Point3::new(a.x, a.y, b.z),
+            Point3::new(b.x, a.y, b.z),
+            Point3::new(a.x, b.y, b.z),
+            Point3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    pub fn volume(&self) -> f32 {
+        let d = self.max - self.min;
+        d.x * d.y * d.z
+    }
+
+    /// The smallest box containing this box and a point
+    pub fn include(&self, point: Point3<f32>) -> Aabb {
+        self.union(&Aabb::new(point, point))
+    }
+
+    /// The smallest box containing both boxes
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        )
+    }
+
+    /// Grows the box by `margin` on every side
+    pub fn expand(&self, margin: f32) -> Aabb {
+        let margin = vec3(margin, margin, margin);
+        Aabb::new(self.min - margin, self.max + margin)
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// True if `other` lies entirely inside this box
+    pub fn contains(&self, other: &Aabb) -> bool {
+        self.min.x <= other.min.x
+            && self.min.y <= other.min.y
+            && self.min.z <= other.min.z
+            && self.max.x >= other.max.x
+            && self.max.y >= other.max.y
+            && self.max.z >= other.max.z
+    }
+
+    pub fn contains_point(&self, point: Point3<f32>) -> bool {
+        (0..3).all(|i| self.min[i] <= point[i] && point[i] <= self.max[i])
+    }
+
+    /// True if the boxes overlap or touch
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+            && self.min.z <= other.max.z
+            && self.max.z >= other.min.z
+    }
+
+    /// The point in the box closest to `point`
+    pub fn closest_point(&self, point: Point3<f32>) -> Point3<f32> {
+        Point3::new(
+            point.x.max(self.min.x).min(self.max.x),
+            point.y.max(self.min.y).min(self.max.y),
+            point.z.max(self.min.z).min(self.max.z),
+        )
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.closest_point(sphere.center).distance2(sphere.center)
+            <= sphere.radius * sphere.radius
+    }
+
+    /// The axis aligned box enclosing this box after a transform
+    pub fn transform(&self, matrix: &Mat4) -> Aabb {
+        let center = matrix.transform_point(self.center());
+        let e = self.half_extents();
+        // each world axis' extent is the sum of the absolute projections
+        // of the transformed box axes
+        let half = vec3(
+            (matrix.x.x * e.x).abs()
+                + (matrix.y.x * e.y).abs()
+                + (matrix.z.x * e.z).abs(),
+            (matrix.x.y * e.x).abs()
+                + (matrix.y.y * e.y).abs()
+                + (matrix.z.y * e.z).abs(),
+            (matrix.x.z * e.x).abs()
+                + (matrix.y.z * e.y).abs()
+                + (matrix.z.z * e.z).abs(),
+        );
+        Aabb::new(center - half, center + half)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn new(center: Point3<f32>, radius: f32) -> Self {
+        Sphere { center, radius }
+    }
+
+    /// The sphere enclosing this sphere after a transform. Non-uniform
+    /// scales use the largest axis scale.
+    pub fn transform(&self, matrix: &Mat4) -> Sphere {
+        let scale = matrix
+            .x
+            .truncate()
+            .magnitude2()
+            .max(matrix.y.truncate().magnitude2())
+            .max(matrix.z.truncate().magnitude2())
+            .sqrt();
+        Sphere::new(matrix.transform_point(self.center), self.radius * scale)
+    }
+
+    /// The smallest sphere enclosing both spheres
+    pub fn union(&self, other: &Sphere) -> Sphere {
+        let offset = other.center - self.center;
+        let distance = offset.magnitude();
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        // move from this sphere's center towards the other's
+        let center = self.center + offset * ((radius - self.radius) / distance);
+        Sphere::new(center, radius)
+    }
+
+    pub fn contains_point(&self, point: Point3<f32>) -> bool {
+        self.center.distance2(point) <= self.radius * self.radius
+    }
+
+    /// True if `other` lies entirely inside this sphere
+    pub fn contains(&self, other: &Sphere) -> bool {
+        self.center.distance(other.center) + other.radius <= self.radius
+    }
+
+    /// True if the spheres overlap or touch
+    pub fn intersects(&self, other: &Sphere) -> bool {
+        let radii = self.radius + other.radius;
+        self.center.distance2(other.center) <= radii * radii
+    }
+
+    /// The axis aligned box enclosing the sphere
+    pub fn aabb(&self) -> Aabb {
+        let r = vec3(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
+}
+
+/// The plane of points `p` where `normal.dot(p) + d == 0`. Points on the
+/// side the normal faces have positive distance.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub d: f32,
+}
+
+impl Plane {
+    pub fn new(normal: Vec3, d: f32) -> Self {
+        Plane { normal, d }
+    }
+
+    /// Creates a plane from `(a, b, c, d)` coefficients, normalizing them
+    pub fn from_coefficients(v: Vec4) -> Self {
+        let len = v.truncate().magnitude();
+        Plane::new(v.truncate() / len, v.w / len)
+    }
+
+    /// The plane through `point`, normalizing `normal`
+    pub fn from_point_normal(point: Point3<f32>, normal: Vec3) -> Self {
+        let normal = normal.normalize();
+        Plane::new(normal, -normal.dot(point.to_vec()))
+    }
+
+    /// The plane through three points, facing the side they wind
+    /// counter-clockwise around
+    pub fn from_points(a: Point3<f32>, b: Point3<f32>, c: Point3<f32>) -> Self {
+        Plane::from_point_normal(a, (b - a).cross(c - a))
+    }
+
+    /// Signed distance from the plane to a point
+    pub fn distance(&self, point: Point3<f32>) -> f32 {
+        self.normal.dot(point.to_vec()) + self.d
+    }
+
+    /// The closest point on the plane
+    pub fn project_point(&self, point: Point3<f32>) -> Point3<f32> {
+        point - self.normal * self.distance(point)
+    }
+
+    /// A point on the plane
+    pub fn origin(&self) -> Point3<f32> {
+        Point3::from_vec(self.normal * -self.d)
+    }
+
+    /// The plane through the transformed points of this plane. The
+    /// normal keeps facing the same side of the transformed points.
+    pub fn transform(&self, matrix: &Mat4) -> Plane {
+        let n = self.normal;
+        let helper = if n.x.abs() < 0.9 {
+            Vec3::unit_x()
+        } else {
+            Vec3::unit_y()
+        };
+        // tangents with t1 x t2 == n
+        let t1 = n.cross(helper).normalize();
+        let t2 = n.cross(t1);
+        let linear = Mat3::from_cols(
+            matrix.x.truncate(),
+            matrix.y.truncate(),
+            matrix.z.truncate(),
+        );
+        let mut normal = (linear * t1).cross(linear * t2);
+        if linear.determinant() < 0.0 {
+            normal = -normal;
+        }
+        Plane::from_point_normal(matrix.transform_point(self.origin()), normal)
+    }
+}
+
+/// A view volume bounded by six planes, with normals facing inwards
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    /// left, right, bottom, top, near, and far planes
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a combined projection and view matrix.
+    /// For a projection alone, the planes are in eye space; for
+    /// `projection * view`, they are in world space.
+    pub fn from_matrix(m: &Mat4) -> Self {
+        let (r0, r1, r2, r3) = (m.row(0), m.row(1), m.row(2), m.row(3));
+        Frustum {
+            planes: [
+                Plane::from_coefficients(r3 + r0),
+                Plane::from_coefficients(r3 - r0),
+                Plane::from_coefficients(r3 + r1),
+                Plane::from_coefficients(r3 - r1),
+                Plane::from_coefficients(r3 + r2),
+                Plane::from_coefficients(r3 - r2),
+            ],
+        }
+    }
+
+    pub fn contains_point(&self, point: Point3<f32>) -> bool {
+        self.planes.iter().all(|p| p.distance(point) >= 0.0)
+    }
+
+    /// Returns false if the sphere is entirely outside the frustum
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes
+            .iter()
+            .all(|p| p.distance(sphere.center) >= -sphere.radius)
+    }
+
+    /// Returns false if the box is entirely outside the frustum. May
+    /// return true for some boxes outside near the frustum's corners.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the box corner furthest along the plane normal
+            let n = plane.normal;
+            let corner = Point3::new(
+                if n.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if n.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if n.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            plane.distance(corner) >= 0.0
+        })
+    }
+
+    /// Returns false if the box is entirely outside the frustum, with the
+    /// same conservativeness as `intersects_aabb`
+    pub fn intersects_obb(&self, obb: &Obb) -> bool {
+        self.planes.iter().all(|plane| {
+            plane.distance(obb.center) >= -obb.projected_radius(plane.normal)
+        })
+    }
+
+    /// True if the sphere is entirely inside the frustum
+    pub fn contains_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes
+            .iter()
+            .all(|p| p.distance(sphere.center) >= sphere.radius)
+    }
+
+    /// True if the box is entirely inside the frustum
+    pub fn contains_aabb(&self, aabb: &Aabb) -> bool {
+        aabb.corners().iter().all(|&c| self.contains_point(c))
+    }
+
+    pub fn transform(&self, matrix: &Mat4) -> Frustum {
+        let mut planes = self.planes;
+        for plane in planes.iter_mut() {
+            *plane = plane.transform(matrix);
+        }
+        Frustum { planes }
+    }
+}
+
+/// A bounding box and sphere enclosing the same geometry
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: Sphere,
+}
+
+impl Bounds {
+    /// Bounds of a set of points, or None if there are none. The sphere
+    /// is centered on the box, with a radius reaching the furthest point.
+    pub fn from_points<I>(points: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = Point3<f32>>,
+        I::IntoIter: Clone,
+    {
+        let points = points.into_iter();
+        let aabb = Aabb::from_points(points.clone())?;
+        let center = aabb.center();
+        let radius = points
+            .map(|p| p.distance2(center))
+            .fold(0.0f32, f32::max)
+            .sqrt();
+        Some(Bounds {
+            aabb,
+            sphere: Sphere::new(center, radius),
+        })
+    }
+
+    pub fn transform(&self, matrix: &Mat4) -> Bounds {
+        Bounds {
+            aabb: self.aabb.transform(matrix),
+            sphere: self.sphere.transform(matrix),
+        }
+    }
+
+    /// Tests the sphere, then the box, against a frustum
+    pub fn intersects_frustum(&self, frustum: &Frustum) -> bool {
+        frustum.intersects_sphere(&self.sphere)
+            && frustum.intersects_aabb(&self.aabb)
+    }
+}
+
+impl Default for Bounds {
+    /// Empty bounds at the origin
+    fn default() -> Self {
+        let origin = Point3::origin();
+        Bounds {
+            aabb: Aabb::new(origin, origin),
+            sphere: Sphere::new(origin, 0.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unit_cube_points() -> Vec<Point3<f32>> {
+        let mut points = Vec::new();
+        for &x in &[-1.0, 1.0] {
+            for &y in &[-1.0, 1.0] {
+                for &z in &[-1.0, 1.0] {
+                    points.push(Point3::new(x, y, z));
+                }
+            }
+        }
+        points
+    }
+
+    fn camera_frustum() -> Frustum {
+        let projection: Mat4 = PerspectiveFov {
+            fovy: Deg(90.0).into(),
+            aspect: 1.0,
+            near: 0.1,
+            far: 100.0,
+        }
+        .into();
+        let view = Mat4::look_at(
+            Point3::new(0.0, 0.0, 10.0),
+            Point3::origin(),
+            Vec3::unit_y(),
+        );
+        Frustum::from_matrix(&(projection * view))
+    }
+
+    fn near(a: Point3<f32>, b: Point3<f32>) -> bool {
+        a.distance(b) < 1e-4
+    }
+
+    #[test]
+    fn test_aabb_merge_and_containment() {
+        let a =
+            Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        let b =
+            Aabb::new(Point3::new(2.0, -1.0, 0.5), Point3::new(3.0, 0.5, 4.0));
+        let merged = a.union(&b);
+        assert_eq!(merged.min, Point3::new(0.0, -1.0, 0.0));
+        assert_eq!(merged.max, Point3::new(3.0, 1.0, 4.0));
+        assert!(merged.contains(&a) && merged.contains(&b));
+        assert!(!a.contains(&merged));
+        assert!(!a.intersects(&b));
+        assert!(merged.intersects(&a));
+        // touching boxes intersect
+        let touching =
+            Aabb::new(Point3::new(1.0, 0.0, 0.0), Point3::new(2.0, 1.0, 1.0));
+        assert!(a.intersects(&touching));
+
+        assert!(a.contains_point(Point3::new(0.5, 0.5, 1.0)));
+        assert!(!a.contains_point(Point3::new(0.5, 1.5, 0.5)));
+        assert_eq!(a.include(Point3::new(-1.0, 0.5, 0.5)).min.x, -1.0);
+        assert_eq!(merged.volume(), 3.0 * 2.0 * 4.0);
+        assert_eq!(a.surface_area(), 6.0);
+        assert_eq!(a.expand(1.0).min, Point3::new(-1.0, -1.0, -1.0));
+        assert_eq!(Aabb::from_points(a.corners().to_vec()), Some(a));
+        assert_eq!(
+            a.closest_point(Point3::new(5.0, 0.5, -2.0)),
+            Point3::new(1.0, 0.5, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_aabb_sphere_overlap() {
+        let a =
+            Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        assert!(
+            a.intersects_sphere(&Sphere::new(Point3::new(1.5, 0.5, 0.5), 0.6))
+        );
+        assert!(
+            !a.intersects_sphere(&Sphere::new(Point3::new(1.5, 0.5, 0.5), 0.4))
+        );
+        // near a corner, the box's bounding box test would pass
+        let corner = Sphere::new(Point3::new(1.5, 1.5, 1.5), 0.8);
+        assert!(corner.aabb().intersects(&a));
+        assert!(!a.intersects_sphere(&corner));
+    }
+
+    #[test]
+    fn test_sphere_tests() {
+        let a = Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0);
+        let b = Sphere::new(Point3::new(3.0, 0.0, 0.0), 2.0);
+        let c = Sphere::new(Point3::new(3.5, 0.0, 0.0), 1.0);
+        assert!(a.intersects(&b), "touching spheres intersect");
+        assert!(!a.intersects(&c));
+        assert!(b.contains(&c));
+        assert!(!c.contains(&b));
+        assert!(b.contains_point(Point3::new(4.5, 0.0, 0.0)));
+        assert!(!a.contains_point(Point3::new(0.8, 0.8, 0.0)));
+
+        let merged = a.union(&b);
+        assert!((merged.radius - 3.0).abs() < 1e-6);
+        assert!(near(merged.center, Point3::new(2.0, 0.0, 0.0)));
+        // allow for rounding in the merged center
+        let shrink = |s: Sphere| Sphere::new(s.center, s.radius - 1e-4);
+        assert!(merged.contains(&shrink(a)) && merged.contains(&shrink(b)));
+        assert_eq!(b.union(&c), b);
+        assert_eq!(c.union(&b), b);
+        assert_eq!(
+            a.aabb(),
+            Aabb::new(
+                Point3::new(-1.0, -1.0, -1.0),
+                Point3::new(1.0, 1.0, 1.0)
+            )
+        );
+    }
+
+    #[test]
+    fn test_plane() {
+        let plane = Plane::from_points(
+            Point3::new(0.0, 2.0, 0.0),
+            Point3::new(0.0, 2.0, 1.0),
+            Point3::new(1.0, 2.0, 0.0),
+        );
+        assert!((plane.normal - Vec3::unit_y()).magnitude() < 1e-6);
+        assert_eq!(plane.d, -2.0);
+        assert_eq!(plane.distance(Point3::new(5.0, 5.0, 5.0)), 3.0);
+        assert_eq!(plane.distance(Point3::new(5.0, 0.0, 5.0)), -2.0);
+        assert!(near(
+            plane.project_point(Point3::new(1.0, 7.0, 3.0)),
+            Point3::new(1.0, 2.0, 3.0)
+        ));
+        let coefficients =
+            Plane::from_coefficients(Vec4::new(0.0, 2.0, 0.0, -4.0));
+        assert_eq!(coefficients, plane);
+    }
+
+    #[test]
+    fn test_transform_plane() {
+        let plane = Plane::from_point_normal(
+            Point3::new(0.0, 2.0, 0.0),
+            Vec3::unit_y(),
+        );
+        let matrix = Mat4::from_translation(vec3(0.0, 0.0, 5.0))
+            * Mat4::from_angle_x(Deg(90.0))
+            * Mat4::from_nonuniform_scale(3.0, 2.0, 1.0);
+        let moved = plane.transform(&matrix);
+        // y = 2 scales to y = 4, then rotates to z = 4, then moves to z = 9
+        assert!((moved.normal - Vec3::unit_z()).magnitude() < 1e-5);
+        assert!((moved.distance(Point3::new(7.0, -3.0, 9.0))).abs() < 1e-5);
+        // points keep their side of the plane
+        let above = Point3::new(1.0, 3.0, 1.0);
+        let moved_above = matrix.transform_point(above);
+        assert!(
+            plane.distance(above) > 0.0 && moved.distance(moved_above) > 0.0
+        );
+        // mirroring keeps points on the same side too
+        let mirror = Mat4::from_nonuniform_scale(1.0, -1.0, 1.0);
+        let mirrored = plane.transform(&mirror);
+        assert!(mirrored.distance(mirror.transform_point(above)) > 0.0);
+    }
+
+    #[test]
+    fn test_frustum_containment() {
+        let frustum = camera_frustum();
+        let inside = Aabb::new(
+            Point3::new(-1.0, -1.0, -1.0),
+            Point3::new(1.0, 1.0, 1.0),
+        );
+        assert!(frustum.contains_aabb(&inside));
+        let straddling =
+            inside.transform(&Mat4::from_translation(vec3(10.0, 0.0, 0.0)));
+        assert!(frustum.intersects_aabb(&straddling));
+        assert!(!frustum.contains_aabb(&straddling));
+        assert!(frustum.contains_sphere(&Sphere::new(Point3::origin(), 1.0)));
+        assert!(!frustum
+            .contains_sphere(&Sphere::new(Point3::new(9.5, 0.0, 0.0), 1.0)));
+
+        let box_at = |x: f32| {
+            Obb::from_aabb(&inside).transform(
+                &(Mat4::from_translation(vec3(x, 0.0, 0.0))
+                    * Mat4::from_angle_y(Deg(45.0))),
+            )
+        };
+        assert!(frustum.intersects_obb(&box_at(0.0)));
+        // the rotated box reaches sqrt(2) units to the side
+        assert!(frustum.intersects_obb(&box_at(11.3)));
+        assert!(!frustum.intersects_obb(&box_at(12.0)));
+    }
+
+    #[test]
+    fn test_transform_frustum() {
+        let frustum = camera_frustum();
+        let shift = Mat4::from_translation(vec3(100.0, 0.0, 0.0));
+        let moved = frustum.transform(&shift);
+        assert!(moved.contains_point(Point3::new(100.0, 0.0, 0.0)));
+        assert!(!moved.contains_point(Point3::origin()));
+        // the same as extracting the planes of the moved camera
+        let projection: Mat4 = PerspectiveFov {
+            fovy: Deg(90.0).into(),
+            aspect: 1.0,
+            near: 0.1,
+            far: 100.0,
+        }
+        .into();
+        let view = Mat4::look_at(
+            Point3::new(100.0, 0.0, 10.0),
+            Point3::new(100.0, 0.0, 0.0),
+            Vec3::unit_y(),
+        );
+        let expected = Frustum::from_matrix(&(projection * view));
+        for (a, b) in moved.planes.iter().zip(&expected.planes) {
+            assert!((a.normal - b.normal).magnitude() < 1e-4);
+            assert!((a.d - b.d).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_bounds_from_points() {
+        let mut points = unit_cube_points();
+        points.push(Point3::new(3.0, 0.0, 0.0));
+        let bounds = Bounds::from_points(points).unwrap();
+        assert_eq!(bounds.aabb.min, Point3::new(-1.0, -1.0, -1.0));
+        assert_eq!(bounds.aabb.max, Point3::new(3.0, 1.0, 1.0));
+        assert_eq!(bounds.sphere.center, Point3::new(1.0, 0.0, 0.0));
+        assert!((bounds.sphere.radius - 6f32.sqrt()).abs() < 1e-6);
+        assert_eq!(Bounds::from_points(Vec::new()), None);
+    }
+
+    #[test]
+    fn test_transform_bounds() {
+        let bounds = Bounds::from_points(unit_cube_points()).unwrap();
+        let matrix = Mat4::from_translation(vec3(5.0, 0.0, 0.0))
+            * Mat4::from_angle_z(Deg(45.0))
+            * Mat4::from_scale(2.0);
+        let moved = bounds.transform(&matrix);
+        let half = 2.0 * 2f32.sqrt();
+        assert!((moved.aabb.max.x - (5.0 + half)).abs() < 1e-5);
+        assert!((moved.aabb.max.y - half).abs() < 1e-5);
+        assert!((moved.aabb.max.z - 2.0).abs() < 1e-5);
+        assert!((moved.sphere.radius - 2.0 * 3f32.sqrt()).abs() < 1e-5);
+        assert!((moved.sphere.center.x - 5.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_frustum_planes() {
+        let frustum = camera_frustum();
+        assert!(frustum.contains_point(Point3::origin()));
+        assert!(!frustum.contains_point(Point3::new(0.0, 0.0, 11.0)));
+        assert!(!frustum.contains_point(Point3::new(0.0, 0.0, -95.0)));
+        // a 90 degree fov sees 10 units to the side at the origin
+        assert!(frustum.contains_point(Point3::new(9.9, 0.0, 0.0)));
+        assert!(!frustum.contains_point(Point3::new(10.1, 0.0, 0.0)));
+        for plane in &frustum.planes {
+            assert!((plane.normal.magnitude() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_frustum_culls_bounds() {
+        let frustum = camera_frustum();
+        let cube = Bounds::from_points(unit_cube_points()).unwrap();
+        let at = |x: f32, z: f32| {
+            cube.transform(&Mat4::from_translation(vec3(x, 0.0, z)))
+        };
+        assert!(at(0.0, 0.0).intersects_frustum(&frustum));
+        // straddling the right plane
+        assert!(at(10.5, 0.0).intersects_frustum(&frustum));
+        assert!(!at(13.0, 0.0).intersects_frustum(&frustum));
+        // behind the camera
+        assert!(!at(0.0, 12.0).intersects_frustum(&frustum));
+        // beyond the far plane
+        assert!(!at(0.0, -200.0).intersects_frustum(&frustum));
+    }
+}
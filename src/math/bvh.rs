@@ -0,0 +1,490 @@
+//! A dynamic bounding volume hierarchy of axis aligned boxes.
+//!
+//! Leaves store a "fat" box, grown by a margin around the box they were
+//! inserted with, so objects moving a little don't need to be reinserted.
+//! Leaves are placed by a surface area heuristic and the tree is kept
+//! height balanced with rotations, so queries stay logarithmic in the
+//! number of leaves as objects move around.
+
+use super::{Aabb, Frustum, Ray, Sphere};
+
+/// Identifies a leaf in a DynamicBvh
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ProxyId(usize);
+
+#[derive(Debug)]
+enum NodeKind<T> {
+    Leaf(T),
+    Branch(usize, usize),
+    Free,
+}
+
+#[derive(Debug)]
+struct Node<T> {
+    aabb: Aabb,
+    parent: Option<usize>,
+    /// Leaves have height 0
+    height: usize,
+    kind: NodeKind<T>,
+}
+
+#[derive(Debug)]
+pub struct DynamicBvh<T> {
+    nodes: Vec<Node<T>>,
+    free: Vec<usize>,
+    root: Option<usize>,
+    margin: f32,
+    len: usize,
+}
+
+impl<T> Default for DynamicBvh<T> {
+    fn default() -> Self {
+        DynamicBvh::new(0.1)
+    }
+}
+
+impl<T> DynamicBvh<T> {
+    /// Creates an empty tree. Leaf boxes are grown by `margin` on every
+    /// side.
+    pub fn new(margin: f32) -> Self {
+        DynamicBvh {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: None,
+            margin,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Height of the tree, zero for a single leaf or an empty tree
+    pub fn height(&self) -> usize {
+        self.root.map_or(0, |root| self.nodes[root].height)
+    }
+
+    pub fn get(&self, id: ProxyId) -> Option<&T> {
+        match self.nodes.get(id.0) {
+            Some(Node {
+                kind: NodeKind::Leaf(data),
+                ..
+            }) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// The grown box stored for a leaf
+    pub fn fat_aabb(&self, id: ProxyId) -> Option<Aabb> {
+        self.get(id).map(|_| self.nodes[id.0].aabb)
+    }
+
+    pub fn insert(&mut self, aabb: Aabb, data: T) -> ProxyId {
+        let leaf = self.alloc(Node {
+            aabb: aabb.expand(self.margin),
+            parent: None,
+            height: 0,
+            kind: NodeKind::Leaf(data),
+        });
+        self.insert_leaf(leaf);
+        self.len += 1;
+        ProxyId(leaf)
+    }
+
+    pub fn remove(&mut self, id: ProxyId) -> Option<T> {
+        self.get(id)?;
+        self.remove_leaf(id.0);
+        self.len -= 1;
+        self.free.push(id.0);
+        match std::mem::replace(&mut self.nodes[id.0].kind, NodeKind::Free) {
+            NodeKind::Leaf(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Moves a leaf to a new box. The leaf is only reinserted if the box
+    /// has left its fat box; returns true if it was.
+    pub fn update(&mut self, id: ProxyId, aabb: Aabb) -> bool {
+        if self.get(id).is_none() || self.nodes[id.0].aabb.contains(&aabb) {
+            return false;
+        }
+        self.remove_leaf(id.0);
+        self.nodes[id.0].aabb = aabb.expand(self.margin);
+        self.insert_leaf(id.0);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.root = None;
+        self.len = 0;
+    }
+
+    /// Visits every leaf whose fat box passes `overlaps`, skipping the
+    /// subtrees of branches which fail it.
+    pub fn query<'a, F, V>(&'a self, mut overlaps: F, mut visit: V)
+    where
+        F: FnMut(&Aabb) -> bool,
+        V: FnMut(ProxyId, &'a T),
+    {
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !overlaps(&node.aabb) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf(ref data) => visit(ProxyId(index), data),
+                NodeKind::Branch(a, b) => {
+                    stack.push(a);
+                    stack.push(b);
+                }
+                NodeKind::Free => unreachable!("free node in tree"),
+            }
+        }
+    }
+
+    /// Leaves whose fat box overlaps `aabb`
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<&T> {
+        self.collect(|node| node.intersects(aabb))
+    }
+
+    /// Leaves whose fat box overlaps `sphere`
+    pub fn query_sphere(&self, sphere: &Sphere) -> Vec<&T> {
+        self.collect(|node| node.intersects_sphere(sphere))
+    }
+
+    /// Leaves whose fat box isn't entirely outside `frustum`
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<&T> {
+        self.collect(|node| frustum.intersects_aabb(node))
+    }
+
+    /// Leaves whose fat box the ray enters within `max_distance`, with
+    /// the distance it enters at, nearest first
+    pub fn query_ray(&self, ray: &Ray, max_distance: f32) -> Vec<(f32, &T)> {
+        let mut hits = Vec::new();
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let entry = match ray.intersect_aabb(&node.aabb) {
+                Some(entry) if entry <= max_distance => entry,
+                _ => continue,
+            };
+            match node.kind {
+                NodeKind::Leaf(ref data) => hits.push((entry, data)),
+                NodeKind::Branch(a, b) => {
+                    stack.push(a);
+                    stack.push(b);
+                }
+                NodeKind::Free => unreachable!("free node in tree"),
+            }
+        }
+        hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+        hits
+    }
+
+    fn collect<F>(&self, overlaps: F) -> Vec<&T>
+    where
+        F: FnMut(&Aabb) -> bool,
+    {
+        let mut found = Vec::new();
+        self.query(overlaps, |_, data| found.push(data));
+        found
+    }
+
+    fn alloc(&mut self, node: Node<T>) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn children(&self, index: usize) -> Option<(usize, usize)> {
+        match self.nodes[index].kind {
+            NodeKind::Branch(a, b) => Some((a, b)),
+            _ => None,
+        }
+    }
+
+    /// Points `parent`'s child link at `new` instead of `old`, or the root
+    /// if there is no parent
+    fn replace_child(&mut self, parent: Option<usize>, old: usize, new: usize) {
+        match parent {
+            Some(parent) => {
+                let (a, b) = self.children(parent).expect("parent is a leaf");
+                self.nodes[parent].kind = if a == old {
+                    NodeKind::Branch(new, b)
+                } else {
+                    NodeKind::Branch(a, new)
+                };
+            }
+            None => self.root = Some(new),
+        }
+        self.nodes[new].parent = parent;
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        let mut index = match self.root {
+            Some(root) => root,
+            None => {
+                self.root = Some(leaf);
+                self.nodes[leaf].parent = None;
+                return;
+            }
+        };
+        let leaf_aabb = self.nodes[leaf].aabb;
+
+        // descend towards the sibling that grows the tree's area least
+        while let Some((a, b)) = self.children(index) {
+            let area = self.nodes[index].aabb.surface_area();
+            let combined = self.nodes[index].aabb.union(&leaf_aabb);
+            // cost of pairing the leaf with this node
+            let cost = 2.0 * combined.surface_area();
+            // growth pushed onto the ancestors by descending further
+            let inherited = 2.0 * (combined.surface_area() - area);
+            let child_cost = |child: usize| {
+                let node = &self.nodes[child];
+                let grown = node.aabb.union(&leaf_aabb).surface_area();
+                match node.kind {
+                    NodeKind::Leaf(_) => grown + inherited,
+                    _ => grown - node.aabb.surface_area() + inherited,
+                }
+            };
+            let (cost_a, cost_b) = (child_cost(a), child_cost(b));
+            if cost < cost_a && cost < cost_b {
+                break;
+            }
+            index = if cost_a < cost_b { a } else { b };
+        }
+
+        let sibling = index;
+        let old_parent = self.nodes[sibling].parent;
+        let branch = self.alloc(Node {
+            aabb: self.nodes[sibling].aabb.union(&leaf_aabb),
+            parent: None,
+            height: self.nodes[sibling].height + 1,
+            kind: NodeKind::Branch(sibling, leaf),
+        });
+        self.replace_child(old_parent, sibling, branch);
+        self.nodes[sibling].parent = Some(branch);
+        self.nodes[leaf].parent = Some(branch);
+        self.refit(Some(branch));
+    }
+
+    /// Unlinks a leaf from the tree, freeing its parent branch. The leaf
+    /// node itself is kept.
+    fn remove_leaf(&mut self, leaf: usize) {
+        if self.root == Some(leaf) {
+            self.root = None;
+            return;
+        }
+        let parent = self.nodes[leaf].parent.expect("leaf without parent");
+        let (a, b) = self.children(parent).expect("parent is a leaf");
+        let sibling = if a == leaf { b } else { a };
+        let grandparent = self.nodes[parent].parent;
+        self.replace_child(grandparent, parent, sibling);
+        self.nodes[parent].kind = NodeKind::Free;
+        self.free.push(parent);
+        self.nodes[leaf].parent = None;
+        self.refit(grandparent);
+    }
+
+    /// Rebalances and recomputes the boxes and heights of a branch and its
+    /// ancestors
+    fn refit(&mut self, mut index: Option<usize>) {
+        while let Some(i) = index {
+            let i = self.balance(i);
+            self.recompute(i);
+            index = self.nodes[i].parent;
+        }
+    }
+
+    fn recompute(&mut self, index: usize) {
+        if let Some((a, b)) = self.children(index) {
+            let (a, b) = (&self.nodes[a], &self.nodes[b]);
+            let aabb = a.aabb.union(&b.aabb);
+            let height = 1 + a.height.max(b.height);
+            let node = &mut self.nodes[index];
+            node.aabb = aabb;
+            node.height = height;
+        }
+    }
+
+    /// Rotates the taller child of `index` above it if the children's
+    /// heights differ by more than one. Returns the node now in its place.
+    fn balance(&mut self, index: usize) -> usize {
+        let (a, b) = match self.children(index) {
+            Some(children) => children,
+            None => return index,
+        };
+        let (height_a, height_b) = (self.nodes[a].height, self.nodes[b].height);
+        if height_a > height_b + 1 {
+            self.rotate(index, a, b)
+        } else if height_b > height_a + 1 {
+            self.rotate(index, b, a)
+        } else {
+            index
+        }
+    }
+
+    /// Promotes the branch `up` into the place of its parent `index`. `up`
+    /// keeps its taller child and gives the shorter one to `index`.
+    fn rotate(&mut self, index: usize, up: usize, other: usize) -> usize {
+        let (f, g) = self.children(up).expect("rotating a leaf");
+        let (tall, short) = if self.nodes[f].height > self.nodes[g].height {
+            (f, g)
+        } else {
+            (g, f)
+        };
+        let parent = self.nodes[index].parent;
+        self.replace_child(parent, index, up);
+
+        self.nodes[index].kind = NodeKind::Branch(other, short);
+        self.nodes[short].parent = Some(index);
+        self.recompute(index);
+
+        self.nodes[up].kind = NodeKind::Branch(index, tall);
+        self.nodes[index].parent = Some(up);
+        self.recompute(up);
+        up
+    }
+
+    /// Checks parent links, boxes, heights and balance below `index`.
+    /// Returns the number of leaves.
+    #[cfg(test)]
+    fn validate(&self, index: usize) -> usize {
+        match self.children(index) {
+            None => {
+                assert_eq!(self.nodes[index].height, 0);
+                1
+            }
+            Some((a, b)) => {
+                let node = &self.nodes[index];
+                for &child in &[a, b] {
+                    assert_eq!(self.nodes[child].parent, Some(index));
+                    assert!(node.aabb.contains(&self.nodes[child].aabb));
+                }
+                let (ha, hb) = (self.nodes[a].height, self.nodes[b].height);
+                assert_eq!(node.height, 1 + ha.max(hb));
+                assert!((ha as isize - hb as isize).abs() <= 1);
+                self.validate(a) + self.validate(b)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cgmath::*;
+
+    fn unit_box_at(x: f32, y: f32, z: f32) -> Aabb {
+        let c = Point3::new(x, y, z);
+        Aabb::new(c - vec3(0.5, 0.5, 0.5), c + vec3(0.5, 0.5, 0.5))
+    }
+
+    fn validate<T>(bvh: &DynamicBvh<T>) {
+        let leaves = bvh.root.map_or(0, |root| bvh.validate(root));
+        assert_eq!(leaves, bvh.len());
+    }
+
+    fn sorted(mut found: Vec<&usize>) -> Vec<usize> {
+        found.sort();
+        found.into_iter().cloned().collect()
+    }
+
+    #[test]
+    fn test_bvh_stays_balanced() {
+        let mut bvh = DynamicBvh::new(0.1);
+        // a line of boxes inserted in order is the worst case without
+        // rotations
+        let ids: Vec<_> = (0..256)
+            .map(|i| bvh.insert(unit_box_at(i as f32 * 2.0, 0.0, 0.0), i))
+            .collect();
+        validate(&bvh);
+        assert!(bvh.height() <= 12, "height {}", bvh.height());
+
+        for id in ids.iter().step_by(2) {
+            bvh.remove(*id).unwrap();
+        }
+        validate(&bvh);
+        assert_eq!(bvh.len(), 128);
+        assert_eq!(bvh.get(ids[0]), None);
+        assert_eq!(bvh.get(ids[1]), Some(&1));
+    }
+
+    #[test]
+    fn test_bvh_update_uses_fat_boxes() {
+        let mut bvh = DynamicBvh::new(0.5);
+        let id = bvh.insert(unit_box_at(0.0, 0.0, 0.0), 0);
+        bvh.insert(unit_box_at(5.0, 0.0, 0.0), 1);
+        assert!(!bvh.update(id, unit_box_at(0.25, 0.0, 0.0)));
+        assert!(bvh.update(id, unit_box_at(10.0, 0.0, 0.0)));
+        validate(&bvh);
+        let fat = bvh.fat_aabb(id).unwrap();
+        assert!(fat.contains(&unit_box_at(10.0, 0.0, 0.0)));
+        assert_eq!(
+            sorted(bvh.query_aabb(&unit_box_at(10.0, 0.0, 0.0))),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn test_bvh_queries() {
+        let mut bvh = DynamicBvh::new(0.0);
+        // a 10x10 grid of boxes two units apart in the xz plane
+        for i in 0..100 {
+            let (x, z) = ((i % 10) as f32 * 2.0, (i / 10) as f32 * 2.0);
+            bvh.insert(unit_box_at(x, 0.0, z), i);
+        }
+        validate(&bvh);
+
+        let region = Aabb::new(
+            Point3::new(-1.0, -1.0, -1.0),
+            Point3::new(3.0, 1.0, 1.0),
+        );
+        assert_eq!(sorted(bvh.query_aabb(&region)), vec![0, 1]);
+
+        let sphere = Sphere::new(Point3::new(2.0, 0.0, 2.0), 1.0);
+        assert_eq!(sorted(bvh.query_sphere(&sphere)), vec![11]);
+
+        let ray = Ray::new(Point3::new(-5.0, 0.0, 4.0), vec3(1.0, 0.0, 0.0));
+        let hits = bvh.query_ray(&ray, 7.0);
+        let found: Vec<_> = hits.iter().map(|&(_, &i)| i).collect();
+        assert_eq!(found, vec![20, 21]);
+        assert!((hits[0].0 - 4.5).abs() < 1e-5);
+
+        let projection: Matrix4<f32> = PerspectiveFov {
+            fovy: Deg(30.0).into(),
+            aspect: 1.0,
+            near: 0.1,
+            far: 100.0,
+        }
+        .into();
+        // looking straight down at the corner box
+        let view = Matrix4::look_at(
+            Point3::new(0.0, 3.0, 0.0),
+            Point3::origin(),
+            Vector3::unit_z(),
+        );
+        let frustum = Frustum::from_matrix(&(projection * view));
+        assert_eq!(sorted(bvh.query_frustum(&frustum)), vec![0]);
+
+        // a test accepting every box visits every leaf
+        let mut visited = 0;
+        bvh.query(|_| true, |_, _| visited += 1);
+        assert_eq!(visited, 100);
+    }
+}
@@ -0,0 +1,178 @@
+//! Capsules, and closest points between line segments.
+
+use super::{Aabb, Mat4};
+use cgmath::*;
+
+/// The points within `radius` of the segment from `a` to `b`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Capsule {
+    pub a: Point3<f32>,
+    pub b: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Capsule {
+    pub fn new(a: Point3<f32>, b: Point3<f32>, radius: f32) -> Self {
+        Capsule { a, b, radius }
+    }
+
+    /// The point on the capsule's segment closest to `point`
+    pub fn segment_point(&self, point: Point3<f32>) -> Point3<f32> {
+        closest_point_on_segment(self.a, self.b, point)
+    }
+
+    pub fn contains_point(&self, point: Point3<f32>) -> bool {
+        self.segment_point(point).distance2(point) <= self.radius * self.radius
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        let r = vec3(self.radius, self.radius, self.radius);
+        Aabb::new(self.a - r, self.a + r)
+            .union(&Aabb::new(self.b - r, self.b + r))
+    }
+
+    /// Transforms the segment, scaling the radius by the largest axis
+    /// scale
+    pub fn transform(&self, matrix: &Mat4) -> Capsule {
+        let scale = matrix
+            .x
+            .truncate()
+            .magnitude2()
+            .max(matrix.y.truncate().magnitude2())
+            .max(matrix.z.truncate().magnitude2())
+            .sqrt();
+        Capsule::new(
+            matrix.transform_point(self.a),
+            matrix.transform_point(self.b),
+            self.radius * scale,
+        )
+    }
+}
+
+/// The point on the segment `ab` closest to `point`
+pub fn closest_point_on_segment(
+    a: Point3<f32>,
+    b: Point3<f32>,
+    point: Point3<f32>,
+) -> Point3<f32> {
+    let ab = b - a;
+    let len2 = ab.magnitude2();
+    if len2 < std::f32::EPSILON {
+        return a;
+    }
+    let t = ((point - a).dot(ab) / len2).max(0.0).min(1.0);
+    a + ab * t
+}
+
+/// The closest pair of points on the segments `p1 q1` and `p2 q2`
+pub fn closest_points_between_segments(
+    p1: Point3<f32>,
+    q1: Point3<f32>,
+    p2: Point3<f32>,
+    q2: Point3<f32>,
+) -> (Point3<f32>, Point3<f32>) {
+    let (d1, d2, r) = (q1 - p1, q2 - p2, p1 - p2);
+    let (a, e, f) = (d1.magnitude2(), d2.magnitude2(), d2.dot(r));
+    let eps = std::f32::EPSILON;
+    if a <= eps && e <= eps {
+        return (p1, p2);
+    }
+    let (s, t) = if a <= eps {
+        (0.0, (f / e).max(0.0).min(1.0))
+    } else {
+        let c = d1.dot(r);
+        if e <= eps {
+            ((-c / a).max(0.0).min(1.0), 0.0)
+        } else {
+            let b = d1.dot(d2);
+            let denom = a * e - b * b;
+            // parallel segments pick an arbitrary s
+            let s = if denom > eps {
+                ((b * f - c * e) / denom).max(0.0).min(1.0)
+            } else {
+                0.0
+            };
+            let t = (b * s + f) / e;
+            if t < 0.0 {
+                ((-c / a).max(0.0).min(1.0), 0.0)
+            } else if t > 1.0 {
+                (((b - c) / a).max(0.0).min(1.0), 1.0)
+            } else {
+                (s, t)
+            }
+        }
+    };
+    (p1 + d1 * s, p2 + d2 * t)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_closest_point_on_segment() {
+        let (a, b) = (Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 4.0, 0.0));
+        let p = |x, y| Point3::new(x, y, 0.0);
+        assert_eq!(closest_point_on_segment(a, b, p(3.0, 2.0)), p(0.0, 2.0));
+        assert_eq!(closest_point_on_segment(a, b, p(3.0, -2.0)), a);
+        assert_eq!(closest_point_on_segment(a, b, p(3.0, 7.0)), b);
+        assert_eq!(closest_point_on_segment(a, a, p(3.0, 7.0)), a);
+    }
+
+    #[test]
+    fn test_closest_points_between_segments() {
+        let p = |x, y, z| Point3::new(x, y, z);
+        // crossing segments, one above the other
+        let (s, t) = closest_points_between_segments(
+            p(-1.0, 0.0, 0.0),
+            p(1.0, 0.0, 0.0),
+            p(0.5, 1.0, -1.0),
+            p(0.5, 1.0, 1.0),
+        );
+        assert!(s.distance(p(0.5, 0.0, 0.0)) < 1e-6);
+        assert!(t.distance(p(0.5, 1.0, 0.0)) < 1e-6);
+        // past the end of the first segment
+        let (s, t) = closest_points_between_segments(
+            p(0.0, 0.0, 0.0),
+            p(1.0, 0.0, 0.0),
+            p(3.0, -1.0, 0.0),
+            p(3.0, 1.0, 0.0),
+        );
+        assert_eq!(s, p(1.0, 0.0, 0.0));
+        assert!(t.distance(p(3.0, 0.0, 0.0)) < 1e-6);
+        // parallel segments are still the right distance apart
+        let (s, t) = closest_points_between_segments(
+            p(0.0, 0.0, 0.0),
+            p(2.0, 0.0, 0.0),
+            p(1.0, 2.0, 0.0),
+            p(3.0, 2.0, 0.0),
+        );
+        assert!((s.distance(t) - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_capsule() {
+        let capsule = Capsule::new(
+            Point3::new(0.0, -1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            0.5,
+        );
+        assert!(capsule.contains_point(Point3::new(0.4, 0.9, 0.0)));
+        assert!(capsule.contains_point(Point3::new(0.0, 1.4, 0.0)));
+        assert!(!capsule.contains_point(Point3::new(0.4, 1.4, 0.0)));
+        assert_eq!(
+            capsule.aabb(),
+            Aabb::new(
+                Point3::new(-0.5, -1.5, -0.5),
+                Point3::new(0.5, 1.5, 0.5)
+            )
+        );
+        let moved = capsule.transform(
+            &(Mat4::from_translation(vec3(2.0, 0.0, 0.0))
+                * Mat4::from_angle_z(Deg(90.0))
+                * Mat4::from_scale(2.0)),
+        );
+        assert!(moved.a.distance(Point3::new(4.0, 0.0, 0.0)) < 1e-5);
+        assert!((moved.radius - 1.0).abs() < 1e-6);
+    }
+}
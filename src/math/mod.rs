@@ -0,0 +1,27 @@
+//! Engine math types: cgmath aliases, geometric primitives with
+//! transforms and intersection tests, and splines.
+
+use cgmath;
+
+mod bounds;
+mod bvh;
+mod capsule;
+mod obb;
+mod ray;
+mod spline;
+mod triangle;
+
+pub use self::bounds::*;
+pub use self::bvh::*;
+pub use self::capsule::*;
+pub use self::obb::*;
+pub use self::ray::*;
+pub use self::spline::*;
+pub use self::triangle::*;
+
+pub type Vec2 = cgmath::Vector2<f32>;
+pub type Vec3 = cgmath::Vector3<f32>;
+pub type Vec4 = cgmath::Vector4<f32>;
+
+pub type Mat3 = cgmath::Matrix3<f32>;
+pub type Mat4 = cgmath::Matrix4<f32>;
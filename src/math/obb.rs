@@ -0,0 +1,207 @@
+//! Oriented bounding boxes.
+
+use super::{Aabb, Mat4, Sphere, Vec3};
+use cgmath::*;
+
+/// A box with its own orthonormal axes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Obb {
+    pub center: Point3<f32>,
+    /// Unit length, mutually perpendicular axes
+    pub axes: [Vec3; 3],
+    /// Half the size of the box along each of `axes`
+    pub half_extents: Vec3,
+}
+
+impl Obb {
+    pub fn new(
+        center: Point3<f32>,
+        axes: [Vec3; 3],
+        half_extents: Vec3,
+    ) -> Self {
+        Obb {
+            center,
+            axes,
+            half_extents,
+        }
+    }
+
+    pub fn from_aabb(aabb: &Aabb) -> Self {
+        Obb::new(
+            aabb.center(),
+            [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()],
+            aabb.half_extents(),
+        )
+    }
+
+    /// Transforms the box. Scales along the box's axes are folded into
+    /// its extents; shears aren't supported.
+    pub fn transform(&self, matrix: &Mat4) -> Obb {
+        let mut axes = self.axes;
+        let mut half_extents = self.half_extents;
+        for i in 0..3 {
+            let axis = matrix.transform_vector(self.axes[i]);
+            let scale = axis.magnitude();
+            if scale > 0.0 {
+                axes[i] = axis / scale;
+            }
+            half_extents[i] *= scale;
+        }
+        Obb::new(matrix.transform_point(self.center), axes, half_extents)
+    }
+
+    /// The eight corners of the box
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let mut corners = [self.center; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            for axis in 0..3 {
+                let sign = if i & (1 << axis) == 0 { -1.0 } else { 1.0 };
+                *corner += self.axes[axis] * (sign * self.half_extents[axis]);
+            }
+        }
+        corners
+    }
+
+    /// Half the length of the box's projection onto a unit vector
+    pub fn projected_radius(&self, direction: Vec3) -> f32 {
+        (0..3)
+            .map(|i| self.half_extents[i] * self.axes[i].dot(direction).abs())
+            .sum()
+    }
+
+    /// The axis aligned box enclosing this box
+    pub fn aabb(&self) -> Aabb {
+        let half = vec3(
+            self.projected_radius(Vec3::unit_x()),
+            self.projected_radius(Vec3::unit_y()),
+            self.projected_radius(Vec3::unit_z()),
+        );
+        Aabb::new(self.center - half, self.center + half)
+    }
+
+    /// Coordinates of a point along the box's axes, relative to its center
+    pub fn to_local(&self, point: Point3<f32>) -> Vec3 {
+        let d = point - self.center;
+        vec3(
+            d.dot(self.axes[0]),
+            d.dot(self.axes[1]),
+            d.dot(self.axes[2]),
+        )
+    }
+
+    pub fn contains_point(&self, point: Point3<f32>) -> bool {
+        let local = self.to_local(point);
+        (0..3).all(|i| local[i].abs() <= self.half_extents[i])
+    }
+
+    /// The point in the box closest to `point`
+    pub fn closest_point(&self, point: Point3<f32>) -> Point3<f32> {
+        let local = self.to_local(point);
+        (0..3).fold(self.center, |p, i| {
+            let h = self.half_extents[i];
+            p + self.axes[i] * local[i].max(-h).min(h)
+        })
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.closest_point(sphere.center).distance2(sphere.center)
+            <= sphere.radius * sphere.radius
+    }
+
+    /// Separating axis test between two boxes
+    pub fn intersects(&self, other: &Obb) -> bool {
+        // candidate separating axes: each box's face normals, and the
+        // cross products of their edges
+        let mut axes = Vec::with_capacity(15);
+        axes.extend_from_slice(&self.axes);
+        axes.extend_from_slice(&other.axes);
+        for a in &self.axes {
+            for b in &other.axes {
+                let cross = a.cross(*b);
+                // parallel edges are covered by the face normals
+                if cross.magnitude2() > 1e-6 {
+                    axes.push(cross.normalize());
+                }
+            }
+        }
+        let offset = other.center - self.center;
+        axes.iter().all(|&axis| {
+            offset.dot(axis).abs()
+                <= self.projected_radius(axis) + other.projected_radius(axis)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unit_box() -> Obb {
+        Obb::from_aabb(&Aabb::new(
+            Point3::new(-1.0, -1.0, -1.0),
+            Point3::new(1.0, 1.0, 1.0),
+        ))
+    }
+
+    fn rotated_box(x: f32, degrees: f32) -> Obb {
+        unit_box().transform(
+            &(Mat4::from_translation(vec3(x, 0.0, 0.0))
+                * Mat4::from_angle_z(Deg(degrees))),
+        )
+    }
+
+    #[test]
+    fn test_obb_transform() {
+        let obb = unit_box().transform(
+            &(Mat4::from_translation(vec3(5.0, 0.0, 0.0))
+                * Mat4::from_angle_z(Deg(90.0))
+                * Mat4::from_nonuniform_scale(2.0, 1.0, 3.0)),
+        );
+        assert!(obb.center.distance(Point3::new(5.0, 0.0, 0.0)) < 1e-5);
+        assert!((obb.half_extents - vec3(2.0, 1.0, 3.0)).magnitude() < 1e-5);
+        assert!((obb.axes[0] - Vec3::unit_y()).magnitude() < 1e-5);
+        // the box's long x axis now lies along y
+        let aabb = obb.aabb();
+        assert!((aabb.half_extents() - vec3(1.0, 2.0, 3.0)).magnitude() < 1e-5);
+        assert!(obb.contains_point(Point3::new(5.9, 1.9, 0.0)));
+        assert!(!obb.contains_point(Point3::new(6.1, 0.0, 0.0)));
+        for corner in obb.corners().iter() {
+            assert!(aabb.expand(1e-4).contains_point(*corner));
+        }
+    }
+
+    #[test]
+    fn test_obb_closest_point() {
+        let obb = rotated_box(0.0, 45.0);
+        // the corner of the rotated box points along x
+        let closest = obb.closest_point(Point3::new(5.0, 0.0, 0.0));
+        assert!(closest.distance(Point3::new(2f32.sqrt(), 0.0, 0.0)) < 1e-5);
+        let inside = Point3::new(0.5, 0.2, 0.1);
+        assert!(obb.closest_point(inside).distance(inside) < 1e-6);
+        assert!(obb
+            .intersects_sphere(&Sphere::new(Point3::new(2.0, 0.0, 0.0), 0.6)));
+        assert!(!obb
+            .intersects_sphere(&Sphere::new(Point3::new(2.0, 2.0, 0.0), 0.6)));
+    }
+
+    #[test]
+    fn test_obb_separating_axes() {
+        let a = unit_box();
+        assert!(!a.intersects(&rotated_box(2.5, 0.0)));
+        assert!(a.intersects(&rotated_box(1.9, 0.0)));
+        // rotating the second box turns a corner towards the first
+        assert!(a.intersects(&rotated_box(2.3, 45.0)));
+        assert!(!a.intersects(&rotated_box(2.5, 45.0)));
+        // diagonally offset and tilted on two axes
+        let edge = |d: f32| {
+            unit_box().transform(
+                &(Mat4::from_translation(vec3(d, d, 0.0))
+                    * Mat4::from_angle_x(Deg(45.0))
+                    * Mat4::from_angle_z(Deg(45.0))),
+            )
+        };
+        assert!(a.intersects(&edge(1.2)));
+        assert!(!a.intersects(&edge(3.0)));
+        assert!(a.intersects(&a));
+    }
+}
@@ -0,0 +1,314 @@
+//! Rays and ray intersection tests, used for picking and line of sight.
+
+use super::{Aabb, Mat4, Obb, Plane, Sphere, Triangle, Vec3, Vec4};
+use cgmath::*;
+
+/// A half-line from `origin` along the unit vector `direction`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vec3,
+}
+
+/// A ray-triangle intersection
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TriangleHit {
+    /// Distance along the ray
+    pub distance: f32,
+    /// Weights of the triangle's three vertices at the hit point
+    pub barycentrics: Vec3,
+}
+
+impl Ray {
+    /// Creates a ray, normalizing `direction`
+    pub fn new(origin: Point3<f32>, direction: Vec3) -> Self {
+        Ray {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    /// The ray from `from` through `to`
+    pub fn between(from: Point3<f32>, to: Point3<f32>) -> Self {
+        Ray::new(from, to - from)
+    }
+
+    /// Unprojects a point in normalized device coordinates through the
+    /// inverse of `view_projection`, a camera's projection times its view.
+    /// Returns None if the matrix isn't invertible.
+    pub fn from_ndc(ndc: Point2<f32>, view_projection: &Mat4) -> Option<Self> {
+        let inverse = view_projection.invert()?;
+        let unproject = |z: f32| {
+            let p = inverse * Vec4::new(ndc.x, ndc.y, z, 1.0);
+            Point3::from_homogeneous(p)
+        };
+        Some(Ray::between(unproject(-1.0), unproject(1.0)))
+    }
+
+    /// The point at `distance` along the ray
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// Transforms the ray. The direction is renormalized, so distances
+    /// along the result are in the transformed space.
+    pub fn transform(&self, matrix: &Mat4) -> Ray {
+        Ray::new(
+            matrix.transform_point(self.origin),
+            matrix.transform_vector(self.direction),
+        )
+    }
+
+    /// Distance to where the ray enters the box, or zero if it starts
+    /// inside. None if it misses.
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = std::f32::INFINITY;
+        for axis in 0..3 {
+            let (origin, dir) = (self.origin[axis], self.direction[axis]);
+            let (min, max) = (aabb.min[axis], aabb.max[axis]);
+            if dir.abs() < std::f32::EPSILON {
+                // parallel to this slab
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+            let (t0, t1) = ((min - origin) / dir, (max - origin) / dir);
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+
+    /// Distance to where the ray enters the sphere, or zero if it starts
+    /// inside. None if it misses.
+    pub fn intersect_sphere(&self, sphere: &Sphere) -> Option<f32> {
+        let to_center = sphere.center - self.origin;
+        let along = to_center.dot(self.direction);
+        let d2 = to_center.magnitude2() - along * along;
+        let r2 = sphere.radius * sphere.radius;
+        if d2 > r2 {
+            return None;
+        }
+        let half_chord = (r2 - d2).sqrt();
+        let (t0, t1) = (along - half_chord, along + half_chord);
+        if t1 < 0.0 {
+            None
+        } else {
+            Some(t0.max(0.0))
+        }
+    }
+
+    /// Distance to where the ray enters the box, or zero if it starts
+    /// inside. None if it misses.
+    pub fn intersect_obb(&self, obb: &Obb) -> Option<f32> {
+        // in the box's frame, it's an axis aligned box at the origin
+        let local = Ray {
+            origin: Point3::from_vec(obb.to_local(self.origin)),
+            direction: vec3(
+                self.direction.dot(obb.axes[0]),
+                self.direction.dot(obb.axes[1]),
+                self.direction.dot(obb.axes[2]),
+            ),
+        };
+        let half = obb.half_extents;
+        local.intersect_aabb(&Aabb::new(
+            Point3::from_vec(-half),
+            Point3::from_vec(half),
+        ))
+    }
+
+    /// Distance to where the ray crosses the plane, from either side.
+    /// None if the ray is parallel to it or points away.
+    pub fn intersect_plane(&self, plane: &Plane) -> Option<f32> {
+        let denom = plane.normal.dot(self.direction);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let distance = -plane.distance(self.origin) / denom;
+        if distance >= 0.0 {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    /// The point on the ray closest to `point`
+    pub fn closest_point(&self, point: Point3<f32>) -> Point3<f32> {
+        self.at((point - self.origin).dot(self.direction).max(0.0))
+    }
+
+    /// Möller-Trumbore intersection with a triangle. Both sides of the
+    /// triangle are hit.
+    pub fn intersect_triangle(
+        &self,
+        triangle: &Triangle,
+    ) -> Option<TriangleHit> {
+        let (a, b, c) = (triangle.a, triangle.b, triangle.c);
+        let (ab, ac) = (b - a, c - a);
+        let p = self.direction.cross(ac);
+        let det = ab.dot(p);
+        if det.abs() < 1e-8 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let ao = self.origin - a;
+        let u = ao.dot(p) * inv_det;
+        if u < 0.0 || u > 1.0 {
+            return None;
+        }
+        let q = ao.cross(ab);
+        let v = self.direction.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = ac.dot(q) * inv_det;
+        if distance < 0.0 {
+            return None;
+        }
+        Some(TriangleHit {
+            distance,
+            barycentrics: Vec3::new(1.0 - u - v, u, v),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn test_ray_aabb() {
+        let ray = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::unit_x());
+        assert_eq!(ray.intersect_aabb(&unit_box()), Some(4.0));
+        let inside = Ray::new(Point3::origin(), Vec3::unit_y());
+        assert_eq!(inside.intersect_aabb(&unit_box()), Some(0.0));
+        let away = Ray::new(Point3::new(-5.0, 0.0, 0.0), -Vec3::unit_x());
+        assert_eq!(away.intersect_aabb(&unit_box()), None);
+        let above = Ray::new(Point3::new(-5.0, 2.0, 0.0), Vec3::unit_x());
+        assert_eq!(above.intersect_aabb(&unit_box()), None);
+        let diagonal = Ray::between(
+            Point3::new(-3.0, -3.0, -3.0),
+            Point3::new(0.0, 0.0, 0.0),
+        );
+        let t = diagonal.intersect_aabb(&unit_box()).unwrap();
+        assert!((t - 2.0 * 3f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_ray_sphere() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, -10.0), 2.0);
+        let ray = Ray::new(Point3::origin(), -Vec3::unit_z());
+        assert_eq!(ray.intersect_sphere(&sphere), Some(8.0));
+        let miss = Ray::new(Point3::new(3.0, 0.0, 0.0), -Vec3::unit_z());
+        assert_eq!(miss.intersect_sphere(&sphere), None);
+        let behind = Ray::new(Point3::origin(), Vec3::unit_z());
+        assert_eq!(behind.intersect_sphere(&sphere), None);
+    }
+
+    #[test]
+    fn test_ray_triangle() {
+        let tri = Triangle::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        );
+        let ray = Ray::new(Point3::new(0.25, 0.5, 2.0), -Vec3::unit_z());
+        let hit = ray.intersect_triangle(&tri).unwrap();
+        assert!((hit.distance - 2.0).abs() < 1e-6);
+        assert!(
+            (hit.barycentrics - Vec3::new(0.25, 0.25, 0.5)).magnitude() < 1e-6
+        );
+        // the back face is hit too
+        let back = Ray::new(Point3::new(0.25, 0.5, -2.0), Vec3::unit_z());
+        assert!(back.intersect_triangle(&tri).is_some());
+        let outside = Ray::new(Point3::new(0.75, 0.75, 2.0), -Vec3::unit_z());
+        assert_eq!(outside.intersect_triangle(&tri), None);
+        let parallel = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::unit_x());
+        assert_eq!(parallel.intersect_triangle(&tri), None);
+    }
+
+    #[test]
+    fn test_ray_obb() {
+        let obb = Obb::from_aabb(&unit_box()).transform(
+            &(Mat4::from_translation(vec3(5.0, 0.0, 0.0))
+                * Mat4::from_angle_y(Deg(45.0))),
+        );
+        let ray = Ray::new(Point3::origin(), Vec3::unit_x());
+        // the rotated box's corner points back at the origin
+        let t = ray.intersect_obb(&obb).unwrap();
+        assert!((t - (5.0 - 2f32.sqrt())).abs() < 1e-5);
+        let past = Ray::new(Point3::new(0.0, 0.0, 1.5), Vec3::unit_x());
+        assert_eq!(past.intersect_obb(&obb), None);
+        // the same offset hits the unrotated box
+        let aabb =
+            unit_box().transform(&Mat4::from_translation(vec3(5.0, 0.0, 0.5)));
+        assert!(past.intersect_aabb(&aabb).is_some());
+    }
+
+    #[test]
+    fn test_ray_plane() {
+        let ground = Plane::new(Vec3::unit_y(), 0.0);
+        let down = Ray::new(Point3::new(1.0, 4.0, 0.0), vec3(0.0, -1.0, 1.0));
+        let t = down.intersect_plane(&ground).unwrap();
+        assert!(down.at(t).distance(Point3::new(1.0, 0.0, 4.0)) < 1e-5);
+        // hits from below too
+        let up = Ray::new(Point3::new(0.0, -2.0, 0.0), Vec3::unit_y());
+        assert_eq!(up.intersect_plane(&ground), Some(2.0));
+        let away = Ray::new(Point3::new(0.0, 2.0, 0.0), Vec3::unit_y());
+        assert_eq!(away.intersect_plane(&ground), None);
+        let along = Ray::new(Point3::new(0.0, 2.0, 0.0), Vec3::unit_x());
+        assert_eq!(along.intersect_plane(&ground), None);
+    }
+
+    #[test]
+    fn test_ray_transform_and_closest_point() {
+        let ray = Ray::new(Point3::new(1.0, 0.0, 0.0), Vec3::unit_x());
+        let moved = ray.transform(
+            &(Mat4::from_translation(vec3(0.0, 2.0, 0.0))
+                * Mat4::from_angle_z(Deg(90.0))
+                * Mat4::from_scale(3.0)),
+        );
+        assert!(moved.origin.distance(Point3::new(0.0, 5.0, 0.0)) < 1e-5);
+        assert!((moved.direction - Vec3::unit_y()).magnitude() < 1e-5);
+        assert_eq!(
+            ray.closest_point(Point3::new(4.0, 3.0, 0.0)),
+            Point3::new(4.0, 0.0, 0.0)
+        );
+        // points behind the ray are closest to its origin
+        assert_eq!(ray.closest_point(Point3::new(-4.0, 3.0, 0.0)), ray.origin);
+    }
+
+    #[test]
+    fn test_ray_from_ndc() {
+        let projection: Mat4 = PerspectiveFov {
+            fovy: Deg(90.0).into(),
+            aspect: 1.0,
+            near: 0.1,
+            far: 100.0,
+        }
+        .into();
+        let view = Mat4::look_at(
+            Point3::new(0.0, 0.0, 10.0),
+            Point3::origin(),
+            Vec3::unit_y(),
+        );
+        let center =
+            Ray::from_ndc(Point2::new(0.0, 0.0), &(projection * view)).unwrap();
+        assert!((center.direction - -Vec3::unit_z()).magnitude() < 1e-5);
+        assert!((center.origin.z - 9.9).abs() < 1e-4);
+        // the right edge of a 90 degree fov is at 45 degrees
+        let right =
+            Ray::from_ndc(Point2::new(1.0, 0.0), &(projection * view)).unwrap();
+        let expected = Vec3::new(1.0, 0.0, -1.0).normalize();
+        assert!((right.direction - expected).magnitude() < 1e-5);
+    }
+}
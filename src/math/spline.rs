@@ -0,0 +1,381 @@
+//! Bezier, Catmull-Rom and Hermite splines of positions and rotations,
+//! with arc-length parameterization.
+//!
+//! Every spline is stored as a chain of cubic Bezier segments, which are
+//! evaluated by repeated interpolation. Rotations interpolate by slerp,
+//! so rotation splines are spherical Beziers, and their tangents are
+//! angular velocities in the space of the rotation they leave.
+
+use super::Vec3;
+use cgmath::*;
+
+/// Values a spline passes through
+pub trait SplineValue: Copy {
+    /// Moves from `a` to `b` by `amount` in [0, 1]
+    fn interpolate(a: Self, b: Self, amount: f32) -> Self;
+
+    /// Moves a value along a tangent for unit time
+    fn advance(self, tangent: Vec3) -> Self;
+
+    /// Tangent moving `from` to `to` in unit time
+    fn difference(from: Self, to: Self) -> Vec3;
+}
+
+impl SplineValue for Vec3 {
+    fn interpolate(a: Self, b: Self, amount: f32) -> Self {
+        a.lerp(b, amount)
+    }
+
+    fn advance(self, tangent: Vec3) -> Self {
+        self + tangent
+    }
+
+    fn difference(from: Self, to: Self) -> Vec3 {
+        to - from
+    }
+}
+
+/// Rotation vector, an axis scaled by an angle in radians, of a rotation
+pub fn rotation_log(q: Quaternion<f32>) -> Vec3 {
+    let q = if q.s < 0.0 { -q } else { q };
+    let sin = q.v.magnitude();
+    if sin < 1e-6 {
+        return q.v * 2.0;
+    }
+    q.v / sin * (2.0 * sin.atan2(q.s))
+}
+
+/// Rotation about the axis of a rotation vector by its length in radians
+pub fn rotation_exp(v: Vec3) -> Quaternion<f32> {
+    let angle = v.magnitude();
+    if angle < 1e-6 {
+        return Quaternion::from_sv(1.0, v * 0.5).normalize();
+    }
+    Quaternion::from_axis_angle(v / angle, Rad(angle))
+}
+
+impl SplineValue for Quaternion<f32> {
+    fn interpolate(a: Self, b: Self, amount: f32) -> Self {
+        let b = if a.dot(b) < 0.0 { -b } else { b };
+        a.slerp(b, amount)
+    }
+
+    fn advance(self, tangent: Vec3) -> Self {
+        self * rotation_exp(tangent)
+    }
+
+    fn difference(from: Self, to: Self) -> Vec3 {
+        rotation_log(from.invert() * to)
+    }
+}
+
+/// A curve through values of T, parameterized over [0, 1] with each
+/// segment taking an equal share of the range
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spline<T: SplineValue> {
+    /// Start, two control points and end of each cubic Bezier segment
+    segments: Vec<[T; 4]>,
+}
+
+impl<T: SplineValue> Spline<T> {
+    /// Joined cubic Bezier curves, given as the first curve's start
+    /// followed by two control points and an end for each curve. A single
+    /// point gives a spline that stays at it.
+    pub fn bezier(points: &[T]) -> Option<Self> {
+        if points.is_empty() || (points.len() - 1) % 3 != 0 {
+            return None;
+        }
+        if points.len() == 1 {
+            return Some(Spline::constant(points[0]));
+        }
+        let segments = (0..points.len() / 3)
+            .map(|i| {
+                let p = &points[i * 3..];
+                [p[0], p[1], p[2], p[3]]
+            })
+            .collect();
+        Some(Spline { segments })
+    }
+
+    /// Hermite curves through each point, leaving it along its tangent.
+    /// Tangents are scaled to a segment taking unit time.
+    pub fn hermite(points: &[T], tangents: &[Vec3]) -> Option<Self> {
+        if points.is_empty() || points.len() != tangents.len() {
+            return None;
+        }
+        if points.len() == 1 {
+            return Some(Spline::constant(points[0]));
+        }
+        let segments = (0..points.len() - 1)
+            .map(|i| {
+                let (p0, p1) = (points[i], points[i + 1]);
+                [
+                    p0,
+                    p0.advance(tangents[i] / 3.0),
+                    p1.advance(tangents[i + 1] / -3.0),
+                    p1,
+                ]
+            })
+            .collect();
+        Some(Spline { segments })
+    }
+
+    /// A smooth curve through every point, whose tangent at each point
+    /// runs parallel to the line between its neighbours
+    pub fn catmull_rom(points: &[T]) -> Option<Self> {
+        let last = points.len().checked_sub(1)?;
+        let tangents: Vec<Vec3> = (0..points.len())
+            .map(|i| {
+                let to = |j: usize| T::difference(points[i], points[j]);
+                // the ends head straight for their only neighbour
+                match (i > 0, i < last) {
+                    (true, true) => (to(i + 1) - to(i - 1)) / 2.0,
+                    (false, true) => to(i + 1),
+                    (true, false) => -to(i - 1),
+                    (false, false) => Vec3::zero(),
+                }
+            })
+            .collect();
+        Spline::hermite(points, &tangents)
+    }
+
+    fn constant(point: T) -> Self {
+        Spline {
+            segments: vec![[point; 4]],
+        }
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// The value at `t`, clamped to [0, 1]
+    pub fn sample(&self, t: f32) -> T {
+        let n = self.segments.len();
+        let scaled = t.max(0.0).min(1.0) * n as f32;
+        let index = (scaled as usize).min(n - 1);
+        let u = scaled - index as f32;
+        let [p0, p1, p2, p3] = self.segments[index];
+        let lerp = |a, b| T::interpolate(a, b, u);
+        let (a, b, c) = (lerp(p0, p1), lerp(p1, p2), lerp(p2, p3));
+        lerp(lerp(a, b), lerp(b, c))
+    }
+
+    /// Measures the spline for sampling at distances along it
+    pub fn arc_length(&self) -> ArcLength {
+        ArcLength::new(self, 16)
+    }
+}
+
+/// Distances along a spline at evenly spaced parameters, mapping
+/// distances back to parameters so the spline can be travelled at an
+/// even speed. Rotation splines measure distance in radians turned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArcLength {
+    /// Distance along the spline at each sample, starting at zero
+    lengths: Vec<f32>,
+}
+
+impl ArcLength {
+    pub fn new<T: SplineValue>(
+        spline: &Spline<T>,
+        samples_per_segment: usize,
+    ) -> Self {
+        let count = spline.segment_count() * samples_per_segment.max(1);
+        let mut lengths = Vec::with_capacity(count + 1);
+        let mut total = 0.0;
+        let mut previous = spline.sample(0.0);
+        lengths.push(total);
+        for i in 1..=count {
+            let point = spline.sample(i as f32 / count as f32);
+            total += T::difference(previous, point).magnitude();
+            lengths.push(total);
+            previous = point;
+        }
+        ArcLength { lengths }
+    }
+
+    /// Total length of the spline
+    pub fn length(&self) -> f32 {
+        self.lengths.last().cloned().unwrap_or(0.0)
+    }
+
+    /// Spline parameter at a distance along the spline, clamped to its
+    /// ends
+    pub fn param(&self, distance: f32) -> f32 {
+        let last = self.lengths.len() - 1;
+        if last == 0 || distance <= 0.0 {
+            return 0.0;
+        }
+        if distance >= self.length() {
+            return 1.0;
+        }
+        // the first sample past `distance`
+        let next = match self
+            .lengths
+            .binary_search_by(|l| l.partial_cmp(&distance).unwrap())
+        {
+            Ok(i) => return i as f32 / last as f32,
+            Err(i) => i,
+        };
+        let (l0, l1) = (self.lengths[next - 1], self.lengths[next]);
+        let u = if l1 > l0 {
+            (distance - l0) / (l1 - l0)
+        } else {
+            0.0
+        };
+        (next as f32 - 1.0 + u) / last as f32
+    }
+
+    /// Spline parameter a fraction of the way along the spline's length
+    pub fn param_at_fraction(&self, fraction: f32) -> f32 {
+        self.param(fraction * self.length())
+    }
+}
+
+/// Timing curves, mapping linear progress in [0, 1] to eased progress
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Easing {
+    Linear,
+    /// Starts slowly and speeds up
+    EaseIn,
+    /// Starts quickly and slows down
+    EaseOut,
+    /// Speeds up, then slows down
+    EaseInOut,
+}
+
+impl Default for Easing {
+    fn default() -> Self {
+        Easing::Linear
+    }
+}
+
+impl Easing {
+    /// Eases `t`, clamped to [0, 1], with cubic curves
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.max(0.0).min(1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut if t < 0.5 => 4.0 * t * t * t,
+            Easing::EaseInOut => 1.0 - (2.0 - 2.0 * t).powi(3) / 2.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn near(a: Vec3, b: Vec3) -> bool {
+        (a - b).magnitude() < 1e-4
+    }
+
+    #[test]
+    fn test_bezier() {
+        let points = [
+            vec3(0.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+            vec3(1.0, 1.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+        ];
+        let spline = Spline::bezier(&points).unwrap();
+        assert!(near(spline.sample(0.0), points[0]));
+        assert!(near(spline.sample(0.5), vec3(0.5, 0.75, 0.0)));
+        assert!(near(spline.sample(2.0), points[3]));
+        assert!(Spline::bezier(&points[..3]).is_none());
+        assert!(near(
+            Spline::bezier(&points[..1]).unwrap().sample(0.5),
+            points[0]
+        ));
+    }
+
+    #[test]
+    fn test_catmull_rom_passes_through_points() {
+        let points = [
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 2.0, 0.0),
+            vec3(3.0, 2.0, 1.0),
+            vec3(4.0, 0.0, 1.0),
+        ];
+        let spline = Spline::catmull_rom(&points).unwrap();
+        assert_eq!(spline.segment_count(), 3);
+        for (i, &point) in points.iter().enumerate() {
+            assert!(near(spline.sample(i as f32 / 3.0), point));
+        }
+        // evenly spaced points on a line are travelled at an even speed
+        let line = [Vec3::zero(), Vec3::unit_x(), vec3(2.0, 0.0, 0.0)];
+        let spline = Spline::catmull_rom(&line).unwrap();
+        assert!(near(spline.sample(0.75), vec3(1.5, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_hermite() {
+        let spline = Spline::hermite(
+            &[Vec3::zero(), Vec3::unit_x()],
+            &[Vec3::unit_y(), Vec3::unit_y()],
+        )
+        .unwrap();
+        assert!(near(spline.sample(0.5), vec3(0.5, 0.0, 0.0)));
+        let early = spline.sample(0.1);
+        assert!(early.y > 0.0 && early.x < 0.1);
+        assert!(Spline::hermite(&[Vec3::zero()], &[]).is_none());
+    }
+
+    #[test]
+    fn test_rotation_splines() {
+        let angles = [0.0, 90.0, 180.0];
+        let points: Vec<Quaternion<f32>> = angles
+            .iter()
+            .map(|&a| Quaternion::from_angle_y(Deg(a)))
+            .collect();
+        let spline = Spline::catmull_rom(&points).unwrap();
+        // turning at an even rate about one axis stays on the axis
+        let half = spline.sample(0.25);
+        let expected = Quaternion::from_angle_y(Deg(45.0));
+        assert!(half.dot(expected).abs() > 1.0 - 1e-5, "{:?}", half);
+        assert!(spline.sample(0.5).dot(points[1]).abs() > 1.0 - 1e-5);
+
+        let arc = spline.arc_length();
+        assert!((arc.length() - std::f32::consts::PI).abs() < 1e-3);
+        assert!(
+            rotation_exp(rotation_log(expected)).dot(expected) > 1.0 - 1e-6
+        );
+    }
+
+    #[test]
+    fn test_arc_length() {
+        // control points bunched at the start make the parameter speed up
+        let points = [
+            Vec3::zero(),
+            Vec3::zero(),
+            Vec3::zero(),
+            vec3(4.0, 0.0, 0.0),
+        ];
+        let spline = Spline::bezier(&points).unwrap();
+        let arc = ArcLength::new(&spline, 64);
+        assert!((arc.length() - 4.0).abs() < 1e-3);
+        assert_eq!(arc.param(-1.0), 0.0);
+        assert_eq!(arc.param(10.0), 1.0);
+        let half = spline.sample(arc.param_at_fraction(0.5));
+        assert!((half.x - 2.0).abs() < 1e-2, "{:?}", half);
+    }
+
+    #[test]
+    fn test_easing() {
+        for &easing in &[
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.apply(-1.0), 0.0);
+            assert_eq!(easing.apply(2.0), 1.0);
+        }
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+        assert!(Easing::EaseIn.apply(0.5) < 0.5);
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+    }
+}
@@ -0,0 +1,260 @@
+//! Triangles, for tests against mesh geometry.
+
+use super::{closest_points_between_segments, Aabb, Mat4, Plane, Sphere, Vec3};
+use cgmath::*;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Triangle {
+    pub a: Point3<f32>,
+    pub b: Point3<f32>,
+    pub c: Point3<f32>,
+}
+
+impl Triangle {
+    pub fn new(a: Point3<f32>, b: Point3<f32>, c: Point3<f32>) -> Self {
+        Triangle { a, b, c }
+    }
+
+    /// The unnormalized normal, facing the side the vertices wind
+    /// counter-clockwise around. Its length is twice the area.
+    pub fn cross(&self) -> Vec3 {
+        (self.b - self.a).cross(self.c - self.a)
+    }
+
+    /// The unit normal, or zero for a degenerate triangle
+    pub fn normal(&self) -> Vec3 {
+        let cross = self.cross();
+        let len = cross.magnitude();
+        if len > 0.0 {
+            cross / len
+        } else {
+            Vec3::zero()
+        }
+    }
+
+    pub fn area(&self) -> f32 {
+        self.cross().magnitude() * 0.5
+    }
+
+    pub fn centroid(&self) -> Point3<f32> {
+        Point3::centroid(&[self.a, self.b, self.c])
+    }
+
+    pub fn plane(&self) -> Plane {
+        Plane::from_points(self.a, self.b, self.c)
+    }
+
+    pub fn transform(&self, matrix: &Mat4) -> Triangle {
+        Triangle::new(
+            matrix.transform_point(self.a),
+            matrix.transform_point(self.b),
+            matrix.transform_point(self.c),
+        )
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(self.a, self.a).include(self.b).include(self.c)
+    }
+
+    /// The point with the given weights of each vertex
+    pub fn point_at(&self, barycentrics: Vec3) -> Point3<f32> {
+        Point3::from_vec(
+            self.a.to_vec() * barycentrics.x
+                + self.b.to_vec() * barycentrics.y
+                + self.c.to_vec() * barycentrics.z,
+        )
+    }
+
+    /// Weights of each vertex for a point's projection onto the
+    /// triangle's plane. All are in `0..=1` for points inside.
+    pub fn barycentrics(&self, point: Point3<f32>) -> Vec3 {
+        let (ab, ac, ap) = (self.b - self.a, self.c - self.a, point - self.a);
+        let (d00, d01, d11) = (ab.dot(ab), ab.dot(ac), ac.dot(ac));
+        let (d20, d21) = (ap.dot(ab), ap.dot(ac));
+        let denom = d00 * d11 - d01 * d01;
+        if denom.abs() < std::f32::EPSILON {
+            return vec3(1.0, 0.0, 0.0);
+        }
+        let v = (d11 * d20 - d01 * d21) / denom;
+        let w = (d00 * d21 - d01 * d20) / denom;
+        vec3(1.0 - v - w, v, w)
+    }
+
+    /// The point on the triangle closest to `point`, found by the
+    /// Voronoi region of the triangle it lies in
+    pub fn closest_point(&self, point: Point3<f32>) -> Point3<f32> {
+        let (a, b, c) = (self.a, self.b, self.c);
+        let (ab, ac, ap) = (b - a, c - a, point - a);
+        let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return a;
+        }
+        let bp = point - b;
+        let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+        if d3 >= 0.0 && d4 <= d3 {
+            return b;
+        }
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            return a + ab * (d1 / (d1 - d3));
+        }
+        let cp = point - c;
+        let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+        if d6 >= 0.0 && d5 <= d6 {
+            return c;
+        }
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            return a + ac * (d2 / (d2 - d6));
+        }
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+            return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+        }
+        let denom = 1.0 / (va + vb + vc);
+        a + ab * (vb * denom) + ac * (vc * denom)
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.closest_point(sphere.center).distance2(sphere.center)
+            <= sphere.radius * sphere.radius
+    }
+
+    /// The closest pair of points on the segment `pq` and the triangle,
+    /// in that order. They're equal if the segment passes through it.
+    pub fn closest_points_to_segment(
+        &self,
+        p: Point3<f32>,
+        q: Point3<f32>,
+    ) -> (Point3<f32>, Point3<f32>) {
+        let normal = self.cross();
+        let (dp, dq) = ((p - self.a).dot(normal), (q - self.a).dot(normal));
+        if dp * dq < 0.0 {
+            let crossing = p + (q - p) * (dp / (dp - dq));
+            let weights = self.barycentrics(crossing);
+            if weights.x >= 0.0 && weights.y >= 0.0 && weights.z >= 0.0 {
+                return (crossing, crossing);
+            }
+        }
+        // otherwise the closest points involve one of the segment's ends
+        // or one of the triangle's edges
+        let edges = [(self.a, self.b), (self.b, self.c), (self.c, self.a)];
+        let candidates =
+            [(p, self.closest_point(p)), (q, self.closest_point(q))]
+                .iter()
+                .cloned()
+                .chain(
+                    edges.iter().map(|&(a, b)| {
+                        closest_points_between_segments(p, q, a, b)
+                    }),
+                )
+                .collect::<Vec<_>>();
+        candidates
+            .into_iter()
+            .min_by(|x, y| {
+                x.0.distance2(x.1).partial_cmp(&y.0.distance2(y.1)).unwrap()
+            })
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn triangle() -> Triangle {
+        Triangle::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 0.0),
+            Point3::new(0.0, 2.0, 0.0),
+        )
+    }
+
+    fn near(a: Point3<f32>, b: Point3<f32>) -> bool {
+        a.distance(b) < 1e-5
+    }
+
+    #[test]
+    fn test_triangle_properties() {
+        let tri = triangle();
+        assert_eq!(tri.normal(), Vec3::unit_z());
+        assert_eq!(tri.area(), 2.0);
+        assert!(near(tri.centroid(), Point3::new(2.0 / 3.0, 2.0 / 3.0, 0.0)));
+        assert_eq!(tri.plane().distance(Point3::new(5.0, 5.0, 3.0)), 3.0);
+        assert_eq!(
+            tri.aabb(),
+            Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(2.0, 2.0, 0.0))
+        );
+        let degenerate =
+            Triangle::new(tri.a, tri.b, Point3::new(1.0, 0.0, 0.0));
+        assert_eq!(degenerate.normal(), Vec3::zero());
+
+        let moved = tri.transform(&Mat4::from_angle_x(Deg(90.0)));
+        assert!((moved.normal() - -Vec3::unit_y()).magnitude() < 1e-6);
+        assert!((moved.area() - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_triangle_barycentrics() {
+        let tri = triangle();
+        let point = Point3::new(0.5, 1.0, 4.0);
+        let weights = tri.barycentrics(point);
+        assert!((weights - vec3(0.25, 0.25, 0.5)).magnitude() < 1e-6);
+        // reconstructs the projection onto the plane
+        assert!(near(tri.point_at(weights), Point3::new(0.5, 1.0, 0.0)));
+        assert!(near(tri.point_at(vec3(0.0, 1.0, 0.0)), tri.b));
+    }
+
+    #[test]
+    fn test_triangle_closest_point() {
+        let tri = triangle();
+        let cases = [
+            // above the face
+            ((0.5, 0.5, 3.0), (0.5, 0.5, 0.0)),
+            // vertex regions
+            ((-1.0, -1.0, 0.0), (0.0, 0.0, 0.0)),
+            ((3.0, -1.0, 1.0), (2.0, 0.0, 0.0)),
+            ((-1.0, 3.0, 0.0), (0.0, 2.0, 0.0)),
+            // edge regions
+            ((1.0, -2.0, 0.0), (1.0, 0.0, 0.0)),
+            ((-2.0, 1.0, 0.0), (0.0, 1.0, 0.0)),
+            ((2.0, 2.0, 0.0), (1.0, 1.0, 0.0)),
+        ];
+        for &((px, py, pz), (ex, ey, ez)) in cases.iter() {
+            let closest = tri.closest_point(Point3::new(px, py, pz));
+            assert!(
+                near(closest, Point3::new(ex, ey, ez)),
+                "closest to {:?} was {:?}",
+                (px, py, pz),
+                closest
+            );
+        }
+        assert!(tri
+            .intersects_sphere(&Sphere::new(Point3::new(1.0, 1.0, 0.5), 0.6)));
+        assert!(!tri
+            .intersects_sphere(&Sphere::new(Point3::new(2.0, 2.0, 0.0), 1.0)));
+    }
+
+    #[test]
+    fn test_triangle_segment_closest_points() {
+        let tri = triangle();
+        let p = Point3::new;
+        // through the face
+        let (s, t) =
+            tri.closest_points_to_segment(p(0.5, 0.5, -1.0), p(0.5, 0.5, 1.0));
+        assert!(near(s, p(0.5, 0.5, 0.0)) && near(t, s));
+        // hovering over the face, the lower end is closest
+        let (s, t) =
+            tri.closest_points_to_segment(p(0.5, 0.5, 2.0), p(0.6, 0.5, 1.0));
+        assert!(near(s, p(0.6, 0.5, 1.0)) && near(t, p(0.6, 0.5, 0.0)));
+        // crossing past an edge, above the plane
+        let (s, t) =
+            tri.closest_points_to_segment(p(1.0, -1.0, 1.0), p(1.0, 3.0, 1.0));
+        assert!((s.distance(t) - 1.0).abs() < 1e-5);
+        assert!(t.z.abs() < 1e-5 && t.x + t.y <= 2.0 + 1e-5);
+        // beside the triangle, parallel to its hypotenuse
+        let (s, t) =
+            tri.closest_points_to_segment(p(3.0, 0.0, 0.0), p(0.0, 3.0, 0.0));
+        assert!((s.distance(t) - 0.5f32.sqrt()).abs() < 1e-5);
+    }
+}
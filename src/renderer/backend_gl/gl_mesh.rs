@@ -1,16 +1,22 @@
 use super::objects::*;
+use crate::math::Bounds;
 use crate::renderer::*;
 #[derive(Debug)]
 pub struct GlMesh {
     pub mesh: Mesh,
     pub buffers: MeshBuffers,
+    pub bounds: Bounds,
 }
 
 impl GlMesh {
     pub fn with_mesh(mesh: Mesh) -> Result<Self, failure::Error> {
         let mut buffers = MeshBuffers::new()?;
         buffers.bind_mesh(&mesh)?;
-        Ok(GlMesh { mesh, buffers })
+        Ok(GlMesh {
+            bounds: mesh.bounds(),
+            mesh,
+            buffers,
+        })
     }
 }
 
@@ -18,4 +24,7 @@ impl RenderMesh for GlMesh {
     fn mesh(&self) -> &Mesh {
         &self.mesh
     }
+    fn bounds(&self) -> Bounds {
+        self.bounds
+    }
 }
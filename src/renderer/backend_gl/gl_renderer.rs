@@ -36,7 +36,15 @@ pub struct GlRenderer {
     envmap_program: PbrProgram,
     sample_mesh: GlMesh,
     env_cube: GlMesh,
+    /// Vertices posed on the cpu for a single draw, kept apart from the
+    /// buffers of the mesh resources other entities share
+    posed_buffers: MeshBuffers,
+    /// Joint palette of entities without a skin, which leaves any joint
+    /// weights of their meshes unposed
+    identity_palette: Vec<Matrix4<f32>>,
     pub materials: Materials,
+    /// Drawable size of the window, in pixels
+    framebuffer_size: Cell<(u32, u32)>,
 
     recompile_flag: Cell<Option<Instant>>,
 }
@@ -72,11 +80,19 @@ impl GlMesh {
             .collect();
 
         let indices: Vec<u32> = (0..vertices.len() as u32).collect();
-        let mesh = Mesh { vertices, indices };
+        let mesh = Mesh {
+            vertices,
+            indices,
+            morph_targets: Vec::new(),
+        };
         let buffers = MeshBuffers::new()?;
         buffers.bind_mesh(&mesh)?;
 
-        Ok(GlMesh { mesh, buffers })
+        Ok(GlMesh {
+            bounds: mesh.bounds(),
+            mesh,
+            buffers,
+        })
     }
 }
 
@@ -111,6 +127,7 @@ impl GlRenderer {
                     .flat_map(|tri| vec![tri.x, tri.y, tri.z])
                     .map(|i| i as u32)
                     .collect(),
+                morph_targets: Vec::new(),
             };
 
             GlMesh::with_mesh(mesh).map_err(|e| RendererError::Lifecycle {
@@ -129,13 +146,21 @@ impl GlRenderer {
                 reason: "could create skybox mesh".to_owned(),
             })?;
 
+        let posed_buffers =
+            MeshBuffers::new().map_err(|e| RendererError::Lifecycle {
+                reason: format!("could not create posed mesh buffers: {:?}", e),
+            })?;
+
         let mut renderer = GlRenderer {
             scene_program,
             envmap_program,
             env_cube,
             sample_mesh: mesh,
+            posed_buffers,
+            identity_palette: vec![Matrix4::identity(); MAX_GPU_JOINTS],
             materials,
             camera: RefCell::new(Camera::new(perspective)),
+            framebuffer_size: Cell::new((width, height)),
             recompile_flag: Cell::new(None),
         };
 
@@ -229,16 +254,95 @@ impl GlRenderer {
         &self.scene_program
     }
 
-    fn draw_entities<CS: TryGetComponent>(&self, scene: &game::EntityWorld<Self, CS>) {
+    /// Sets the viewport and scissor rect for a camera, clears it, and
+    /// binds the camera's projection
+    fn begin_view(&self, view: &RenderView) {
+        let (_, height) = self.framebuffer_size.get();
+        let rect = view.viewport.to_pixels(self.framebuffer_size.get());
+        // gl's window coordinates start at the bottom left
+        let y = height as i32 - rect.y - rect.height as i32;
+        unsafe {
+            gl::Viewport(rect.x, y, rect.width as i32, rect.height as i32);
+            gl::Scissor(rect.x, y, rect.width as i32, rect.height as i32);
+            let mut mask = 0;
+            if let Some(color) = view.clear.color {
+                gl::ClearColor(color.r, color.g, color.b, color.a);
+                mask |= gl::COLOR_BUFFER_BIT;
+            }
+            if view.clear.depth {
+                mask |= gl::DEPTH_BUFFER_BIT;
+            }
+            if mask != 0 {
+                gl::Clear(mask);
+            }
+        }
+        for program in &[&self.scene_program, &self.envmap_program] {
+            program.use_program();
+            program
+                .bind_uniform(program.uniforms().projection, &view.projection);
+        }
+    }
+
+    fn draw_skybox(&self, cam_view: &Matrix4<f32>) {
+        use std::ptr;
+        let GlMesh {
+            ref buffers,
+            ref mesh,
+            ..
+        } = self.env_cube;
+
+        let uniforms = self.envmap_program.uniforms();
+        let program = &self.envmap_program;
+        program.use_program();
+        program.bind_uniform(uniforms.modelview, cam_view);
+        unsafe {
+            gl::Disable(gl::CULL_FACE);
+            gl::DepthFunc(gl::LEQUAL);
+            gl::BindVertexArray(buffers.vertex_array.id());
+            gl::DrawElements(
+                gl::TRIANGLES,
+                mesh.indices.len() as i32,
+                gl::UNSIGNED_INT,
+                ptr::null(),
+            );
+            gl::DepthFunc(gl::LESS);
+        }
+    }
+
+    fn bind_material<CS: TryGetComponent>(
+        &self,
+        scene: &game::EntityWorld<Self, CS>,
+        material: &material::Material<game::resource::TextureHandle>,
+    ) {
+        let material = material.transform_textures(|handle, _| {
+            scene.resources.textures.get(handle).cloned()
+        });
+        if let Err(e) = self.materials.base_material_ubo.set_material(&material)
+        {
+            error!("could not set material: {:?}", e);
+        }
+        self.scene_program.bind_material_textures(&material);
+    }
+
+    /// Draws the mesh entities visible to a view
+    fn draw_entities<CS: TryGetComponent>(
+        &self,
+        scene: &game::EntityWorld<Self, CS>,
+        view: &RenderView,
+    ) {
+        use crate::game::{
+            animation::{morph_weights, MorphWeights, SkinComponent},
+            built_in_components::*,
+        };
         use crate::math::*;
         use std::ptr;
         let program = &self.scene_program;
         let uniforms = program.uniforms();
+        let cam_view = &view.view;
 
         program.use_program();
         unsafe { gl::Enable(gl::CULL_FACE) };
 
-        let cam_view = scene.main_camera.transform();
         let light_positions: &[Vec3] = &[
             vec3(10.0, 10.0, 10.0),
             vec3(10.0, -10.0, 10.0),
@@ -257,6 +361,115 @@ impl GlRenderer {
                 gl::Uniform3fv(id as _, 4, light_pos_ptr as *const _);
             })
             .unwrap_or(());
+
+        let components = &scene.components;
+        let (meshes, transforms) = match (
+            components.get_components::<MeshComponent>(),
+            components.get_components::<TransformComponent>(),
+        ) {
+            (Some(meshes), Some(transforms)) => (meshes, transforms),
+            _ => return,
+        };
+        let materials = components.get_components::<MaterialComponent>();
+        let skins = components.get_components::<SkinComponent>();
+        let morphs = components.get_components::<MorphWeights>();
+        let meshes = meshes.read().expect("poisoned RwLock");
+        let transforms = transforms.read().expect("poisoned RwLock");
+        let materials =
+            materials.as_ref().map(|m| m.read().expect("poisoned RwLock"));
+        let skins = skins.as_ref().map(|s| s.read().expect("poisoned RwLock"));
+        let morphs =
+            morphs.as_ref().map(|m| m.read().expect("poisoned RwLock"));
+
+        for &entity in &view.visible {
+            let GlMesh {
+                ref buffers,
+                ref mesh,
+                ..
+            } = match meshes[*entity]
+                .as_ref()
+                .and_then(|m| scene.resources.meshes.get(&m.mesh))
+            {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            let model = world_matrix(&transforms, entity)
+                .unwrap_or_else(Matrix4::identity);
+            let modelview = cam_view * model;
+            let normal_matrix = modelview
+                .invert()
+                .map(|m| m.transpose())
+                .unwrap_or(modelview);
+            program.bind_uniform(uniforms.modelview, &modelview);
+            program.bind_uniform(uniforms.normal_matrix, &normal_matrix);
+
+            // gl 4.1 can't read morph targets from buffers in the vertex
+            // shader, so they're blended on the cpu. The blended vertices
+            // are streamed in place of the mesh's own, so meshes with
+            // targets are uploaded even when their weights are all zero.
+            let skin = skins.as_ref().and_then(|s| s[*entity].as_ref());
+            let morphed = if mesh.morph_targets.is_empty() {
+                None
+            } else {
+                let weights = morphs
+                    .as_ref()
+                    .and_then(|m| morph_weights(m, &transforms, entity))
+                    .unwrap_or(&[]);
+                Some(mesh.morphed(weights))
+            };
+            let posed = match skin {
+                Some(skin) if !skin.needs_cpu_skinning() => {
+                    program
+                        .bind_uniform(uniforms.joint_matrices, &skin.palette);
+                    morphed
+                }
+                // skinned vertices have no weights left for the shader
+                Some(skin) => Some(
+                    morphed.as_ref().unwrap_or(mesh).skinned(&skin.palette),
+                ),
+                None => {
+                    program.bind_uniform(
+                        uniforms.joint_matrices,
+                        &self.identity_palette,
+                    );
+                    morphed
+                }
+            };
+            let buffers = match posed {
+                Some(posed) => match self.posed_buffers.stream_mesh(&posed) {
+                    Ok(posed_buffers) => posed_buffers,
+                    Err(e) => {
+                        error!("could not upload posed mesh: {:?}", e);
+                        continue;
+                    }
+                },
+                None => buffers,
+            };
+
+            match materials.as_ref().and_then(|m| m[*entity].as_ref()) {
+                Some(component) => {
+                    self.bind_material(scene, &component.material)
+                }
+                None => {
+                    let material = &self.materials.default_material;
+                    if let Err(e) =
+                        self.materials.base_material_ubo.set_material(material)
+                    {
+                        error!("could not set material: {:?}", e);
+                    }
+                }
+            }
+            program.use_program();
+            unsafe {
+                gl::BindVertexArray(buffers.vertex_array.id());
+                gl::DrawElements(
+                    gl::TRIANGLES,
+                    mesh.indices.len() as i32,
+                    gl::UNSIGNED_INT,
+                    ptr::null(),
+                );
+            }
+        }
     }
 }
 
@@ -275,6 +488,19 @@ impl Renderer for GlRenderer {
         self.camera.borrow()
     }
 
+    fn create_mesh(&self, mesh: &Mesh) -> Result<GlMesh, failure::Error> {
+        GlMesh::with_mesh(mesh.clone())
+    }
+
+    fn create_texture(
+        &self,
+        image: &gltf::image::Data,
+    ) -> Result<ManagedTexture, failure::Error> {
+        let mut texture = GlTexture::new()?;
+        texture.load_from_image(image)?;
+        Ok(Arc::new(texture))
+    }
+
     fn set_clear_color(&mut self, color: ColorRGBA) {
         unsafe {
             gl::ClearColor(color.r, color.g, color.b, color.a);
@@ -283,6 +509,7 @@ impl Renderer for GlRenderer {
 
     fn on_resize(&self, size: (u32, u32)) {
         self.camera.borrow_mut().on_resize(size);
+        self.framebuffer_size.set(size);
         let (width, height) = size;
         self.scene_program.use_program();
         let projection = &self.camera.borrow().projection;
@@ -317,39 +544,22 @@ impl Renderer for GlRenderer {
         }
     }
 
-    fn render_scene<CS: TryGetComponent>(&self, scene: &game::EntityWorld<Self, CS>) {
-        use std::ptr;
-
-        use crate::math::*;
-        let _program = self.scene_program();
-        let cam_view = scene.main_camera.transform();
-
-        let _uniforms = &self.scene_program.uniforms();
-
-        {
-            let GlMesh {
-                ref buffers,
-                ref mesh,
-            } = self.env_cube;
-
-            let uniforms = self.envmap_program.uniforms();
-            let program = &self.envmap_program;
-            let modelview = cam_view;
-            program.use_program();
-            program.bind_uniform(uniforms.modelview, &modelview);
-            unsafe {
-                gl::Disable(gl::CULL_FACE);
-                gl::DepthFunc(gl::LEQUAL);
-                gl::BindVertexArray(buffers.vertex_array.id());
-                gl::DrawElements(
-                    gl::TRIANGLES,
-                    mesh.indices.len() as i32,
-                    gl::UNSIGNED_INT,
-                    ptr::null(),
-                );
-                gl::DepthFunc(gl::LESS);
-            }
+    /// Draws each active camera of the scene into its viewport
+    fn render_scene<CS: TryGetComponent>(
+        &self,
+        scene: &game::EntityWorld<Self, CS>,
+    ) {
+        let size = self.framebuffer_size.get();
+        let views = scene.render_views(size, self.projection());
+        unsafe { gl::Enable(gl::SCISSOR_TEST) };
+        for view in &views {
+            self.begin_view(view);
+            self.draw_skybox(&view.view);
+            self.draw_entities(scene, view);
+        }
+        unsafe {
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::Viewport(0, 0, size.0 as i32, size.1 as i32);
         }
-        self.draw_entities(scene);
     }
 }
@@ -185,6 +185,20 @@ impl MeshBuffers {
     }
 
     pub fn bind_mesh(&self, mesh: &Mesh) -> Result<&Self, GlErrors> {
+        self.upload_mesh(mesh, gl::STATIC_DRAW)
+    }
+
+    /// Uploads a mesh which is replaced every draw, such as one posed on
+    /// the cpu
+    pub fn stream_mesh(&self, mesh: &Mesh) -> Result<&Self, GlErrors> {
+        self.upload_mesh(mesh, gl::STREAM_DRAW)
+    }
+
+    fn upload_mesh(
+        &self,
+        mesh: &Mesh,
+        usage: gl::types::GLenum,
+    ) -> Result<&Self, GlErrors> {
         use crate::renderer::Vertex;
         use std::mem::size_of;
         drain_error_stack();
@@ -197,14 +211,14 @@ impl MeshBuffers {
                 gl::ARRAY_BUFFER,
                 mesh.verts_size() as isize,
                 mesh.vertices.as_ptr() as *const _,
-                gl::STATIC_DRAW,
+                usage,
             );
 
             gl::BufferData(
                 gl::ELEMENT_ARRAY_BUFFER,
                 mesh.indices_size() as isize,
                 mesh.indices.as_ptr() as *const _,
-                gl::STATIC_DRAW,
+                usage,
             );
 
             gl::VertexAttribPointer(
@@ -248,12 +262,29 @@ impl MeshBuffers {
                 size_of::<Vertex>() as i32,
                 offset_of!(Vertex, bitangent) as *const _,
             );
+            gl::VertexAttribIPointer(
+                5,
+                4,
+                gl::UNSIGNED_INT,
+                size_of::<Vertex>() as i32,
+                offset_of!(Vertex, joints) as *const _,
+            );
+            gl::VertexAttribPointer(
+                6,
+                4,
+                gl::FLOAT,
+                gl::FALSE,
+                size_of::<Vertex>() as i32,
+                offset_of!(Vertex, weights) as *const _,
+            );
 
             gl::EnableVertexAttribArray(0);
             gl::EnableVertexAttribArray(1);
             gl::EnableVertexAttribArray(2);
             gl::EnableVertexAttribArray(3);
             gl::EnableVertexAttribArray(4);
+            gl::EnableVertexAttribArray(5);
+            gl::EnableVertexAttribArray(6);
         }
 
         dump_errors()?;
@@ -64,6 +64,7 @@ pub struct PbrShaderUniforms {
     pub normal_map: Option<u32>,
     pub ao_map: Option<u32>,
     pub emissive_map: Option<u32>,
+    pub joint_matrices: Option<u32>,
     user_uniforms: HashMap<String, Option<u32>>,
 }
 
@@ -101,6 +102,7 @@ impl ShaderUniforms for PbrShaderUniforms {
                 (&mut self.normal_map, "normal_map"),
                 (&mut self.ao_map, "ao_map"),
                 (&mut self.emissive_map, "emissive_map"),
+                (&mut self.joint_matrices, "joint_matrices"),
             ];
             for (ref mut ptr, name) in uniforms {
                 **ptr = program.uniform_location(name).unwrap_or_else(|_e| {
@@ -148,6 +150,7 @@ impl Default for PbrShaderUniforms {
             normal_map: None,
             ao_map: None,
             emissive_map: None,
+            joint_matrices: None,
             user_uniforms: HashMap::new(),
         }
     }
@@ -336,6 +339,26 @@ where
     }
 }
 
+/// Binds an array of matrices, such as a skin's joint palette
+impl<U> BindUniform<Vec<Matrix4<f32>>> for Program<U>
+where
+    U: ShaderUniforms,
+{
+    type Id = Option<u32>;
+    fn bind_uniform(&self, id: Self::Id, val: &Vec<Matrix4<f32>>) {
+        if let (Some(id), Some(first)) = (id, val.first()) {
+            unsafe {
+                gl::UniformMatrix4fv(
+                    id as _,
+                    val.len() as i32,
+                    gl::FALSE,
+                    first.as_ptr(),
+                );
+            }
+        }
+    }
+}
+
 impl Default for ProgramBuilder {
     fn default() -> Self {
         ProgramBuilder {
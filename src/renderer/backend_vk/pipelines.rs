@@ -1,6 +1,8 @@
 use super::VkContextError;
 use super::*;
-use crate::renderer::Vertex as SlsVertex;
+use crate::renderer::{
+    Vertex as SlsVertex, MAX_GPU_JOINTS, MAX_GPU_MORPH_TARGETS,
+};
 use cgmath::*;
 use failure::{self, bail};
 use std::sync::Arc;
@@ -24,6 +26,20 @@ mod main_fs {
     }
 }
 
+mod clear_vs {
+    vulkano_shaders::shader! {
+    ty: "vertex",
+    path: "assets/shaders/vulkan/clear.vert",
+    }
+}
+
+pub mod clear_fs {
+    vulkano_shaders::shader! {
+    ty: "fragment",
+    path: "assets/shaders/vulkan/clear.frag",
+    }
+}
+
 unsafe impl vertex::Vertex for SlsVertex {
     fn member(name: &str) -> Option<vertex::VertexMemberInfo> {
         use self::vertex::{VertexMemberInfo, VertexMemberTy};
@@ -53,6 +69,16 @@ unsafe impl vertex::Vertex for SlsVertex {
                 ty: VertexMemberTy::F32,
                 array_size: 3,
             }),
+            "v_joints" => Some(VertexMemberInfo {
+                offset: offset_of!(SlsVertex, joints),
+                ty: VertexMemberTy::U32,
+                array_size: 4,
+            }),
+            "v_weights" => Some(VertexMemberInfo {
+                offset: offset_of!(SlsVertex, weights),
+                ty: VertexMemberTy::F32,
+                array_size: 4,
+            }),
             _ => None,
         }
     }
@@ -62,8 +88,57 @@ unsafe impl vertex::Vertex for SlsVertex {
 pub struct RendererPipelines {
     pub main_pipeline: DynGraphicsPipeline,
     pub matrix_ubo: CpuBufferPool<main_vs::ty::MatrixData>,
+    /// Joint palettes of skinned meshes
+    pub joint_ubo: CpuBufferPool<main_vs::ty::JointData>,
+    /// Morph target weights of blend-shaped meshes
+    pub morph_ubo: CpuBufferPool<main_vs::ty::MorphData>,
     pub matrix_desc_pool:
         RwLock<FixedSizeDescriptorSetsPool<DynGraphicsPipeline>>,
+    /// Clear a view's color and depth
+    pub clear_pipeline: DynGraphicsPipeline,
+    /// Clear a view's color, keeping its depth
+    pub clear_color_pipeline: DynGraphicsPipeline,
+    /// Clear a view's depth, keeping its color
+    pub clear_depth_pipeline: DynGraphicsPipeline,
+}
+
+/// Builds a pipeline drawing a triangle over the viewport, clipped to the
+/// scissor rect, which writes the pushed color and the far depth
+fn clear_pipeline(
+    device: &Arc<Device>,
+    subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    color: bool,
+    depth: bool,
+) -> Result<DynGraphicsPipeline, failure::Error> {
+    use vulkano::pipeline::blend::AttachmentBlend;
+    use vulkano::pipeline::depth_stencil::{Compare, DepthStencil};
+    use vulkano::pipeline::vertex::BufferlessDefinition;
+
+    let vs = clear_vs::Shader::load(device.clone())?;
+    let fs = clear_fs::Shader::load(device.clone())?;
+    let blend = AttachmentBlend {
+        mask_red: color,
+        mask_green: color,
+        mask_blue: color,
+        mask_alpha: color,
+        ..AttachmentBlend::pass_through()
+    };
+    let depth_stencil = DepthStencil {
+        depth_write: depth,
+        depth_compare: Compare::Always,
+        ..DepthStencil::disabled()
+    };
+    let pipeline = GraphicsPipeline::start()
+        .vertex_input(BufferlessDefinition)
+        .vertex_shader(vs.main_entry_point(), ())
+        .triangle_list()
+        .viewports_scissors_dynamic(1)
+        .fragment_shader(fs.main_entry_point(), ())
+        .blend_collective(blend)
+        .depth_stencil(depth_stencil)
+        .render_pass(subpass)
+        .build(device.clone())?;
+    Ok(Arc::new(pipeline))
 }
 
 impl RendererPipelines {
@@ -71,6 +146,8 @@ impl RendererPipelines {
         device: &Arc<Device>,
         render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
         matrix_ubo: CpuBufferPool<main_vs::ty::MatrixData>,
+        joint_ubo: CpuBufferPool<main_vs::ty::JointData>,
+        morph_ubo: CpuBufferPool<main_vs::ty::MorphData>,
     ) -> Result<Self, failure::Error> {
         let vs = main_vs::Shader::load(device.clone())?;
         let fs = main_fs::Shader::load(device.clone())?;
@@ -90,7 +167,7 @@ impl RendererPipelines {
                 .front_face_clockwise()
                 .cull_mode_back()
                 // .polygon_mode_line()
-                .render_pass(subpass)
+                .render_pass(subpass.clone())
                 .build(device.clone())
                 .map(&Arc::new)?;
 
@@ -99,10 +176,19 @@ impl RendererPipelines {
             0,
         ));
 
+        let clear = |color, depth| {
+            clear_pipeline(device, subpass.clone(), color, depth)
+        };
+
         Ok(RendererPipelines {
             main_pipeline,
             matrix_ubo,
+            joint_ubo,
+            morph_ubo,
             matrix_desc_pool,
+            clear_pipeline: clear(true, true)?,
+            clear_color_pipeline: clear(true, false)?,
+            clear_depth_pipeline: clear(false, true)?,
         })
     }
     pub fn new(
@@ -110,9 +196,16 @@ impl RendererPipelines {
         render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
     ) -> Result<Self, VkContextError> {
         let matrix_ubo = CpuBufferPool::new(device.clone(), BufferUsage::all());
-        Self::new_internal(device, render_pass, matrix_ubo).map_err(|e| {
-            VkContextError::component_creation("pipelines", Some(e))
-        })
+        let joint_ubo = CpuBufferPool::new(device.clone(), BufferUsage::all());
+        let morph_ubo = CpuBufferPool::new(device.clone(), BufferUsage::all());
+        Self::new_internal(
+            device,
+            render_pass,
+            matrix_ubo,
+            joint_ubo,
+            morph_ubo,
+        )
+        .map_err(|e| VkContextError::component_creation("pipelines", Some(e)))
     }
 }
 
@@ -173,3 +266,32 @@ impl From<MatrixUniformData> for main_vs::ty::MatrixData {
         }
     }
 }
+
+/// Joint palette uniform of a skinned mesh. Unused joints are left as
+/// identities; unskinned meshes can pass an empty palette.
+pub fn joint_data(palette: &[Matrix4<f32>]) -> main_vs::ty::JointData {
+    let identity: [[f32; 4]; 4] = Matrix4::identity().into();
+    let mut joints = [identity; MAX_GPU_JOINTS];
+    for (joint, matrix) in joints.iter_mut().zip(palette) {
+        *joint = (*matrix).into();
+    }
+    main_vs::ty::JointData { joints }
+}
+
+/// Morph weight uniform of a mesh with `targets` morph targets and
+/// `vertices` vertices. Targets without a weight are left out.
+pub fn morph_data(
+    weights: &[f32],
+    targets: usize,
+    vertices: usize,
+) -> main_vs::ty::MorphData {
+    let targets = targets.min(MAX_GPU_MORPH_TARGETS);
+    let mut packed = [[0.0; 4]; MAX_GPU_MORPH_TARGETS / 4];
+    for (i, &weight) in weights.iter().take(targets).enumerate() {
+        packed[i / 4][i % 4] = weight;
+    }
+    main_vs::ty::MorphData {
+        weights: packed,
+        counts: [targets as u32, vertices as u32, 0, 0],
+    }
+}
@@ -1,4 +1,5 @@
 use super::VulkanRenderer;
+use crate::math::Bounds;
 use crate::renderer::{Mesh, RenderMesh, Vertex};
 use failure as f;
 use std::sync::Arc;
@@ -12,8 +13,29 @@ use vulkano::{
 #[derive(Debug)]
 pub struct VkMesh {
     mesh: Mesh,
+    pub bounds: Bounds,
     pub vertex_buffer: Arc<DeviceLocalBuffer<[Vertex]>>,
     pub index_buffer: Arc<DeviceLocalBuffer<[u32]>>,
+    /// Morph target deltas read by the vertex shader
+    pub morph_buffer: Arc<DeviceLocalBuffer<[[f32; 4]]>>,
+}
+
+/// Lays out the morph targets of a mesh for the vertex shader: the
+/// position, normal and tangent deltas of each vertex, target by target.
+/// Meshes without targets get a single unused delta, as buffers can't be
+/// empty.
+fn morph_deltas(mesh: &Mesh) -> Vec<[f32; 4]> {
+    let n = mesh.vertices.len();
+    let mut deltas = vec![[0.0; 4]; (mesh.morph_targets.len() * n * 3).max(1)];
+    for (t, target) in mesh.morph_targets.iter().enumerate() {
+        let attributes = [&target.positions, &target.normals, &target.tangents];
+        for (a, attribute) in attributes.iter().enumerate() {
+            for (v, d) in attribute.iter().enumerate().take(n) {
+                deltas[(t * n + v) * 3 + a] = [d[0], d[1], d[2], 0.0];
+            }
+        }
+    }
+    deltas
 }
 
 impl VkMesh {
@@ -29,6 +51,13 @@ impl VkMesh {
             mesh.vertices.clone().into_iter(),
         )
         .map_err(&f::Error::from)?;
+        let deltas = morph_deltas(&mesh);
+        let staging_morphs = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::all(),
+            deltas.iter().cloned(),
+        )
+        .map_err(&f::Error::from)?;
         let staging_ibo: Arc<CpuAccessibleBuffer<[u32]>> =
             CpuAccessibleBuffer::from_iter(
                 device.clone(),
@@ -52,18 +81,32 @@ impl VkMesh {
         )
         .map_err(&f::Error::from)?;
 
+        let morphs = DeviceLocalBuffer::<[[f32; 4]]>::array(
+            device.clone(),
+            deltas.len(),
+            BufferUsage::all(),
+            Some(staging_queue.family()),
+        )
+        .map_err(&f::Error::from)?;
+
         let mut cbb = AutoCommandBufferBuilder::new(
             device.clone(),
             staging_queue.family(),
         )?;
         cbb = cbb.copy_buffer(staging_ibo, indices.clone())?;
         cbb = cbb.copy_buffer(staging_vbo, vertices.clone())?;
+        cbb = cbb.copy_buffer(staging_morphs, morphs.clone())?;
         let cb = cbb.build()?;
+        // the copy finishes in the background; frames drawn with the mesh
+        // wait for it on the GPU
         let fut = cb.execute(staging_queue.clone())?;
-        (fut.then_signal_fence_and_flush()?).wait(None).unwrap();
+        let upload = fut.then_signal_semaphore_and_flush()?;
+        renderer.push_upload(Box::new(upload));
         Ok(VkMesh {
+            bounds: mesh.bounds(),
             vertex_buffer: vertices,
             index_buffer: indices,
+            morph_buffer: morphs,
             mesh,
         })
     }
@@ -73,4 +116,7 @@ impl RenderMesh for VkMesh {
     fn mesh(&self) -> &Mesh {
         &self.mesh
     }
+    fn bounds(&self) -> Bounds {
+        self.bounds
+    }
 }
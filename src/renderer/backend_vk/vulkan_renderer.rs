@@ -1,6 +1,13 @@
 use super::*;
 use crate::{
-    game::{built_in_components::*, component::*, prelude::*, EntityWorld},
+    config::VSync,
+    game::{
+        animation::{morph_weights, MorphWeights, SkinComponent},
+        built_in_components::*,
+        component::*,
+        prelude::*,
+        EntityWorld,
+    },
     math::*,
     renderer::mesh::*,
     renderer::*,
@@ -24,6 +31,7 @@ use vulkano::{
 };
 
 use slsengine_entityalloc::IndexArray;
+use vulkano::pipeline::viewport::Viewport;
 
 struct Foo {
     f: FenceSignalFuture<Box<GpuFuture>>,
@@ -209,6 +217,24 @@ fn pick_surface_format(capabilities: &Capabilities) -> (Format, ColorSpace) {
         .unwrap_or(&capabilities.supported_formats[0])
 }
 
+/// Picks the present mode for a vsync setting, falling back to Fifo, which
+/// every device supports
+fn pick_present_mode(
+    capabilities: &Capabilities,
+    vsync: VSync,
+) -> PresentMode {
+    let preferred: &[PresentMode] = match vsync {
+        VSync::Off => &[PresentMode::Immediate, PresentMode::Mailbox],
+        VSync::On => &[],
+        VSync::Adaptive => &[PresentMode::Relaxed],
+    };
+    preferred
+        .iter()
+        .cloned()
+        .find(|&mode| capabilities.present_modes.supports(mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
 fn create_swapchain(
     _instance: &Arc<Instance>,
     surface: &Arc<Surface<VulkanWinType>>,
@@ -216,11 +242,13 @@ fn create_swapchain(
     device: &Arc<Device>,
     queues: &VulkanQueues,
     window: &Window,
+    vsync: VSync,
 ) -> Result<(Arc<SdlSwapchain>, Vec<Arc<SdlSwapchainImage>>), failure::Error> {
     use vulkano::swapchain::SurfaceTransform;
 
     let capabilities = surface.capabilities(*physical_device)?;
     let (format, _colorspace) = pick_surface_format(&capabilities);
+    let present_mode = pick_present_mode(&capabilities, vsync);
     let usage = capabilities.supported_usage_flags;
     let alpha = capabilities
         .supported_composite_alpha
@@ -249,7 +277,7 @@ fn create_swapchain(
         sharing,
         SurfaceTransform::Identity,
         alpha,
-        PresentMode::Fifo,
+        present_mode,
         true,
         None,
     )
@@ -261,6 +289,7 @@ struct Builder<'a> {
     // Resources used in context build steps. Not builder params per-se
     // Instead, more like a traditional nullable+mutable initialization pattern
     window: &'a Window,
+    vsync: VSync,
     instance: Option<Arc<Instance>>,
     device: Option<Arc<Device>>,
     queues: Option<VulkanQueues>,
@@ -273,9 +302,10 @@ struct Builder<'a> {
 }
 
 impl<'a> Builder<'a> {
-    fn new(window: &'a Window) -> Self {
+    fn new(window: &'a Window, vsync: VSync) -> Self {
         Builder {
             window,
+            vsync,
             instance: None,
             device: None,
             queues: None,
@@ -342,6 +372,7 @@ impl<'a> Builder<'a> {
                 render_pass: self.render_pass.unwrap(),
                 recreate_swapchain: AtomicBool::new(false),
                 previous_frame_end,
+                pending_uploads: RefCell::new(Vec::new()),
 
                 state: RefCell::new(RenderingState {
                     swapchain: self.swapchain.unwrap(),
@@ -420,6 +451,7 @@ impl<'a> Builder<'a> {
             device,
             queues,
             window,
+            self.vsync,
         )
         .map_err(|e| {
             VkContextError::component_creation("swapchain", Some(e))
@@ -476,6 +508,9 @@ pub struct VulkanRenderer {
     /// or future for synchronizing last frame end
     recreate_swapchain: AtomicBool,
     previous_frame_end: RefCell<Option<FenceSignalFuture<Box<dyn GpuFuture>>>>,
+    /// Buffer copies submitted by uploads, which the next frame waits for
+    /// on the GPU rather than the upload blocking on them
+    pending_uploads: RefCell<Vec<Box<dyn GpuFuture>>>,
     /// lock for managing resources replaced during program's progress, such as flagging swapchain recreation
     pub state: RefCell<RenderingState>,
 }
@@ -496,7 +531,16 @@ impl fmt::Debug for VulkanRenderer {
 impl VulkanRenderer {
     /// Creates a new Renderer from an SDL window
     pub fn new(window: &Window) -> Result<Self, VkContextError> {
-        let builder = Builder::new(window);
+        VulkanRenderer::with_vsync(window, VSync::default())
+    }
+
+    /// Creates a new Renderer from an SDL window, presenting with the
+    /// given vsync setting
+    pub fn with_vsync(
+        window: &Window,
+        vsync: VSync,
+    ) -> Result<Self, VkContextError> {
+        let builder = Builder::new(window, vsync);
         let renderer = builder.build()?;
         renderer.window_size_fb_setup()?;
         Ok(renderer)
@@ -609,6 +653,9 @@ impl VulkanRenderer {
         world: &EntityWorld<Self, CS>,
         modelview: Mat4,
         projection: Mat4,
+        palette: &[Mat4],
+        morph: pipelines::main_vs::ty::MorphData,
+        morph_deltas: Arc<DeviceLocalBuffer<[[f32; 4]]>>,
     ) -> Result<Arc<impl DescriptorSet + Send + Sync>, failure::Error> {
         let ubo_subbuffer = {
             use cgmath::*;
@@ -616,10 +663,16 @@ impl VulkanRenderer {
                 .unwrap();
             self.pipelines.matrix_ubo.next(data.into())?
         };
+        let joint_subbuffer =
+            self.pipelines.joint_ubo.next(pipelines::joint_data(palette))?;
+        let morph_subbuffer = self.pipelines.morph_ubo.next(morph)?;
 
         if let Ok(mut pool) = self.pipelines.matrix_desc_pool.write() {
             pool.next()
                 .add_buffer(ubo_subbuffer)
+                .and_then(|pds| pds.add_buffer(joint_subbuffer))
+                .and_then(|pds| pds.add_buffer(morph_subbuffer))
+                .and_then(|pds| pds.add_buffer(morph_deltas))
                 .map_err(&failure::Error::from)
                 .and_then(|pds| pds.build().map_err(&failure::Error::from))
                 .map(|pds| Arc::new(pds))
@@ -628,18 +681,168 @@ impl VulkanRenderer {
         }
     }
 
+    /// Records a view's clear, drawn over its pixels with the scissor
+    /// limited to them
+    fn clear_rect(
+        &self,
+        cb: AutoCommandBufferBuilder,
+        clear: &ClearRect,
+    ) -> Result<AutoCommandBufferBuilder, failure::Error> {
+        let pipeline = match (clear.color.is_some(), clear.depth) {
+            (true, true) => &self.pipelines.clear_pipeline,
+            (true, false) => &self.pipelines.clear_color_pipeline,
+            (false, true) => &self.pipelines.clear_depth_pipeline,
+            (false, false) => return Ok(cb),
+        };
+        let rect = clear.rect;
+        if rect.width == 0 || rect.height == 0 {
+            return Ok(cb);
+        }
+        let dynamic_state = DynamicState {
+            viewports: Some(vec![Viewport {
+                origin: [rect.x as f32, rect.y as f32],
+                dimensions: [rect.width as f32, rect.height as f32],
+                depth_range: 0.0..1.0,
+            }]),
+            scissors: Some(vec![Scissor {
+                origin: [rect.x, rect.y],
+                dimensions: [rect.width, rect.height],
+            }]),
+            ..DynamicState::none()
+        };
+        let color = clear.color.unwrap_or_else(|| color4f(0.0, 0.0, 0.0, 0.0));
+        let clear_data = pipelines::clear_fs::ty::ClearData {
+            color: [color.r, color.g, color.b, color.a],
+        };
+        let vertices = vulkano::pipeline::vertex::BufferlessVertices {
+            vertices: 3,
+            instances: 1,
+        };
+        let cb = cb.draw(
+            pipeline.clone(),
+            &dynamic_state,
+            vertices,
+            (),
+            clear_data,
+        )?;
+        Ok(cb)
+    }
+
+    /// Records draw commands for the mesh entities visible to the view
+    fn draw_view<CS: crate::game::TryGetComponent>(
+        &self,
+        mut cb: AutoCommandBufferBuilder,
+        world: &EntityWorld<Self, CS>,
+        view: &RenderView,
+        framebuffer_size: (u32, u32),
+    ) -> Result<AutoCommandBufferBuilder, failure::Error> {
+        let rect = view.viewport.to_pixels(framebuffer_size);
+        let dynamic_state = DynamicState {
+            viewports: Some(vec![Viewport {
+                origin: [rect.x as f32, rect.y as f32],
+                dimensions: [rect.width as f32, rect.height as f32],
+                depth_range: 0.0..1.0,
+            }]),
+            ..DynamicState::none()
+        };
+        let (meshes, transforms) = match (
+            world.components.get_components::<MeshComponent>(),
+            world.components.get_components::<TransformComponent>(),
+        ) {
+            (Some(meshes), Some(transforms)) => (meshes, transforms),
+            _ => return Ok(cb),
+        };
+        let skins = world.components.get_components::<SkinComponent>();
+        let morphs = world.components.get_components::<MorphWeights>();
+        let meshes = meshes.read().expect("poisoned RwLock");
+        let transforms = transforms.read().expect("poisoned RwLock");
+        let skins = skins.as_ref().map(|s| s.read().expect("poisoned RwLock"));
+        let morphs =
+            morphs.as_ref().map(|m| m.read().expect("poisoned RwLock"));
+        for &entity in &view.visible {
+            let mesh = match meshes[*entity]
+                .as_ref()
+                .and_then(|m| world.resources.meshes.get(&m.mesh))
+            {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            let model = match world_matrix(&transforms, entity) {
+                Some(model) => model,
+                None => continue,
+            };
+            let skin = skins.as_ref().and_then(|s| s[*entity].as_ref());
+            let weights = morphs
+                .as_ref()
+                .and_then(|m| morph_weights(m, &transforms, entity))
+                .unwrap_or(&[]);
+            let targets = mesh.mesh().morph_targets.len();
+            let cpu_skinning = skin.map_or(false, |s| s.needs_cpu_skinning());
+            let (palette, morph, vertex_buffer): (
+                &[Mat4],
+                _,
+                Arc<dyn BufferAccess + Send + Sync>,
+            ) = if cpu_skinning || targets > MAX_GPU_MORPH_TARGETS {
+                // poses the mesh on the cpu, leaving no morph targets or
+                // joint weights for the shader to apply. Morph targets
+                // are blended before skinning, so they move to the cpu
+                // along with the skin.
+                let mut posed = mesh.mesh().morphed(weights);
+                if let Some(skin) = skin.filter(|_| cpu_skinning) {
+                    posed = posed.skinned(&skin.palette);
+                }
+                let buffer = CpuAccessibleBuffer::from_iter(
+                    self.device.clone(),
+                    BufferUsage::vertex_buffer(),
+                    posed.vertices.into_iter(),
+                )?;
+                let palette = match skin {
+                    Some(skin) if !cpu_skinning => skin.palette.as_slice(),
+                    _ => &[],
+                };
+                (palette, pipelines::morph_data(&[], 0, 0), buffer)
+            } else {
+                let palette = skin.map_or(&[][..], |s| s.palette.as_slice());
+                let vertices = mesh.mesh().vertices.len();
+                let morph = pipelines::morph_data(weights, targets, vertices);
+                (palette, morph, mesh.vertex_buffer.clone())
+            };
+            let descriptor_set = self.create_transform_descriptorset(
+                world,
+                view.view * model,
+                view.projection,
+                palette,
+                morph,
+                mesh.morph_buffer.clone(),
+            )?;
+            let vertices: Vec<Arc<dyn BufferAccess + Send + Sync>> =
+                vec![vertex_buffer];
+            cb = cb.draw_indexed(
+                self.pipelines.main_pipeline.clone(),
+                &dynamic_state,
+                vertices,
+                mesh.index_buffer.clone(),
+                descriptor_set,
+                (),
+            )?;
+        }
+        Ok(cb)
+    }
+
+    /// Queues an upload's submitted copy for the next frame to wait for
+    pub(crate) fn push_upload(&self, upload: Box<dyn GpuFuture>) {
+        self.pending_uploads.borrow_mut().push(upload);
+    }
+
     pub fn draw_frame<CS>(&self, window: &Window, world: &EntityWorld<Self, CS>)
     where
         CS: crate::game::TryGetComponent,
     {
-        use crate::game::resource::MeshHandle;
-
         let mut recreate_swapchain = match self.check_swapchain_validity(window)
         {
             Ok(t) => t,
             Err(_) => return,
         };
-        let camera_view = world.main_camera.transform();
         {
             let mut prev_frame = self.previous_frame_end.replace(None);
             if let Some(mut fence_fut) = prev_frame {
@@ -662,17 +865,27 @@ impl VulkanRenderer {
                     Err(e) => panic!("unexpected error: {:?}", e),
                 };
 
+            let dimensions =
+                SdlSwapchainImage::dimensions(&state.swapchain_images[0]);
+            let framebuffer_size = (dimensions[0], dimensions[1]);
+            let views =
+                world.render_views(framebuffer_size, self.camera().projection);
+
+            // vulkano can't clear part of an attachment, so the render pass
+            // clears the whole framebuffer and views clear their own pixels
+            // by drawing over them
+            let clears = FrameClears::new(
+                &views,
+                framebuffer_size,
+                color4f(0.0, 0.0, 1.0, 1.0),
+            );
+            let clear_color = clears.color;
             let clear_values = vec![
-                [0.0, 0.0, 1.0, 1.0].into(), // color buffer
-                1f32.into(),                 // depth buffer
+                [clear_color.r, clear_color.g, clear_color.b, clear_color.a]
+                    .into(), // color buffer
+                1f32.into(), // depth buffer
             ];
 
-            let VkMesh {
-                ref vertex_buffer,
-                ref index_buffer,
-                ..
-            } = &world.resources.meshes[&MeshHandle(0)];
-
             let mut cb_builder: Result<
                 AutoCommandBufferBuilder,
                 failure::Error,
@@ -690,6 +903,15 @@ impl VulkanRenderer {
                 .map_err(&failure::Error::from)
             });
 
+            for (view, clear) in views.iter().zip(&clears.views) {
+                if let Some(clear) = clear {
+                    cb_builder =
+                        cb_builder.and_then(|cb| self.clear_rect(cb, clear));
+                }
+                cb_builder = cb_builder.and_then(|cb| {
+                    self.draw_view(cb, world, view, framebuffer_size)
+                });
+            }
             cb_builder = cb_builder.and_then(|cb| {
                 cb.end_render_pass().map_err(&failure::Error::from)
             });
@@ -699,8 +921,13 @@ impl VulkanRenderer {
                     panic!("could not create command buffer: {:?}", e)
                 });
 
+            // the frame's commands run after any uploads still copying
+            let mut before: Box<dyn GpuFuture> = Box::new(acquire_future);
+            for upload in self.pending_uploads.borrow_mut().drain(..) {
+                before = Box::new(before.join(upload));
+            }
             let future: Box<dyn GpuFuture> = Box::new(
-                acquire_future
+                before
                     .then_execute(
                         self.queues.graphics_queue.clone(),
                         command_buffer,
@@ -741,7 +968,33 @@ impl Renderer for VulkanRenderer {
         self.camera.borrow()
     }
 
+    fn create_mesh(&self, mesh: &Mesh) -> Result<VkMesh, failure::Error> {
+        VkMesh::new(self, mesh.clone())
+    }
+
+    /// Vulkan textures are not yet implemented, so images are not uploaded
+    fn create_texture(
+        &self,
+        _image: &gltf::image::Data,
+    ) -> Result<VkTexture, failure::Error> {
+        Ok(VkTexture)
+    }
+
     fn on_resize(&self, _size: (u32, u32)) {
         self.recreate_swapchain.store(true, Ordering::Relaxed);
     }
+
+    /// Polls the uploads still copying, freeing the staging buffers of
+    /// the finished ones
+    fn on_update<CS>(
+        &mut self,
+        _delta_time: std::time::Duration,
+        _world: &EntityWorld<Self, CS>,
+    ) where
+        CS: crate::game::TryGetComponent,
+    {
+        for upload in self.pending_uploads.get_mut().iter_mut() {
+            upload.cleanup_finished();
+        }
+    }
 }
@@ -1,4 +1,6 @@
+use super::color::ColorRGBA;
 use super::traits::*;
+use crate::game::component::Entity;
 use cgmath::*;
 /*
  *  Camera
@@ -49,3 +51,340 @@ impl Resizable for Camera {
         self.build_perspective();
     }
 }
+
+/// A camera's projection. The aspect ratio is taken from the camera's
+/// viewport when the projection matrix is built.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    Perspective {
+        fovy: Rad<f32>,
+        near: f32,
+        far: f32,
+    },
+    /// Orthographic projection, `height` world units tall
+    Orthographic {
+        height: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Projection {
+    pub fn matrix(&self, aspect: f32) -> Matrix4<f32> {
+        match *self {
+            Projection::Perspective { fovy, near, far } => PerspectiveFov {
+                fovy,
+                aspect,
+                near,
+                far,
+            }
+            .into(),
+            Projection::Orthographic { height, near, far } => {
+                let half_h = height / 2.0;
+                let half_w = half_h * aspect;
+                ortho(-half_w, half_w, -half_h, half_h, near, far)
+            }
+        }
+    }
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        let perspective = default_perspective();
+        Projection::Perspective {
+            fovy: perspective.fovy,
+            near: perspective.near,
+            far: perspective.far,
+        }
+    }
+}
+
+/// A rectangle of the framebuffer in pixels, with the origin at the
+/// top left
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PixelRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The region of the framebuffer a camera draws to, normalized so that
+/// (0, 0) is the top left corner and (1, 1) the bottom right
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Viewport {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Covers the whole framebuffer
+    pub fn full() -> Self {
+        Viewport::new(0.0, 0.0, 1.0, 1.0)
+    }
+
+    /// The `index`th cell of a grid, counting left to right then top to
+    /// bottom. `Viewport::grid(2, 1, 1)` is the right half of a two
+    /// player split screen.
+    pub fn grid(columns: u32, rows: u32, index: u32) -> Self {
+        let (columns, rows) = (columns.max(1), rows.max(1));
+        let width = 1.0 / columns as f32;
+        let height = 1.0 / rows as f32;
+        Viewport::new(
+            (index % columns) as f32 * width,
+            (index / columns) as f32 * height,
+            width,
+            height,
+        )
+    }
+
+    pub fn to_pixels(&self, (width, height): (u32, u32)) -> PixelRect {
+        let (fw, fh) = (width as f32, height as f32);
+        let x0 = (self.x * fw).round();
+        let y0 = (self.y * fh).round();
+        let x1 = ((self.x + self.width) * fw).round().max(x0);
+        let y1 = ((self.y + self.height) * fh).round().max(y0);
+        PixelRect {
+            x: x0 as i32,
+            y: y0 as i32,
+            width: (x1 - x0) as u32,
+            height: (y1 - y0) as u32,
+        }
+    }
+
+    /// Aspect ratio of the viewport on a framebuffer of the given size
+    pub fn aspect(&self, (width, height): (u32, u32)) -> f32 {
+        let w = self.width * width as f32;
+        let h = self.height * height as f32;
+        if h > 0.0 {
+            w / h
+        } else {
+            1.0
+        }
+    }
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport::full()
+    }
+}
+
+/// What a camera clears in its viewport before drawing
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ClearSettings {
+    /// Color to clear to, or None to draw over what is already there
+    pub color: Option<ColorRGBA>,
+    pub depth: bool,
+}
+
+impl Default for ClearSettings {
+    fn default() -> Self {
+        ClearSettings {
+            color: None,
+            depth: true,
+        }
+    }
+}
+
+/// A camera resolved for drawing a single frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderView {
+    pub view: Matrix4<f32>,
+    pub projection: Matrix4<f32>,
+    pub viewport: Viewport,
+    pub clear: ClearSettings,
+    pub priority: i32,
+    /// Mesh entities inside the view's frustum
+    pub visible: Vec<Entity>,
+}
+
+/// A view's clear of its own pixels, for backends whose render pass can
+/// only clear whole attachments
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ClearRect {
+    pub rect: PixelRect,
+    pub color: Option<ColorRGBA>,
+    pub depth: bool,
+}
+
+/// The clears of a frame's views, split into the clear of the whole
+/// framebuffer which starts the frame and the rectangles each view
+/// clears before it draws
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameClears {
+    /// Color the whole framebuffer starts as. Depth starts at 1.0.
+    pub color: ColorRGBA,
+    /// The clear drawn before each view, if it needs one
+    pub views: Vec<Option<ClearRect>>,
+}
+
+impl FrameClears {
+    /// Clears for views drawn in order. Pixels no view clears start as
+    /// `background`, unless the first view covers the whole framebuffer,
+    /// when its clear color starts the frame instead.
+    pub fn new(
+        views: &[RenderView],
+        framebuffer_size: (u32, u32),
+        background: ColorRGBA,
+    ) -> Self {
+        let full = Viewport::full().to_pixels(framebuffer_size);
+        let mut color = background;
+        let clears = views
+            .iter()
+            .enumerate()
+            .map(|(i, view)| {
+                let rect = view.viewport.to_pixels(framebuffer_size);
+                let mut clear = view.clear;
+                if i == 0 {
+                    // nothing has been drawn over the starting depth yet
+                    clear.depth = false;
+                    if rect == full {
+                        if let Some(view_color) = clear.color.take() {
+                            color = view_color;
+                        }
+                    }
+                }
+                if clear.color.is_none() && !clear.depth {
+                    return None;
+                }
+                Some(ClearRect {
+                    rect,
+                    color: clear.color,
+                    depth: clear.depth,
+                })
+            })
+            .collect();
+        FrameClears {
+            color,
+            views: clears,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_viewport_grid() {
+        let right = Viewport::grid(2, 1, 1);
+        assert_eq!(right, Viewport::new(0.5, 0.0, 0.5, 1.0));
+        assert_eq!(
+            right.to_pixels((1280, 720)),
+            PixelRect {
+                x: 640,
+                y: 0,
+                width: 640,
+                height: 720
+            }
+        );
+        assert_eq!(right.aspect((1280, 720)), 640.0 / 720.0);
+
+        let bottom_left = Viewport::grid(2, 2, 2);
+        assert_eq!(bottom_left, Viewport::new(0.0, 0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_viewport_pixels_cover_framebuffer() {
+        let size = (1001, 333);
+        let (left, right) = (Viewport::grid(3, 1, 0), Viewport::grid(3, 1, 1));
+        let last = Viewport::grid(3, 1, 2);
+        let total: u32 = [left, right, last]
+            .iter()
+            .map(|v| v.to_pixels(size).width)
+            .sum();
+        assert_eq!(total, 1001);
+        assert_eq!(left.to_pixels(size).width as i32, right.to_pixels(size).x);
+    }
+    fn render_view(viewport: Viewport, clear: ClearSettings) -> RenderView {
+        RenderView {
+            view: Matrix4::identity(),
+            projection: Matrix4::identity(),
+            viewport,
+            clear,
+            priority: 0,
+            visible: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_overlapping_view_clears() {
+        use crate::renderer::color::color4f;
+        let size = (800, 600);
+        let background = color4f(0.0, 0.0, 0.0, 1.0);
+        let (red, green) =
+            (color4f(1.0, 0.0, 0.0, 1.0), color4f(0.0, 1.0, 0.0, 1.0));
+        let inset = Viewport::new(0.5, 0.0, 0.5, 0.5);
+        let main = render_view(
+            Viewport::full(),
+            ClearSettings {
+                color: Some(red),
+                depth: true,
+            },
+        );
+        let overlay = render_view(
+            inset,
+            ClearSettings {
+                color: Some(green),
+                depth: true,
+            },
+        );
+
+        // the full view's clear starts the frame, and the inset clears
+        // its own color and depth over it
+        let clears = FrameClears::new(
+            &[main.clone(), overlay.clone()],
+            size,
+            background,
+        );
+        assert_eq!(clears.color, red);
+        assert_eq!(
+            clears.views,
+            vec![
+                None,
+                Some(ClearRect {
+                    rect: inset.to_pixels(size),
+                    color: Some(green),
+                    depth: true,
+                }),
+            ]
+        );
+
+        // an overlay keeping what's under it only clears depth
+        let hud = render_view(
+            inset,
+            ClearSettings {
+                color: None,
+                depth: true,
+            },
+        );
+        let clears = FrameClears::new(&[main, hud], size, background);
+        assert_eq!(clears.views[1].unwrap().color, None);
+        assert!(clears.views[1].unwrap().depth);
+
+        // an inset drawn first leaves the rest of the frame to the
+        // background
+        let clears = FrameClears::new(&[overlay], size, background);
+        assert_eq!(clears.color, background);
+        assert_eq!(
+            clears.views,
+            vec![Some(ClearRect {
+                rect: inset.to_pixels(size),
+                color: Some(green),
+                depth: false,
+            })]
+        );
+    }
+}
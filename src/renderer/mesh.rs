@@ -1,4 +1,5 @@
 use super::color::ColorRGBA;
+use crate::math::Bounds;
 use cgmath::*;
 use genmesh::{
     generators::{IndexedPolygon, SharedVertex},
@@ -6,6 +7,15 @@ use genmesh::{
 };
 use std::slice::Chunks;
 
+/// Most joints a skin can have to be skinned in the vertex shaders. Skins
+/// with more joints are skinned on the cpu with `Mesh::skinned`.
+pub const MAX_GPU_JOINTS: usize = 48;
+
+/// Most morph targets the vulkan vertex shader blends. Meshes with more
+/// targets, and all meshes under gl, are blended on the cpu with
+/// `Mesh::morphed`.
+pub const MAX_GPU_MORPH_TARGETS: usize = 8;
+
 /// A cffi and GPU-friendly vertex representaion
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
@@ -16,6 +26,10 @@ pub struct Vertex {
     pub bitangent: [f32; 3],
     pub uv: [f32; 2],
     pub color: [f32; 4],
+    /// Indices into the skin's joints of the joints moving the vertex
+    pub joints: [u32; 4],
+    /// Influence of each of `joints`. Unskinned vertices have zero weights.
+    pub weights: [f32; 4],
 }
 
 impl Default for Vertex {
@@ -27,6 +41,8 @@ impl Default for Vertex {
             bitangent: [0., 0., 0.],
             uv: [0., 0.],
             color: [1., 1., 1., 1.],
+            joints: [0; 4],
+            weights: [0.; 4],
         }
     }
 }
@@ -37,6 +53,8 @@ impl PartialEq for Vertex {
             && self.normal == other.normal
             && self.uv == other.uv
             && self.color == other.color
+            && self.joints == other.joints
+            && self.weights == other.weights
     }
 
     fn ne(&self, other: &Self) -> bool {
@@ -52,12 +70,27 @@ pub trait RenderMesh {
     fn indices(&self) -> &[u32] {
         &self.mesh().indices
     }
+    /// Bounds of the mesh's vertices. Computed on each call unless the
+    /// implementation caches it.
+    fn bounds(&self) -> Bounds {
+        self.mesh().bounds()
+    }
+}
+
+/// Per-vertex displacements blended into a mesh by a morph weight.
+/// Attributes a target doesn't displace are empty.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct MorphTarget {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tangents: Vec<[f32; 3]>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
+    pub morph_targets: Vec<MorphTarget>,
 }
 
 impl Mesh {
@@ -65,6 +98,7 @@ impl Mesh {
         Mesh {
             vertices: Vec::new(),
             indices: Vec::new(),
+            morph_targets: Vec::new(),
         }
     }
 
@@ -77,6 +111,14 @@ impl Mesh {
         (self.indices.len() * size_of::<u32>())
     }
 
+    /// Computes the bounding box and sphere of the mesh's vertices
+    pub fn bounds(&self) -> Bounds {
+        Bounds::from_points(
+            self.vertices.iter().map(|v| Point3::from(v.position)),
+        )
+        .unwrap_or_default()
+    }
+
     pub fn triangle_indices(&self) -> Chunks<u32> {
         self.indices.chunks(3)
     }
@@ -125,6 +167,91 @@ impl Mesh {
         }
     }
 
+    /// Whether any vertex is moved by a skin's joints
+    pub fn is_skinned(&self) -> bool {
+        self.vertices.iter().any(|v| v.weights != [0.; 4])
+    }
+
+    /// Skins the mesh on the cpu with a palette of joint matrices,
+    /// returning a copy in the pose of the palette with no joints or
+    /// morph targets left to apply. Blend morph targets in first.
+    /// Weights short of one leave part of the vertex in place, so
+    /// unskinned vertices keep their positions.
+    pub fn skinned(&self, palette: &[Matrix4<f32>]) -> Mesh {
+        let vertices = self
+            .vertices
+            .iter()
+            .map(|v| {
+                let skin = skin_matrix(v, palette);
+                let transform_dir = |d: [f32; 3]| -> [f32; 3] {
+                    (skin * Vector3::from(d).extend(0.0)).truncate().into()
+                };
+                let normal = (skin * Vector3::from(v.normal).extend(0.0))
+                    .truncate()
+                    .normalize();
+                Vertex {
+                    position: (skin * Vector3::from(v.position).extend(1.0))
+                        .truncate()
+                        .into(),
+                    normal: if normal.x.is_finite() {
+                        normal.into()
+                    } else {
+                        v.normal
+                    },
+                    tangent: transform_dir(v.tangent),
+                    bitangent: transform_dir(v.bitangent),
+                    joints: [0; 4],
+                    weights: [0.; 4],
+                    ..*v
+                }
+            })
+            .collect();
+        Mesh {
+            vertices,
+            indices: self.indices.clone(),
+            morph_targets: Vec::new(),
+        }
+    }
+
+    /// Blends the morph targets into the mesh with the given weights,
+    /// returning a copy without morph targets. Joints and weights are
+    /// kept, so the result can still be skinned.
+    pub fn morphed(&self, weights: &[f32]) -> Mesh {
+        let mut vertices = self.vertices.clone();
+        let add = |attr: &mut [f32; 3], delta: &[f32; 3], weight: f32| {
+            for (a, d) in attr.iter_mut().zip(delta.iter()) {
+                *a += d * weight;
+            }
+        };
+        for (target, &weight) in self.morph_targets.iter().zip(weights) {
+            if weight == 0.0 {
+                continue;
+            }
+            for (v, d) in vertices.iter_mut().zip(&target.positions) {
+                add(&mut v.position, d, weight);
+            }
+            for (v, d) in vertices.iter_mut().zip(&target.normals) {
+                add(&mut v.normal, d, weight);
+            }
+            for (v, d) in vertices.iter_mut().zip(&target.tangents) {
+                add(&mut v.tangent, d, weight);
+            }
+        }
+        if self.morph_targets.iter().any(|t| !t.normals.is_empty()) {
+            for v in &mut vertices {
+                let normal = Vector3::from(v.normal).normalize();
+                if normal.x.is_finite() {
+                    v.normal = normal.into();
+                }
+            }
+        }
+        Mesh {
+            vertices,
+            indices: self.indices.clone(),
+            morph_targets: Vec::new(),
+        }
+    }
+
     /// Creates a mesh from a genmesh geometry.
     pub fn from_genmesh<G>(generator: G) -> Self
     where
@@ -143,12 +270,30 @@ impl Mesh {
                 .indexed_polygon_iter()
                 .flat_map(|t| vec![t.x as u32, t.y as u32, t.z as u32])
                 .collect(),
+            morph_targets: Vec::new(),
         };
         m.calculate_tangents();
         m
     }
 }
 
+/// Blend of the joint matrices moving a vertex, as computed by the scene
+/// vertex shaders
+pub fn skin_matrix(vertex: &Vertex, palette: &[Matrix4<f32>]) -> Matrix4<f32> {
+    let mut total = 0.0;
+    let mut skin = Matrix4::zero();
+    for (&joint, &weight) in vertex.joints.iter().zip(vertex.weights.iter()) {
+        if weight == 0.0 {
+            continue;
+        }
+        if let Some(matrix) = palette.get(joint as usize) {
+            skin += matrix * weight;
+            total += weight;
+        }
+    }
+    skin + Matrix4::identity() * (1.0 - total)
+}
+
 impl RenderMesh for Mesh {
     fn mesh(&self) -> &Mesh {
         self
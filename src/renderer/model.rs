@@ -1,9 +1,12 @@
 // Gltf Model
 // and scene presentation structure
+use crate::game::animation::{
+    AnimationClip, Channel, Interpolation, Keyframes,
+};
 use crate::math::*;
 
 use crate::renderer::material;
-use crate::renderer::Mesh;
+use crate::renderer::{Mesh, MorphTarget};
 use cgmath::*;
 use failure;
 use gltf;
@@ -13,6 +16,7 @@ use std::{
     cell::{Ref, RefCell},
     collections::HashMap,
     path::Path,
+    sync::Arc,
 };
 
 #[derive(Clone, PartialEq, Debug)]
@@ -21,10 +25,16 @@ pub struct MeshData {
     pub draw_mode: mesh::Mode,
     pub transform_index: Option<usize>,
     pub material_index: Option<usize>,
+    /// Bounds of the mesh, computed on load
+    pub bounds: Bounds,
+    /// Weights of the mesh's morph targets when nothing else drives them
+    pub default_weights: Vec<f32>,
 }
 impl MeshData {
     fn new(mesh: Mesh, draw_mode: mesh::Mode) -> Self {
         MeshData {
+            bounds: mesh.bounds(),
+            default_weights: vec![0.0; mesh.morph_targets.len()],
             mesh,
             draw_mode,
             transform_index: None,
@@ -33,6 +43,31 @@ impl MeshData {
     }
 }
 
+/// A node of the gltf scene graph.
+#[derive(Clone, PartialEq, Debug)]
+pub struct NodeData {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// transform relative to the parent node
+    pub transform: Decomposed<Vec3, Quaternion<f32>>,
+    /// indices into `Model::meshes` for each of the node's mesh primitives
+    pub mesh_indices: Vec<usize>,
+    /// index into `Model::skins` of the skin deforming the node's meshes
+    pub skin: Option<usize>,
+}
+
+/// A gltf skin: the nodes acting as joints of skinned meshes
+#[derive(Clone, PartialEq, Debug)]
+pub struct SkinData {
+    pub name: Option<String>,
+    /// joint nodes, in the order the meshes' joint indices refer to
+    pub joints: Vec<usize>,
+    /// transforms from mesh space into the space of each joint in the
+    /// bind pose
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
 #[derive(Clone)]
 pub struct GltfImport {
     pub document: gltf::Document,
@@ -45,6 +80,12 @@ pub struct Model {
     pub meshes: Vec<MeshData>,
     pub transforms: Vec<Mat4>,
     pub materials: HashMap<Option<usize>, material::Material<usize>>,
+    pub nodes: Vec<NodeData>,
+    /// nodes at the top of the default scene
+    pub root_nodes: Vec<usize>,
+    pub skins: Vec<SkinData>,
+    /// animations of the model's nodes
+    pub animations: Vec<Arc<AnimationClip>>,
     imports: GltfImport,
 }
 
@@ -54,6 +95,10 @@ impl Model {
             meshes: Vec::new(),
             transforms: Vec::new(),
             materials: HashMap::new(),
+            nodes: Vec::new(),
+            root_nodes: Vec::new(),
+            skins: Vec::new(),
+            animations: Vec::new(),
             imports,
         }
     }
@@ -115,15 +160,38 @@ impl Model {
             } = model.imports;
 
             model.transforms.push(Mat4::identity());
+            // indices into model.meshes for the primitives of each gltf mesh
+            let mut mesh_primitives = Vec::new();
             for ref g_mesh in document.meshes() {
                 let meshes: Vec<_> = make_mesh(g_mesh, &buffers)?;
+                let mut primitives = Vec::with_capacity(meshes.len());
                 for m in meshes {
                     let mut md = MeshData::new(m.mesh, m.mode);
                     md.material_index = m.material;
+                    if let Some(weights) = g_mesh.weights() {
+                        md.default_weights = weights.to_vec();
+                    }
 
+                    primitives.push(model.meshes.len());
                     model.meshes.push(md);
                 }
+                mesh_primitives.push(primitives);
             }
+            model.nodes = load_nodes(document, &mesh_primitives);
+            model.root_nodes = match document
+                .default_scene()
+                .or_else(|| document.scenes().next())
+            {
+                Some(scene) => scene.nodes().map(|n| n.index()).collect(),
+                None => (0..model.nodes.len())
+                    .filter(|&i| model.nodes[i].parent.is_none())
+                    .collect(),
+            };
+            model.skins = load_skins(document, buffers)?;
+            model.animations = load_animations(document, buffers)?
+                .into_iter()
+                .map(Arc::new)
+                .collect();
         }
 
         model.load_materials();
@@ -131,6 +199,175 @@ impl Model {
     }
 }
 
+fn load_nodes(
+    document: &gltf::Document,
+    mesh_primitives: &[Vec<usize>],
+) -> Vec<NodeData> {
+    let mut nodes: Vec<NodeData> = document
+        .nodes()
+        .map(|node| {
+            let (translation, rotation, scale) = node.transform().decomposed();
+            if scale[0] != scale[1] || scale[0] != scale[2] {
+                warn!(
+                    "node {:?} has non-uniform scale {:?}, using largest axis",
+                    node.name(),
+                    scale
+                );
+            }
+            NodeData {
+                name: node.name().map(String::from),
+                parent: None,
+                children: node.children().map(|c| c.index()).collect(),
+                transform: Decomposed {
+                    scale: scale[0].max(scale[1]).max(scale[2]),
+                    // gltf stores quaternions as [x, y, z, w]
+                    rot: Quaternion::new(
+                        rotation[3],
+                        rotation[0],
+                        rotation[1],
+                        rotation[2],
+                    ),
+                    disp: translation.into(),
+                },
+                mesh_indices: node
+                    .mesh()
+                    .map(|m| mesh_primitives[m.index()].clone())
+                    .unwrap_or_default(),
+                skin: node.skin().map(|s| s.index()),
+            }
+        })
+        .collect();
+
+    for parent in 0..nodes.len() {
+        for child in nodes[parent].children.clone() {
+            nodes[child].parent = Some(parent);
+        }
+    }
+    nodes
+}
+
+fn load_skins(
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+) -> Result<Vec<SkinData>, failure::Error> {
+    let get_buffer_data =
+        |buffer: gltf::Buffer| Some(&*buffers[buffer.index()]);
+    let mut skins = Vec::new();
+    for skin in document.skins() {
+        let joints: Vec<usize> = skin.joints().map(|j| j.index()).collect();
+        // without inverse bind matrices, joints are bound at the origin
+        let inverse_bind_matrices: Vec<Mat4> =
+            match skin.reader(get_buffer_data).read_inverse_bind_matrices() {
+                Some(matrices) => matrices.map(Mat4::from).collect(),
+                None => vec![Mat4::identity(); joints.len()],
+            };
+        if inverse_bind_matrices.len() != joints.len() {
+            bail!(
+                "skin {:?} has {} joints but {} inverse bind matrices",
+                skin.name(),
+                joints.len(),
+                inverse_bind_matrices.len()
+            );
+        }
+        skins.push(SkinData {
+            name: skin.name().map(String::from),
+            joints,
+            inverse_bind_matrices,
+        });
+    }
+    Ok(skins)
+}
+
+fn load_animations(
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+) -> Result<Vec<AnimationClip>, failure::Error> {
+    use gltf::animation::{util::ReadOutputs, Interpolation as GltfInterp};
+    let get_buffer_data =
+        |buffer: gltf::Buffer| Some(&*buffers[buffer.index()]);
+    let mut clips = Vec::new();
+    for animation in document.animations() {
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let reader = channel.reader(get_buffer_data);
+            let times: Vec<f32> = reader
+                .read_inputs()
+                .ok_or(format_err!("animation channel is missing times"))?
+                .collect();
+            let keyframes = match reader.read_outputs() {
+                Some(ReadOutputs::Translations(t)) => {
+                    Keyframes::Translation(t.map(Vec3::from).collect())
+                }
+                Some(ReadOutputs::Scales(s)) => {
+                    Keyframes::Scale(s.map(Vec3::from).collect())
+                }
+                // gltf stores quaternions as [x, y, z, w]
+                Some(ReadOutputs::Rotations(r)) => Keyframes::Rotation(
+                    r.into_f32()
+                        .map(|[x, y, z, w]| Quaternion::new(w, x, y, z))
+                        .collect(),
+                ),
+                Some(ReadOutputs::MorphTargetWeights(w)) => {
+                    Keyframes::Weights(w.into_f32().collect())
+                }
+                None => bail!("animation channel is missing values"),
+            };
+            let interpolation = match channel.sampler().interpolation() {
+                GltfInterp::Step => Interpolation::Step,
+                GltfInterp::CubicSpline => Interpolation::CubicSpline,
+                _ => Interpolation::Linear,
+            };
+            let per_keyframe = match interpolation {
+                Interpolation::CubicSpline => 3,
+                _ => 1,
+            };
+            let values = match keyframes {
+                Keyframes::Translation(ref v) | Keyframes::Scale(ref v) => {
+                    v.len()
+                }
+                Keyframes::Rotation(ref v) => v.len(),
+                // one weight per morph target of the node's mesh
+                Keyframes::Weights(ref v) => {
+                    let targets = channel
+                        .target()
+                        .node()
+                        .mesh()
+                        .and_then(|m| m.primitives().next())
+                        .map_or(0, |p| p.morph_targets().count());
+                    if targets == 0 || v.len() % targets != 0 {
+                        bail!(
+                            "animation {:?} has {} weights for {} morph targets",
+                            animation.name(),
+                            v.len(),
+                            targets
+                        );
+                    }
+                    v.len() / targets
+                }
+            };
+            if times.is_empty() || values != times.len() * per_keyframe {
+                bail!(
+                    "animation {:?} has {} keyframe times but {} values",
+                    animation.name(),
+                    times.len(),
+                    values
+                );
+            }
+            channels.push(Channel {
+                node: channel.target().node().index(),
+                interpolation,
+                times,
+                keyframes,
+            });
+        }
+        clips.push(AnimationClip::new(
+            animation.name().map(String::from),
+            channels,
+        ));
+    }
+    Ok(clips)
+}
+
 struct ParsedMesh {
     mesh: Mesh,
     mode: mesh::Mode,
@@ -173,6 +410,26 @@ fn make_mesh(
                 vertices[i].uv = uv.clone();
             }
         }
+        if let Some(joints) = reader.read_joints(0) {
+            for (i, joints) in joints.into_u16().enumerate() {
+                let [a, b, c, d] = joints;
+                vertices[i].joints = [a.into(), b.into(), c.into(), d.into()];
+            }
+        }
+        if let Some(weights) = reader.read_weights(0) {
+            for (i, weights) in weights.into_f32().enumerate() {
+                vertices[i].weights = weights;
+            }
+        }
+
+        let mut morph_targets = Vec::new();
+        for (positions, normals, tangents) in reader.read_morph_targets() {
+            morph_targets.push(MorphTarget {
+                positions: positions.map(Iterator::collect).unwrap_or_default(),
+                normals: normals.map(Iterator::collect).unwrap_or_default(),
+                tangents: tangents.map(Iterator::collect).unwrap_or_default(),
+            });
+        }
 
         let indices: Vec<u32> = if let Some(index_enum) = reader.read_indices()
         {
@@ -181,7 +438,11 @@ fn make_mesh(
             panic!("model doesn't have indices");
         };
 
-        let mut mesh = Mesh { indices, vertices };
+        let mut mesh = Mesh {
+            indices,
+            vertices,
+            morph_targets,
+        };
         mesh.calculate_tangents();
 
         let mesh_data = ParsedMesh {
@@ -213,4 +474,107 @@ mod test {
             panic!("No meshes found")
         }
     }
+
+    #[test]
+    fn test_model_nodes() {
+        let model = Model::from_gltf("assets/models/DamagedHelmet.glb")
+            .expect("could not load DamagedHelmet.glb");
+        assert_eq!(model.root_nodes, vec![0]);
+        assert_eq!(model.nodes.len(), 1);
+        let node = &model.nodes[0];
+        assert_eq!(node.parent, None);
+        assert_eq!(node.mesh_indices, vec![0]);
+        assert!(node.transform.rot.s > 0.7 && node.transform.rot.v.x > 0.7);
+        assert!(model.animations.is_empty());
+        assert!(model.skins.is_empty());
+        assert_eq!(node.skin, None);
+        assert!(!model.meshes[0].mesh.is_skinned());
+    }
+
+    #[test]
+    fn test_model_skins() {
+        let model = Model::from_gltf("assets/models/SkinnedStrip.gltf")
+            .expect("could not load SkinnedStrip.gltf");
+        assert_eq!(model.skins.len(), 1);
+        let skin = &model.skins[0];
+        assert_eq!(skin.name.as_ref().unwrap(), "Leg");
+        assert_eq!(skin.joints, vec![1, 2]);
+        assert_eq!(skin.inverse_bind_matrices[0], Mat4::identity());
+        assert_eq!(
+            skin.inverse_bind_matrices[1],
+            Mat4::from_translation(vec3(0.0, -1.0, 0.0))
+        );
+        assert_eq!(model.nodes[0].skin, Some(0));
+        assert_eq!(model.nodes[1].skin, None);
+
+        let mesh = &model.meshes[0].mesh;
+        assert!(mesh.is_skinned());
+        let middle = &mesh.vertices[2];
+        assert_eq!(middle.joints, [0, 1, 0, 0]);
+        assert_eq!(middle.weights, [0.5, 0.5, 0.0, 0.0]);
+        assert_eq!(mesh.vertices[4].joints, [1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_model_morph_targets() {
+        let model = Model::from_gltf("assets/models/MorphQuad.gltf")
+            .expect("could not load MorphQuad.gltf");
+        let mesh_data = &model.meshes[0];
+        assert_eq!(mesh_data.default_weights, vec![0.5, 0.0]);
+        let targets = &mesh_data.mesh.morph_targets;
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].positions[2], [0.0, 1.0, 0.0]);
+        assert_eq!(targets[0].normals.len(), 4);
+        assert!(targets[0].tangents.is_empty());
+        assert!(targets[1].normals.is_empty());
+
+        let morphed = mesh_data.mesh.morphed(&[1.0, 0.5]);
+        assert!(morphed.morph_targets.is_empty());
+        assert_eq!(morphed.vertices[0].position, [0.0, 0.0, 0.0]);
+        assert_eq!(morphed.vertices[1].position, [1.5, 0.0, 0.0]);
+        assert_eq!(morphed.vertices[2].position, [1.5, 2.0, 0.0]);
+        assert_eq!(morphed.vertices[3].position, [0.0, 2.0, 0.0]);
+
+        let channel = &model.animations[0].channels[0];
+        assert_eq!(channel.target_count(), 2);
+        match channel.keyframes {
+            Keyframes::Weights(ref w) => assert_eq!(w.len(), 6),
+            ref other => panic!("expected weights, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_model_animations() {
+        let model = Model::from_gltf("assets/models/AnimatedProp.gltf")
+            .expect("could not load AnimatedProp.gltf");
+        assert_eq!(model.animations.len(), 2);
+        let bounce = &model.animations[0];
+        assert_eq!(bounce.name.as_ref().unwrap(), "Bounce");
+        assert_eq!(bounce.duration(), 2.0);
+        let interpolations: Vec<_> = bounce
+            .channels
+            .iter()
+            .map(|c| (c.node, c.interpolation))
+            .collect();
+        assert_eq!(
+            interpolations,
+            vec![
+                (0, Interpolation::Linear),
+                (1, Interpolation::Step),
+                (1, Interpolation::CubicSpline),
+            ]
+        );
+        match bounce.channels[1].keyframes {
+            Keyframes::Rotation(ref r) => {
+                assert_eq!(r[0], Quaternion::one());
+                assert!((r[1].v.y - r[1].s).abs() < 1e-6);
+            }
+            ref other => panic!("expected rotations, got {:?}", other),
+        }
+        // glTF's default interpolation is linear
+        assert_eq!(
+            model.animations[1].channels[0].interpolation,
+            Interpolation::Linear
+        );
+    }
 }
@@ -16,6 +16,16 @@ pub trait Renderer: Sized {
 
     fn clear(&self) {}
     fn camera(&self) -> Ref<Camera>;
+
+    /// Uploads a mesh into the renderer's mesh representation
+    fn create_mesh(&self, mesh: &Mesh) -> Result<Self::Mesh, failure::Error>;
+
+    /// Uploads a gltf image into the renderer's texture representation
+    fn create_texture(
+        &self,
+        image: &gltf::image::Data,
+    ) -> Result<Self::Texture, failure::Error>;
+
     fn set_clear_color(&mut self, _color: ColorRGBA) {}
     fn on_resize(&self, _size: (u32, u32)) {}
     fn on_update<CS>(
@@ -13,7 +13,7 @@ use std::fmt;
 use std::ptr;
 use std::rc::Rc;
 
-use crate::config::PlatformConfig;
+use crate::config::{PlatformConfig, VSync};
 
 pub enum PlatformError {}
 
@@ -225,6 +225,7 @@ pub mod gl_platform {
 
             let gl_ctx = load_opengl(&window, video_subsystem)
                 .map_err(&failure::err_msg)?;
+            set_swap_interval(video_subsystem, platform_builder.config.vsync);
             {
                 self.gl_ctx.replace(Some(gl_ctx));
             }
@@ -304,6 +305,29 @@ pub mod gl_platform {
         };
         warn!("\n------------------------------");
     }
+
+    /// Sets whether buffer swaps wait for the vertical blank. Adaptive
+    /// vsync falls back to plain vsync where the driver lacks it.
+    pub fn set_swap_interval(video_subsystem: &VideoSubsystem, vsync: VSync) {
+        use sdl2::video::SwapInterval;
+        let interval = match vsync {
+            VSync::Off => SwapInterval::Immediate,
+            VSync::On => SwapInterval::VSync,
+            VSync::Adaptive => SwapInterval::LateSwapTearing,
+        };
+        let result = video_subsystem
+            .gl_set_swap_interval(interval)
+            .or_else(|e| match vsync {
+                VSync::Adaptive => {
+                    video_subsystem.gl_set_swap_interval(SwapInterval::VSync)
+                }
+                _ => Err(e),
+            });
+        if let Err(e) = result {
+            warn!("could not set swap interval for {:?}: {}", vsync, e);
+        }
+    }
+
     pub fn load_opengl(
         window: &Window,
         video_subsystem: &VideoSubsystem,
@@ -339,4 +363,4 @@ pub mod gl_platform {
 }
 
 #[cfg(feature = "backend-gl")]
-pub use self::gl_platform::{load_opengl, GlPlatformBuilder};
+pub use self::gl_platform::{load_opengl, set_swap_interval, GlPlatformBuilder};
@@ -0,0 +1,38 @@
+//! Plays the mixer through SDL's dummy audio driver, which needs no
+//! sound hardware.
+
+use slsengine::game::audio::{AudioClip, AudioSystem, VoiceSettings};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_dummy_driver_pulls_mixer() {
+    sdl2::hint::set("SDL_AUDIODRIVER", "dummy");
+    let sdl = sdl2::init().expect("could not init SDL");
+    let mut audio = AudioSystem::default();
+    audio.open_output(&sdl).expect("could not open dummy audio device");
+    assert_eq!(audio.output().unwrap().driver(), "dummy");
+
+    let clip = Arc::new(AudioClip::new(vec![0.1; 44_100], 1, 44_100));
+    audio.mixer().lock().unwrap().set_voice(
+        0,
+        VoiceSettings {
+            clip,
+            gains: [1.0, 1.0],
+            pitch: 1.0,
+            looping: true,
+            mono: true,
+        },
+    );
+    // the device's thread mixes on time even though nothing is heard
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        let time = audio.mixer().lock().unwrap().voice_time(0).unwrap();
+        if time > 0.0 {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("the dummy driver never pulled samples from the mixer");
+}